@@ -29,7 +29,7 @@ impl eip_operator_shared::controller::Context for Context {
     type Resource = Pod;
     type Error = Error;
 
-    const FINALIZER_NAME: &'static str = "eip.materialize.cloud/cilium-no-masquerade-rule";
+    const FINALIZER_NAME: &'static str = "cilium-no-masquerade-rule";
 
     #[instrument(skip(self, _client, _api, pod), err)]
     async fn apply(
@@ -160,14 +160,26 @@ async fn run() -> Result<(), Error> {
     debug!("Getting namespace from env...");
     let namespace = std::env::var("NAMESPACE").ok();
 
+    debug!("Getting metadata domain from env...");
+    if let Ok(domain) = std::env::var("METADATA_DOMAIN") {
+        eip_operator_shared::set_metadata_domain(domain);
+    }
+
+    debug!("Getting pod label selector from env...");
+    let pod_label_selector = std::env::var("POD_LABEL_SELECTOR").ok();
+
     debug!("Getting rtnetlink handle");
     let (connection, handle, _) = new_connection()?;
     tokio::spawn(connection);
 
     info!("Watching for events...");
     let context = Context::new(handle, vpc_cidr);
+    let label_selector = match pod_label_selector {
+        Some(selector) => format!("{},{}", *MANAGE_EIP_LABEL, selector),
+        None => MANAGE_EIP_LABEL.to_owned(),
+    };
     let list_params = ListParams::default()
-        .labels(MANAGE_EIP_LABEL)
+        .labels(&label_selector)
         .fields(&format!("spec.nodeName={}", node_name));
     let controller = match namespace {
         Some(ref namespace) => Controller::namespaced(namespace, k8s_client, list_params, context),
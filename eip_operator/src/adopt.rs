@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use kube::api::{Api, Patch, PatchParams};
+use kube::{Client, Resource};
+use kube_runtime::events::{Event, EventType, Recorder};
+
+use eip_operator::aws::{self, Ec2Clients};
+use eip_operator::eip::v2::{Eip, EipSelector, EipSpec};
+use eip_operator_shared::Error;
+
+use crate::cli::Args;
+
+/// Brings a hand-managed address under the operator's control: tags it with
+/// the cluster, namespace, and name of the Eip, then creates or updates the
+/// Eip, annotated to adopt the address, which the Eip controller then tags
+/// with the Eip's UID. An Event on the Eip records who adopted the address.
+pub(crate) async fn run(
+    args: Args,
+    allocation_id: String,
+    eip_name: String,
+    selector: EipSelector,
+) -> Result<(), Error> {
+    let client = Client::try_default().await?;
+    let namespace = args
+        .namespace
+        .clone()
+        .unwrap_or_else(|| "default".to_owned());
+    let eip_api = Api::<Eip>::namespaced(client.clone(), &namespace);
+    let existing = eip_api.get_opt(&eip_name).await?;
+    if let Some(current) = existing.as_ref().and_then(|eip| eip.allocation_id()) {
+        if current != allocation_id {
+            eprintln!(
+                "Eip {}/{} already has address {}.",
+                namespace, eip_name, current
+            );
+            return Err(Error::AddressNotAdoptable(allocation_id));
+        }
+    }
+
+    let mut eip = Eip::new(
+        &eip_name,
+        EipSpec {
+            selector,
+            provider: None,
+            dns_name: None,
+            transfer_to: None,
+            tags: None,
+        },
+    );
+    eip.metadata.annotations = Some(BTreeMap::from([(
+        crate::ADOPT_ALLOCATION_ID_ANNOTATION.clone(),
+        allocation_id.clone(),
+    )]));
+
+    let aws_config = crate::load_aws_config(&args).await?;
+    let ec2_clients = Ec2Clients::new(
+        &aws_config,
+        &args.namespace_role_arns,
+        args.aws_use_fips_endpoint
+            .then(|| crate::fips_endpoint(&aws_config, "ec2"))
+            .transpose()?,
+    );
+    let providers = crate::providers(&args, ec2_clients);
+    let provider = providers.for_eip(&eip)?;
+    let address = provider.describe(&namespace, &allocation_id).await?;
+    // Addresses of other Eips, or of other clusters, are never taken over.
+    let existing_uid = existing
+        .as_ref()
+        .and_then(|eip| eip.metadata.uid.as_deref());
+    let claimed = address
+        .tag(aws::EIP_UID_TAG)
+        .map_or(false, |uid| Some(uid) != existing_uid)
+        || address
+            .tag(aws::CLUSTER_NAME_TAG)
+            .map_or(false, |cluster_name| cluster_name != args.cluster_name);
+    if claimed {
+        eprintln!(
+            "{} is already managed by another Eip or cluster: {:?}",
+            allocation_id, address.tags
+        );
+        return Err(Error::AddressNotAdoptable(allocation_id));
+    }
+
+    println!("Tagging {} ({}).", allocation_id, address.public_ip);
+    provider
+        .converge_tags(
+            &namespace,
+            &address,
+            &[
+                (aws::MANAGED_BY_TAG, aws::MANAGED_BY),
+                (aws::OPERATOR_VERSION_TAG, aws::OPERATOR_VERSION),
+                (aws::CLUSTER_NAME_TAG, &args.cluster_name),
+                (aws::NAMESPACE_TAG, &namespace),
+                (aws::EIP_NAME_TAG, &eip_name),
+            ],
+        )
+        .await?;
+
+    println!("Applying Eip {}/{}.", namespace, eip_name);
+    let eip = eip_api
+        .patch(
+            &eip_name,
+            &PatchParams::apply(eip_operator_shared::metadata_domain()),
+            &Patch::Apply(&eip),
+        )
+        .await?;
+
+    let adopted_by = aws::caller_identity(&aws_config).await;
+    let recorder = Recorder::new(
+        client,
+        eip_operator_shared::metadata_domain().to_owned().into(),
+        eip.object_ref(&()),
+    );
+    recorder
+        .publish(Event {
+            type_: EventType::Normal,
+            reason: "AddressAdopted".to_owned(),
+            note: Some(format!(
+                "Adopted {} ({}), by {}",
+                allocation_id,
+                address.public_ip,
+                adopted_by.as_deref().unwrap_or("an unknown identity"),
+            )),
+            action: "Adopt".to_owned(),
+            secondary: None,
+        })
+        .await?;
+    println!(
+        "The operator adopts {} for Eip {}/{} when it next reconciles the Eip.",
+        allocation_id, namespace, eip_name
+    );
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use kube::api::Api;
+use kube::{Client, ResourceExt};
+use tracing::{event, instrument, Level};
+
+use eip_operator_shared::Error;
+
+use crate::eip::v2::Eip;
+use crate::eip_index::EipIndex;
+use crate::provider::Providers;
+
+/// Periodically checks that the address of each of the cluster's Eips still
+/// exists, so that one released outside of the operator is replaced, rather
+/// than left recorded in the Eip's status.
+pub(crate) async fn run(
+    client: Client,
+    interval: Duration,
+    cluster_name: String,
+    namespace: Option<String>,
+    eip_index: EipIndex,
+    providers: Providers,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        if let Err(err) = audit_allocations(
+            &client,
+            &cluster_name,
+            namespace.as_deref(),
+            &eip_index,
+            &providers,
+        )
+        .await
+        {
+            event!(Level::ERROR, err = %err, "Failed to audit EIP allocations.");
+        }
+    }
+}
+
+#[instrument(skip(client, eip_index, providers), err)]
+async fn audit_allocations(
+    client: &Client,
+    cluster_name: &str,
+    namespace: Option<&str>,
+    eip_index: &EipIndex,
+    providers: &Providers,
+) -> Result<(), Error> {
+    if !eip_index.ready() {
+        event!(Level::DEBUG, "Not auditing Eips before they are listed.");
+        return Ok(());
+    }
+    // Listed before the addresses, so that every allocation the Eips record
+    // was made before the addresses are described.
+    let eips = eip_index.all();
+    let mut allocation_ids = HashSet::new();
+    for (_, provider) in providers.all() {
+        for address in provider
+            .describe_cluster_addresses(cluster_name, namespace)
+            .await?
+        {
+            allocation_ids.insert(address.allocation_id);
+        }
+    }
+    for eip in eips {
+        let allocation_id = match eip.allocation_id() {
+            Some(allocation_id) if !allocation_ids.contains(allocation_id) => allocation_id,
+            _ => continue,
+        };
+        // Tags may have been edited, or not be visible yet, so only an
+        // address which can't be described is taken to be gone.
+        let eip_namespace = eip.namespace().unwrap_or_default();
+        match providers
+            .for_eip(&eip)?
+            .describe(&eip_namespace, allocation_id)
+            .await
+        {
+            Err(err) if err.is_allocation_not_found() => {
+                let api = Api::<Eip>::namespaced(client.clone(), &eip_namespace);
+                crate::controller::eip::report_allocation_lost(client, &api, &eip).await?;
+            }
+            Err(err) => return Err(err),
+            Ok(_) => event!(
+                Level::DEBUG,
+                eip = %eip.name_any(),
+                %allocation_id,
+                "The Eip's address is no longer tagged for the cluster."
+            ),
+        }
+    }
+    Ok(())
+}
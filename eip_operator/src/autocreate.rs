@@ -0,0 +1,316 @@
+use std::collections::{BTreeMap, HashMap};
+
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::ResourceExt;
+use serde::Deserialize;
+
+use eip_operator_shared::Error;
+
+use crate::eip::v2::{Eip, Provider};
+
+/// How Eips are created for pods with the autocreate label.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Autocreate {
+    /// The labels copied from each pod onto its Eip, and into the tags of its
+    /// address.
+    pub(crate) propagated_labels: Vec<String>,
+    /// The template of the names of Eips whose pods don't have their own.
+    pub(crate) name_template: Option<String>,
+    /// The templates pods can name for their Eips to inherit from.
+    pub(crate) templates: HashMap<String, EipTemplate>,
+}
+
+/// Fields of the Eip spec which autocreated Eips inherit from the template
+/// their pod names.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub(crate) struct EipTemplate {
+    /// The cloud which allocates the address.
+    pub(crate) provider: Option<Provider>,
+    /// Tags of the address, which propagated labels take precedence over.
+    #[serde(default)]
+    pub(crate) tags: BTreeMap<String, String>,
+    /// The DNS name, in which `{namespace}` and `{pod}` are replaced.
+    pub(crate) dns_name: Option<String>,
+    /// Whether the Eip is protected, so that its address outlives the pod
+    /// until the Eip is unprotected.
+    #[serde(default)]
+    pub(crate) protect: bool,
+}
+
+impl Autocreate {
+    /// The Eip to create for the pod.
+    pub(crate) fn eip_for(&self, pod: &Pod) -> Result<Eip, Error> {
+        let pod_name = pod.name_any();
+        let mut eip =
+            crate::eip::for_pod(&self.name_for(pod)?, &pod_name, pod.metadata.uid.as_deref());
+        eip.metadata.owner_references = controller_reference(pod).map(|owner| vec![owner]);
+        let mut tags = BTreeMap::new();
+        if let Some(template) = self.template_for(pod)? {
+            eip.spec.provider = template.provider;
+            eip.spec.dns_name = template.dns_name.as_ref().map(|dns_name| {
+                replace_placeholders(dns_name, &pod.namespace().unwrap_or_default(), &pod_name)
+            });
+            tags.extend(template.tags.clone());
+            if template.protect {
+                eip.metadata.annotations = Some(BTreeMap::from([(
+                    crate::PROTECT_ANNOTATION.clone(),
+                    "true".to_owned(),
+                )]));
+            }
+        }
+        let labels: BTreeMap<String, String> = self
+            .propagated_labels
+            .iter()
+            .filter_map(|key| Some((key.clone(), pod.labels().get(key)?.clone())))
+            .collect();
+        tags.extend(labels.clone());
+        if !tags.is_empty() {
+            eip.spec.tags = Some(tags);
+        }
+        if !labels.is_empty() {
+            eip.metadata.labels = Some(labels);
+        }
+        Ok(eip)
+    }
+
+    /// The template named by the pod's annotation, if any.
+    fn template_for(&self, pod: &Pod) -> Result<Option<&EipTemplate>, Error> {
+        match pod
+            .annotations()
+            .get(crate::EIP_TEMPLATE_ANNOTATION.as_str())
+        {
+            Some(name) => self
+                .templates
+                .get(name)
+                .map(Some)
+                .ok_or_else(|| Error::UnknownEipTemplate(name.clone())),
+            None => Ok(None),
+        }
+    }
+
+    /// The name of the Eip created for the pod: its name template, from its
+    /// annotation or the operator's, rendered, or else the pod's name.
+    pub(crate) fn name_for(&self, pod: &Pod) -> Result<String, Error> {
+        let pod_name = pod.metadata.name.as_ref().ok_or(Error::MissingPodName)?;
+        let template = pod
+            .annotations()
+            .get(crate::AUTOCREATE_NAME_ANNOTATION.as_str())
+            .or(self.name_template.as_ref());
+        match template {
+            Some(template) => render_name(template, &pod.namespace().unwrap_or_default(), pod_name),
+            None => Ok(pod_name.clone()),
+        }
+    }
+}
+
+/// Renders the Eip name template, replacing `{namespace}` and `{pod}`, and
+/// checks that the result is a valid name.
+pub(crate) fn render_name(
+    template: &str,
+    namespace: &str,
+    pod_name: &str,
+) -> Result<String, Error> {
+    let invalid = |reason: &str| Error::InvalidEipNameTemplate {
+        template: template.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let name = replace_placeholders(template, namespace, pod_name);
+    if name.contains(|c: char| c == '{' || c == '}') {
+        return Err(invalid("only {namespace} and {pod} can be replaced"));
+    }
+    let valid_char = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    if name.is_empty()
+        || name.len() > 253
+        || !name.chars().all(|c| valid_char(c) || c == '-' || c == '.')
+        || !name.starts_with(valid_char)
+        || !name.ends_with(valid_char)
+    {
+        return Err(invalid(&format!("{:?} is not a valid Eip name", name)));
+    }
+    Ok(name)
+}
+
+fn replace_placeholders(template: &str, namespace: &str, pod_name: &str) -> String {
+    template
+        .replace("{namespace}", namespace)
+        .replace("{pod}", pod_name)
+}
+
+/// A reference to the pod's controller, such as its StatefulSet, for the Eip
+/// autocreated for the pod to be owned by. It doesn't make the controller the
+/// Eip's controller, nor block the controller's deletion.
+fn controller_reference(pod: &Pod) -> Option<OwnerReference> {
+    pod.owner_references()
+        .iter()
+        .find(|owner| owner.controller == Some(true))
+        .map(|owner| OwnerReference {
+            controller: None,
+            block_owner_deletion: None,
+            ..owner.clone()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pod(labels: &[(&str, &str)], owners: Vec<OwnerReference>) -> Pod {
+        let mut pod = Pod::default();
+        pod.metadata.name = Some("my-pod-0".to_owned());
+        pod.metadata.labels = Some(
+            labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
+        pod.metadata.owner_references = Some(owners);
+        pod
+    }
+
+    fn owner(kind: &str, controller: Option<bool>) -> OwnerReference {
+        OwnerReference {
+            api_version: "apps/v1".to_owned(),
+            kind: kind.to_owned(),
+            name: "my-pod".to_owned(),
+            uid: "01234567-89ab-cdef-0123-456789abcdef".to_owned(),
+            controller,
+            block_owner_deletion: Some(true),
+        }
+    }
+
+    #[test]
+    fn eips_are_owned_by_the_controller_of_their_pod() {
+        let pod = pod(
+            &[],
+            vec![owner("Other", None), owner("StatefulSet", Some(true))],
+        );
+        let eip = Autocreate::default().eip_for(&pod).unwrap();
+        assert_eq!(eip.name_any(), "my-pod-0");
+        let owners = eip.metadata.owner_references.unwrap();
+        assert_eq!(owners.len(), 1);
+        assert_eq!(owners[0].kind, "StatefulSet");
+        assert_eq!(owners[0].controller, None);
+        assert_eq!(owners[0].block_owner_deletion, None);
+
+        let eip = Autocreate::default()
+            .eip_for(&self::pod(&[], vec![]))
+            .unwrap();
+        assert_eq!(eip.metadata.owner_references, None);
+    }
+
+    #[test]
+    fn eips_select_only_the_instance_of_their_pod() {
+        let mut pod = pod(&[], vec![]);
+        pod.metadata.uid = Some("01234567".to_owned());
+        let eip = Autocreate::default().eip_for(&pod).unwrap();
+        assert!(eip.matches_pod_uid("01234567"));
+        assert!(!eip.matches_pod_uid("89abcdef"));
+    }
+
+    #[test]
+    fn eips_get_the_propagated_labels_of_their_pod() {
+        let autocreate = Autocreate {
+            propagated_labels: vec!["team".to_owned(), "env".to_owned()],
+            ..Default::default()
+        };
+        let pod = pod(&[("team", "payments"), ("app", "api")], vec![]);
+        let eip = autocreate.eip_for(&pod).unwrap();
+        let expected = BTreeMap::from([("team".to_owned(), "payments".to_owned())]);
+        assert_eq!(eip.metadata.labels.as_ref(), Some(&expected));
+        assert_eq!(eip.spec.tags.as_ref(), Some(&expected));
+
+        let eip = Autocreate::default().eip_for(&pod).unwrap();
+        assert_eq!(eip.metadata.labels, None);
+        assert_eq!(eip.spec.tags, None);
+    }
+
+    #[test]
+    fn eips_are_named_by_the_template_of_their_pod_or_the_operator() {
+        let mut pod = pod(&[], vec![]);
+        pod.metadata.namespace = Some("default".to_owned());
+        assert_eq!(Autocreate::default().name_for(&pod).unwrap(), "my-pod-0");
+
+        let autocreate = Autocreate {
+            name_template: Some("{namespace}-{pod}-egress".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            autocreate.name_for(&pod).unwrap(),
+            "default-my-pod-0-egress"
+        );
+
+        pod.metadata.annotations = Some(BTreeMap::from([(
+            crate::AUTOCREATE_NAME_ANNOTATION.clone(),
+            "{pod}-ingress".to_owned(),
+        )]));
+        assert_eq!(autocreate.name_for(&pod).unwrap(), "my-pod-0-ingress");
+        let eip = autocreate.eip_for(&pod).unwrap();
+        assert_eq!(eip.name_any(), "my-pod-0-ingress");
+        assert!(eip.matches_pod("my-pod-0"));
+    }
+
+    #[test]
+    fn eips_inherit_the_template_named_by_their_pod() {
+        let autocreate = Autocreate {
+            propagated_labels: vec!["team".to_owned()],
+            templates: HashMap::from([(
+                "public".to_owned(),
+                serde_json::from_value(serde_json::json!({
+                    "provider": "aws",
+                    "tags": {"team": "platform", "cost-center": "42"},
+                    "dnsName": "{pod}.{namespace}.example.com",
+                    "protect": true,
+                }))
+                .unwrap(),
+            )]),
+            ..Default::default()
+        };
+        let mut pod = pod(&[("team", "payments")], vec![]);
+        pod.metadata.namespace = Some("default".to_owned());
+        let eip = autocreate.eip_for(&pod).unwrap();
+        assert_eq!(eip.spec.dns_name, None);
+        assert_eq!(
+            eip.annotations().get(crate::PROTECT_ANNOTATION.as_str()),
+            None
+        );
+
+        pod.metadata.annotations = Some(BTreeMap::from([(
+            crate::EIP_TEMPLATE_ANNOTATION.clone(),
+            "public".to_owned(),
+        )]));
+        let eip = autocreate.eip_for(&pod).unwrap();
+        assert_eq!(eip.spec.provider, Some(Provider::Aws));
+        assert_eq!(
+            eip.spec.dns_name.as_deref(),
+            Some("my-pod-0.default.example.com")
+        );
+        assert_eq!(
+            eip.spec.tags,
+            Some(BTreeMap::from([
+                ("cost-center".to_owned(), "42".to_owned()),
+                ("team".to_owned(), "payments".to_owned()),
+            ]))
+        );
+        assert_eq!(
+            eip.annotations().get(crate::PROTECT_ANNOTATION.as_str()),
+            Some(&"true".to_owned())
+        );
+
+        pod.metadata.annotations = Some(BTreeMap::from([(
+            crate::EIP_TEMPLATE_ANNOTATION.clone(),
+            "private".to_owned(),
+        )]));
+        let err = autocreate.eip_for(&pod).unwrap_err();
+        assert_eq!(err.reason(), "UnknownEipTemplate");
+    }
+
+    #[test]
+    fn invalid_name_templates_are_refused() {
+        for template in ["{node}-{pod}", "{pod}_egress", "-{pod}", "{pod}-", ""] {
+            let err = render_name(template, "default", "my-pod-0").unwrap_err();
+            assert_eq!(err.reason(), "InvalidEipNameTemplate");
+        }
+    }
+}
@@ -1,164 +1,691 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::time::Duration;
 
+use futures::Future;
+
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::SdkConfig;
 use aws_sdk_ec2::error::{
-    AllocateAddressError, AssociateAddressError, DescribeAddressesError, DescribeInstancesError,
-    DisassociateAddressError, ReleaseAddressError,
+    AssignPrivateIpAddressesError, AssociateAddressError, CreateTagsError, DescribeAddressesError,
+    DescribeInstancesError, DescribeNetworkInterfacesError, DisassociateAddressError,
+    ReleaseAddressError,
+};
+use aws_sdk_ec2::model::{
+    DomainType, Filter, Instance, InstanceStateName, NetworkInterface,
+    NetworkInterfaceAttachmentChanges, ResourceType, Tag, TagSpecification,
 };
-use aws_sdk_ec2::model::{Address, DomainType, Filter, ResourceType, Tag, TagSpecification};
 use aws_sdk_ec2::output::{
     AllocateAddressOutput, AssociateAddressOutput, DescribeAddressesOutput,
     DescribeInstancesOutput, ReleaseAddressOutput,
 };
 use aws_sdk_ec2::types::SdkError;
 use aws_sdk_ec2::Client as Ec2Client;
-use tracing::{debug, info, instrument};
+use aws_smithy_client::erase::DynConnector;
+use aws_smithy_client::http_connector::HttpConnector;
+use aws_smithy_client::hyper_ext::Adapter;
+use aws_smithy_http::endpoint::Endpoint;
+use eip_operator_shared::proxy::ProxySettings;
+use hyper_tls::HttpsConnector;
+use tracing::{debug, info, instrument, warn};
 
-pub(crate) const LEGACY_CLUSTER_NAME_TAG: &str = "eip.aws.materialize.com/cluster_name";
+use crate::provider::{dry_run, Address, AllocationRequest, CloudProvider};
 
-pub(crate) const POD_NAME_TAG: &str = "eip.materialize.cloud/pod_name";
-pub(crate) const NODE_SELECTOR_TAG: &str = "eip.materialize.cloud/node_selector";
-pub(crate) const EIP_UID_TAG: &str = "eip.materialize.cloud/eip_uid";
-pub(crate) const EIP_NAME_TAG: &str = "eip.materialize.cloud/eip_name";
-pub(crate) const CLUSTER_NAME_TAG: &str = "eip.materialize.cloud/cluster_name";
-pub(crate) const NAMESPACE_TAG: &str = "eip.materialize.cloud/namespace";
-pub(crate) const NAME_TAG: &str = "Name";
+pub const LEGACY_CLUSTER_NAME_TAG: &str = "eip.aws.materialize.com/cluster_name";
 
-/// Allocates an AWS Elastic IP, and tags it with the pod uid it will later be associated with.
-#[instrument(skip(ec2_client), err)]
-pub(crate) async fn allocate_address(
-    ec2_client: &Ec2Client,
-    eip_uid: &str,
-    eip_name: &str,
-    eip_selector: &crate::eip::v2::EipSelector,
-    cluster_name: &str,
-    namespace: &str,
-    default_tags: &HashMap<String, String>,
-) -> Result<AllocateAddressOutput, SdkError<AllocateAddressError>> {
-    let mut tags: Vec<Tag> = default_tags
-        .iter()
-        .map(|(k, v)| Tag::builder().key(k).value(v).build())
-        .collect();
-    tags.push(Tag::builder().key(EIP_UID_TAG).value(eip_uid).build());
-    tags.push(Tag::builder().key(EIP_NAME_TAG).value(eip_name).build());
-    tags.push(Tag::builder().key(NAMESPACE_TAG).value(namespace).build());
-    tags.push(
-        Tag::builder()
-            .key(CLUSTER_NAME_TAG)
-            .value(cluster_name)
-            .build(),
-    );
-    match eip_selector {
-        crate::eip::v2::EipSelector::Pod { pod_name } => {
-            tags.push(Tag::builder().key(POD_NAME_TAG).value(pod_name).build());
+pub const POD_NAME_TAG: &str = "eip.materialize.cloud/pod_name";
+pub const NODE_SELECTOR_TAG: &str = "eip.materialize.cloud/node_selector";
+pub const EIP_UID_TAG: &str = "eip.materialize.cloud/eip_uid";
+pub const EIP_NAME_TAG: &str = "eip.materialize.cloud/eip_name";
+pub const CLUSTER_NAME_TAG: &str = "eip.materialize.cloud/cluster_name";
+pub const NAMESPACE_TAG: &str = "eip.materialize.cloud/namespace";
+pub const NAME_TAG: &str = "Name";
+pub const ALLOCATED_AT_TAG: &str = "eip.materialize.cloud/allocated_at";
+pub const MANAGED_BY_TAG: &str = "eip.materialize.cloud/managed_by";
+pub const OPERATOR_VERSION_TAG: &str = "eip.materialize.cloud/operator_version";
+pub const CREATED_BY_TAG: &str = "eip.materialize.cloud/created_by";
+pub const TRANSFERRED_FROM_TAG: &str = "eip.materialize.cloud/transferred_from";
+pub const PROTECTED_TAG: &str = "eip.materialize.cloud/protected";
+/// The tag of a network interface dedicated to node EIPs, naming its instance.
+pub const DEDICATED_ENI_TAG: &str = "eip.materialize.cloud/dedicated_eni_for";
+/// The tag which stops the Amazon VPC CNI from managing a network interface.
+const VPC_CNI_NO_MANAGE_TAG: &str = "node.k8s.amazonaws.com/no_manage";
+
+pub const MANAGED_BY: &str = "eip-operator";
+pub const OPERATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The keys of the tags added to every address the operator allocates, besides
+/// one of the pod name or node selector tags.
+pub const GENERATED_TAGS: [&str; 9] = [
+    EIP_UID_TAG,
+    EIP_NAME_TAG,
+    NAMESPACE_TAG,
+    ALLOCATED_AT_TAG,
+    CLUSTER_NAME_TAG,
+    NAME_TAG,
+    MANAGED_BY_TAG,
+    OPERATOR_VERSION_TAG,
+    CREATED_BY_TAG,
+];
+
+/// Regions in which EC2 and Service Quotas offer FIPS endpoints. Standard
+/// endpoints in the GovCloud regions are already FIPS validated.
+const FIPS_REGIONS: [&str; 5] = [
+    "us-east-1",
+    "us-east-2",
+    "us-west-1",
+    "us-west-2",
+    "ca-central-1",
+];
+const FIPS_GOVCLOUD_REGIONS: [&str; 2] = ["us-gov-east-1", "us-gov-west-1"];
+
+const MAX_TAGS: usize = 50;
+const MAX_TAG_KEY_LENGTH: usize = 128;
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+// EC2 is eventually consistent, so a new association may not be visible right away
+const ASSOCIATION_CHECK_ATTEMPTS: u32 = 5;
+const ASSOCIATION_CHECK_INITIAL_DELAY: Duration = Duration::from_millis(250);
+
+/// Awaits an AWS API call, recording its latency and outcome in the AWS API metrics.
+pub async fn observe_call<T, E: Display>(
+    operation: &'static str,
+    call: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let timer = crate::metrics::AWS_API_LATENCY
+        .with_label_values(&[operation])
+        .start_timer();
+    let result = call.await;
+    timer.observe_duration();
+    let outcome = match &result {
+        Ok(_) => "success",
+        Err(e) if is_throttle(e) => "throttled",
+        Err(e) if is_dry_run(e) => "dry_run",
+        Err(_) => "error",
+    };
+    crate::metrics::AWS_API_CALLS
+        .with_label_values(&[operation, outcome])
+        .inc();
+    result
+}
+
+/// Checks whether an AWS error was caused by API rate limiting.
+fn is_throttle(error: &impl Display) -> bool {
+    let message = error.to_string();
+    message.contains("RequestLimitExceeded") || message.contains("Throttling")
+}
+
+/// Checks whether an AWS error only reports that a call would have been
+/// executed, if not for dry-run mode.
+fn is_dry_run(error: &impl Display) -> bool {
+    error.to_string().contains("DryRunOperation")
+}
+
+/// Checks whether an AWS error was caused by the allocation ID no longer
+/// existing, for example because the address was released out-of-band.
+fn is_allocation_not_found(error: &impl Display) -> bool {
+    error.to_string().contains("InvalidAllocationID.NotFound")
+}
+
+/// EC2 clients using the operator's own credentials, and for each namespace
+/// configured to allocate its EIPs under its own IAM role.
+#[derive(Clone)]
+pub struct Ec2Clients {
+    default: Ec2Client,
+    by_namespace: HashMap<String, Ec2Client>,
+}
+
+impl Ec2Clients {
+    /// Creates the clients, assuming each namespace's role using the
+    /// operator's own credentials, and using the endpoint if one is given.
+    pub fn new(
+        aws_config: &SdkConfig,
+        namespace_roles: &HashMap<String, String>,
+        endpoint: Option<Endpoint>,
+    ) -> Self {
+        let builder = || {
+            let builder = aws_sdk_ec2::config::Builder::from(aws_config);
+            match &endpoint {
+                Some(endpoint) => builder.endpoint_resolver(endpoint.clone()),
+                None => builder,
+            }
+        };
+        let by_namespace = namespace_roles
+            .iter()
+            .map(|(namespace, role_arn)| {
+                let mut provider =
+                    AssumeRoleProvider::builder(role_arn).session_name("eip-operator");
+                if let Some(region) = aws_config.region() {
+                    provider = provider.region(region.clone());
+                }
+                if let Some(HttpConnector::Prebuilt(Some(connector))) = aws_config.http_connector()
+                {
+                    provider = provider.connection(connector.clone());
+                }
+                let provider = provider.build(
+                    aws_config
+                        .credentials_provider()
+                        .expect("No AWS credentials provider configured.")
+                        .clone(),
+                );
+                let config = builder().credentials_provider(provider).build();
+                (namespace.to_owned(), Ec2Client::from_conf(config))
+            })
+            .collect();
+        Self {
+            default: Ec2Client::from_conf(builder().build()),
+            by_namespace,
         }
-        crate::eip::v2::EipSelector::Node { selector } => {
-            tags.push(
-                Tag::builder()
-                    .key(NODE_SELECTOR_TAG)
-                    .value(serde_json::to_string(selector).unwrap())
+    }
+
+    /// The client using the operator's own credentials, for anything which
+    /// isn't an EIP of a particular namespace, such as describing instances.
+    pub fn default_client(&self) -> &Ec2Client {
+        &self.default
+    }
+
+    /// The client for the EIPs of Eips in the namespace.
+    pub fn for_namespace(&self, namespace: &str) -> &Ec2Client {
+        self.by_namespace.get(namespace).unwrap_or(&self.default)
+    }
+
+    /// Every client, along with the namespace it is used for, if it isn't
+    /// the default client.
+    pub fn all(&self) -> impl Iterator<Item = (Option<&str>, &Ec2Client)> {
+        std::iter::once((None, &self.default)).chain(
+            self.by_namespace
+                .iter()
+                .map(|(namespace, client)| (Some(namespace.as_str()), client)),
+        )
+    }
+}
+
+/// Manages EIPs with EC2, using each namespace's client for its EIPs.
+pub struct AwsProvider {
+    clients: Ec2Clients,
+}
+
+impl AwsProvider {
+    pub fn new(clients: Ec2Clients) -> Self {
+        Self { clients }
+    }
+}
+
+#[async_trait::async_trait]
+impl CloudProvider for AwsProvider {
+    async fn allocate(&self, request: AllocationRequest<'_>) -> Result<Address, crate::Error> {
+        let ec2_client = self.clients.for_namespace(request.namespace);
+        let tags = allocation_tags(&request)?;
+        let response = allocate_address(ec2_client, tags.clone()).await?;
+        Ok(Address {
+            allocation_id: response
+                .allocation_id
+                .ok_or(crate::Error::MissingAllocationId)?,
+            public_ip: response.public_ip.ok_or(crate::Error::MissingPublicIp)?,
+            association_id: None,
+            eni: None,
+            private_ip_address: None,
+            instance_id: None,
+            tags: tags
+                .into_iter()
+                .filter_map(|tag| Some((tag.key?, tag.value?)))
+                .collect(),
+        })
+    }
+
+    async fn describe(
+        &self,
+        namespace: &str,
+        allocation_id: &str,
+    ) -> Result<Address, crate::Error> {
+        let output = describe_address(self.clients.for_namespace(namespace), allocation_id).await?;
+        to_addresses(output)?
+            .into_iter()
+            .next()
+            .ok_or(crate::Error::MissingAddresses)
+    }
+
+    async fn describe_by_tag(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<Address>, crate::Error> {
+        let ec2_client = self.clients.for_namespace(namespace);
+        to_addresses(describe_addresses_with_tag_value(ec2_client, key, value).await?)
+    }
+
+    async fn describe_cluster_addresses(
+        &self,
+        cluster_name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Vec<Address>, crate::Error> {
+        let mut addresses = vec![];
+        for (client_namespace, ec2_client) in self.clients.all() {
+            let namespace = match (namespace, client_namespace) {
+                (Some(namespace), Some(client_namespace)) if namespace != client_namespace => {
+                    continue;
+                }
+                (namespace, client_namespace) => client_namespace.or(namespace),
+            };
+            let mut describe_addresses = ec2_client.describe_addresses().filters(
+                Filter::builder()
+                    .name(format!("tag:{}", CLUSTER_NAME_TAG))
+                    .values(cluster_name.to_owned())
                     .build(),
             );
+            if let Some(namespace) = namespace {
+                describe_addresses = describe_addresses.filters(
+                    Filter::builder()
+                        .name(format!("tag:{}", NAMESPACE_TAG))
+                        .values(namespace.to_owned())
+                        .build(),
+                )
+            }
+            let output = observe_call("DescribeAddresses", describe_addresses.send()).await?;
+            addresses.extend(to_addresses(output)?);
         }
+        let legacy_output = describe_addresses_with_tag_value(
+            self.clients.default_client(),
+            LEGACY_CLUSTER_NAME_TAG,
+            cluster_name,
+        )
+        .await?;
+        addresses.extend(to_addresses(legacy_output)?);
+        // An address may be found by more than one client in the same account.
+        let mut seen = HashSet::new();
+        addresses.retain(|address| seen.insert(address.allocation_id.clone()));
+        Ok(addresses)
     }
 
-    tags.push(
-        Tag::builder()
-            .key(NAME_TAG)
-            .value(format!(
-                "eip-operator:{}:{}:{}",
-                cluster_name, namespace, eip_name
-            ))
-            .build(),
-    );
-    ec2_client
+    async fn network_interface(
+        &self,
+        instance_id: &str,
+        private_ip: &str,
+    ) -> Result<String, crate::Error> {
+        // Instances are always described with the operator's own credentials.
+        let instances = describe_instance(self.clients.default_client(), instance_id).await?;
+        get_eni_from_private_ip(&instances, instance_id, private_ip)
+    }
+
+    async fn dedicated_network_interface(
+        &self,
+        instance_id: &str,
+        ip_count: usize,
+    ) -> Result<(String, Vec<String>), crate::Error> {
+        // Network interfaces belong to instances, so are always managed with
+        // the operator's own credentials.
+        let ec2_client = self.clients.default_client();
+        let instances = describe_instance(ec2_client, instance_id).await?;
+        let instance = find_instance(&instances, instance_id)?;
+        let eni = match describe_dedicated_network_interface(ec2_client, instance_id).await? {
+            Some(eni) => eni,
+            None => create_dedicated_network_interface(ec2_client, instance).await?,
+        };
+        let eni_id = eni
+            .network_interface_id
+            .ok_or(crate::Error::MissingNetworkInterfaces)?;
+        let mut private_ips = private_ips(&eni);
+        if private_ips.is_empty() {
+            return Err(crate::Error::NoInterfaceWithThatIp);
+        }
+        let attached_instance = eni
+            .attachment
+            .as_ref()
+            .and_then(|attachment| attachment.instance_id.as_deref());
+        if attached_instance != Some(instance_id) {
+            attach_dedicated_network_interface(ec2_client, instance, &eni_id).await?;
+        }
+        if private_ips.len() < ip_count {
+            let assigned =
+                assign_private_ips(ec2_client, &eni_id, ip_count - private_ips.len()).await?;
+            private_ips.extend(assigned);
+            private_ips[1..].sort_by_key(|ip| ip.parse::<std::net::IpAddr>().ok());
+        }
+        Ok((eni_id, private_ips))
+    }
+
+    async fn associate(
+        &self,
+        namespace: &str,
+        allocation_id: &str,
+        eni: &str,
+        private_ip: &str,
+    ) -> Result<(), crate::Error> {
+        let ec2_client = self.clients.for_namespace(namespace);
+        associate_eip(ec2_client, allocation_id, eni, private_ip).await?;
+        wait_for_association(ec2_client, allocation_id, eni, private_ip).await
+    }
+
+    async fn disassociate(&self, namespace: &str, allocation_id: &str) -> Result<(), crate::Error> {
+        disassociate_allocation(self.clients.for_namespace(namespace), allocation_id).await
+    }
+
+    async fn release(&self, namespace: &str, address: &Address) -> Result<(), crate::Error> {
+        disassociate_and_release_address(self.clients.for_namespace(namespace), address).await
+    }
+
+    async fn converge_tags(
+        &self,
+        namespace: &str,
+        address: &Address,
+        tags: &[(&str, &str)],
+    ) -> Result<(), crate::Error> {
+        converge_tags(self.clients.for_namespace(namespace), address, tags).await
+    }
+}
+
+/// The tags added to every EIP, and the extra tags added to the EIPs of
+/// particular namespaces, such as to identify a tenant.
+#[derive(Clone, Debug, Default)]
+pub struct DefaultTags {
+    all: HashMap<String, String>,
+    by_namespace: HashMap<String, HashMap<String, String>>,
+}
+
+impl DefaultTags {
+    /// Creates the default tags, checking them as they will be combined for
+    /// each namespace. A namespace's tags take precedence over the others.
+    pub fn new(
+        all: HashMap<String, String>,
+        by_namespace: HashMap<String, HashMap<String, String>>,
+    ) -> Result<Self, crate::Error> {
+        let tags = Self { all, by_namespace };
+        validate_default_tags(&tags.all)?;
+        for namespace in tags.by_namespace.keys() {
+            validate_default_tags(&tags.for_namespace(namespace))?;
+        }
+        Ok(tags)
+    }
+
+    /// Adds tags to the EIPs of every namespace.
+    pub fn extend(&mut self, tags: HashMap<String, String>) {
+        self.all.extend(tags);
+    }
+
+    /// The tags to add to the EIPs of Eips in the namespace.
+    pub fn for_namespace(&self, namespace: &str) -> HashMap<String, String> {
+        let mut tags = self.all.clone();
+        if let Some(namespace_tags) = self.by_namespace.get(namespace) {
+            tags.extend(namespace_tags.clone());
+        }
+        tags
+    }
+}
+
+/// The ARN of the IAM identity the operator runs as, for the created-by tag.
+pub async fn caller_identity(aws_config: &SdkConfig) -> Option<String> {
+    let sts_client = aws_sdk_sts::Client::new(aws_config);
+    match observe_call("GetCallerIdentity", sts_client.get_caller_identity().send()).await {
+        Ok(response) => response.arn,
+        Err(err) => {
+            warn!(%err, "Failed to get caller identity, so EIPs won't be tagged with it.");
+            None
+        }
+    }
+}
+
+/// The tags identifying addresses as created by the operator, by this
+/// version, and with the given IAM identity, to add to the default tags.
+pub fn audit_tags(created_by: Option<String>) -> HashMap<String, String> {
+    let mut tags = HashMap::from([
+        (MANAGED_BY_TAG.to_owned(), MANAGED_BY.to_owned()),
+        (OPERATOR_VERSION_TAG.to_owned(), OPERATOR_VERSION.to_owned()),
+    ]);
+    if let Some(created_by) = created_by {
+        tags.insert(CREATED_BY_TAG.to_owned(), created_by);
+    }
+    tags
+}
+
+/// Sets any of the tags which the address lacks or has a different value for.
+#[instrument(skip(ec2_client, address), err)]
+async fn converge_tags(
+    ec2_client: &Ec2Client,
+    address: &Address,
+    tags: &[(&str, &str)],
+) -> Result<(), crate::Error> {
+    let stale: Vec<Tag> = tags
+        .iter()
+        .filter(|(key, value)| address.tag(key) != Some(*value))
+        .map(|(key, value)| Tag::builder().key(*key).value(*value).build())
+        .collect();
+    if stale.is_empty() {
+        return Ok(());
+    }
+    let request = ec2_client
+        .create_tags()
+        .dry_run(dry_run())
+        .resources(&address.allocation_id)
+        .set_tags(Some(stale));
+    observe_call("CreateTags", request.send()).await?;
+    Ok(())
+}
+
+/// A connector for the AWS clients which connects through the proxy, except
+/// to hosts in `NO_PROXY`.
+pub fn proxied_connector(proxy: &ProxySettings) -> HttpConnector {
+    let tls = native_tls::TlsConnector::new().expect("Failed to create a TLS connector.");
+    let connector = proxy.connector(HttpsConnector::new(), tls, false);
+    HttpConnector::Prebuilt(Some(DynConnector::new(Adapter::builder().build(connector))))
+}
+
+/// Returns the FIPS endpoint of the service (such as `ec2`) in the region, or
+/// an error naming the region if it doesn't offer one.
+pub fn fips_endpoint(service: &str, region: &str) -> Result<Endpoint, String> {
+    let host = if FIPS_REGIONS.contains(&region) {
+        format!("{}-fips.{}.amazonaws.com", service, region)
+    } else if FIPS_GOVCLOUD_REGIONS.contains(&region) {
+        format!("{}.{}.amazonaws.com", service, region)
+    } else {
+        return Err(region.to_owned());
+    };
+    Ok(Endpoint::immutable(
+        format!("https://{}", host)
+            .parse()
+            .expect("FIPS endpoint is a valid URI"),
+    ))
+}
+
+/// Checks a tag against the EC2 tag restrictions.
+pub fn validate_tag(key: &str, value: &str) -> Result<(), crate::Error> {
+    let invalid = |reason: String| crate::Error::InvalidTag {
+        key: key.to_owned(),
+        reason,
+    };
+    if key.is_empty() {
+        return Err(invalid("keys must not be empty".to_owned()));
+    }
+    if key.chars().count() > MAX_TAG_KEY_LENGTH {
+        return Err(invalid(format!(
+            "keys must be at most {} characters",
+            MAX_TAG_KEY_LENGTH
+        )));
+    }
+    if value.chars().count() > MAX_TAG_VALUE_LENGTH {
+        return Err(invalid(format!(
+            "values must be at most {} characters",
+            MAX_TAG_VALUE_LENGTH
+        )));
+    }
+    if key.to_lowercase().starts_with("aws:") {
+        return Err(invalid("the aws: prefix is reserved".to_owned()));
+    }
+    if key.chars().chain(value.chars()).any(char::is_control) {
+        return Err(invalid("control characters are not allowed".to_owned()));
+    }
+    Ok(())
+}
+
+/// Whether the tag is one the operator sets itself, which other tags mustn't
+/// replace.
+pub fn is_operator_tag(key: &str) -> bool {
+    GENERATED_TAGS.contains(&key)
+        || key == POD_NAME_TAG
+        || key == NODE_SELECTOR_TAG
+        || key == TRANSFERRED_FROM_TAG
+        || key == PROTECTED_TAG
+}
+
+/// Checks the default tags against the EC2 tag restrictions, and that they
+/// neither replace the operator's own tags nor leave too little room for them.
+fn validate_default_tags(default_tags: &HashMap<String, String>) -> Result<(), crate::Error> {
+    for (key, value) in default_tags {
+        validate_tag(key, value)?;
+        if is_operator_tag(key) {
+            return Err(crate::Error::InvalidTag {
+                key: key.to_owned(),
+                reason: "the key is set by the operator".to_owned(),
+            });
+        }
+    }
+    // Room is left for one of the pod name or node selector tags, and for the
+    // tags marking an address handed over from another cluster or protected.
+    let max_default_tags = MAX_TAGS - GENERATED_TAGS.len() - 3;
+    if default_tags.len() > max_default_tags {
+        return Err(crate::Error::InvalidTag {
+            key: "DEFAULT_TAGS".to_owned(),
+            reason: format!(
+                "at most {} default tags fit alongside the operator's own",
+                max_default_tags
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// The tags of a new address, identifying the Eip it is allocated for.
+fn allocation_tags(request: &AllocationRequest<'_>) -> Result<Vec<Tag>, crate::Error> {
+    request
+        .tags()
+        .into_iter()
+        .map(|(key, value)| {
+            validate_tag(&key, &value)?;
+            Ok(Tag::builder().key(key).value(value).build())
+        })
+        .collect()
+}
+
+/// Allocates an AWS Elastic IP with the tags.
+#[instrument(skip(ec2_client), err)]
+async fn allocate_address(
+    ec2_client: &Ec2Client,
+    tags: Vec<Tag>,
+) -> Result<AllocateAddressOutput, crate::Error> {
+    let request = ec2_client
         .allocate_address()
+        .dry_run(dry_run())
         .domain(DomainType::Vpc)
         .tag_specifications(
             TagSpecification::builder()
                 .resource_type(ResourceType::ElasticIp)
                 .set_tags(Some(tags))
                 .build(),
-        )
-        .send()
-        .await
+        );
+    Ok(observe_call("AllocateAddress", request.send()).await?)
 }
 
 /// Releases (deletes) an AWS Elastic IP.
 #[instrument(skip(ec2_client), err)]
-pub(crate) async fn release_address(
+async fn release_address(
     ec2_client: &Ec2Client,
     allocation_id: &str,
 ) -> Result<ReleaseAddressOutput, SdkError<ReleaseAddressError>> {
-    ec2_client
+    let request = ec2_client
         .release_address()
-        .allocation_id(allocation_id)
-        .send()
-        .await
+        .dry_run(dry_run())
+        .allocation_id(allocation_id);
+    observe_call("ReleaseAddress", request.send()).await
 }
 
 /// Associates an AWS Elastic IP with the Elastic Network Interface.
 /// The private IP of the association will be the pod IP supplied.
 #[instrument(skip(ec2_client), err)]
-pub(crate) async fn associate_eip(
+async fn associate_eip(
     ec2_client: &Ec2Client,
     eip_id: &str,
     eni_id: &str,
     private_ip: &str,
 ) -> Result<AssociateAddressOutput, SdkError<AssociateAddressError>> {
-    ec2_client
+    let request = ec2_client
         .associate_address()
+        .dry_run(dry_run())
         .allocation_id(eip_id)
         .allow_reassociation(true)
         .network_interface_id(eni_id)
-        .private_ip_address(private_ip)
-        .send()
-        .await
+        .private_ip_address(private_ip);
+    observe_call("AssociateAddress", request.send()).await
+}
+
+/// Waits until describing the EIP shows it associated with the ENI and
+/// private IP, retrying with exponential backoff a few times before giving up.
+#[instrument(skip(ec2_client), err)]
+async fn wait_for_association(
+    ec2_client: &Ec2Client,
+    allocation_id: &str,
+    eni_id: &str,
+    private_ip: &str,
+) -> Result<(), crate::Error> {
+    let mut delay = ASSOCIATION_CHECK_INITIAL_DELAY;
+    for attempt in 1..=ASSOCIATION_CHECK_ATTEMPTS {
+        let associated = describe_address(ec2_client, allocation_id)
+            .await?
+            .addresses
+            .unwrap_or_default()
+            .iter()
+            .any(|address| {
+                address.network_interface_id.as_deref() == Some(eni_id)
+                    && address.private_ip_address.as_deref() == Some(private_ip)
+            });
+        if associated {
+            return Ok(());
+        }
+        debug!(attempt, "Association not yet visible.");
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+    Err(crate::Error::AssociationNotVisible)
 }
 
 /// Describes a single EIP with the specified allocation ID.
 #[instrument(skip(ec2_client), err)]
-pub(crate) async fn describe_address(
+async fn describe_address(
     ec2_client: &Ec2Client,
     allocation_id: &str,
 ) -> Result<DescribeAddressesOutput, SdkError<DescribeAddressesError>> {
-    ec2_client
+    let request = ec2_client
         .describe_addresses()
-        .allocation_ids(allocation_id)
-        .send()
-        .await
+        .allocation_ids(allocation_id);
+    observe_call("DescribeAddresses", request.send()).await
 }
 
-/// Describes any EIPs tagged with the specified pod uid.
+/// Describes any EIPs tagged with the specified value.
 #[instrument(skip(ec2_client), err)]
-pub(crate) async fn describe_addresses_with_tag_value(
+async fn describe_addresses_with_tag_value(
     ec2_client: &Ec2Client,
     key: &str,
     value: &str,
 ) -> Result<DescribeAddressesOutput, SdkError<DescribeAddressesError>> {
-    ec2_client
-        .describe_addresses()
-        .filters(
-            Filter::builder()
-                .name(format!("tag:{}", key))
-                .values(value)
-                .build(),
-        )
-        .send()
-        .await
+    let request = ec2_client.describe_addresses().filters(
+        Filter::builder()
+            .name(format!("tag:{}", key))
+            .values(value)
+            .build(),
+    );
+    observe_call("DescribeAddresses", request.send()).await
 }
 
 /// Disassociates an Elastic IP from an Elastic Network Interface.
 #[instrument(skip(ec2_client), err)]
-pub(crate) async fn disassociate_eip(
+async fn disassociate_eip(
     ec2_client: &Ec2Client,
     association_id: &str,
 ) -> Result<(), SdkError<DisassociateAddressError>> {
-    match ec2_client
+    let request = ec2_client
         .disassociate_address()
-        .association_id(association_id)
-        .send()
-        .await
-    {
+        .dry_run(dry_run())
+        .association_id(association_id);
+    match observe_call("DisassociateAddress", request.send()).await {
         Ok(_) => Ok(()),
         Err(e) if e.to_string().contains("InvalidAssociationID.NotFound") => {
             info!(already_disassociated = true);
@@ -168,71 +695,289 @@ pub(crate) async fn disassociate_eip(
     }
 }
 
+/// Disassociates the EIP with the allocation ID from any NIC it is attached to.
+/// An address which no longer exists has nothing to disassociate.
+#[instrument(skip(ec2_client), err)]
+async fn disassociate_allocation(
+    ec2_client: &Ec2Client,
+    allocation_id: &str,
+) -> Result<(), crate::Error> {
+    let addresses = match describe_address(ec2_client, allocation_id).await {
+        Ok(output) => output.addresses.ok_or(crate::Error::MissingAddresses)?,
+        Err(err) if is_allocation_not_found(&err) => vec![],
+        Err(err) => return Err(err.into()),
+    };
+    for address in addresses {
+        if let Some(association_id) = address.association_id {
+            disassociate_eip(ec2_client, &association_id).await?;
+        }
+    }
+    Ok(())
+}
+
 /// Disassociates EIP if it is attached to a NIC, then deletes the EIP.
 #[instrument(skip(ec2_client), err)]
-pub(crate) async fn disassociate_and_release_address(
+async fn disassociate_and_release_address(
     ec2_client: &Ec2Client,
     address: &Address,
 ) -> Result<(), crate::Error> {
     if let Some(association_id) = &address.association_id {
         disassociate_eip(ec2_client, association_id).await?;
     }
-    if let Some(allocation_id) = &address.allocation_id {
-        // Is it actually possible the allocation_id won't exist?
-        release_address(ec2_client, allocation_id).await?;
-    }
+    release_address(ec2_client, &address.allocation_id).await?;
     Ok(())
 }
 
-/// Searches tags on the supplied address and returns the value if it exists.
-pub(crate) fn get_tag_from_address<'a>(address: &'a Address, key: &str) -> Option<&'a str> {
-    address
-        .tags
-        .as_ref()?
-        .iter()
-        .find(|&tag| tag.key.as_deref() == Some(key))?
-        .value
-        .as_deref()
+/// Converts an EC2 address, which must have an allocation ID and public IP.
+fn to_address(address: aws_sdk_ec2::model::Address) -> Result<Address, crate::Error> {
+    Ok(Address {
+        allocation_id: address
+            .allocation_id
+            .ok_or(crate::Error::MissingAllocationId)?,
+        public_ip: address.public_ip.ok_or(crate::Error::MissingPublicIp)?,
+        association_id: address.association_id,
+        eni: address.network_interface_id,
+        private_ip_address: address.private_ip_address,
+        instance_id: address.instance_id,
+        tags: address
+            .tags
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|tag| Some((tag.key?, tag.value?)))
+            .collect(),
+    })
+}
+
+/// Converts the addresses of a DescribeAddresses response.
+fn to_addresses(output: DescribeAddressesOutput) -> Result<Vec<Address>, crate::Error> {
+    output
+        .addresses
+        .unwrap_or_default()
+        .into_iter()
+        .map(to_address)
+        .collect()
 }
 
 /// Describes an AWS EC2 instance with the supplied instance_id.
 #[instrument(skip(ec2_client), err)]
-pub(crate) async fn describe_instance(
+async fn describe_instance(
     ec2_client: &Ec2Client,
     instance_id: &str,
 ) -> Result<DescribeInstancesOutput, SdkError<DescribeInstancesError>> {
-    ec2_client
-        .describe_instances()
-        .instance_ids(instance_id)
-        .send()
-        .await
+    let request = ec2_client.describe_instances().instance_ids(instance_id);
+    observe_call("DescribeInstances", request.send()).await
 }
 
-pub(crate) fn get_eni_from_private_ip(
-    instance: &DescribeInstancesOutput,
-    private_ip_address: &str,
-) -> Option<String> {
-    instance
+/// Finds the network interface dedicated to node EIPs on the instance, if
+/// the operator created one.
+#[instrument(skip(ec2_client), err)]
+async fn describe_dedicated_network_interface(
+    ec2_client: &Ec2Client,
+    instance_id: &str,
+) -> Result<Option<NetworkInterface>, SdkError<DescribeNetworkInterfacesError>> {
+    let request = ec2_client.describe_network_interfaces().filters(
+        Filter::builder()
+            .name(format!("tag:{}", DEDICATED_ENI_TAG))
+            .values(instance_id)
+            .build(),
+    );
+    let output = observe_call("DescribeNetworkInterfaces", request.send()).await?;
+    Ok(output
+        .network_interfaces
+        .unwrap_or_default()
+        .into_iter()
+        .next())
+}
+
+/// Creates a network interface dedicated to node EIPs for the instance, in the
+/// subnet and with the security groups of its primary network interface.
+#[instrument(skip(ec2_client, instance), err)]
+async fn create_dedicated_network_interface(
+    ec2_client: &Ec2Client,
+    instance: &Instance,
+) -> Result<NetworkInterface, crate::Error> {
+    let instance_id = instance.instance_id.as_deref().unwrap_or_default();
+    let primary = instance
+        .network_interfaces
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .find(|nic| device_index(nic) == Some(0))
+        .ok_or_else(|| crate::Error::MissingPrimaryNetworkInterface(instance_id.to_owned()))?;
+    let groups = primary
+        .groups
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|group| group.group_id.clone())
+        .collect();
+    let request = ec2_client
+        .create_network_interface()
+        .dry_run(dry_run())
+        .set_subnet_id(primary.subnet_id.clone())
+        .set_groups(Some(groups))
+        .description(format!("EIPs of node Eips on {}", instance_id))
+        .tag_specifications(
+            TagSpecification::builder()
+                .resource_type(ResourceType::NetworkInterface)
+                .tags(
+                    Tag::builder()
+                        .key(DEDICATED_ENI_TAG)
+                        .value(instance_id)
+                        .build(),
+                )
+                .tags(Tag::builder().key(MANAGED_BY_TAG).value(MANAGED_BY).build())
+                .tags(
+                    Tag::builder()
+                        .key(VPC_CNI_NO_MANAGE_TAG)
+                        .value("true")
+                        .build(),
+                )
+                .build(),
+        );
+    let output = observe_call("CreateNetworkInterface", request.send()).await?;
+    output
+        .network_interface
+        .ok_or(crate::Error::MissingNetworkInterfaces)
+}
+
+/// Attaches the dedicated network interface to the instance at its lowest
+/// free device index, to be deleted along with the instance.
+#[instrument(skip(ec2_client, instance), err)]
+async fn attach_dedicated_network_interface(
+    ec2_client: &Ec2Client,
+    instance: &Instance,
+    eni_id: &str,
+) -> Result<(), crate::Error> {
+    let used: HashSet<i32> = instance
+        .network_interfaces
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(device_index)
+        .collect();
+    let free = (1..).find(|index| !used.contains(index)).unwrap();
+    let request = ec2_client
+        .attach_network_interface()
+        .dry_run(dry_run())
+        .set_instance_id(instance.instance_id.clone())
+        .network_interface_id(eni_id)
+        .device_index(free);
+    let output = observe_call("AttachNetworkInterface", request.send()).await?;
+    let request = ec2_client
+        .modify_network_interface_attribute()
+        .dry_run(dry_run())
+        .network_interface_id(eni_id)
+        .attachment(
+            NetworkInterfaceAttachmentChanges::builder()
+                .set_attachment_id(output.attachment_id)
+                .delete_on_termination(true)
+                .build(),
+        );
+    observe_call("ModifyNetworkInterfaceAttribute", request.send()).await?;
+    Ok(())
+}
+
+/// Assigns more secondary private IPs to the network interface, returning
+/// them. Assigning private IPs can't be dry run, so is skipped instead.
+#[instrument(skip(ec2_client), err)]
+async fn assign_private_ips(
+    ec2_client: &Ec2Client,
+    eni_id: &str,
+    count: usize,
+) -> Result<Vec<String>, SdkError<AssignPrivateIpAddressesError>> {
+    if dry_run() {
+        info!("Not assigning private IPs in dry-run mode.");
+        return Ok(vec![]);
+    }
+    let request = ec2_client
+        .assign_private_ip_addresses()
+        .network_interface_id(eni_id)
+        .secondary_private_ip_address_count(count as i32);
+    let output = observe_call("AssignPrivateIpAddresses", request.send()).await?;
+    Ok(output
+        .assigned_private_ip_addresses
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|assigned| assigned.private_ip_address)
+        .collect())
+}
+
+/// The private IPs of the network interface: its primary IP, then its
+/// secondary IPs in order, so that they are handed out deterministically.
+fn private_ips(eni: &NetworkInterface) -> Vec<String> {
+    let mut secondary: Vec<String> = eni
+        .private_ip_addresses
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .filter(|ip| ip.primary != Some(true))
+        .filter_map(|ip| ip.private_ip_address.clone())
+        .collect();
+    secondary.sort_by_key(|ip| ip.parse::<std::net::IpAddr>().ok());
+    eni.private_ip_address
+        .clone()
+        .into_iter()
+        .chain(secondary)
+        .collect()
+}
+
+fn device_index(nic: &aws_sdk_ec2::model::InstanceNetworkInterface) -> Option<i32> {
+    nic.attachment.as_ref()?.device_index
+}
+
+/// Finds the described instance, unless it is terminating or terminated.
+fn find_instance<'a>(
+    instances: &'a DescribeInstancesOutput,
+    instance_id: &str,
+) -> Result<&'a Instance, crate::Error> {
+    let instance = instances
         .reservations
         .as_ref()
-        .and_then(|reservations| reservations[0].instances.as_ref())
-        .and_then(|instances| instances[0].network_interfaces.as_ref())
-        .and_then(|network_interfaces| {
-            network_interfaces.iter().find_map(|nic| {
-                nic.private_ip_addresses.as_ref()?.iter().find_map(|ip| {
-                    match ip.private_ip_address.as_ref()? {
-                        x if x == private_ip_address => {
-                            debug!(
-                                "Found matching NIC: {} {} {}",
-                                nic.network_interface_id.as_ref()?,
-                                private_ip_address,
-                                ip.private_ip_address.as_ref()?,
-                            );
-                            Some(nic.network_interface_id.as_ref()?.to_owned())
-                        }
-                        _ => None,
+        .ok_or(crate::Error::MissingReservations)?
+        .iter()
+        .filter_map(|reservation| reservation.instances.as_ref())
+        .flatten()
+        .find(|instance| instance.instance_id.as_deref() == Some(instance_id))
+        .ok_or_else(|| crate::Error::InstanceNotFound(instance_id.to_owned()))?;
+    if matches!(
+        instance
+            .state
+            .as_ref()
+            .and_then(|state| state.name.as_ref()),
+        Some(InstanceStateName::ShuttingDown | InstanceStateName::Terminated)
+    ) {
+        return Err(crate::Error::InstanceTerminated(instance_id.to_owned()));
+    }
+    Ok(instance)
+}
+
+/// Finds the ENI of the described instance which has the supplied private IP.
+fn get_eni_from_private_ip(
+    instances: &DescribeInstancesOutput,
+    instance_id: &str,
+    private_ip_address: &str,
+) -> Result<String, crate::Error> {
+    find_instance(instances, instance_id)?
+        .network_interfaces
+        .as_ref()
+        .ok_or(crate::Error::MissingNetworkInterfaces)?
+        .iter()
+        .find_map(|nic| {
+            nic.private_ip_addresses.as_ref()?.iter().find_map(|ip| {
+                match ip.private_ip_address.as_ref()? {
+                    x if x == private_ip_address => {
+                        debug!(
+                            "Found matching NIC: {} {} {}",
+                            nic.network_interface_id.as_ref()?,
+                            private_ip_address,
+                            ip.private_ip_address.as_ref()?,
+                        );
+                        Some(nic.network_interface_id.as_ref()?.to_owned())
                     }
-                })
+                    _ => None,
+                }
             })
         })
+        .ok_or(crate::Error::NoInterfaceWithThatIp)
 }
@@ -0,0 +1,594 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::client::HttpConnector;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request};
+use hyper_tls::HttpsConnector;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, instrument};
+
+use eip_operator_shared::Error;
+
+use crate::aws::{
+    CLUSTER_NAME_TAG, GENERATED_TAGS, NAMESPACE_TAG, NODE_SELECTOR_TAG, POD_NAME_TAG,
+    PROTECTED_TAG, TRANSFERRED_FROM_TAG,
+};
+use crate::provider::{dry_run, Address, AllocationRequest, CloudProvider};
+
+const ARM_API: &str = "https://management.azure.com";
+const NETWORK_API_VERSION: &str = "2022-07-01";
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token?api-version=2018-02-01&resource=https%3A%2F%2Fmanagement.azure.com%2F";
+
+/// Tokens are refreshed this long before they expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+// Changes to ARM resources finish asynchronously, so their provisioning state is polled.
+const PROVISIONING_CHECK_ATTEMPTS: u32 = 90;
+const PROVISIONING_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Creates Standard SKU static public IPs with the Azure Resource Manager API,
+/// and associates them with the IP configurations of network interfaces.
+///
+/// Public IPs are identified by their name. Azure tag keys can't contain `/`,
+/// so tags are stored with `:` in place of it.
+pub struct AzureProvider {
+    subscription_id: String,
+    resource_group: String,
+    node_resource_group: String,
+    location: String,
+    client_id: Option<String>,
+    client: hyper::Client<HttpsConnector<HttpConnector>>,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl AzureProvider {
+    pub fn new(
+        subscription_id: String,
+        resource_group: String,
+        node_resource_group: String,
+        location: String,
+        client_id: Option<String>,
+    ) -> Self {
+        Self {
+            subscription_id,
+            resource_group,
+            node_resource_group,
+            location,
+            client_id,
+            client: hyper::Client::builder().build(HttpsConnector::new()),
+            token: Mutex::new(None),
+        }
+    }
+
+    fn public_ips_path(&self) -> String {
+        format!(
+            "/subscriptions/{}/resourceGroups/{}/providers/Microsoft.Network/publicIPAddresses",
+            self.subscription_id, self.resource_group
+        )
+    }
+
+    fn public_ip_path(&self, name: &str) -> String {
+        format!("{}/{}", self.public_ips_path(), name)
+    }
+
+    /// An access token of the managed identity, from the instance metadata service.
+    async fn token(&self) -> Result<String, Error> {
+        if let Some((token, expires)) = &*self.token.lock().unwrap() {
+            if *expires > Instant::now() + TOKEN_EXPIRY_MARGIN {
+                return Ok(token.clone());
+            }
+        }
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+            expires_in: String,
+        }
+        let mut url = IMDS_TOKEN_URL.to_owned();
+        if let Some(client_id) = &self.client_id {
+            url.push_str(&format!("&client_id={}", client_id));
+        }
+        let request = Request::get(url)
+            .header("Metadata", "true")
+            .body(Body::empty())?;
+        let response: TokenResponse =
+            serde_json::from_value(self.send("imds.token", request).await?)?;
+        let expires_in = response.expires_in.parse().unwrap_or_default();
+        let expires = Instant::now() + Duration::from_secs(expires_in);
+        *self.token.lock().unwrap() = Some((response.access_token.clone(), expires));
+        Ok(response.access_token)
+    }
+
+    async fn send(
+        &self,
+        operation: &'static str,
+        request: Request<Body>,
+    ) -> Result<serde_json::Value, Error> {
+        let response = self.client.request(request).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            return Err(Error::AzureApi {
+                operation,
+                status: status.as_u16(),
+                message: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+        if body.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Calls the ARM API with the path of a resource, or a full `nextLink` URL.
+    /// In dry-run mode, fails without making any call which isn't a `GET`.
+    async fn call(
+        &self,
+        operation: &'static str,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value, Error> {
+        if method != Method::GET && dry_run() {
+            return Err(Error::AzureDryRun(operation));
+        }
+        let url = if path.starts_with("https://") {
+            path.to_owned()
+        } else {
+            format!("{}{}?api-version={}", ARM_API, path, NETWORK_API_VERSION)
+        };
+        let request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token().await?))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))?;
+        self.send(operation, request).await
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        operation: &'static str,
+        path: &str,
+    ) -> Result<T, Error> {
+        Ok(serde_json::from_value(
+            self.call(operation, Method::GET, path, None).await?,
+        )?)
+    }
+
+    /// Waits until the resource has finished provisioning.
+    async fn wait_for_provisioning(
+        &self,
+        operation: &'static str,
+        path: &str,
+    ) -> Result<(), Error> {
+        for attempt in 1..=PROVISIONING_CHECK_ATTEMPTS {
+            let resource: Provisioned = self.get(operation, path).await?;
+            match resource.properties.provisioning_state.as_str() {
+                "Succeeded" => return Ok(()),
+                "Failed" => {
+                    return Err(Error::AzureOperation {
+                        operation,
+                        message: format!("provisioning {} failed", path),
+                    })
+                }
+                state => debug!(attempt, %state, "Still provisioning."),
+            }
+            tokio::time::sleep(PROVISIONING_CHECK_INTERVAL).await;
+        }
+        Err(Error::AzureOperation {
+            operation,
+            message: format!("timed out waiting for {} to be provisioned", path),
+        })
+    }
+
+    async fn get_public_ip(&self, name: &str) -> Result<PublicIp, Error> {
+        match self
+            .get("publicIPAddresses.get", &self.public_ip_path(name))
+            .await
+        {
+            Err(Error::AzureApi { status: 404, .. }) => {
+                Err(Error::AzurePublicIpNotFound(name.to_owned()))
+            }
+            result => result,
+        }
+    }
+
+    /// Lists the resource group's public IPs with all of the tags.
+    async fn list_public_ips(&self, tags: &[(&str, &str)]) -> Result<Vec<Address>, Error> {
+        let mut public_ips = vec![];
+        let mut next = Some(self.public_ips_path());
+        while let Some(path) = next {
+            let page: PublicIpList = self.get("publicIPAddresses.list", &path).await?;
+            public_ips.extend(page.value);
+            next = page.next_link;
+        }
+        let mut addresses = vec![];
+        for public_ip in public_ips {
+            let matches = tags.iter().all(|(key, value)| {
+                public_ip.tags.get(&tag_key(key)).map(String::as_str) == Some(*value)
+            });
+            if matches {
+                addresses.push(self.to_address(public_ip).await?);
+            }
+        }
+        Ok(addresses)
+    }
+
+    /// The address of a public IP, with the private IP and VM of the IP
+    /// configuration it is associated with, if any.
+    async fn to_address(&self, public_ip: PublicIp) -> Result<Address, Error> {
+        let mut address = Address {
+            allocation_id: public_ip.name,
+            public_ip: public_ip
+                .properties
+                .ip_address
+                .ok_or(Error::MissingPublicIp)?,
+            association_id: None,
+            eni: None,
+            private_ip_address: None,
+            instance_id: None,
+            tags: from_tags(public_ip.tags),
+        };
+        let ip_configuration_id = match public_ip.properties.ip_configuration {
+            Some(ip_configuration) => ip_configuration.id,
+            None => return Ok(address),
+        };
+        let nic_id = nic_id(&ip_configuration_id);
+        let nic: Nic = match self.get("networkInterfaces.get", nic_id).await {
+            Err(Error::AzureApi { status: 404, .. }) => return Ok(address),
+            result => result?,
+        };
+        address.private_ip_address = nic
+            .properties
+            .ip_configurations
+            .iter()
+            .find(|config| config.id.eq_ignore_ascii_case(&ip_configuration_id))
+            .and_then(|config| config.properties.private_ip_address.clone());
+        address.instance_id = nic
+            .properties
+            .virtual_machine
+            .and_then(|vm| vm.id.rsplit('/').next().map(str::to_owned));
+        address.eni = Some(nic.id);
+        address.association_id = Some(ip_configuration_id);
+        Ok(address)
+    }
+
+    /// Sets or removes the public IP of the NIC's IP configuration which
+    /// matches, then waits for the NIC to be updated.
+    async fn update_ip_configuration(
+        &self,
+        operation: &'static str,
+        nic_id: &str,
+        matches: impl Fn(&serde_json::Value) -> bool,
+        public_ip_id: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut nic = self
+            .call("networkInterfaces.get", Method::GET, nic_id, None)
+            .await?;
+        let config = nic["properties"]["ipConfigurations"]
+            .as_array_mut()
+            .and_then(|configs| configs.iter_mut().find(|config| matches(config)))
+            .ok_or(Error::NoInterfaceWithThatIp)?;
+        let properties = config["properties"]
+            .as_object_mut()
+            .ok_or(Error::NoInterfaceWithThatIp)?;
+        match public_ip_id {
+            Some(public_ip_id) => {
+                properties.insert("publicIPAddress".to_owned(), json!({ "id": public_ip_id }));
+            }
+            None => {
+                properties.remove("publicIPAddress");
+            }
+        }
+        self.call(operation, Method::PUT, nic_id, Some(nic)).await?;
+        self.wait_for_provisioning(operation, nic_id).await
+    }
+}
+
+#[async_trait::async_trait]
+impl CloudProvider for AzureProvider {
+    #[instrument(skip(self, request), err)]
+    async fn allocate(&self, request: AllocationRequest<'_>) -> Result<Address, Error> {
+        let name = format!("eip-{}", request.eip_uid);
+        let tags: HashMap<String, String> = request
+            .tags()
+            .into_iter()
+            .map(|(key, value)| (tag_key(&key), value))
+            .collect();
+        let body = json!({
+            "location": self.location,
+            "sku": { "name": "Standard" },
+            "tags": tags,
+            "properties": {
+                "publicIPAllocationMethod": "Static",
+                "publicIPAddressVersion": "IPv4",
+            },
+        });
+        let path = self.public_ip_path(&name);
+        self.call(
+            "publicIPAddresses.createOrUpdate",
+            Method::PUT,
+            &path,
+            Some(body),
+        )
+        .await?;
+        self.wait_for_provisioning("publicIPAddresses.createOrUpdate", &path)
+            .await?;
+        let public_ip = self.get_public_ip(&name).await?;
+        self.to_address(public_ip).await
+    }
+
+    async fn describe(&self, _namespace: &str, allocation_id: &str) -> Result<Address, Error> {
+        let public_ip = self.get_public_ip(allocation_id).await?;
+        self.to_address(public_ip).await
+    }
+
+    async fn describe_by_tag(
+        &self,
+        _namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<Address>, Error> {
+        self.list_public_ips(&[(key, value)]).await
+    }
+
+    async fn describe_cluster_addresses(
+        &self,
+        cluster_name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Vec<Address>, Error> {
+        let mut tags = vec![(CLUSTER_NAME_TAG, cluster_name)];
+        if let Some(namespace) = namespace {
+            tags.push((NAMESPACE_TAG, namespace));
+        }
+        self.list_public_ips(&tags).await
+    }
+
+    #[instrument(skip(self), err)]
+    async fn network_interface(
+        &self,
+        instance_id: &str,
+        private_ip: &str,
+    ) -> Result<String, Error> {
+        let mut nics = vec![];
+        let mut next = Some(format!(
+            "/subscriptions/{}/resourceGroups/{}/providers/Microsoft.Network/networkInterfaces",
+            self.subscription_id, self.node_resource_group
+        ));
+        while let Some(path) = next {
+            let page: NicList = self.get("networkInterfaces.list", &path).await?;
+            nics.extend(page.value);
+            next = page.next_link;
+        }
+        nics.into_iter()
+            .find(|nic| {
+                let on_instance = nic
+                    .properties
+                    .virtual_machine
+                    .as_ref()
+                    .map_or(true, |vm| vm.id.rsplit('/').next() == Some(instance_id));
+                on_instance
+                    && nic.properties.ip_configurations.iter().any(|config| {
+                        config.properties.private_ip_address.as_deref() == Some(private_ip)
+                    })
+            })
+            .map(|nic| nic.id)
+            .ok_or(Error::NoInterfaceWithThatIp)
+    }
+
+    #[instrument(skip(self), err)]
+    async fn associate(
+        &self,
+        namespace: &str,
+        allocation_id: &str,
+        eni: &str,
+        private_ip: &str,
+    ) -> Result<(), Error> {
+        let public_ip = self.get_public_ip(allocation_id).await?;
+        // A public IP is associated with at most one IP configuration.
+        if let Some(ip_configuration) = &public_ip.properties.ip_configuration {
+            if !nic_id(&ip_configuration.id).eq_ignore_ascii_case(eni) {
+                self.disassociate(namespace, allocation_id).await?;
+            }
+        }
+        self.update_ip_configuration(
+            "networkInterfaces.createOrUpdate",
+            eni,
+            |config| config["properties"]["privateIPAddress"].as_str() == Some(private_ip),
+            Some(&public_ip.id),
+        )
+        .await?;
+        info!(%allocation_id, %eni, %private_ip, "Associated public IP with IP configuration.");
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn disassociate(&self, _namespace: &str, allocation_id: &str) -> Result<(), Error> {
+        let public_ip = match self.get_public_ip(allocation_id).await {
+            Ok(public_ip) => public_ip,
+            Err(err) if err.is_allocation_not_found() => {
+                debug!(already_released = true);
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        let ip_configuration_id = match public_ip.properties.ip_configuration {
+            Some(ip_configuration) => ip_configuration.id,
+            None => return Ok(()),
+        };
+        let result = self
+            .update_ip_configuration(
+                "networkInterfaces.createOrUpdate",
+                nic_id(&ip_configuration_id),
+                |config| {
+                    config["id"]
+                        .as_str()
+                        .map_or(false, |id| id.eq_ignore_ascii_case(&ip_configuration_id))
+                },
+                None,
+            )
+            .await;
+        match result {
+            Err(Error::AzureApi { status: 404, .. }) => Ok(()),
+            result => result,
+        }
+    }
+
+    #[instrument(skip(self, address), fields(allocation_id = %address.allocation_id), err)]
+    async fn release(&self, namespace: &str, address: &Address) -> Result<(), Error> {
+        if address.association_id.is_some() {
+            self.disassociate(namespace, &address.allocation_id).await?;
+        }
+        let path = self.public_ip_path(&address.allocation_id);
+        self.call("publicIPAddresses.delete", Method::DELETE, &path, None)
+            .await?;
+        for _ in 0..PROVISIONING_CHECK_ATTEMPTS {
+            match self.get_public_ip(&address.allocation_id).await {
+                Ok(_) => tokio::time::sleep(PROVISIONING_CHECK_INTERVAL).await,
+                Err(err) if err.is_allocation_not_found() => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+        Err(Error::AzureOperation {
+            operation: "publicIPAddresses.delete",
+            message: format!("timed out waiting for {} to be deleted", path),
+        })
+    }
+
+    async fn converge_tags(
+        &self,
+        _namespace: &str,
+        address: &Address,
+        tags: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        if tags
+            .iter()
+            .all(|(key, value)| address.tag(key) == Some(*value))
+        {
+            return Ok(());
+        }
+        let mut current = self.get_public_ip(&address.allocation_id).await?.tags;
+        current.extend(
+            tags.iter()
+                .map(|(key, value)| (tag_key(key), (*value).to_owned())),
+        );
+        // Updating tags replaces all of them.
+        self.call(
+            "publicIPAddresses.updateTags",
+            Method::PATCH,
+            &self.public_ip_path(&address.allocation_id),
+            Some(json!({ "tags": current })),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct Provisioned {
+    properties: ProvisioningState,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProvisioningState {
+    provisioning_state: String,
+}
+
+#[derive(Deserialize)]
+struct SubResource {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct PublicIp {
+    id: String,
+    name: String,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    properties: PublicIpProperties,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicIpProperties {
+    ip_address: Option<String>,
+    ip_configuration: Option<SubResource>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicIpList {
+    #[serde(default)]
+    value: Vec<PublicIp>,
+    next_link: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Nic {
+    id: String,
+    properties: NicProperties,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NicProperties {
+    #[serde(default)]
+    ip_configurations: Vec<IpConfiguration>,
+    virtual_machine: Option<SubResource>,
+}
+
+#[derive(Deserialize)]
+struct IpConfiguration {
+    id: String,
+    properties: IpConfigurationProperties,
+}
+
+#[derive(Deserialize)]
+struct IpConfigurationProperties {
+    #[serde(rename = "privateIPAddress")]
+    private_ip_address: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NicList {
+    #[serde(default)]
+    value: Vec<Nic>,
+    next_link: Option<String>,
+}
+
+/// The ID of the NIC an IP configuration belongs to.
+fn nic_id(ip_configuration_id: &str) -> &str {
+    ip_configuration_id
+        .split_once("/ipConfigurations/")
+        .map_or(ip_configuration_id, |(nic_id, _)| nic_id)
+}
+
+/// The Azure tag key of a tag key.
+fn tag_key(key: &str) -> String {
+    key.replace('/', ":")
+}
+
+/// The tags of a public IP, with the keys of the operator's own tags restored.
+fn from_tags(tags: HashMap<String, String>) -> HashMap<String, String> {
+    tags.into_iter()
+        .map(|(key, value)| {
+            let key = GENERATED_TAGS
+                .iter()
+                .chain(&[
+                    POD_NAME_TAG,
+                    NODE_SELECTOR_TAG,
+                    TRANSFERRED_FROM_TAG,
+                    PROTECTED_TAG,
+                ])
+                .find(|operator_key| tag_key(operator_key) == key)
+                .map_or(key, |operator_key| (*operator_key).to_owned());
+            (key, value)
+        })
+        .collect()
+}
@@ -0,0 +1,254 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use k8s_openapi::api::authorization::v1::{
+    ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
+};
+use kube::api::{Api, PostParams};
+use kube::Client;
+
+use eip_operator::aws::Ec2Clients;
+use eip_operator_shared::Error;
+
+use crate::cli::Args;
+
+/// How long to wait for a TCP connection to the OpenTelemetry collector.
+const OTEL_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The outcomes of the checks, in the order they were run.
+#[derive(Default)]
+struct Report(Vec<(String, Result<(), String>)>);
+
+impl Report {
+    fn record(&mut self, name: impl Into<String>, result: Result<(), impl Display>) {
+        let name = name.into();
+        match &result {
+            Ok(()) => println!("PASS  {}", name),
+            Err(err) => println!("FAIL  {}: {}", name, err),
+        }
+        self.0.push((name, result.map_err(|err| err.to_string())));
+    }
+
+    fn failures(&self) -> usize {
+        self.0.iter().filter(|(_, result)| result.is_err()).count()
+    }
+}
+
+/// Verifies that the operator can reach, and has the permissions it needs in,
+/// Kubernetes, AWS, and the OpenTelemetry collector if one is configured,
+/// printing a line for each check. Fails if any check does.
+pub(crate) async fn run(args: Args) -> Result<(), Error> {
+    let mut report = Report::default();
+
+    match Client::try_default().await {
+        Ok(client) => check_kubernetes(&args, client, &mut report).await,
+        Err(err) => report.record("Kubernetes client", Err(err)),
+    }
+    check_aws(&args, &mut report).await;
+    if let Ok(endpoint) = std::env::var("OPENTELEMETRY_ENDPOINT") {
+        report.record(
+            format!("OpenTelemetry collector {}", endpoint),
+            check_otel(&endpoint).await,
+        );
+    }
+
+    let failures = report.failures();
+    println!(
+        "{} of {} checks passed.",
+        report.0.len() - failures,
+        report.0.len()
+    );
+    match failures {
+        0 => Ok(()),
+        failures => Err(Error::ChecksFailed(failures)),
+    }
+}
+
+async fn check_kubernetes(args: &Args, client: Client, report: &mut Report) {
+    report.record(
+        "Kubernetes API server",
+        client.apiserver_version().await.map(|_| ()),
+    );
+    report.record(
+        "Eip CustomResourceDefinition established",
+        eip_operator::eip::verify_custom_resource(client.clone()).await,
+    );
+
+    let mut permissions = vec![
+        ("", "pods", None, "get"),
+        ("", "pods", None, "list"),
+        ("", "pods", None, "watch"),
+        ("", "nodes", None, "get"),
+        ("", "nodes", None, "list"),
+        ("", "nodes", None, "watch"),
+        ("materialize.cloud", "eips", None, "get"),
+        ("materialize.cloud", "eips", None, "list"),
+        ("materialize.cloud", "eips", None, "watch"),
+        (
+            "apiextensions.k8s.io",
+            "customresourcedefinitions",
+            None,
+            "get",
+        ),
+    ];
+    if !args.observe_only {
+        permissions.extend([
+            ("", "pods", None, "patch"),
+            ("", "nodes", None, "patch"),
+            ("materialize.cloud", "eips", None, "create"),
+            ("materialize.cloud", "eips", None, "patch"),
+            ("materialize.cloud", "eips", Some("status"), "patch"),
+            ("events.k8s.io", "events", None, "create"),
+        ]);
+        if args.manage_crds {
+            permissions.push((
+                "apiextensions.k8s.io",
+                "customresourcedefinitions",
+                None,
+                "patch",
+            ));
+        }
+    }
+    if args.service_load_balancer_status {
+        permissions.extend([
+            ("", "services", None, "list"),
+            ("", "services", None, "watch"),
+        ]);
+        if !args.observe_only {
+            permissions.push(("", "services", Some("status"), "patch"));
+        }
+    }
+    let api = Api::<SelfSubjectAccessReview>::all(client);
+    for (group, resource, subresource, verb) in permissions {
+        let name = format!(
+            "RBAC {} {}{}{}",
+            verb,
+            resource,
+            subresource.map_or(String::new(), |subresource| format!("/{}", subresource)),
+            if group.is_empty() {
+                String::new()
+            } else {
+                format!(".{}", group)
+            },
+        );
+        let attributes = ResourceAttributes {
+            group: Some(group.to_owned()),
+            resource: Some(resource.to_owned()),
+            subresource: subresource.map(str::to_owned),
+            verb: Some(verb.to_owned()),
+            namespace: args.namespace.clone(),
+            ..Default::default()
+        };
+        report.record(name, access_allowed(&api, attributes).await);
+    }
+}
+
+async fn access_allowed(
+    api: &Api<SelfSubjectAccessReview>,
+    attributes: ResourceAttributes,
+) -> Result<(), String> {
+    let review = SelfSubjectAccessReview {
+        spec: SelfSubjectAccessReviewSpec {
+            resource_attributes: Some(attributes),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let status = api
+        .create(&PostParams::default(), &review)
+        .await
+        .map_err(|err| err.to_string())?
+        .status
+        .ok_or_else(|| "no review status".to_owned())?;
+    if status.allowed {
+        Ok(())
+    } else {
+        Err(status.reason.unwrap_or_else(|| "denied".to_owned()))
+    }
+}
+
+async fn check_aws(args: &Args, report: &mut Report) {
+    let aws_config = match crate::load_aws_config(args).await {
+        Ok(aws_config) => aws_config,
+        Err(err) => {
+            report.record("AWS config", Err(err));
+            return;
+        }
+    };
+    let use_fips = args.aws_use_fips_endpoint;
+    let ec2_endpoint = match use_fips
+        .then(|| crate::fips_endpoint(&aws_config, "ec2"))
+        .transpose()
+    {
+        Ok(ec2_endpoint) => ec2_endpoint,
+        Err(err) => {
+            report.record("AWS FIPS endpoints", Err(err));
+            return;
+        }
+    };
+    let ec2_clients = Ec2Clients::new(&aws_config, &args.namespace_role_arns, ec2_endpoint);
+    let ec2_client = ec2_clients.default_client();
+
+    // Dry runs check the permission without making any change.
+    report.record(
+        "IAM ec2:DescribeAddresses",
+        dry_run_allowed(ec2_client.describe_addresses().dry_run(true).send().await),
+    );
+    report.record(
+        "IAM ec2:DescribeInstances",
+        dry_run_allowed(ec2_client.describe_instances().dry_run(true).send().await),
+    );
+    if !args.observe_only {
+        report.record(
+            "IAM ec2:AllocateAddress",
+            dry_run_allowed(ec2_client.allocate_address().dry_run(true).send().await),
+        );
+    }
+
+    let quota_client = match crate::quota_client(&aws_config, use_fips) {
+        Ok(quota_client) => quota_client,
+        Err(err) => {
+            report.record("AWS FIPS endpoints", Err(err));
+            return;
+        }
+    };
+    report.record(
+        format!("IAM servicequotas:GetServiceQuota {}", args.eip_quota_code),
+        quota_client
+            .get_service_quota()
+            .service_code("ec2")
+            .quota_code(&args.eip_quota_code)
+            .send()
+            .await
+            .map(|_| ()),
+    );
+}
+
+/// Passes if a dry-run call was refused only because it was a dry run.
+fn dry_run_allowed<T, E: Display>(result: Result<T, E>) -> Result<(), String> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) if err.to_string().contains("DryRunOperation") => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Checks that a TCP connection can be made to the collector.
+async fn check_otel(endpoint: &str) -> Result<(), String> {
+    let uri: hyper::Uri = endpoint.parse().map_err(|err| format!("{}", err))?;
+    let host = uri.host().ok_or_else(|| "no host".to_owned())?;
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("http") => 80,
+        _ => 443,
+    });
+    match tokio::time::timeout(
+        OTEL_CONNECT_TIMEOUT,
+        tokio::net::TcpStream::connect((host, port)),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err("timed out".to_owned()),
+    }
+}
@@ -0,0 +1,65 @@
+use json_patch::{AddOperation, PatchOperation, RemoveOperation};
+use kube::api::{Api, ApiResource, DynamicObject, GroupVersionKind, Patch};
+use kube::Client;
+use tracing::{event, instrument, Level};
+
+use eip_operator_shared::Error;
+
+/// The label Cilium egress gateway policies select the gateway node by.
+pub(crate) const HOSTNAME_LABEL: &str = "kubernetes.io/hostname";
+
+fn egress_gateway_policy_api(client: Client) -> Api<DynamicObject> {
+    let resource = ApiResource::from_gvk_with_plural(
+        &GroupVersionKind::gvk("cilium.io", "v2", "CiliumEgressGatewayPolicy"),
+        "ciliumegressgatewaypolicies",
+    );
+    Api::all_with(client, &resource)
+}
+
+/// Points the CiliumEgressGatewayPolicy at the node holding an EIP, so that
+/// the traffic it selects leaves the cluster from the node's private IP, and
+/// so from the EIP associated with it.
+#[instrument(skip(client), err)]
+pub(crate) async fn sync_egress_gateway_policy(
+    client: Client,
+    policy_name: &str,
+    hostname: &str,
+    egress_ip: &str,
+) -> Result<(), Error> {
+    let api = egress_gateway_policy_api(client);
+    let policy = api.get(policy_name).await?;
+    let node_selector = serde_json::json!({ "matchLabels": { HOSTNAME_LABEL: hostname } });
+    let egress_gateway = &policy.data["spec"]["egressGateway"];
+    if egress_gateway["nodeSelector"] == node_selector
+        && egress_gateway["egressIP"] == egress_ip
+        && egress_gateway.get("interface").is_none()
+    {
+        return Ok(());
+    }
+    event!(Level::INFO, "Updating the egress gateway policy.");
+    // Adding a member replaces it, so any other node selector is dropped
+    // rather than merged. The egress IP takes the place of an interface.
+    let mut operations = vec![
+        PatchOperation::Add(AddOperation {
+            path: "/spec/egressGateway/nodeSelector".to_owned(),
+            value: node_selector,
+        }),
+        PatchOperation::Add(AddOperation {
+            path: "/spec/egressGateway/egressIP".to_owned(),
+            value: egress_ip.into(),
+        }),
+    ];
+    if egress_gateway.get("interface").is_some() {
+        operations.push(PatchOperation::Remove(RemoveOperation {
+            path: "/spec/egressGateway/interface".to_owned(),
+        }));
+    }
+    let patch = json_patch::Patch(operations);
+    api.patch(
+        policy_name,
+        &eip_operator_shared::patch_params(),
+        &Patch::Json::<()>(patch),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,522 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{ArgAction, ArgGroup, Parser, Subcommand, ValueEnum};
+use kube::api::ListParams;
+
+use eip_operator_shared::controller::RequeueIntervals;
+use eip_operator_shared::DEFAULT_METADATA_DOMAIN;
+
+use crate::autocreate::EipTemplate;
+use crate::controller::ForeignAssociationPolicy;
+use crate::migrate::MigrationMode;
+use crate::OrphanCleanupMode;
+
+/// Manages AWS Elastic IPs for Kubernetes pods and nodes.
+///
+/// Every flag can also be set with the environment variable shown, or in the
+/// config file.
+#[derive(Debug, Parser)]
+#[command(
+    version = crate::LONG_VERSION,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    group(ArgGroup::new("webhook_tls").args(["webhook_tls_cert_file", "webhook_service"]))
+)]
+pub(crate) struct Args {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// A YAML, or with a .toml extension TOML, file of settings, keyed by
+    /// lowercased environment variable name.
+    #[arg(long, value_name = "PATH")]
+    pub(crate) config: Option<PathBuf>,
+
+    /// The domain of the operator's labels, annotations, and finalizers.
+    #[arg(long, env = "METADATA_DOMAIN", default_value = DEFAULT_METADATA_DOMAIN)]
+    pub(crate) metadata_domain: String,
+
+    /// The name of the cluster, used to tag and find its EIPs.
+    #[arg(long, env = "CLUSTER_NAME")]
+    pub(crate) cluster_name: String,
+    /// A directory of kubeconfigs for remote clusters to also manage, named
+    /// after each cluster.
+    #[arg(long, env = "REMOTE_KUBECONFIG_DIR")]
+    pub(crate) remote_kubeconfig_dir: Option<PathBuf>,
+    /// The namespace to manage. All namespaces are managed if unset.
+    #[arg(long, env = "NAMESPACE")]
+    pub(crate) namespace: Option<String>,
+    /// Comma-separated namespaces to manage, excluding all others.
+    #[arg(long, env = "NAMESPACE_ALLOWLIST")]
+    pub(crate) namespace_allowlist: Option<String>,
+    /// Comma-separated namespaces not to manage.
+    #[arg(long, env = "NAMESPACE_DENYLIST")]
+    pub(crate) namespace_denylist: Option<String>,
+    /// Tags to add to every EIP, as a JSON object.
+    #[arg(long, env = "DEFAULT_TAGS", default_value = "{}", value_parser = parse_json_map)]
+    pub(crate) default_tags: HashMap<String, String>,
+    /// Extra tags to add to the EIPs of each namespace, as a JSON object of
+    /// namespaces to tags.
+    #[arg(long, env = "NAMESPACE_DEFAULT_TAGS", default_value = "{}", value_parser = parse_namespace_tags)]
+    pub(crate) namespace_default_tags: HashMap<String, HashMap<String, String>>,
+
+    /// Overrides the endpoint of the AWS APIs.
+    #[arg(long, env = "AWS_ENDPOINT_URL")]
+    pub(crate) aws_endpoint_url: Option<String>,
+    /// A directory of static AWS credentials, such as a mounted Secret.
+    #[arg(long, env = "AWS_CREDENTIALS_DIR")]
+    pub(crate) aws_credentials_dir: Option<PathBuf>,
+    /// Use the FIPS endpoints of the EC2 and Service Quotas APIs.
+    #[arg(long, env = "AWS_USE_FIPS_ENDPOINT")]
+    pub(crate) aws_use_fips_endpoint: bool,
+    /// IAM roles to manage each namespace's EIPs with, as a JSON object.
+    #[arg(long, env = "NAMESPACE_ROLE_ARNS", default_value = "{}", value_parser = parse_json_map)]
+    pub(crate) namespace_role_arns: HashMap<String, String>,
+    /// The GCP project to reserve the addresses of `gcp` Eips in. Eips may only
+    /// use the `gcp` provider when it is set.
+    #[arg(long, env = "GCP_PROJECT", requires = "gcp_region")]
+    pub(crate) gcp_project: Option<String>,
+    /// The GCP region to reserve the addresses of `gcp` Eips in.
+    #[arg(long, env = "GCP_REGION", requires = "gcp_project")]
+    pub(crate) gcp_region: Option<String>,
+    /// The Azure subscription to create the public IPs of `azure` Eips in.
+    /// Eips may only use the `azure` provider when it is set.
+    #[arg(
+        long,
+        env = "AZURE_SUBSCRIPTION_ID",
+        requires_all = ["azure_resource_group", "azure_location"],
+    )]
+    pub(crate) azure_subscription_id: Option<String>,
+    /// The resource group to create the public IPs of `azure` Eips in.
+    #[arg(long, env = "AZURE_RESOURCE_GROUP", requires = "azure_subscription_id")]
+    pub(crate) azure_resource_group: Option<String>,
+    /// The location to create the public IPs of `azure` Eips in.
+    #[arg(long, env = "AZURE_LOCATION", requires = "azure_subscription_id")]
+    pub(crate) azure_location: Option<String>,
+    /// The resource group of the cluster's node NICs, the resource group of
+    /// the public IPs by default.
+    #[arg(
+        long,
+        env = "AZURE_NODE_RESOURCE_GROUP",
+        requires = "azure_subscription_id"
+    )]
+    pub(crate) azure_node_resource_group: Option<String>,
+    /// The client ID of the user-assigned managed identity to authenticate
+    /// with, rather than the system-assigned one.
+    #[arg(long, env = "AZURE_CLIENT_ID")]
+    pub(crate) azure_client_id: Option<String>,
+
+    /// The EC2 quota code of the EIP quota that allocated EIPs are reported against.
+    #[arg(long, env = "EIP_QUOTA_CODE", default_value = crate::DEFAULT_EIP_QUOTA_CODE)]
+    pub(crate) eip_quota_code: String,
+    /// How many EIPs of the EIP quota to leave for other uses. Eips aren't
+    /// allocated EIPs which would leave fewer.
+    #[arg(long, env = "EIP_QUOTA_RESERVED", default_value_t = 0)]
+    pub(crate) eip_quota_reserved: usize,
+    /// Publish a Warning event when fewer EIPs of the EIP quota than this are
+    /// unallocated.
+    #[arg(long, env = "EIP_QUOTA_HEADROOM_WARNING", default_value_t = 5)]
+    pub(crate) eip_quota_headroom_warning: usize,
+    /// The most EIPs the Eips of each namespace may have, as a JSON object
+    /// mapping namespaces to their budgets.
+    #[arg(long, env = "NAMESPACE_EIP_BUDGETS", default_value = "{}", value_parser = parse_namespace_budgets)]
+    pub(crate) namespace_eip_budgets: HashMap<String, usize>,
+    /// The most EIPs the Eips of namespaces without their own budget may have.
+    #[arg(long, env = "DEFAULT_NAMESPACE_EIP_BUDGET")]
+    pub(crate) default_namespace_eip_budget: Option<usize>,
+    /// Deny pods which would autocreate an Eip beyond their namespace's budget
+    /// in the webhook, rather than only leaving their Eips unallocated.
+    #[arg(long, env = "WEBHOOK_ENFORCE_EIP_BUDGETS")]
+    pub(crate) webhook_enforce_eip_budgets: bool,
+    /// The hourly price of an attached EIP, for the estimated cost metrics.
+    #[arg(long, env = "EIP_HOURLY_PRICE_ATTACHED", default_value_t = 0.005)]
+    pub(crate) eip_hourly_price_attached: f64,
+    /// The hourly price of an idle EIP, for the estimated cost metrics.
+    #[arg(long, env = "EIP_HOURLY_PRICE_IDLE", default_value_t = 0.005)]
+    pub(crate) eip_hourly_price_idle: f64,
+    /// Comma-separated codes of other EC2 quotas to report.
+    #[arg(long, env = "EXTRA_QUOTA_CODES", value_delimiter = ',')]
+    pub(crate) extra_quota_codes: Vec<String>,
+
+    /// Apply the Eip CRD at startup, rather than only checking that it is installed.
+    #[arg(long, env = "MANAGE_CRDS", default_value_t = true, action = ArgAction::Set)]
+    pub(crate) manage_crds: bool,
+
+    /// Only check AWS changes with EC2's dry-run checks, rather than making them.
+    #[arg(long, env = "DRY_RUN")]
+    pub(crate) dry_run: bool,
+
+    /// Only watch and report on Eips, pods, and nodes, without changing
+    /// anything in Kubernetes or AWS.
+    #[arg(long, env = "OBSERVE_ONLY")]
+    pub(crate) observe_only: bool,
+
+    /// The port to serve metrics on.
+    #[arg(long, env = "METRICS_PORT")]
+    pub(crate) metrics_port: Option<u16>,
+    /// The token required by the debug endpoints, which are disabled if unset.
+    #[arg(long, env = "DEBUG_TOKEN", hide_env_values = true)]
+    pub(crate) debug_token: Option<String>,
+    /// The port to serve the mutating webhook for pods on, over TLS.
+    #[arg(long, env = "WEBHOOK_PORT", requires = "webhook_tls")]
+    pub(crate) webhook_port: Option<u16>,
+    /// The PEM certificate the webhook is served with.
+    #[arg(long, env = "WEBHOOK_TLS_CERT_FILE", requires = "webhook_tls_key_file")]
+    pub(crate) webhook_tls_cert_file: Option<PathBuf>,
+    /// The PEM PKCS #8 private key of the webhook's certificate.
+    #[arg(long, env = "WEBHOOK_TLS_KEY_FILE")]
+    pub(crate) webhook_tls_key_file: Option<PathBuf>,
+    /// The Service in front of the webhook, as `<namespace>/<name>`. If set
+    /// instead of the certificate files, the webhook is served with a
+    /// self-signed certificate for the Service, kept in the Secret
+    /// `<name>-tls` in its namespace.
+    #[arg(long, env = "WEBHOOK_SERVICE", value_parser = parse_service)]
+    pub(crate) webhook_service: Option<(String, String)>,
+    /// The MutatingWebhookConfiguration whose webhooks are given the
+    /// self-signed certificate as their CA bundle.
+    #[arg(
+        long,
+        env = "WEBHOOK_CONFIGURATION_NAME",
+        default_value = "eip-operator"
+    )]
+    pub(crate) webhook_configuration_name: String,
+    /// The environment variable the webhook exposes pods' public IPs as.
+    #[arg(long, env = "WEBHOOK_PUBLIC_IP_ENV", default_value = "PUBLIC_IP")]
+    pub(crate) webhook_public_ip_env: String,
+    /// The port to serve the read-only query API for address assignments on.
+    #[arg(long, env = "QUERY_API_PORT", requires = "query_api_token")]
+    pub(crate) query_api_port: Option<u16>,
+    /// The token required by the query API.
+    #[arg(long, env = "QUERY_API_TOKEN", hide_env_values = true)]
+    pub(crate) query_api_token: Option<String>,
+
+    /// Narrows the Eip watch to Eips with these labels.
+    #[arg(long, env = "EIP_LABEL_SELECTOR")]
+    pub(crate) eip_label_selector: Option<String>,
+    /// Narrows the Eip watch to Eips with these fields.
+    #[arg(long, env = "EIP_FIELD_SELECTOR")]
+    pub(crate) eip_field_selector: Option<String>,
+    /// Narrows the pod watch to pods with these labels.
+    #[arg(long, env = "POD_LABEL_SELECTOR")]
+    pub(crate) pod_label_selector: Option<String>,
+    /// Narrows the pod watch to pods with these fields.
+    #[arg(long, env = "POD_FIELD_SELECTOR")]
+    pub(crate) pod_field_selector: Option<String>,
+    /// Narrows the node watch to nodes with these labels.
+    #[arg(long, env = "NODE_LABEL_SELECTOR")]
+    pub(crate) node_label_selector: Option<String>,
+    /// Narrows the node watch to nodes with these fields.
+    #[arg(long, env = "NODE_FIELD_SELECTOR")]
+    pub(crate) node_field_selector: Option<String>,
+
+    /// How many reconciliations each controller runs at the same time.
+    #[arg(long, env = "RECONCILE_CONCURRENCY", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) reconcile_concurrency: Option<u64>,
+    /// How long after a successful reconciliation to ignore further events.
+    #[arg(long, env = "RECONCILE_DEBOUNCE_MS", default_value_t = 1000)]
+    pub(crate) reconcile_debounce_ms: u64,
+    /// The minimum delay before resyncing a successfully reconciled resource.
+    #[arg(long, env = "REQUEUE_SUCCESS_MIN_SECS")]
+    pub(crate) requeue_success_min_secs: Option<u64>,
+    /// The maximum delay before resyncing a successfully reconciled resource.
+    #[arg(long, env = "REQUEUE_SUCCESS_MAX_SECS")]
+    pub(crate) requeue_success_max_secs: Option<u64>,
+    /// The minimum delay before retrying a failed reconciliation.
+    #[arg(long, env = "REQUEUE_ERROR_MIN_MS")]
+    pub(crate) requeue_error_min_ms: Option<u64>,
+    /// The maximum delay before retrying a failed reconciliation.
+    #[arg(long, env = "REQUEUE_ERROR_MAX_MS")]
+    pub(crate) requeue_error_max_ms: Option<u64>,
+    /// The longest a repeatedly failing reconciliation backs off for.
+    #[arg(long, env = "REQUEUE_ERROR_BACKOFF_MAX_SECS")]
+    pub(crate) requeue_error_backoff_max_secs: Option<u64>,
+
+    /// How long to wait for a replacement pod before detaching a deleted pod's EIP.
+    #[arg(long, env = "POD_DETACH_GRACE_SECS", default_value_t = 0)]
+    pub(crate) pod_detach_grace_secs: u64,
+    /// Remove the operator's scheduling gate from pods once their Eip has an address.
+    #[arg(long, env = "POD_SCHEDULING_GATE")]
+    pub(crate) pod_scheduling_gate: bool,
+    /// Labels added to the node selector of gated pods as their gate is removed.
+    #[arg(
+        long,
+        env = "POD_SCHEDULING_GATE_NODE_SELECTOR",
+        default_value = "{}",
+        value_parser = parse_json_map,
+    )]
+    pub(crate) pod_scheduling_gate_node_selector: HashMap<String, String>,
+    /// Report the attached address of the Eip named by a managed service in
+    /// its load balancer status.
+    #[arg(long, env = "SERVICE_LOAD_BALANCER_STATUS")]
+    pub(crate) service_load_balancer_status: bool,
+    /// Move EIPs off nodes Karpenter is disrupting, and keep Karpenter from
+    /// voluntarily disrupting pods holding an EIP.
+    #[arg(long, env = "KARPENTER_AWARE")]
+    pub(crate) karpenter_aware: bool,
+    /// Stop the cluster autoscaler from removing nodes holding an EIP.
+    #[arg(long, env = "NODE_SCALE_DOWN_PROTECTION")]
+    pub(crate) node_scale_down_protection: bool,
+    /// The annotation external-dns reads a pod's target IPs from.
+    #[arg(
+        long,
+        env = "EXTERNAL_DNS_TARGET_ANNOTATION",
+        default_value = crate::DEFAULT_EXTERNAL_DNS_TARGET_ANNOTATION,
+    )]
+    pub(crate) external_dns_target_annotation: String,
+    /// Set the external-dns hostname annotation of pods to their Eip's
+    /// `spec.dnsName`.
+    #[arg(long, env = "EXTERNAL_DNS_HOSTNAME")]
+    pub(crate) external_dns_hostname: bool,
+    /// Also label pods with their public IP under this key, with the colons
+    /// of IPv6 addresses replaced by dashes.
+    #[arg(long, env = "POD_PUBLIC_IP_LABEL")]
+    pub(crate) pod_public_ip_label: Option<String>,
+    /// Don't release addresses which a pod's external-dns target still points at.
+    #[arg(long, env = "DNS_SAFETY_CHECK")]
+    pub(crate) dns_safety_check: bool,
+    /// Comma-separated labels copied from pods onto their autocreated Eips,
+    /// and into the tags of their EIPs.
+    #[arg(long, env = "AUTOCREATE_PROPAGATED_LABELS", value_delimiter = ',')]
+    pub(crate) autocreate_propagated_labels: Vec<String>,
+    /// The template of the names of autocreated Eips, in which `{namespace}`
+    /// and `{pod}` are replaced, rather than the pod's name.
+    #[arg(long, env = "AUTOCREATE_NAME_TEMPLATE", value_parser = parse_name_template)]
+    pub(crate) autocreate_name_template: Option<String>,
+    /// Named templates of fields of the spec of autocreated Eips, as a JSON
+    /// object mapping names to templates, which pods name in their
+    /// `eip.materialize.cloud/eip_template` annotation.
+    #[arg(long, env = "AUTOCREATE_TEMPLATES", default_value = "{}", value_parser = parse_eip_templates)]
+    pub(crate) autocreate_templates: HashMap<String, EipTemplate>,
+    /// Release all but one of the EIPs tagged for the same Eip.
+    #[arg(long, env = "RESOLVE_DUPLICATE_EIPS")]
+    pub(crate) resolve_duplicate_eips: bool,
+    /// Have Eips without an EIP adopt one tagged with their cluster,
+    /// namespace, and name, such as after an etcd restore.
+    #[arg(long, env = "ADOPT_EXISTING")]
+    pub(crate) adopt_existing: bool,
+    /// Report Eips whose EIP has been allocated but unattached for longer
+    /// than this with a Warning event.
+    #[arg(long, env = "IDLE_EIP_WARNING_SECS", default_value_t = 86400)]
+    pub(crate) idle_eip_warning_secs: u64,
+    /// Release EIPs which have been allocated but unattached for longer than
+    /// this, until their Eip has a pod or node to attach to.
+    #[arg(long, env = "IDLE_EIP_RELEASE_SECS")]
+    pub(crate) idle_eip_release_secs: Option<u64>,
+    /// How often the addresses of Eips are checked to still exist, so that
+    /// those released outside of the operator are replaced. Zero disables the
+    /// audit.
+    #[arg(long, env = "ALLOCATION_AUDIT_INTERVAL_SECS", default_value_t = 300)]
+    pub(crate) allocation_audit_interval_secs: u64,
+    /// The S3 bucket to periodically write snapshots of each cluster's Eips
+    /// to, for restoring them after a cluster is rebuilt.
+    #[arg(long, env = "SNAPSHOT_S3_BUCKET")]
+    pub(crate) snapshot_s3_bucket: Option<String>,
+    /// Prepended to the cluster name to form the key of its snapshot.
+    #[arg(long, env = "SNAPSHOT_S3_PREFIX", default_value = "eip-operator/")]
+    pub(crate) snapshot_s3_prefix: String,
+    /// How often the snapshots are written.
+    #[arg(long, env = "SNAPSHOT_INTERVAL_SECS", default_value_t = 300)]
+    pub(crate) snapshot_interval_secs: u64,
+    /// What the startup sweep does with orphaned EIPs.
+    #[arg(
+        long,
+        env = "ORPHAN_CLEANUP_MODE",
+        value_enum,
+        default_value = "delete"
+    )]
+    pub(crate) orphan_cleanup_mode: OrphanCleanupMode,
+    /// EIPs allocated more recently than this are never released by the sweep.
+    #[arg(long, env = "ORPHAN_CLEANUP_MIN_AGE_SECS", default_value_t = 900)]
+    pub(crate) orphan_cleanup_min_age_secs: u64,
+    /// What the pod and node controllers do with an Eip's address found
+    /// associated with a network interface the Eip doesn't recognize.
+    #[arg(
+        long,
+        env = "FOREIGN_ASSOCIATION_POLICY",
+        value_enum,
+        default_value = "reclaim"
+    )]
+    pub(crate) foreign_association_policy: ForeignAssociationPolicy,
+    /// What the startup migration does with objects left by earlier versions.
+    #[arg(long, env = "MIGRATION_MODE", value_enum, default_value = "migrate")]
+    pub(crate) migration_mode: MigrationMode,
+
+    /// The probability of failing each cloud call as if throttled, in builds
+    /// with the `fault-injection` feature.
+    #[arg(long, env = "FAULT_THROTTLE_RATE", default_value_t = 0.0, value_parser = parse_rate)]
+    pub(crate) fault_throttle_rate: f64,
+    /// The probability of failing each cloud call on an address as if it
+    /// didn't exist.
+    #[arg(long, env = "FAULT_NOT_FOUND_RATE", default_value_t = 0.0, value_parser = parse_rate)]
+    pub(crate) fault_not_found_rate: f64,
+    /// The probability of each cloud call hanging, then failing.
+    #[arg(long, env = "FAULT_TIMEOUT_RATE", default_value_t = 0.0, value_parser = parse_rate)]
+    pub(crate) fault_timeout_rate: f64,
+    /// The probability of failing each Eip status patch.
+    #[arg(long, env = "FAULT_STATUS_PATCH_RATE", default_value_t = 0.0, value_parser = parse_rate)]
+    pub(crate) fault_status_patch_rate: f64,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Prints the version and git commit the operator was built from.
+    Version,
+    /// Checks the connectivity and permissions the operator needs, printing
+    /// whether each check passed.
+    Check,
+    /// Prints every Eip of the cluster with its address, as seen by both
+    /// Kubernetes and the cloud provider, flagging any discrepancies.
+    Export {
+        #[arg(long, value_enum, default_value = "json")]
+        format: ExportFormat,
+    },
+    /// Prints where the statuses of the cluster's Eips disagree with their
+    /// addresses, without changing anything, and fails if they do.
+    Verify {
+        /// Keep verifying at this interval, rather than exiting.
+        #[arg(long)]
+        interval_secs: Option<u64>,
+    },
+    /// Brings an existing EIP under the operator's control, tagging it for
+    /// the Eip, in NAMESPACE or `default`, which is created if need be.
+    Adopt {
+        /// The allocation ID of the EIP.
+        allocation_id: String,
+        /// The name of the Eip.
+        eip_name: String,
+        /// The pod the Eip is for.
+        #[arg(
+            long,
+            required_unless_present = "node_selector",
+            conflicts_with = "node_selector"
+        )]
+        pod: Option<String>,
+        /// The labels of the nodes the Eip is for, as a JSON object.
+        #[arg(long, value_parser = parse_json_map)]
+        node_selector: Option<HashMap<String, String>>,
+    },
+}
+
+/// The output format of the `export` subcommand.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub(crate) enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl Args {
+    pub(crate) fn namespace_allowlist(&self) -> Option<HashSet<String>> {
+        self.namespace_allowlist
+            .as_deref()
+            .map(namespaces_from_list)
+    }
+
+    pub(crate) fn namespace_denylist(&self) -> HashSet<String> {
+        self.namespace_denylist
+            .as_deref()
+            .map(namespaces_from_list)
+            .unwrap_or_default()
+    }
+
+    /// Whether any faults are to be injected.
+    pub(crate) fn injects_faults(&self) -> bool {
+        [
+            self.fault_throttle_rate,
+            self.fault_not_found_rate,
+            self.fault_timeout_rate,
+            self.fault_status_patch_rate,
+        ]
+        .iter()
+        .any(|rate| *rate > 0.0)
+    }
+
+    pub(crate) fn requeue_intervals(&self) -> RequeueIntervals {
+        let defaults = RequeueIntervals::default();
+        RequeueIntervals {
+            success_min: self
+                .requeue_success_min_secs
+                .map_or(defaults.success_min, Duration::from_secs),
+            success_max: self
+                .requeue_success_max_secs
+                .map_or(defaults.success_max, Duration::from_secs),
+            error_min: self
+                .requeue_error_min_ms
+                .map_or(defaults.error_min, Duration::from_millis),
+            error_max: self
+                .requeue_error_max_ms
+                .map_or(defaults.error_max, Duration::from_millis),
+            error_backoff_max: self
+                .requeue_error_backoff_max_secs
+                .map_or(defaults.error_backoff_max, Duration::from_secs),
+        }
+    }
+}
+
+/// Adds any configured label and field selectors to the list params. Label
+/// selectors are added to any already in the list params.
+pub(crate) fn list_params_with(
+    mut list_params: ListParams,
+    labels: Option<&str>,
+    fields: Option<&str>,
+) -> ListParams {
+    if let Some(labels) = labels {
+        list_params.label_selector = Some(match list_params.label_selector {
+            Some(base) => format!("{},{}", base, labels),
+            None => labels.to_owned(),
+        });
+    }
+    if let Some(fields) = fields {
+        list_params.field_selector = Some(fields.to_owned());
+    }
+    list_params
+}
+
+fn namespaces_from_list(namespaces: &str) -> HashSet<String> {
+    namespaces
+        .split(',')
+        .map(str::trim)
+        .filter(|namespace| !namespace.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn parse_service(value: &str) -> Result<(String, String), String> {
+    match value.split_once('/') {
+        Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => {
+            Ok((namespace.to_owned(), name.to_owned()))
+        }
+        _ => Err("must be <namespace>/<name>".to_owned()),
+    }
+}
+
+fn parse_json_map(value: &str) -> Result<HashMap<String, String>, serde_json::Error> {
+    serde_json::from_str(value)
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err("must be a probability from 0 to 1".to_owned()),
+    }
+}
+
+fn parse_namespace_tags(
+    value: &str,
+) -> Result<HashMap<String, HashMap<String, String>>, serde_json::Error> {
+    serde_json::from_str(value)
+}
+
+fn parse_namespace_budgets(value: &str) -> Result<HashMap<String, usize>, serde_json::Error> {
+    serde_json::from_str(value)
+}
+
+fn parse_name_template(value: &str) -> Result<String, String> {
+    crate::autocreate::render_name(value, "namespace", "pod")
+        .map(|_| value.to_owned())
+        .map_err(|err| err.to_string())
+}
+
+fn parse_eip_templates(value: &str) -> Result<HashMap<String, EipTemplate>, serde_json::Error> {
+    serde_json::from_str(value)
+}
@@ -0,0 +1,45 @@
+use std::path::Path;
+
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::{Client, Config};
+use tracing::info;
+
+use eip_operator_shared::Error;
+
+/// Creates a client for each remote cluster with a kubeconfig in `dir`,
+/// named after the kubeconfig's file name. Hidden files are skipped, so the
+/// directory may be a mounted Secret.
+///
+/// A remote cluster may not share the name of the local cluster, since its
+/// EIPs are tagged, and orphans found, by cluster name.
+pub(crate) async fn remote_clients(
+    dir: &Path,
+    local_cluster_name: &str,
+) -> Result<Vec<(String, Client)>, Error> {
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || !entry.path().is_file() {
+            continue;
+        }
+        paths.push((name, entry.path()));
+    }
+    paths.sort();
+
+    let mut clients = vec![];
+    for (cluster_name, path) in paths {
+        if cluster_name == local_cluster_name {
+            return Err(Error::InvalidConfig(
+                path.display().to_string(),
+                "a remote cluster cannot be named after the local cluster".to_owned(),
+            ));
+        }
+        let kubeconfig = Kubeconfig::read_from(&path)?;
+        let config =
+            Config::from_custom_kubeconfig(kubeconfig, &KubeConfigOptions::default()).await?;
+        info!(cluster_name = %cluster_name, "Loaded remote cluster kubeconfig.");
+        clients.push((cluster_name, Client::try_from(config)?));
+    }
+    Ok(clients)
+}
@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use clap::CommandFactory;
+use serde_yaml::Value;
+
+use eip_operator_shared::Error;
+
+use crate::cli::Args;
+
+/// Settings read straight from the environment, rather than through `Args`,
+/// which the config file may also set.
+const ENVIRONMENT_SETTINGS: &[&str] = &[
+    "AWS_REGION",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "OPENTELEMETRY_CA_CERT",
+    "OPENTELEMETRY_CA_CERT_FILE",
+    "OPENTELEMETRY_ENDPOINT",
+    "OPENTELEMETRY_HEADERS",
+    "OPENTELEMETRY_LEVEL_TARGETS",
+    "OPENTELEMETRY_PROTOCOL",
+    "OPENTELEMETRY_SAMPLE_RATE",
+    "OPENTELEMETRY_TOPLEVEL_FIELDS",
+    "RUST_LOG",
+    "SENTRY_DSN",
+    "SENTRY_ENVIRONMENT",
+];
+
+/// Loads the YAML or TOML config file at `path` into the environment, so that it is
+/// read exactly like the environment variables it replaces. Each top-level
+/// key is the lowercased name of an environment variable, such as
+/// `cluster_name`. Environment variables which are already set take
+/// precedence, so can be used as overrides.
+///
+/// Mappings are set as JSON, like `default_tags`, and sequences are joined
+/// with commas, like `namespace_allowlist`. Keys which aren't settings are
+/// rejected, so that a misspelled setting isn't silently ignored.
+///
+/// This must be called before any other threads are started.
+pub(crate) fn load_into_env(path: &Path) -> Result<(), Error> {
+    let contents = std::fs::read_to_string(path)?;
+    let settings = parse(&contents, Format::of(path))
+        .map_err(|reason| Error::InvalidConfig(path.display().to_string(), reason))?;
+    for (name, value) in settings {
+        if std::env::var_os(&name).is_none() {
+            std::env::set_var(name, value);
+        }
+    }
+    Ok(())
+}
+
+/// The format of a config file, chosen by its extension.
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// TOML for a `.toml` file, and YAML otherwise.
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::Toml,
+            _ => Self::Yaml,
+        }
+    }
+}
+
+/// Parses the config file into the environment variables it sets. TOML is
+/// read into the same values as YAML, so both are set alike.
+fn parse(contents: &str, format: Format) -> Result<Vec<(String, String)>, String> {
+    let config: BTreeMap<String, Value> = match format {
+        Format::Yaml => serde_yaml::from_str(contents).map_err(|err| err.to_string())?,
+        Format::Toml => toml::from_str(contents).map_err(|err| err.to_string())?,
+    };
+    let known = setting_names();
+    config
+        .into_iter()
+        .map(|(key, value)| {
+            let name = key.to_uppercase();
+            if !known.contains(&name) {
+                return Err(format!("{} is not a setting", key));
+            }
+            let value = to_env_value(&value).ok_or_else(|| format!("{} is invalid", key))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// The environment variables of every setting.
+fn setting_names() -> HashSet<String> {
+    let command = Args::command();
+    command
+        .get_arguments()
+        .chain(
+            command
+                .get_subcommands()
+                .flat_map(|sub| sub.get_arguments()),
+        )
+        .filter_map(|arg| arg.get_env())
+        .map(|env| env.to_string_lossy().into_owned())
+        .chain(ENVIRONMENT_SETTINGS.iter().map(|name| (*name).to_owned()))
+        .collect()
+}
+
+/// The config file path given by `--config <path>` or `--config=<path>`.
+pub(crate) fn path_from_args() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_owned());
+        }
+    }
+    None
+}
+
+fn to_env_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.to_owned()),
+        Value::Bool(value) => Some(value.to_string()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Sequence(values) => values
+            .iter()
+            .map(to_env_value)
+            .collect::<Option<Vec<_>>>()
+            .map(|values| values.join(",")),
+        Value::Mapping(_) => serde_json::to_string(value).ok(),
+        Value::Null => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_loaded_by_their_environment_variables() {
+        let settings = parse(
+            "cluster_name: my-cluster\nnamespace_allowlist: [a, b]\n",
+            Format::Yaml,
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            [
+                ("CLUSTER_NAME".to_owned(), "my-cluster".to_owned()),
+                ("NAMESPACE_ALLOWLIST".to_owned(), "a,b".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert_eq!(
+            parse("cluster_nmae: my-cluster\n", Format::Yaml),
+            Err("cluster_nmae is not a setting".to_owned())
+        );
+    }
+
+    #[test]
+    fn toml_settings_are_loaded_like_yaml_ones() {
+        let settings = parse(
+            r#"
+cluster_name = "my-cluster"
+namespace_allowlist = ["a", "b"]
+
+[default_tags]
+team = "net"
+"#,
+            Format::Toml,
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            [
+                ("CLUSTER_NAME".to_owned(), "my-cluster".to_owned()),
+                ("DEFAULT_TAGS".to_owned(), r#"{"team":"net"}"#.to_owned()),
+                ("NAMESPACE_ALLOWLIST".to_owned(), "a,b".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_format_follows_the_extension() {
+        assert!(matches!(Format::of(Path::new("eip.toml")), Format::Toml));
+        assert!(matches!(Format::of(Path::new("eip.yaml")), Format::Yaml));
+        assert!(matches!(Format::of(Path::new("eip")), Format::Yaml));
+    }
+}
@@ -1,3 +1,103 @@
+use std::collections::HashSet;
+
+use clap::ValueEnum;
+use kube::Client;
+use tracing::{event, Level};
+
+use eip_operator_shared::Error;
+
+use crate::eip::v2::Eip;
+use crate::provider::Address;
+
 pub(crate) mod eip;
 pub(crate) mod node;
 pub(crate) mod pod;
+pub(crate) mod service;
+
+/// Restricts which namespaces Eips and pods are managed in, so that tenants
+/// can't opt into EIP management just by adding the label.
+#[derive(Clone, Debug)]
+pub(crate) struct NamespaceFilter {
+    allow: Option<HashSet<String>>,
+    deny: HashSet<String>,
+}
+
+impl NamespaceFilter {
+    /// Creates a filter allowing only the namespaces in `allow`, if any are
+    /// given, and none of the namespaces in `deny`.
+    pub(crate) fn new(allow: Option<HashSet<String>>, deny: HashSet<String>) -> Self {
+        Self { allow, deny }
+    }
+
+    /// Checks whether resources in the namespace may be managed.
+    pub(crate) fn allows(&self, namespace: &str) -> bool {
+        !self.deny.contains(namespace)
+            && self
+                .allow
+                .as_ref()
+                .map_or(true, |allow| allow.contains(namespace))
+    }
+}
+
+/// What the pod and node controllers do with an Eip's address which they
+/// find associated with a network interface the Eip doesn't recognize, such
+/// as one of an unrelated instance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ForeignAssociationPolicy {
+    /// Associate the address with the pod or node anyway.
+    #[default]
+    Reclaim,
+    /// Leave the address alone, failing to reconcile until it is
+    /// disassociated.
+    StandDown,
+}
+
+impl ForeignAssociationPolicy {
+    /// Checks the association of the address, before it is associated with
+    /// the network interface instead. A foreign association is reported with
+    /// a Warning event on the Eip, and unless the address is reclaimed,
+    /// fails, so that the Eip's status records it.
+    pub(crate) async fn check(
+        self,
+        client: &Client,
+        eip: &Eip,
+        address: &Address,
+        eni_id: &str,
+    ) -> Result<(), Error> {
+        let foreign_eni = match address.eni.as_deref() {
+            Some(eni) if eni != eni_id && !eip.recognizes_eni(eni) => eni,
+            _ => return Ok(()),
+        };
+        let allocation_id = &address.allocation_id;
+        event!(
+            Level::WARN,
+            %allocation_id,
+            %foreign_eni,
+            policy = ?self,
+            "EIP is associated with a network interface the Eip doesn't recognize."
+        );
+        let err = Error::ForeignAssociation {
+            allocation_id: allocation_id.to_owned(),
+            eni: foreign_eni.to_owned(),
+        };
+        // Standing down fails every retry, but is only reported once.
+        let reported = eip
+            .status
+            .as_ref()
+            .and_then(|status| status.last_error_reason.as_deref())
+            == Some(err.reason());
+        if !reported {
+            crate::metrics::FOREIGN_ASSOCIATIONS.inc();
+            let action = match self {
+                Self::Reclaim => "Reclaim",
+                Self::StandDown => "StandDown",
+            };
+            self::eip::publish_warning(client, eip, "ForeignAssociation", action, err.to_string())
+                .await;
+        }
+        match self {
+            Self::Reclaim => Ok(()),
+            Self::StandDown => Err(err),
+        }
+    }
+}
@@ -1,30 +1,687 @@
-use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::chrono::{self, DateTime, Utc};
 use kube::api::Api;
-use kube::{Client, ResourceExt};
+use kube::{Client, Resource, ResourceExt};
+use kube_runtime::controller::Action;
+use kube_runtime::events::{Event, EventType, Recorder};
+use kube_runtime::finalizer;
 use tracing::{event, instrument, Level};
 
+use eip_operator_shared::controller::{fingerprint_of, RequeueIntervals};
 use eip_operator_shared::Error;
 
-use crate::eip::v2::Eip;
+use crate::aws::DefaultTags;
+use crate::controller::NamespaceFilter;
+use crate::eip::v2::{Eip, EipSelector};
+use crate::node_index::NodeIndex;
+use crate::provider::{Address, AllocationRequest, Providers};
+use crate::quota::AllocationLimits;
+
+/// How the Eip controller finds and releases addresses.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct AddressPolicy {
+    /// Whether addresses which a pod's external-dns target still points at
+    /// are kept rather than released.
+    pub(crate) dns_safety_check: bool,
+    /// Whether all but one of the addresses tagged for the same Eip are
+    /// released.
+    pub(crate) resolve_duplicates: bool,
+    /// Whether an Eip without an address adopts one tagged with its cluster,
+    /// namespace, and name, rather than allocating a new one.
+    pub(crate) adopt_existing: bool,
+    /// How long an address may be allocated but unattached before its Eip is
+    /// reported idle.
+    pub(crate) idle_warning_after: Option<Duration>,
+    /// How long an address may be allocated but unattached before it is
+    /// released, until its Eip has a pod or node to attach to.
+    pub(crate) idle_release_after: Option<Duration>,
+}
 
 pub(crate) struct Context {
-    ec2_client: aws_sdk_ec2::Client,
+    providers: Providers,
     cluster_name: String,
-    default_tags: HashMap<String, String>,
+    default_tags: DefaultTags,
+    policy: AddressPolicy,
+    node_index: NodeIndex,
+    namespaces: NamespaceFilter,
+    limits: AllocationLimits,
 }
 
 impl Context {
     pub(crate) fn new(
-        ec2_client: aws_sdk_ec2::Client,
+        providers: Providers,
         cluster_name: String,
-        default_tags: HashMap<String, String>,
+        default_tags: DefaultTags,
+        policy: AddressPolicy,
+        node_index: NodeIndex,
+        namespaces: NamespaceFilter,
+        limits: AllocationLimits,
     ) -> Self {
         Self {
-            ec2_client,
+            providers,
             cluster_name,
             default_tags,
+            policy,
+            node_index,
+            namespaces,
+            limits,
+        }
+    }
+
+    /// Checks whether a node-selector Eip is attached to a node whose labels
+    /// no longer match its selector, or which Karpenter is disrupting, so the
+    /// address should move elsewhere.
+    fn attached_to_unselected_node(&self, eip: &Eip) -> bool {
+        match (&eip.spec.selector, eip.attachment().2) {
+            (EipSelector::Node { .. }, Some(private_ip)) => {
+                self.node_index
+                    .labels_for_ip(private_ip)
+                    .map_or(false, |labels| !eip.matches_node(&labels))
+                    || self.node_index.disrupting_ip(private_ip)
+            }
+            _ => false,
+        }
+    }
+
+    /// The CiliumEgressGatewayPolicy a node-selector Eip keeps pointed at its
+    /// node, and the private IP of the node it is attached to, if any.
+    fn egress_gateway<'a>(&self, eip: &'a Eip) -> Option<(&'a str, &'a str)> {
+        let policy_name = eip
+            .annotations()
+            .get(crate::EGRESS_GATEWAY_POLICY_ANNOTATION.as_str())?;
+        match (&eip.spec.selector, eip.attachment().2) {
+            (EipSelector::Node { .. }, Some(private_ip)) => Some((policy_name, private_ip)),
+            _ => None,
+        }
+    }
+
+    /// Whether the pod or a node the Eip selects exists, so that it needs an
+    /// address.
+    async fn has_target(&self, client: &Client, eip: &Eip) -> Result<bool, Error> {
+        match &eip.spec.selector {
+            EipSelector::Pod { pod_name, .. } => {
+                let pod_api = Api::<Pod>::namespaced(client.clone(), &eip.namespace().unwrap());
+                Ok(pod_api.get_opt(pod_name).await?.map_or(false, |pod| {
+                    eip.matches_pod_uid(pod.metadata.uid.as_deref().unwrap_or_default())
+                }))
+            }
+            EipSelector::Node { selector } => Ok(!self.node_index.matching(selector).is_empty()),
+        }
+    }
+
+    /// Reports the Eip idle once its address has been allocated but
+    /// unattached for longer than the policy allows, with a Warning event and
+    /// an entry in its history, and releases the address once it has been for
+    /// longer still. Protected Eips are only reported.
+    async fn check_idle(
+        &self,
+        client: &Client,
+        api: &Api<Eip>,
+        eip: &Eip,
+        address: &Address,
+    ) -> Result<(), Error> {
+        let namespace = eip.namespace().unwrap();
+        let name = eip.name_any();
+        let idle_since = match eip
+            .idle_since()
+            .filter(|_| address.association_id.is_none())
+        {
+            Some(idle_since) => idle_since,
+            None => {
+                let _ = crate::metrics::EIP_IDLE_SINCE.remove_label_values(&[&namespace, &name]);
+                return Ok(());
+            }
+        };
+        crate::metrics::EIP_IDLE_SINCE
+            .with_label_values(&[&namespace, &name])
+            .set(idle_since.timestamp());
+        let idle_for = (Utc::now() - idle_since).to_std().unwrap_or_default();
+        let release_due = self
+            .policy
+            .idle_release_after
+            .map_or(false, |after| idle_for >= after);
+        if release_due && !protected(eip) {
+            if self.policy.dns_safety_check {
+                let pod_api = Api::<Pod>::namespaced(client.clone(), &namespace);
+                if crate::controller::pod::dns_target_in_use(&pod_api, &address.public_ip).await? {
+                    event!(
+                        Level::INFO,
+                        allocation_id = %address.allocation_id,
+                        "Not releasing an idle EIP which a pod's external-dns target points at."
+                    );
+                    return Ok(());
+                }
+            }
+            event!(
+                Level::INFO,
+                allocation_id = %address.allocation_id,
+                %idle_since,
+                "Releasing an idle EIP."
+            );
+            self.providers
+                .for_eip(eip)?
+                .release(&namespace, address)
+                .await?;
+            crate::eip::set_status_idle_released(api, eip).await?;
+            let _ = crate::metrics::EIP_IDLE_SINCE.remove_label_values(&[&namespace, &name]);
+            let note = format!(
+                "Released {}, unattached since {}",
+                address.allocation_id, idle_since
+            );
+            publish_warning(client, eip, "IdleEipReleased", "ReleaseIdle", note).await;
+        } else if self
+            .policy
+            .idle_warning_after
+            .map_or(false, |after| idle_for >= after)
+            && !eip.idle_reported()
+        {
+            event!(
+                Level::WARN,
+                allocation_id = %address.allocation_id,
+                %idle_since,
+                "EIP is idle."
+            );
+            let note = format!(
+                "{} has been allocated but unattached since {}",
+                address.allocation_id, idle_since
+            );
+            publish_warning(client, eip, "EipIdle", "ReportIdle", note).await;
+            crate::eip::set_status_idle(api, eip).await?;
         }
+        Ok(())
+    }
+
+    /// When the Eip will next be due to be reported idle or released.
+    fn idle_deadline(&self, eip: &Eip) -> Option<DateTime<Utc>> {
+        let idle_since = eip.idle_since()?;
+        let warning = self
+            .policy
+            .idle_warning_after
+            .filter(|_| !eip.idle_reported());
+        let release = self.policy.idle_release_after.filter(|_| !protected(eip));
+        [warning, release]
+            .into_iter()
+            .flatten()
+            .filter_map(|after| chrono::Duration::from_std(after).ok())
+            .map(|after| idle_since + after)
+            .min()
+    }
+
+    /// Finds the address tagged for this Eip, allocating one if none exists.
+    async fn ensure_allocated(
+        &self,
+        client: &Client,
+        eip: &Eip,
+        uid: &str,
+        name: &str,
+    ) -> Result<Address, Error> {
+        let namespace = eip.namespace().unwrap();
+        let provider = self.providers.for_eip(eip)?;
+        let addresses = provider
+            .describe_by_tag(&namespace, crate::aws::EIP_UID_TAG, uid)
+            .await?;
+        match addresses.len() {
+            0 => {
+                // The status patch triggers another reconciliation, which
+                // allocates a new address for the updated Eip.
+                if let Some(allocation_id) = eip.allocation_id() {
+                    match provider.describe(&namespace, allocation_id).await {
+                        Err(err) if err.is_allocation_not_found() => {
+                            let api = Api::<Eip>::namespaced(client.clone(), &namespace);
+                            report_allocation_lost(client, &api, eip).await?;
+                            return Err(Error::AllocationLost(allocation_id.to_owned()));
+                        }
+                        Err(err) => return Err(err),
+                        // The address lost its tags, such as when tagging it
+                        // failed after it was allocated, so claim it again
+                        // rather than leak it by allocating another.
+                        Ok(address) if untagged(&address) => {
+                            return self.retag(eip, uid, name, address).await
+                        }
+                        // Tagged for another Eip, such as the one this Eip
+                        // was restored from, which adoption handles.
+                        Ok(_) => {}
+                    }
+                }
+                let adoptable = match adopted_allocation_id(eip) {
+                    Some(allocation_id) => {
+                        Some(provider.describe(&namespace, allocation_id).await?)
+                    }
+                    None if self.policy.adopt_existing => self.find_adoptable(eip, name).await?,
+                    None => None,
+                };
+                match adoptable {
+                    Some(address) => self.adopt(eip, uid, name, address).await,
+                    None => {
+                        self.check_limits(eip).await?;
+                        let mut tags = self.default_tags.for_namespace(&namespace);
+                        tags.extend(
+                            spec_tags(eip)?
+                                .into_iter()
+                                .map(|(key, value)| (key.to_owned(), value.to_owned())),
+                        );
+                        let address = provider
+                            .allocate(AllocationRequest {
+                                eip_uid: uid,
+                                eip_name: name,
+                                selector: &eip.spec.selector,
+                                cluster_name: &self.cluster_name,
+                                namespace: &namespace,
+                                default_tags: &tags,
+                            })
+                            .await?;
+                        self.limits.quota.count_allocation();
+                        Ok(address)
+                    }
+                }
+            }
+            1 => {
+                let address = addresses.into_iter().next().unwrap();
+                self.converge_tags(eip, &address, name).await?;
+                Ok(address)
+            }
+            _ if self.policy.resolve_duplicates && !protected(eip) => {
+                let kept = self.release_duplicates(client, eip, addresses).await?;
+                self.converge_tags(eip, &kept, name).await?;
+                Ok(kept)
+            }
+            _ => Err(Error::MultipleEipsTaggedForPod),
+        }
+    }
+
+    /// Tags the address of the Eip's status, which is missing its UID tag,
+    /// for the Eip again.
+    async fn retag(
+        &self,
+        eip: &Eip,
+        uid: &str,
+        name: &str,
+        address: Address,
+    ) -> Result<Address, Error> {
+        let namespace = eip.namespace().unwrap();
+        let allocation_id = &address.allocation_id;
+        event!(Level::INFO, %allocation_id, "Restoring the UID tag of the Eip's address.");
+        self.providers
+            .for_eip(eip)?
+            .converge_tags(&namespace, &address, &[(crate::aws::EIP_UID_TAG, uid)])
+            .await?;
+        self.converge_tags(eip, &address, name).await?;
+        Ok(address)
+    }
+
+    /// Fails if allocating an address for the Eip would eat into the reserved
+    /// EIPs of the quota, or exceed the budget of its namespace, which is
+    /// counted in addresses tagged for the namespace by this cluster.
+    async fn check_limits(&self, eip: &Eip) -> Result<(), Error> {
+        self.limits.quota.check(eip)?;
+        let namespace = eip.namespace().unwrap();
+        if self.limits.budgets.for_namespace(&namespace).is_some() {
+            let mut allocated = 0;
+            for (_, provider) in self.providers.all() {
+                allocated += provider
+                    .describe_cluster_addresses(&self.cluster_name, Some(&namespace))
+                    .await?
+                    .len();
+            }
+            self.limits.budgets.check(&namespace, allocated)?;
+        }
+        Ok(())
+    }
+
+    /// Finds the address left behind by an earlier Eip of the same name, such
+    /// as one lost in an etcd restore. Names are unique within a namespace, so
+    /// an address tagged with this cluster, namespace, and name can't belong
+    /// to any other Eip.
+    async fn find_adoptable(&self, eip: &Eip, name: &str) -> Result<Option<Address>, Error> {
+        let namespace = eip.namespace().unwrap();
+        let mut addresses: Vec<_> = self
+            .providers
+            .for_eip(eip)?
+            .describe_by_tag(&namespace, crate::aws::EIP_NAME_TAG, name)
+            .await?
+            .into_iter()
+            .filter(|address| self.adoptable(address, &namespace, name))
+            .collect();
+        match addresses.len() {
+            0 | 1 => Ok(addresses.pop()),
+            _ => Err(Error::MultipleAddressesAdoptable(name.to_owned())),
+        }
+    }
+
+    /// Checks that the address is tagged with the cluster, namespace, and name
+    /// of the Eip, so that an Eip can't take the address of another.
+    fn adoptable(&self, address: &Address, namespace: &str, name: &str) -> bool {
+        address.tag(crate::aws::CLUSTER_NAME_TAG) == Some(self.cluster_name.as_str())
+            && address.tag(crate::aws::NAMESPACE_TAG) == Some(namespace)
+            && address.tag(crate::aws::EIP_NAME_TAG) == Some(name)
+    }
+
+    /// Adopts the address an Eip restored from a snapshot or recreated after
+    /// a disaster had before, by tagging it with the Eip's new UID.
+    async fn adopt(
+        &self,
+        eip: &Eip,
+        uid: &str,
+        name: &str,
+        address: Address,
+    ) -> Result<Address, Error> {
+        let namespace = eip.namespace().unwrap();
+        let provider = self.providers.for_eip(eip)?;
+        let allocation_id = &address.allocation_id;
+        if !self.adoptable(&address, &namespace, name) {
+            return Err(Error::AddressNotAdoptable(allocation_id.to_owned()));
+        }
+        event!(Level::INFO, %allocation_id, "Adopting the Eip's previous address.");
+        let mut tags = vec![(crate::aws::EIP_UID_TAG, uid)];
+        if address.tag(crate::aws::TRANSFERRED_FROM_TAG).is_some() {
+            // The handover is complete.
+            tags.push((crate::aws::TRANSFERRED_FROM_TAG, ""));
+        }
+        provider.converge_tags(&namespace, &address, &tags).await?;
+        self.converge_tags(eip, &address, name).await?;
+        Ok(address)
+    }
+
+    /// Reconciles the Eip with its address, leaving the recording of any
+    /// error in its status to the caller.
+    async fn reconcile(
+        &self,
+        client: &Client,
+        api: &Api<Eip>,
+        eip: &Eip,
+        uid: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        let namespace = eip.namespace().unwrap();
+        if !self.namespaces.allows(&namespace) {
+            return Err(Error::NamespaceNotManaged(namespace));
+        }
+        if eip.selector_unset() {
+            // The patch triggers another reconciliation, with the selector.
+            crate::eip::set_default_selector(api, eip).await?;
+            return Ok(());
+        }
+        if let Some(cluster_name) = &eip.spec.transfer_to {
+            return self.transfer(eip, uid, cluster_name).await;
+        }
+        if eip.idle_released() && !self.has_target(client, eip).await? {
+            event!(
+                Level::DEBUG,
+                "Not allocating an address for an Eip released for being idle, until it has a pod or node."
+            );
+            return Ok(());
+        }
+        let allocation = self.ensure_allocated(client, eip, uid, name).await?;
+        crate::eip::set_status_created(api, eip, &allocation.allocation_id, &allocation.public_ip)
+            .await?;
+        if drifted(eip, &allocation) {
+            event!(
+                Level::WARN,
+                allocation_id = %allocation.allocation_id,
+                eni = ?allocation.eni,
+                private_ip_address = ?allocation.private_ip_address,
+                "EIP association drifted from its status."
+            );
+            crate::metrics::ASSOCIATION_DRIFT.inc();
+            crate::eip::set_status_drifted(api, eip).await?;
+        } else if self.attached_to_unselected_node(eip) {
+            event!(
+                Level::INFO,
+                "Detaching EIP from a node which no longer matches its selector, or is being disrupted."
+            );
+            ensure_unprotected(client, eip, "Detach").await?;
+            self.providers
+                .for_eip(eip)?
+                .disassociate(&namespace, &allocation.allocation_id)
+                .await?;
+            crate::eip::set_status_detached(api, eip).await?;
+        } else if let Some((policy_name, private_ip)) = self.egress_gateway(eip) {
+            match self
+                .node_index
+                .labels_for_ip(private_ip)
+                .and_then(|labels| labels.get(crate::cilium::HOSTNAME_LABEL).cloned())
+            {
+                Some(hostname) => {
+                    crate::cilium::sync_egress_gateway_policy(
+                        client.clone(),
+                        policy_name,
+                        &hostname,
+                        private_ip,
+                    )
+                    .await?
+                }
+                None => event!(Level::DEBUG, "The Eip's node isn't indexed yet."),
+            }
+        }
+        if eip
+            .detach_deadline()
+            .map_or(false, |deadline| deadline <= Utc::now())
+        {
+            event!(
+                Level::INFO,
+                "Detaching EIP whose pod was not replaced in time."
+            );
+            ensure_unprotected(client, eip, "Detach").await?;
+            self.providers
+                .for_eip(eip)?
+                .disassociate(&namespace, &allocation.allocation_id)
+                .await?;
+            crate::eip::set_status_detached(api, eip).await?;
+        }
+        self.check_idle(client, api, eip, &allocation).await?;
+        Ok(())
+    }
+
+    /// Hands the Eip's address over to another cluster, by tagging it with
+    /// that cluster's name, so that an Eip of the same namespace and name
+    /// there can adopt it. The address stays associated until then, and
+    /// isn't released when this Eip is deleted.
+    async fn transfer(&self, eip: &Eip, uid: &str, cluster_name: &str) -> Result<(), Error> {
+        let namespace = eip.namespace().unwrap();
+        let provider = self.providers.for_eip(eip)?;
+        let addresses = provider
+            .describe_by_tag(&namespace, crate::aws::EIP_UID_TAG, uid)
+            .await?;
+        for address in addresses {
+            if address.tag(crate::aws::CLUSTER_NAME_TAG) == Some(cluster_name) {
+                continue;
+            }
+            event!(
+                Level::INFO,
+                allocation_id = %address.allocation_id,
+                %cluster_name,
+                "Transferring the EIP to another cluster."
+            );
+            provider
+                .converge_tags(
+                    &namespace,
+                    &address,
+                    &[
+                        (crate::aws::CLUSTER_NAME_TAG, cluster_name),
+                        (crate::aws::TRANSFERRED_FROM_TAG, &self.cluster_name),
+                    ],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Restores the audit tags on an existing address, in case they were
+    /// removed or it was allocated by an older version of the operator. The
+    /// created-by tag records the original allocator, so is left alone.
+    /// The protected tag follows the Eip's annotation, so that the orphan
+    /// sweep leaves the address alone even once the Eip is gone. The tags of
+    /// the Eip's spec are added, but those removed from it are left.
+    async fn converge_tags(&self, eip: &Eip, address: &Address, name: &str) -> Result<(), Error> {
+        let namespace = eip.namespace().unwrap();
+        let mut tags: Vec<(&str, &str)> = vec![
+            (crate::aws::MANAGED_BY_TAG, crate::aws::MANAGED_BY),
+            (
+                crate::aws::OPERATOR_VERSION_TAG,
+                crate::aws::OPERATOR_VERSION,
+            ),
+            (crate::aws::CLUSTER_NAME_TAG, &self.cluster_name),
+            (crate::aws::NAMESPACE_TAG, &namespace),
+            (crate::aws::EIP_NAME_TAG, name),
+        ];
+        if protected(eip) {
+            tags.push((crate::aws::PROTECTED_TAG, "true"));
+        } else if address.tag(crate::aws::PROTECTED_TAG).is_some() {
+            tags.push((crate::aws::PROTECTED_TAG, ""));
+        }
+        tags.extend(spec_tags(eip)?);
+        self.providers
+            .for_eip(eip)?
+            .converge_tags(&namespace, address, &tags)
+            .await
+    }
+
+    /// Keeps the associated address, or the oldest one if none is associated,
+    /// and releases the rest, publishing a Warning event on the Eip.
+    async fn release_duplicates(
+        &self,
+        client: &Client,
+        eip: &Eip,
+        mut addresses: Vec<Address>,
+    ) -> Result<Address, Error> {
+        addresses.sort_by_key(|address| {
+            (
+                address.association_id.is_none(),
+                allocated_at(address),
+                address.allocation_id.clone(),
+            )
+        });
+        let kept = addresses.remove(0);
+        let namespace = eip.namespace().unwrap();
+        let mut released = vec![];
+        for address in &addresses {
+            if self.policy.dns_safety_check {
+                let pod_api = Api::<Pod>::namespaced(client.clone(), &namespace);
+                if crate::controller::pod::dns_target_in_use(&pod_api, &address.public_ip).await? {
+                    return Err(Error::AddressStillInDns(address.public_ip.clone()));
+                }
+            }
+            self.providers
+                .for_eip(eip)?
+                .release(&namespace, address)
+                .await?;
+            released.push(address.allocation_id.clone());
+        }
+        event!(
+            Level::WARN,
+            kept = %kept.allocation_id,
+            released = ?released,
+            "Released duplicate EIPs tagged for the same Eip."
+        );
+        let note = format!(
+            "Kept {}, released duplicates {}",
+            kept.allocation_id,
+            released.join(", ")
+        );
+        publish_warning(
+            client,
+            eip,
+            "DuplicateEipsReleased",
+            "ReleaseDuplicates",
+            note,
+        )
+        .await;
+        Ok(kept)
+    }
+}
+
+/// Reports with a Warning event that the Eip's address was released outside
+/// of the operator, and clears it from the Eip's status, so that another is
+/// allocated.
+pub(crate) async fn report_allocation_lost(
+    client: &Client,
+    api: &Api<Eip>,
+    eip: &Eip,
+) -> Result<(), Error> {
+    let allocation_id = eip.allocation_id().unwrap_or_default();
+    event!(
+        Level::WARN,
+        %allocation_id,
+        "The Eip's address was released outside of the operator."
+    );
+    crate::metrics::ALLOCATIONS_LOST.inc();
+    let note = format!(
+        "{} no longer exists, so another address will be allocated",
+        allocation_id
+    );
+    publish_warning(client, eip, "AllocationLost", "Audit", note).await;
+    crate::eip::set_status_allocation_lost(api, eip).await?;
+    Ok(())
+}
+
+/// Publishes a Warning event on the Eip.
+pub(crate) async fn publish_warning(
+    client: &Client,
+    eip: &Eip,
+    reason: &str,
+    action: &str,
+    note: String,
+) {
+    let recorder = Recorder::new(
+        client.clone(),
+        eip_operator_shared::metadata_domain().to_owned().into(),
+        eip.object_ref(&()),
+    );
+    if let Err(err) = recorder
+        .publish(Event {
+            type_: EventType::Warning,
+            reason: reason.to_owned(),
+            note: Some(note),
+            action: action.to_owned(),
+            secondary: None,
+        })
+        .await
+    {
+        event!(Level::WARN, err = %err, "Failed to publish event.");
+    }
+}
+
+/// The tags of the Eip's spec, which mustn't replace the operator's own.
+fn spec_tags(eip: &Eip) -> Result<Vec<(&str, &str)>, Error> {
+    eip.spec
+        .tags
+        .iter()
+        .flatten()
+        .map(|(key, value)| {
+            if crate::aws::is_operator_tag(key) {
+                return Err(Error::InvalidTag {
+                    key: key.to_owned(),
+                    reason: "the key is set by the operator".to_owned(),
+                });
+            }
+            Ok((key.as_str(), value.as_str()))
+        })
+        .collect()
+}
+
+/// When the address was allocated, according to its tag. Addresses without
+/// the tag predate it, so sort first.
+fn allocated_at(address: &Address) -> Option<DateTime<Utc>> {
+    address
+        .tag(crate::aws::ALLOCATED_AT_TAG)
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|value| value.with_timezone(&Utc))
+}
+
+/// Checks whether the Eip status records an attachment which the address's
+/// actual association no longer matches, for example because the ENI was
+/// deleted along with its node, or the address was associated out-of-band.
+fn drifted(eip: &Eip, address: &Address) -> bool {
+    match &eip.status {
+        Some(status) if eip.attached() => {
+            status.allocation_id.as_ref() == Some(&address.allocation_id)
+                && (status.eni != address.eni
+                    || status.private_ip_address != address.private_ip_address)
+        }
+        _ => false,
     }
 }
 
@@ -33,12 +690,12 @@ impl eip_operator_shared::controller::Context for Context {
     type Resource = Eip;
     type Error = Error;
 
-    const FINALIZER_NAME: &'static str = "eip.materialize.cloud/destroy";
+    const FINALIZER_NAME: &'static str = "destroy";
 
-    #[instrument(skip(self, _client, api, eip), err)]
+    #[instrument(skip(self, client, api, eip), err)]
     async fn apply(
         &self,
-        _client: Client,
+        client: Client,
         api: Api<Self::Resource>,
         eip: &Self::Resource,
     ) -> Result<(), Self::Error> {
@@ -46,71 +703,738 @@ impl eip_operator_shared::controller::Context for Context {
         let name = eip.metadata.name.as_ref().ok_or(Error::MissingEipName)?;
         let selector = &eip.spec.selector;
         event!(Level::INFO, %uid, %name, %selector, "Applying EIP.");
-        let addresses = crate::aws::describe_addresses_with_tag_value(
-            &self.ec2_client,
-            crate::aws::EIP_UID_TAG,
-            uid,
-        )
-        .await?
-        .addresses
-        .ok_or(Error::MissingAddresses)?;
-        let (allocation_id, public_ip) = match addresses.len() {
-            0 => {
-                let response = crate::aws::allocate_address(
-                    &self.ec2_client,
-                    uid,
-                    name,
-                    selector,
-                    &self.cluster_name,
-                    &eip.namespace().unwrap(),
-                    &self.default_tags,
-                )
-                .await?;
-                let allocation_id = response.allocation_id.ok_or(Error::MissingAllocationId)?;
-                let public_ip = response.public_ip.ok_or(Error::MissingPublicIp)?;
-                (allocation_id, public_ip)
+        let result = self.reconcile(&client, &api, eip, uid, name).await;
+        if let Err(err) = &result {
+            crate::eip::set_status_error(&api, name, err).await?;
+        }
+        result
+    }
+
+    fn fingerprint(&self, eip: &Self::Resource) -> Option<u64> {
+        Some(fingerprint_of(&(
+            &eip.spec,
+            &eip.metadata.finalizers,
+            &eip.metadata.deletion_timestamp,
+            eip.allocation_id(),
+            protected(eip),
+            self.attached_to_unselected_node(eip),
+            // Changes once a deferred detachment is due.
+            eip.detach_deadline().map(|deadline| deadline <= Utc::now()),
+            // Changes once the Eip is due to be reported idle or released.
+            self.idle_deadline(eip)
+                .map(|deadline| deadline <= Utc::now()),
+            self.egress_gateway(eip),
+        )))
+    }
+
+    fn on_success(&self, eip: &Self::Resource, intervals: &RequeueIntervals) -> Action {
+        // Come back when a deferred detachment is due, or the Eip is due to
+        // be reported idle or released.
+        match [eip.detach_deadline(), self.idle_deadline(eip)]
+            .into_iter()
+            .flatten()
+            .min()
+            .and_then(|deadline| (deadline - Utc::now()).to_std().ok())
+        {
+            Some(remaining) => Action::requeue(remaining),
+            None => Action::requeue(intervals.success_for(eip)),
+        }
+    }
+
+    fn on_error(
+        self: Arc<Self>,
+        _eip: Arc<Self::Resource>,
+        err: &finalizer::Error<Self::Error>,
+        intervals: &RequeueIntervals,
+        consecutive_failures: u32,
+    ) -> Action {
+        match err {
+            // Retrying can't succeed until EIPs are released or the quota or
+            // budget is raised, so wait as long as any backoff.
+            finalizer::Error::ApplyFailed(err)
+                if matches!(err.reason(), "QuotaExceeded" | "NamespaceBudgetExceeded") =>
+            {
+                Action::requeue(intervals.error_backoff_max)
             }
+            _ => Action::requeue(intervals.error(consecutive_failures)),
+        }
+    }
+
+    fn is_dry_run(&self, err: &Self::Error) -> bool {
+        err.is_dry_run()
+    }
+
+    #[instrument(skip(self, client, eip), err)]
+    async fn observe(&self, client: Client, eip: &Self::Resource) -> Result<(), Self::Error> {
+        let uid = eip.metadata.uid.as_ref().ok_or(Error::MissingEipUid)?;
+        let addresses = self
+            .providers
+            .for_eip(eip)?
+            .describe_by_tag(&eip.namespace().unwrap(), crate::aws::EIP_UID_TAG, uid)
+            .await?;
+        match addresses.len() {
+            0 => event!(Level::INFO, "No EIP is allocated for the Eip."),
             1 => {
-                let allocation_id = addresses[0]
-                    .allocation_id
-                    .as_ref()
-                    .ok_or(Error::MissingAllocationId)?;
-                let public_ip = addresses[0]
-                    .public_ip
-                    .as_ref()
-                    .ok_or(Error::MissingPublicIp)?;
-                (allocation_id.to_owned(), public_ip.to_owned())
-            }
-            _ => {
-                return Err(Error::MultipleEipsTaggedForPod);
+                let allocation = addresses.into_iter().next().unwrap();
+                if drifted(eip, &allocation) {
+                    event!(
+                        Level::WARN,
+                        allocation_id = %allocation.allocation_id,
+                        eni = ?allocation.eni,
+                        private_ip_address = ?allocation.private_ip_address,
+                        "EIP association drifted from its status."
+                    );
+                    crate::metrics::ASSOCIATION_DRIFT.inc();
+                    let note = format!(
+                        "{} is associated with ENI {} and private IP {}",
+                        allocation.allocation_id,
+                        allocation.eni.as_deref().unwrap_or("none"),
+                        allocation.private_ip_address.as_deref().unwrap_or("none"),
+                    );
+                    publish_warning(&client, eip, "AssociationDrifted", "Observe", note).await;
+                }
             }
-        };
-        crate::eip::set_status_created(&api, name, &allocation_id, &public_ip).await?;
+            count => event!(Level::WARN, count, "Multiple EIPs are tagged for the Eip."),
+        }
         Ok(())
     }
 
-    #[instrument(skip(self, _client, _api, eip), err)]
+    #[instrument(skip(self, client, api, eip), err)]
     async fn cleanup(
         &self,
-        _client: Client,
-        _api: Api<Self::Resource>,
+        client: Client,
+        api: Api<Self::Resource>,
         eip: &Self::Resource,
     ) -> Result<(), Self::Error> {
         let name = eip.metadata.name.as_ref().ok_or(Error::MissingEipName)?;
         let uid = eip.metadata.uid.as_ref().ok_or(Error::MissingEipUid)?;
         event!(Level::INFO, name = %name, uid = %uid, "Cleaning up eip.");
-        let addresses = crate::aws::describe_addresses_with_tag_value(
-            &self.ec2_client,
-            crate::aws::EIP_UID_TAG,
-            uid,
-        )
-        .await?
-        .addresses;
-        if let Some(addresses) = addresses {
-            for address in addresses {
-                crate::aws::disassociate_and_release_address(&self.ec2_client, &address).await?;
+        // Keep the finalizer, and so the Eip, until it is unprotected.
+        if let Err(err) = ensure_unprotected(&client, eip, "Release").await {
+            crate::eip::set_status_error(&api, name, &err).await?;
+            return Err(err);
+        }
+        let namespace = eip.namespace().unwrap();
+        let _ = crate::metrics::EIP_IDLE_SINCE.remove_label_values(&[&namespace, name]);
+        let provider = self.providers.for_eip(eip)?;
+        let addresses = provider
+            .describe_by_tag(&namespace, crate::aws::EIP_UID_TAG, uid)
+            .await?;
+        let pod_api = Api::<Pod>::namespaced(client, &namespace);
+        for address in addresses {
+            let transferred = address
+                .tag(crate::aws::TRANSFERRED_FROM_TAG)
+                .map_or(false, |cluster| !cluster.is_empty());
+            if transferred {
+                event!(
+                    Level::INFO,
+                    allocation_id = %address.allocation_id,
+                    "Not releasing an EIP transferred to another cluster."
+                );
+                continue;
             }
+            if self.policy.dns_safety_check
+                && crate::controller::pod::dns_target_in_use(&pod_api, &address.public_ip).await?
+            {
+                return Err(Error::AddressStillInDns(address.public_ip));
+            }
+            provider.release(&namespace, &address).await?;
         }
         Ok(())
     }
 }
+
+/// Whether the Eip's EIP is protected from being disassociated or released.
+pub(crate) fn protected(eip: &Eip) -> bool {
+    eip.annotations()
+        .get(crate::PROTECT_ANNOTATION.as_str())
+        .map_or(false, |protect| protect == "true")
+}
+
+/// Fails with a Warning event if the Eip is protected.
+async fn ensure_unprotected(client: &Client, eip: &Eip, action: &str) -> Result<(), Error> {
+    if !protected(eip) {
+        return Ok(());
+    }
+    let name = eip.name_any();
+    event!(Level::WARN, %name, action, "Not changing the EIP of a protected Eip.");
+    let err = Error::ReleaseProtected(name.clone());
+    publish_warning(client, eip, "ReleaseProtected", action, err.to_string()).await;
+    Err(err)
+}
+
+/// Whether the address is tagged for no Eip.
+fn untagged(address: &Address) -> bool {
+    address
+        .tag(crate::aws::EIP_UID_TAG)
+        .map_or(true, str::is_empty)
+}
+
+/// The allocation ID the Eip should adopt, if it was restored from a snapshot.
+pub(crate) fn adopted_allocation_id(eip: &Eip) -> Option<&str> {
+    eip.annotations()
+        .get(crate::ADOPT_ALLOCATION_ID_ANNOTATION.as_str())
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use eip_operator_shared::controller::Context as _;
+
+    use super::*;
+    use crate::aws::{
+        CLUSTER_NAME_TAG, EIP_NAME_TAG, EIP_UID_TAG, MANAGED_BY, MANAGED_BY_TAG, NAMESPACE_TAG,
+        PROTECTED_TAG, TRANSFERRED_FROM_TAG,
+    };
+    use crate::eip::v2::EipSpec;
+    use crate::eip::{EipHistoryEntry, EipStatus};
+    use crate::mock::{self, MockProvider};
+    use crate::quota::{EipBudgets, EipQuota};
+
+    const UID: &str = "01234567-89ab-cdef-0123-456789abcdef";
+    const OLD_UID: &str = "fedcba98-7654-3210-fedc-ba9876543210";
+    const STATUS_PATH: &str = "/apis/materialize.cloud/v2/namespaces/default/eips/my-eip/status";
+
+    fn eip(annotations: &[(&str, &str)]) -> Eip {
+        let mut eip = Eip::new(
+            "my-eip",
+            EipSpec {
+                selector: EipSelector::Pod {
+                    pod_name: "my-pod".to_owned(),
+                    pod_uid: None,
+                    network: None,
+                },
+                provider: None,
+                dns_name: None,
+                transfer_to: None,
+                tags: None,
+            },
+        );
+        eip.metadata.namespace = Some("default".to_owned());
+        eip.metadata.uid = Some(UID.to_owned());
+        eip.metadata.annotations = Some(
+            annotations
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        );
+        eip
+    }
+
+    /// The Eip, attached to the address through eni-1 and 10.0.0.1.
+    fn attached(mut eip: Eip, allocation_id: &str) -> Eip {
+        eip.status = Some(EipStatus {
+            allocation_id: Some(allocation_id.to_owned()),
+            public_ip_address: Some(mock::address(allocation_id, &[]).public_ip),
+            eni: Some("eni-1".to_owned()),
+            private_ip_address: Some("10.0.0.1".to_owned()),
+            ..Default::default()
+        });
+        eip
+    }
+
+    /// The Eip, allocated the address but unattached since the history entry
+    /// recording the event, that many hours ago.
+    fn idle(mut eip: Eip, allocation_id: &str, event: &str, hours: i64) -> Eip {
+        let time = Utc::now() - k8s_openapi::chrono::Duration::hours(hours);
+        eip.status = Some(EipStatus {
+            allocation_id: Some(allocation_id.to_owned()).filter(|_| event != "IdleReleased"),
+            history: Some(vec![EipHistoryEntry {
+                time: time.to_rfc3339(),
+                event: event.to_owned(),
+                allocation_id: Some(allocation_id.to_owned()),
+                target: None,
+                eni: None,
+                private_ip_address: None,
+            }]),
+            ..Default::default()
+        });
+        eip
+    }
+
+    /// The tags of an address allocated for the Eip by this cluster.
+    fn tags(uid: &str) -> Vec<(&str, &str)> {
+        vec![
+            (EIP_UID_TAG, uid),
+            (CLUSTER_NAME_TAG, "my-cluster"),
+            (NAMESPACE_TAG, "default"),
+            (EIP_NAME_TAG, "my-eip"),
+        ]
+    }
+
+    /// The calls which changed an address other than by tagging it.
+    fn untagged_calls(provider: &MockProvider) -> Vec<String> {
+        provider
+            .calls()
+            .into_iter()
+            .filter(|call| !call.starts_with("tag "))
+            .collect()
+    }
+
+    fn context(provider: &Arc<MockProvider>, policy: AddressPolicy) -> Context {
+        Context::new(
+            Providers::new(provider.clone()),
+            "my-cluster".to_owned(),
+            DefaultTags::default(),
+            policy,
+            NodeIndex::new(false),
+            NamespaceFilter::new(None, HashSet::new()),
+            AllocationLimits::default(),
+        )
+    }
+
+    /// Reconciles the Eip against a Kubernetes API which echoes it back,
+    /// returning the result and the requests made of the API.
+    async fn apply(context: &Context, eip: &Eip) -> (Result<(), Error>, Vec<String>) {
+        let echoed = serde_json::to_value(eip).unwrap();
+        let (client, requests) = mock::client(move |_| echoed.clone());
+        let api = Api::namespaced(client.clone(), "default");
+        let result = context.apply(client, api, eip).await;
+        let requests = requests.lock().unwrap().clone();
+        (result, requests)
+    }
+
+    async fn cleanup(context: &Context, eip: &Eip) -> Result<(), Error> {
+        let echoed = serde_json::to_value(eip).unwrap();
+        let (client, _) = mock::client(move |_| echoed.clone());
+        let api = Api::namespaced(client.clone(), "default");
+        context.cleanup(client, api, eip).await
+    }
+
+    #[tokio::test]
+    async fn apply_allocates_an_address_when_none_is_tagged() {
+        let provider = MockProvider::new([]);
+        let (result, requests) = apply(&context(&provider, Default::default()), &eip(&[])).await;
+        result.unwrap();
+        assert_eq!(provider.calls(), ["allocate eipalloc-new1"]);
+        let address = provider.address("eipalloc-new1").unwrap();
+        assert_eq!(address.tag(EIP_UID_TAG), Some(UID));
+        assert_eq!(address.tag(CLUSTER_NAME_TAG), Some("my-cluster"));
+        assert!(requests.contains(&format!("PATCH {}", STATUS_PATH)));
+    }
+
+    #[tokio::test]
+    async fn apply_defaults_an_unset_selector_before_allocating() {
+        let provider = MockProvider::new([]);
+        let mut eip = eip(&[]);
+        eip.spec.selector = EipSelector::default();
+        let (result, requests) = apply(&context(&provider, Default::default()), &eip).await;
+        result.unwrap();
+        assert!(provider.calls().is_empty());
+        assert_eq!(
+            requests,
+            [format!("PATCH {}", STATUS_PATH.trim_end_matches("/status"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_allocates_only_with_quota_headroom() {
+        let provider = MockProvider::new([]);
+        let mut context = context(&provider, Default::default());
+        context.limits.quota = EipQuota::new(2, HashSet::new());
+        context.limits.quota.update(3, 5.0);
+        let (result, requests) = apply(&context, &eip(&[])).await;
+        assert_eq!(result.unwrap_err().reason(), "QuotaExceeded");
+        assert!(provider.calls().is_empty());
+        assert!(requests.contains(&format!("PATCH {}", STATUS_PATH)));
+
+        context.limits.quota.update(2, 5.0);
+        let (result, _) = apply(&context, &eip(&[])).await;
+        result.unwrap();
+        assert_eq!(provider.calls(), ["allocate eipalloc-new1"]);
+        // The allocation is counted, so another Eip's would eat into the reserve.
+        let mut eip = eip(&[]);
+        eip.metadata.uid = Some(OLD_UID.to_owned());
+        let (result, _) = apply(&context, &eip).await;
+        assert_eq!(result.unwrap_err().reason(), "QuotaExceeded");
+    }
+
+    #[tokio::test]
+    async fn apply_allocates_only_within_the_namespace_budget() {
+        let mut other_namespace = tags(OLD_UID);
+        other_namespace[1] = (NAMESPACE_TAG, "other");
+        let provider = MockProvider::new([
+            mock::address("eipalloc-1", &tags(OLD_UID)),
+            mock::address("eipalloc-2", &other_namespace),
+        ]);
+        let mut context = context(&provider, Default::default());
+        context.limits.budgets = EipBudgets::new(Some(1), Default::default());
+        let (result, _) = apply(&context, &eip(&[])).await;
+        assert_eq!(result.unwrap_err().reason(), "NamespaceBudgetExceeded");
+        assert!(provider.calls().is_empty());
+
+        context.limits.budgets = EipBudgets::new(Some(1), [("default".to_owned(), 2)].into());
+        let (result, _) = apply(&context, &eip(&[])).await;
+        result.unwrap();
+        assert_eq!(provider.calls(), ["allocate eipalloc-new1"]);
+    }
+
+    #[tokio::test]
+    async fn apply_tags_the_address_with_the_tags_of_the_spec() {
+        let provider = MockProvider::new([]);
+        let context = context(&provider, Default::default());
+        let mut eip = eip(&[]);
+        eip.spec.tags = Some([("team".to_owned(), "payments".to_owned())].into());
+        let (result, _) = apply(&context, &eip).await;
+        result.unwrap();
+        let address = provider.address("eipalloc-new1").unwrap();
+        assert_eq!(address.tag("team"), Some("payments"));
+
+        eip.spec.tags = Some([(EIP_UID_TAG.to_owned(), OLD_UID.to_owned())].into());
+        let (result, _) = apply(&context, &eip).await;
+        assert_eq!(result.unwrap_err().reason(), "InvalidTag");
+    }
+
+    #[tokio::test]
+    async fn apply_converges_the_tags_of_the_tagged_address() {
+        let provider = MockProvider::new([mock::address("eipalloc-1", &[(EIP_UID_TAG, UID)])]);
+        let (result, _) = apply(&context(&provider, Default::default()), &eip(&[])).await;
+        result.unwrap();
+        let address = provider.address("eipalloc-1").unwrap();
+        for (key, value) in tags(UID) {
+            assert_eq!(address.tag(key), Some(value), "{}", key);
+        }
+        assert_eq!(address.tag(MANAGED_BY_TAG), Some(MANAGED_BY));
+        assert!(untagged_calls(&provider).is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_retags_the_address_of_its_status() {
+        let untagged: Vec<_> = tags(UID)
+            .into_iter()
+            .filter(|(key, _)| *key != EIP_UID_TAG)
+            .collect();
+        let provider = MockProvider::new([mock::associated(
+            mock::address("eipalloc-1", &untagged),
+            "eni-1",
+            "10.0.0.1",
+        )]);
+        let eip = attached(eip(&[]), "eipalloc-1");
+        let (result, _) = apply(&context(&provider, Default::default()), &eip).await;
+        result.unwrap();
+        assert!(untagged_calls(&provider).is_empty());
+        let address = provider.address("eipalloc-1").unwrap();
+        assert_eq!(address.tag(EIP_UID_TAG), Some(UID));
+    }
+
+    #[tokio::test]
+    async fn apply_leaves_the_address_of_its_status_tagged_for_another_eip() {
+        let provider = MockProvider::new([mock::associated(
+            mock::address("eipalloc-1", &tags(OLD_UID)),
+            "eni-1",
+            "10.0.0.1",
+        )]);
+        let eip = attached(eip(&[]), "eipalloc-1");
+        let (result, _) = apply(&context(&provider, Default::default()), &eip).await;
+        result.unwrap();
+        assert_eq!(untagged_calls(&provider), ["allocate eipalloc-new1"]);
+        let address = provider.address("eipalloc-1").unwrap();
+        assert_eq!(address.tag(EIP_UID_TAG), Some(OLD_UID));
+    }
+
+    #[tokio::test]
+    async fn apply_adopts_the_annotated_address() {
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags(OLD_UID))]);
+        let eip = eip(&[(crate::ADOPT_ALLOCATION_ID_ANNOTATION.as_str(), "eipalloc-1")]);
+        let (result, _) = apply(&context(&provider, Default::default()), &eip).await;
+        result.unwrap();
+        assert_eq!(provider.addresses().len(), 1);
+        assert_eq!(
+            provider.address("eipalloc-1").unwrap().tag(EIP_UID_TAG),
+            Some(UID)
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_refuses_to_adopt_the_address_of_another_cluster() {
+        let mut tags = tags(OLD_UID);
+        tags[1] = (CLUSTER_NAME_TAG, "other-cluster");
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags)]);
+        let eip = eip(&[(crate::ADOPT_ALLOCATION_ID_ANNOTATION.as_str(), "eipalloc-1")]);
+        let (result, requests) = apply(&context(&provider, Default::default()), &eip).await;
+        assert_eq!(result.unwrap_err().reason(), "AddressNotAdoptable");
+        assert!(provider.calls().is_empty());
+        assert!(requests.contains(&format!("PATCH {}", STATUS_PATH)));
+    }
+
+    #[tokio::test]
+    async fn apply_adopts_an_address_by_name_only_when_enabled() {
+        let mut tags = tags(OLD_UID);
+        tags.push((TRANSFERRED_FROM_TAG, "old-cluster"));
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags)]);
+        let (result, _) = apply(&context(&provider, Default::default()), &eip(&[])).await;
+        result.unwrap();
+        assert_eq!(provider.calls()[0], "allocate eipalloc-new1");
+
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags)]);
+        let policy = AddressPolicy {
+            adopt_existing: true,
+            ..Default::default()
+        };
+        let (result, _) = apply(&context(&provider, policy), &eip(&[])).await;
+        result.unwrap();
+        let address = provider.address("eipalloc-1").unwrap();
+        assert_eq!(address.tag(EIP_UID_TAG), Some(UID));
+        assert_eq!(address.tag(TRANSFERRED_FROM_TAG), Some(""));
+        assert_eq!(provider.addresses().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn apply_fails_when_several_addresses_are_tagged() {
+        let provider = MockProvider::new([
+            mock::address("eipalloc-1", &tags(UID)),
+            mock::address("eipalloc-2", &tags(UID)),
+        ]);
+        let (result, _) = apply(&context(&provider, Default::default()), &eip(&[])).await;
+        assert_eq!(result.unwrap_err().reason(), "MultipleEipsTagged");
+        assert_eq!(provider.addresses().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn apply_releases_duplicates_keeping_the_associated_address() {
+        let provider = MockProvider::new([
+            mock::address("eipalloc-1", &tags(UID)),
+            mock::associated(mock::address("eipalloc-2", &tags(UID)), "eni-1", "10.0.0.1"),
+        ]);
+        let policy = AddressPolicy {
+            resolve_duplicates: true,
+            ..Default::default()
+        };
+        let (result, _) = apply(&context(&provider, policy), &eip(&[])).await;
+        result.unwrap();
+        assert_eq!(untagged_calls(&provider), ["release eipalloc-1"]);
+        assert!(provider.address("eipalloc-2").is_some());
+    }
+
+    #[tokio::test]
+    async fn apply_keeps_the_duplicates_of_a_protected_eip() {
+        let provider = MockProvider::new([
+            mock::address("eipalloc-1", &tags(UID)),
+            mock::address("eipalloc-2", &tags(UID)),
+        ]);
+        let policy = AddressPolicy {
+            resolve_duplicates: true,
+            ..Default::default()
+        };
+        let eip = eip(&[(crate::PROTECT_ANNOTATION.as_str(), "true")]);
+        let (result, _) = apply(&context(&provider, policy), &eip).await;
+        assert_eq!(result.unwrap_err().reason(), "MultipleEipsTagged");
+        assert!(provider.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_transfers_the_address_to_another_cluster() {
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags(UID))]);
+        let mut eip = eip(&[]);
+        eip.spec.transfer_to = Some("other-cluster".to_owned());
+        let (result, _) = apply(&context(&provider, Default::default()), &eip).await;
+        result.unwrap();
+        let address = provider.address("eipalloc-1").unwrap();
+        assert_eq!(address.tag(CLUSTER_NAME_TAG), Some("other-cluster"));
+        assert_eq!(address.tag(TRANSFERRED_FROM_TAG), Some("my-cluster"));
+    }
+
+    #[tokio::test]
+    async fn apply_clears_a_drifted_attachment() {
+        let provider = MockProvider::new([mock::associated(
+            mock::address("eipalloc-1", &tags(UID)),
+            "eni-2",
+            "10.0.0.2",
+        )]);
+        let eip = attached(eip(&[]), "eipalloc-1");
+        let (result, requests) = apply(&context(&provider, Default::default()), &eip).await;
+        result.unwrap();
+        let status_patches = requests
+            .iter()
+            .filter(|request| **request == format!("PATCH {}", STATUS_PATH))
+            .count();
+        assert_eq!(status_patches, 2);
+        assert!(untagged_calls(&provider).is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_records_a_failure_after_allocation() {
+        let provider = MockProvider::new([mock::associated(
+            mock::address("eipalloc-1", &tags(UID)),
+            "eni-2",
+            "10.0.0.2",
+        )]);
+        let eip = attached(eip(&[]), "eipalloc-1");
+        let echoed = serde_json::to_value(&eip).unwrap();
+        let status_patches = AtomicUsize::new(0);
+        // Answers the second patch of the status, clearing the drifted
+        // attachment, with something other than an Eip.
+        let (client, requests) = mock::client(move |request| {
+            let status_patch = request.method() == "PATCH" && request.uri().path() == STATUS_PATH;
+            if status_patch && status_patches.fetch_add(1, Ordering::SeqCst) == 1 {
+                serde_json::json!({})
+            } else {
+                echoed.clone()
+            }
+        });
+        let api = Api::namespaced(client.clone(), "default");
+        let context = context(&provider, Default::default());
+        let err = context.apply(client, api, &eip).await.unwrap_err();
+        assert_eq!(err.reason(), "KubernetesError");
+        let status_patches = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| **request == format!("PATCH {}", STATUS_PATH))
+            .count();
+        // Created, drifted, and then the error.
+        assert_eq!(status_patches, 3);
+    }
+
+    #[tokio::test]
+    async fn apply_detaches_once_the_detach_deadline_passes() {
+        let provider = MockProvider::new([mock::associated(
+            mock::address("eipalloc-1", &tags(UID)),
+            "eni-1",
+            "10.0.0.1",
+        )]);
+        let mut eip = attached(eip(&[]), "eipalloc-1");
+        let deadline = (Utc::now() - k8s_openapi::chrono::Duration::seconds(1)).to_rfc3339();
+        eip.status.as_mut().unwrap().detach_deadline = Some(deadline);
+        let (result, _) = apply(&context(&provider, Default::default()), &eip).await;
+        result.unwrap();
+        assert_eq!(untagged_calls(&provider), ["disassociate eipalloc-1"]);
+        assert_eq!(provider.address("eipalloc-1").unwrap().eni, None);
+    }
+
+    #[tokio::test]
+    async fn apply_does_not_detach_a_protected_eip() {
+        let mut tags = tags(UID);
+        tags.push((PROTECTED_TAG, "true"));
+        let provider = MockProvider::new([mock::associated(
+            mock::address("eipalloc-1", &tags),
+            "eni-1",
+            "10.0.0.1",
+        )]);
+        let mut eip = attached(
+            eip(&[(crate::PROTECT_ANNOTATION.as_str(), "true")]),
+            "eipalloc-1",
+        );
+        let deadline = (Utc::now() - k8s_openapi::chrono::Duration::seconds(1)).to_rfc3339();
+        eip.status.as_mut().unwrap().detach_deadline = Some(deadline);
+        let (result, _) = apply(&context(&provider, Default::default()), &eip).await;
+        assert_eq!(result.unwrap_err().reason(), "ReleaseProtected");
+        assert!(untagged_calls(&provider).is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_reports_an_idle_eip_once() {
+        const EVENTS_PATH: &str = "/apis/events.k8s.io/v1/namespaces/default/events";
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags(UID))]);
+        let policy = AddressPolicy {
+            idle_warning_after: Some(Duration::from_secs(86400)),
+            ..Default::default()
+        };
+        let context = context(&provider, policy);
+        let (result, requests) =
+            apply(&context, &idle(eip(&[]), "eipalloc-1", "Allocated", 1)).await;
+        result.unwrap();
+        assert!(!requests.contains(&format!("POST {}", EVENTS_PATH)));
+
+        let (result, requests) =
+            apply(&context, &idle(eip(&[]), "eipalloc-1", "Allocated", 25)).await;
+        result.unwrap();
+        assert!(requests.contains(&format!("POST {}", EVENTS_PATH)));
+        assert!(untagged_calls(&provider).is_empty());
+
+        let mut eip = idle(eip(&[]), "eipalloc-1", "Allocated", 25);
+        let history = eip.status.as_mut().unwrap().history.as_mut().unwrap();
+        history.push(EipHistoryEntry {
+            event: "Idle".to_owned(),
+            ..history[0].clone()
+        });
+        let (result, requests) = apply(&context, &eip).await;
+        result.unwrap();
+        assert!(!requests.contains(&format!("POST {}", EVENTS_PATH)));
+    }
+
+    #[tokio::test]
+    async fn apply_releases_an_address_idle_beyond_the_policy() {
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags(UID))]);
+        let policy = AddressPolicy {
+            idle_release_after: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let context = context(&provider, policy);
+        let protected = idle(
+            eip(&[(crate::PROTECT_ANNOTATION.as_str(), "true")]),
+            "eipalloc-1",
+            "Detached",
+            2,
+        );
+        let (result, _) = apply(&context, &protected).await;
+        result.unwrap();
+        assert!(provider.address("eipalloc-1").is_some());
+
+        let (result, requests) =
+            apply(&context, &idle(eip(&[]), "eipalloc-1", "Detached", 2)).await;
+        result.unwrap();
+        assert_eq!(untagged_calls(&provider), ["release eipalloc-1"]);
+        assert!(requests.contains(&format!("PATCH {}", STATUS_PATH)));
+    }
+
+    #[tokio::test]
+    async fn apply_allocates_no_address_for_an_idle_released_eip_without_a_target() {
+        let provider = MockProvider::new([]);
+        let mut eip = idle(eip(&[]), "eipalloc-1", "IdleReleased", 2);
+        eip.spec.selector = EipSelector::Node {
+            selector: [("role".to_owned(), "egress".to_owned())].into(),
+        };
+        let (result, _) = apply(&context(&provider, Default::default()), &eip).await;
+        result.unwrap();
+        assert!(provider.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_ignores_eips_in_unmanaged_namespaces() {
+        let provider = MockProvider::new([]);
+        let mut context = context(&provider, Default::default());
+        context.namespaces = NamespaceFilter::new(None, HashSet::from(["default".to_owned()]));
+        let (result, _) = apply(&context, &eip(&[])).await;
+        assert_eq!(result.unwrap_err().reason(), "NamespaceNotManaged");
+        assert!(provider.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cleanup_releases_the_tagged_addresses() {
+        let provider = MockProvider::new([
+            mock::address("eipalloc-1", &tags(UID)),
+            mock::address("eipalloc-2", &tags(OLD_UID)),
+        ]);
+        cleanup(&context(&provider, Default::default()), &eip(&[]))
+            .await
+            .unwrap();
+        assert_eq!(provider.calls(), ["release eipalloc-1"]);
+    }
+
+    #[tokio::test]
+    async fn cleanup_keeps_transferred_addresses() {
+        let mut tags = tags(UID);
+        tags.push((TRANSFERRED_FROM_TAG, "my-cluster"));
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags)]);
+        cleanup(&context(&provider, Default::default()), &eip(&[]))
+            .await
+            .unwrap();
+        assert!(provider.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cleanup_keeps_the_address_of_a_protected_eip() {
+        let provider = MockProvider::new([mock::address("eipalloc-1", &tags(UID))]);
+        let eip = eip(&[(crate::PROTECT_ANNOTATION.as_str(), "true")]);
+        let err = cleanup(&context(&provider, Default::default()), &eip)
+            .await
+            .unwrap_err();
+        assert_eq!(err.reason(), "ReleaseProtected");
+        assert!(provider.calls().is_empty());
+    }
+}
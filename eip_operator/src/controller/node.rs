@@ -1,24 +1,204 @@
+use std::sync::Arc;
+
 use k8s_openapi::api::core::v1::Node;
-use kube::api::{Api, ListParams};
-use kube::Client;
+use kube::api::{Api, Patch, PatchParams};
+use kube::{Client, ResourceExt};
 use tracing::{event, instrument, Level};
 
+use eip_operator_shared::controller::{fingerprint_of, paused};
 use eip_operator_shared::Error;
 
+use crate::controller::{ForeignAssociationPolicy, NamespaceFilter};
 use crate::eip::v2::Eip;
+use crate::eip_index::EipIndex;
 use crate::kube_ext::NodeExt;
+use crate::node_index::NodeIndex;
+use crate::provider::Providers;
+
+/// The annotation which stops the cluster autoscaler from removing a node.
+const SCALE_DOWN_DISABLED_ANNOTATION: &str = "cluster-autoscaler.kubernetes.io/scale-down-disabled";
 
 pub(crate) struct Context {
-    ec2_client: aws_sdk_ec2::Client,
+    providers: Providers,
     namespace: Option<String>,
+    eip_index: EipIndex,
+    node_index: NodeIndex,
+    namespaces: NamespaceFilter,
+    karpenter_aware: bool,
+    scale_down_protection: bool,
+    foreign_associations: ForeignAssociationPolicy,
 }
 
 impl Context {
-    pub(crate) fn new(ec2_client: aws_sdk_ec2::Client, namespace: Option<String>) -> Self {
+    pub(crate) fn new(
+        providers: Providers,
+        namespace: Option<String>,
+        eip_index: EipIndex,
+        namespaces: NamespaceFilter,
+        karpenter_aware: bool,
+        scale_down_protection: bool,
+    ) -> Self {
         Self {
-            ec2_client,
+            providers,
             namespace,
+            eip_index,
+            node_index: NodeIndex::default(),
+            namespaces,
+            karpenter_aware,
+            scale_down_protection,
+            foreign_associations: ForeignAssociationPolicy::default(),
+        }
+    }
+
+    /// Sets the index of nodes, through which an Eip already attached to
+    /// another node it selects is left there.
+    pub(crate) fn with_node_index(mut self, node_index: NodeIndex) -> Self {
+        self.node_index = node_index;
+        self
+    }
+
+    /// Sets what is done with addresses associated with a network interface
+    /// their Eip doesn't recognize.
+    pub(crate) fn with_foreign_associations(mut self, policy: ForeignAssociationPolicy) -> Self {
+        self.foreign_associations = policy;
+        self
+    }
+
+    /// Checks whether an Eip selecting the node is attached to it.
+    fn holds_eip(&self, node: &Node) -> bool {
+        let namespace = self.namespace.as_deref().unwrap_or("default");
+        let node_labels = match node.labels() {
+            Some(node_labels) => node_labels,
+            None => return false,
+        };
+        self.eip_index
+            .find_for_node(namespace, node_labels)
+            .unwrap_or_default()
+            .iter()
+            .any(|eip| eip.attachment().2.map_or(false, |ip| node.has_ip(ip)))
+    }
+
+    /// The other node the Eip is attached to, if that node still matches the
+    /// Eip's selector and isn't being disrupted. Such an Eip is left where it
+    /// is, rather than every node it selects taking it in turn.
+    fn held_elsewhere(&self, node: &Node, eip: &Eip) -> Option<String> {
+        let ip = eip.attachment().2?;
+        if node.has_ip(ip) || self.node_index.disrupting_ip(ip) {
+            return None;
+        }
+        let labels = self.node_index.labels_for_ip(ip)?;
+        if !eip.matches_node(&labels) {
+            return None;
+        }
+        self.node_index.name_for_ip(ip)
+    }
+
+    /// The node's ENI and private IP, for the one Eip which doesn't ask for
+    /// a dedicated network interface.
+    async fn node_target(&self, node: &Node, eip: &Eip) -> Result<(String, String), Error> {
+        let node_ip = node.ip().ok_or(Error::MissingNodeIp)?;
+        let instance_id = crate::node_index::instance_id(node)?;
+        let eni_id = self
+            .providers
+            .for_eip(eip)?
+            .network_interface(instance_id, node_ip)
+            .await?;
+        Ok((eni_id, node_ip.to_owned()))
+    }
+
+    /// The network interface dedicated to node EIPs on the node, and a
+    /// private IP of it for each of the Eips, assigning the interface a
+    /// secondary private IP for each Eip beyond the first.
+    async fn dedicated_targets(
+        &self,
+        node_api: &Api<Node>,
+        node: &Node,
+        eips: &[Arc<Eip>],
+    ) -> Result<Vec<Result<(String, String), Error>>, Error> {
+        let node_name = node.metadata.name.as_ref().ok_or(Error::MissingNodeName)?;
+        let instance_id = crate::node_index::instance_id(node)?;
+        let (eni_id, ips) = self
+            .providers
+            .for_eip(&eips[0])?
+            .dedicated_network_interface(instance_id, eips.len())
+            .await?;
+        if node.dedicated_eni_ips() != ips {
+            set_dedicated_eni_ips(node_api, node_name, &ips).await?;
+        }
+        Ok(assign_private_ips(eips, &ips)
+            .into_iter()
+            .zip(eips)
+            .map(|(ip, eip)| match ip {
+                Some(ip) => Ok((eni_id.clone(), ip.to_owned())),
+                None => Err(Error::NoFreePrivateIp(eip.name_any())),
+            })
+            .collect())
+    }
+
+    /// Associates the Eip's address with the network interface and private
+    /// IP of its target, recording any failure in the Eip's status.
+    async fn attach(
+        &self,
+        client: &Client,
+        eip_api: &Api<Eip>,
+        node: &Node,
+        eip: &Eip,
+        target: Result<(String, String), Error>,
+    ) -> Result<(), Error> {
+        let eip_name = eip.name().ok_or(Error::MissingEipName)?;
+        let allocation_id = eip.allocation_id().ok_or(Error::MissingAllocationId)?;
+        let result = match target {
+            Ok((eni_id, private_ip)) => {
+                self.associate(
+                    client,
+                    eip_api,
+                    node,
+                    eip,
+                    allocation_id,
+                    &eni_id,
+                    &private_ip,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            if err.is_allocation_not_found() {
+                crate::eip::set_status_allocation_lost(eip_api, eip).await?;
+            }
+            crate::eip::set_status_error(eip_api, eip_name, &err).await?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Associates the Eip's address with the network interface and private IP.
+    async fn associate(
+        &self,
+        client: &Client,
+        eip_api: &Api<Eip>,
+        node: &Node,
+        eip: &Eip,
+        allocation_id: &str,
+        eni_id: &str,
+        private_ip: &str,
+    ) -> Result<(), Error> {
+        let node_name = node.metadata.name.as_ref().ok_or(Error::MissingNodeName)?;
+        let namespace = self.namespace.as_deref().unwrap_or("default");
+        let provider = self.providers.for_eip(eip)?;
+        let address = provider.describe(namespace, allocation_id).await?;
+        if address.eni.as_deref() != Some(eni_id)
+            || address.private_ip_address.as_deref() != Some(private_ip)
+        {
+            self.foreign_associations
+                .check(client, eip, &address, eni_id)
+                .await?;
+            provider
+                .associate(namespace, allocation_id, eni_id, private_ip)
+                .await?;
         }
+        crate::eip::set_status_attached(eip_api, eip, node_name, eni_id, private_ip).await?;
+        Ok(())
     }
 }
 
@@ -27,54 +207,182 @@ impl eip_operator_shared::controller::Context for Context {
     type Resource = Node;
     type Error = Error;
 
-    const FINALIZER_NAME: &'static str = "eip.materialize.cloud/disassociate_node";
+    const FINALIZER_NAME: &'static str = "disassociate_node";
 
-    #[instrument(skip(self, client, _api, node), err)]
+    #[instrument(skip(self, client, api, node), err)]
     async fn apply(
         &self,
         client: Client,
-        _api: Api<Self::Resource>,
+        api: Api<Self::Resource>,
         node: &Self::Resource,
     ) -> Result<(), Self::Error> {
         let name = node.metadata.name.as_ref().ok_or(Error::MissingNodeName)?;
         event!(Level::INFO, name = %name, "Applying node.");
 
-        let eip_api = Api::<Eip>::namespaced(
-            client.clone(),
-            self.namespace.as_deref().unwrap_or("default"),
-        );
+        let namespace = self.namespace.as_deref().unwrap_or("default");
+        if !self.namespaces.allows(namespace) {
+            return Err(Error::NamespaceNotManaged(namespace.to_owned()));
+        }
+        let eip_api = Api::<Eip>::namespaced(client.clone(), namespace);
+
+        // Attaching an Eip updates its status, which reconciles the node
+        // again, so the annotation follows the attachment.
+        let protect = self.holds_eip(node);
+        if self.scale_down_protection && scale_down_disabled(node) != protect {
+            event!(
+                Level::INFO,
+                protect,
+                "Updating the node's scale-down protection."
+            );
+            set_scale_down_disabled(&api, name, protect).await?;
+        }
+
+        if self.karpenter_aware && node.disrupting() {
+            // The Eip reconciler moves any EIP it holds to another node.
+            event!(
+                Level::INFO,
+                "Not attaching an EIP to a node being disrupted."
+            );
+            return Ok(());
+        }
 
-        let node_ip = node.ip().ok_or(Error::MissingNodeIp)?;
         let node_labels = node.labels().ok_or(Error::MissingNodeLabels)?;
-        let provider_id = node.provider_id().ok_or(Error::MissingProviderId)?;
-        let instance_id = provider_id
-            .rsplit_once('/')
-            .ok_or(Error::MalformedProviderId)?
-            .1;
-        let all_eips = eip_api.list(&ListParams::default()).await?.items;
-        let eip = all_eips
-            .into_iter()
-            .find(|eip| eip.matches_node(node_labels))
-            .ok_or(Error::NoEipResourceWithThatNodeSelector)?;
-        let eip_name = eip.name().ok_or(Error::MissingEipName)?;
-        let allocation_id = eip.allocation_id().ok_or(Error::MissingAllocationId)?;
-        let eip_description = crate::aws::describe_address(&self.ec2_client, allocation_id)
-            .await?
-            .addresses
-            .ok_or(Error::MissingAddresses)?
-            .swap_remove(0);
-        let instance_description =
-            crate::aws::describe_instance(&self.ec2_client, instance_id).await?;
-
-        let eni_id = crate::aws::get_eni_from_private_ip(&instance_description, node_ip)
-            .ok_or(Error::NoInterfaceWithThatIp)?;
-        if eip_description.network_interface_id != Some(eni_id.to_owned())
-            || eip_description.private_ip_address != Some(node_ip.to_owned())
-        {
-            crate::aws::associate_eip(&self.ec2_client, allocation_id, &eni_id, node_ip).await?;
+        let mut eips = self.eip_index.find_for_node(namespace, node_labels)?;
+        if eips.is_empty() {
+            return Err(Error::NoEipResourceWithThatNodeSelector);
+        }
+        eips.retain(|eip| match self.held_elsewhere(node, eip) {
+            Some(holder) => {
+                event!(
+                    Level::INFO,
+                    eip = %eip.name_any(),
+                    node = %holder,
+                    "Leaving the EIP attached to another node the Eip selects."
+                );
+                false
+            }
+            None => true,
+        });
+        // Sorted, so that the node's private IPs are handed out
+        // deterministically.
+        eips.sort_by_key(|eip| eip.name_any());
+        let (dedicated, shared): (Vec<_>, Vec<_>) =
+            eips.into_iter().partition(|eip| dedicated_eni(eip));
+        let mut targets = vec![];
+        let mut result = Ok(());
+        // Only one Eip can have the node's own private IP, so the others
+        // record why they are left unattached.
+        let mut shared = shared.into_iter();
+        if let Some(eip) = shared.next() {
+            for other in shared {
+                let err = Error::NodeIpTaken {
+                    node: name.to_owned(),
+                    eip: eip.name_any(),
+                };
+                crate::eip::set_status_error(&eip_api, &other.name_any(), &err).await?;
+                result = result.and(Err(err));
+            }
+            let target = self.node_target(node, &eip).await;
+            targets.push((eip, target));
+        }
+        if !dedicated.is_empty() {
+            match self.dedicated_targets(&api, node, &dedicated).await {
+                Ok(dedicated_targets) => {
+                    targets.extend(dedicated.into_iter().zip(dedicated_targets))
+                }
+                Err(err) => {
+                    for eip in &dedicated {
+                        crate::eip::set_status_error(&eip_api, &eip.name_any(), &err).await?;
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        for (eip, target) in targets {
+            if paused(&*eip) {
+                event!(
+                    Level::INFO,
+                    eip = %eip.name_any(),
+                    "Not associating the EIP of a paused Eip."
+                );
+                continue;
+            }
+            // Keep attaching the other Eips, but report the first failure.
+            result = result.and(self.attach(&client, &eip_api, node, &eip, target).await);
         }
-        crate::eip::set_status_attached(&eip_api, eip_name, &eni_id, node_ip).await?;
+        result
+    }
+
+    fn fingerprint(&self, node: &Self::Resource) -> Option<u64> {
+        // Include the attachments of matching Eips, so that a node whose Eip
+        // was found to have drifted is reconciled again.
+        let namespace = self.namespace.as_deref().unwrap_or("default");
+        let attachments: Vec<_> = node
+            .labels()
+            .and_then(|labels| self.eip_index.find_for_node(namespace, labels).ok())
+            .unwrap_or_default()
+            .iter()
+            .map(|eip| (eip.attachment(), paused(&**eip)))
+            .collect();
+        Some(fingerprint_of(&(
+            &node.metadata.labels,
+            &node.metadata.finalizers,
+            &node.metadata.deletion_timestamp,
+            node.ip(),
+            node.dedicated_eni_ips(),
+            node.provider_id(),
+            node.disrupting(),
+            scale_down_disabled(node),
+            attachments,
+        )))
+    }
 
+    fn is_dry_run(&self, err: &Self::Error) -> bool {
+        err.is_dry_run()
+    }
+
+    #[instrument(skip(self, _client, node), err)]
+    async fn observe(&self, _client: Client, node: &Self::Resource) -> Result<(), Self::Error> {
+        let namespace = self.namespace.as_deref().unwrap_or("default");
+        let node_labels = match node.labels() {
+            Some(node_labels) if self.namespaces.allows(namespace) => node_labels,
+            _ => return Ok(()),
+        };
+        let eips = self.eip_index.find_for_node(namespace, node_labels)?;
+        if eips.is_empty() {
+            event!(Level::INFO, "No Eip selects the node.");
+        }
+        for eip in eips {
+            let allocation_id = match eip.allocation_id() {
+                Some(allocation_id) => allocation_id,
+                None => {
+                    event!(
+                        Level::INFO,
+                        eip = %eip.name_any(),
+                        "The node's Eip has no EIP allocated."
+                    );
+                    continue;
+                }
+            };
+            let address = self
+                .providers
+                .for_eip(&eip)?
+                .describe(namespace, allocation_id)
+                .await?;
+            if !address
+                .private_ip_address
+                .as_deref()
+                .map_or(false, |ip| node.has_ip(ip))
+            {
+                event!(
+                    Level::WARN,
+                    %allocation_id,
+                    private_ip_address = ?address.private_ip_address,
+                    "The node's EIP is not associated with it."
+                );
+            }
+        }
         Ok(())
     }
 
@@ -88,34 +396,263 @@ impl eip_operator_shared::controller::Context for Context {
         let name = node.metadata.name.as_ref().ok_or(Error::MissingNodeName)?;
         event!(Level::INFO, name = %name, "Cleaning up node.");
 
-        let eip_api = Api::<Eip>::namespaced(
-            client.clone(),
-            self.namespace.as_deref().unwrap_or("default"),
-        );
+        let namespace = self.namespace.as_deref().unwrap_or("default");
+        let eip_api = Api::<Eip>::namespaced(client.clone(), namespace);
 
         let node_labels = node.labels().ok_or(Error::MissingNodeLabels)?;
-        let all_eips = eip_api.list(&ListParams::default()).await?.items;
-        let eip = all_eips
+        let eips = self
+            .eip_index
+            .find_for_node(namespace, node_labels)?
             .into_iter()
-            .filter(|eip| eip.attached())
-            .find(|eip| eip.matches_node(node_labels));
-        if let Some(eip) = eip {
+            .filter(|eip| eip.attachment().2.map_or(false, |ip| node.has_ip(ip)));
+        for eip in eips {
             let allocation_id = eip.allocation_id().ok_or(Error::MissingAllocationId)?;
-            let addresses = crate::aws::describe_address(&self.ec2_client, allocation_id)
-                .await?
-                .addresses
-                .ok_or(Error::MissingAddresses)?;
-            for address in addresses {
-                if let Some(association_id) = address.association_id {
-                    crate::aws::disassociate_eip(&self.ec2_client, &association_id).await?;
-                }
-            }
-            crate::eip::set_status_detached(
-                &eip_api,
-                eip.metadata.name.as_ref().ok_or(Error::MissingEipName)?,
-            )
-            .await?;
+            self.providers
+                .for_eip(&eip)?
+                .disassociate(namespace, allocation_id)
+                .await?;
+            crate::eip::set_status_detached(&eip_api, &eip).await?;
         }
         Ok(())
     }
 }
+
+/// Whether the Eip's address should be associated with a network interface
+/// dedicated to node EIPs, rather than the node's primary one.
+fn dedicated_eni(eip: &Eip) -> bool {
+    eip.annotations()
+        .get(crate::DEDICATED_ENI_ANNOTATION.as_str())
+        .map_or(false, |dedicated| dedicated == "true")
+}
+
+/// Gives each Eip one of the private IPs: the one it is attached to, if no
+/// Eip before it was, or else the first one left.
+fn assign_private_ips<'a>(eips: &[Arc<Eip>], ips: &'a [String]) -> Vec<Option<&'a str>> {
+    let mut taken = vec![false; ips.len()];
+    let mut assigned: Vec<Option<&str>> = eips
+        .iter()
+        .map(|eip| {
+            let index = ips
+                .iter()
+                .position(|ip| eip.attachment().2 == Some(ip.as_str()))?;
+            if taken[index] {
+                return None;
+            }
+            taken[index] = true;
+            Some(ips[index].as_str())
+        })
+        .collect();
+    for slot in assigned.iter_mut().filter(|slot| slot.is_none()) {
+        if let Some(index) = taken.iter().position(|taken| !taken) {
+            taken[index] = true;
+            *slot = Some(ips[index].as_str());
+        }
+    }
+    assigned
+}
+
+/// Records the private IPs of the node's dedicated network interface, so
+/// that addresses associated with them are known to be on the node. The
+/// annotation is merge-patched, leaving the ones applied server-side alone.
+#[instrument(skip(api), err)]
+async fn set_dedicated_eni_ips(
+    api: &Api<Node>,
+    name: &str,
+    ips: &[String],
+) -> Result<Node, kube::Error> {
+    let annotation = crate::DEDICATED_ENI_IPS_ANNOTATION.as_str();
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                annotation: ips.join(","),
+            }
+        }
+    });
+    api.patch(
+        name,
+        &eip_operator_shared::patch_params(),
+        &Patch::Merge(&patch),
+    )
+    .await
+}
+
+fn scale_down_disabled(node: &Node) -> bool {
+    node.annotations()
+        .get(SCALE_DOWN_DISABLED_ANNOTATION)
+        .map_or(false, |value| value == "true")
+}
+
+/// Annotates the node so that the cluster autoscaler doesn't remove it, or
+/// removes the annotation again. As the patch is applied server-side, an
+/// annotation set by anything else is left alone.
+#[instrument(skip(api), err)]
+async fn set_scale_down_disabled(
+    api: &Api<Node>,
+    name: &str,
+    disabled: bool,
+) -> Result<Node, kube::Error> {
+    let annotations = match disabled {
+        true => serde_json::json!({ SCALE_DOWN_DISABLED_ANNOTATION: "true" }),
+        false => serde_json::json!({}),
+    };
+    let patch = serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "Node",
+        "metadata": {
+            "annotations": annotations
+        }
+    });
+    let patch = Patch::Apply(&patch);
+    let params = PatchParams::apply(eip_operator_shared::metadata_domain());
+    api.patch(name, &params, &patch).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+
+    use eip_operator_shared::controller::Context as _;
+    use k8s_openapi::api::core::v1::{NodeAddress, NodeSpec, NodeStatus};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use kube_runtime::watcher;
+
+    use super::*;
+    use crate::eip::v2::{EipSelector, EipSpec};
+    use crate::eip::EipStatus;
+    use crate::mock::{self, MockProvider};
+
+    /// A node Eip, attached to the private IP if given one.
+    fn eip(name: &str, private_ip: Option<&str>) -> Arc<Eip> {
+        let mut eip = Eip::new(
+            name,
+            EipSpec {
+                selector: EipSelector::Node {
+                    selector: Default::default(),
+                },
+                provider: None,
+                dns_name: None,
+                transfer_to: None,
+                tags: None,
+            },
+        );
+        eip.status = private_ip.map(|private_ip| EipStatus {
+            private_ip_address: Some(private_ip.to_owned()),
+            ..Default::default()
+        });
+        Arc::new(eip)
+    }
+
+    #[test]
+    fn private_ips_stick_to_their_eips_and_the_rest_are_handed_out_in_order() {
+        let ips = vec![
+            "10.0.0.1".to_owned(),
+            "10.0.0.2".to_owned(),
+            "10.0.0.3".to_owned(),
+        ];
+        let eips = [
+            eip("a", None),
+            eip("b", Some("10.0.0.1")),
+            eip("c", Some("10.0.0.1")),
+            eip("d", Some("10.0.0.9")),
+        ];
+        assert_eq!(
+            assign_private_ips(&eips, &ips),
+            vec![Some("10.0.0.2"), Some("10.0.0.1"), Some("10.0.0.3"), None],
+        );
+    }
+
+    /// A node with the role label, whose instance and internal IP are named
+    /// after it.
+    fn node(name: &str, ip: &str, role: &str) -> Node {
+        Node {
+            metadata: ObjectMeta {
+                name: Some(name.to_owned()),
+                labels: Some(BTreeMap::from([("role".to_owned(), role.to_owned())])),
+                ..Default::default()
+            },
+            spec: Some(NodeSpec {
+                provider_id: Some(format!("aws:///us-east-1a/i-{}", name)),
+                ..Default::default()
+            }),
+            status: Some(NodeStatus {
+                addresses: Some(vec![NodeAddress {
+                    type_: "InternalIP".to_owned(),
+                    address: ip.to_owned(),
+                }]),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// An Eip selecting egress nodes, attached to node-a.
+    fn egress_eip() -> Eip {
+        let mut eip = Eip::new(
+            "egress",
+            EipSpec {
+                selector: EipSelector::Node {
+                    selector: BTreeMap::from([("role".to_owned(), "egress".to_owned())]),
+                },
+                provider: None,
+                dns_name: None,
+                transfer_to: None,
+                tags: None,
+            },
+        );
+        eip.metadata.namespace = Some("default".to_owned());
+        eip.status = Some(EipStatus {
+            allocation_id: Some("eipalloc-1".to_owned()),
+            eni: Some("eni-i-node-a".to_owned()),
+            private_ip_address: Some("10.0.0.1".to_owned()),
+            ..Default::default()
+        });
+        eip
+    }
+
+    /// Reconciles node-b, with node-a labelled with the role, against an
+    /// Eip attached to node-a, returning the calls made of the provider.
+    async fn apply_node_b(node_a_role: &str) -> Vec<String> {
+        let eip = egress_eip();
+        let provider = MockProvider::new([mock::associated(
+            mock::address("eipalloc-1", &[]),
+            "eni-i-node-a",
+            "10.0.0.1",
+        )]);
+        let eip_index = EipIndex::default();
+        eip_index.apply(watcher::Event::Restarted(vec![eip.clone()]));
+        let node_b = node("node-b", "10.0.0.2", "egress");
+        let node_index = NodeIndex::default();
+        node_index.apply(watcher::Event::Restarted(vec![
+            node("node-a", "10.0.0.1", node_a_role),
+            node_b.clone(),
+        ]));
+        let context = Context::new(
+            Providers::new(provider.clone()),
+            None,
+            eip_index,
+            NamespaceFilter::new(None, HashSet::new()),
+            false,
+            false,
+        )
+        .with_node_index(node_index);
+        let echoed = serde_json::to_value(&eip).unwrap();
+        let (client, _) = mock::client(move |_| echoed.clone());
+        context
+            .apply(client.clone(), Api::all(client), &node_b)
+            .await
+            .unwrap();
+        provider.calls()
+    }
+
+    #[tokio::test]
+    async fn eips_stay_on_another_node_they_still_select() {
+        assert!(apply_node_b("egress").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn eips_move_off_nodes_they_no_longer_select() {
+        assert_eq!(
+            apply_node_b("other").await,
+            ["associate eipalloc-1 eni-i-node-b 10.0.0.2"]
+        );
+    }
+}
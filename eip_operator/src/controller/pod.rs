@@ -1,21 +1,214 @@
 use k8s_openapi::api::core::v1::{Node, Pod};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use k8s_openapi::chrono::{self, Utc};
 use kube::api::{Api, ListParams, Patch, PatchParams};
 use kube::{Client, ResourceExt};
+use kube_runtime::controller::Action;
 use serde::Deserialize;
 use tracing::{event, instrument, Level};
 
-use eip_operator_shared::Error;
+use eip_operator_shared::controller::{fingerprint_of, paused, RequeueIntervals};
+use eip_operator_shared::{Error, MANAGE_EIP_LABEL};
 
+use crate::autocreate::Autocreate;
+use crate::controller::{ForeignAssociationPolicy, NamespaceFilter};
 use crate::eip::v2::Eip;
-use crate::kube_ext::{NodeExt, PodExt};
+use crate::eip_index::EipIndex;
+use crate::kube_ext::PodExt;
+use crate::node_index::NodeIndex;
+use crate::provider::Providers;
+
+/// How soon a pod which isn't ready to have its EIP associated is checked again.
+const NOT_READY_REQUEUE: Duration = Duration::from_secs(5);
+
+/// The name of the extended resource requested by pods using per-pod security
+/// groups, and of the annotation describing the branch ENI they are given.
+const POD_ENI: &str = "vpc.amazonaws.com/pod-eni";
+
+/// The annotation which stops Karpenter from voluntarily disrupting a pod, and
+/// so from consolidating away its node.
+const KARPENTER_DO_NOT_DISRUPT_ANNOTATION: &str = "karpenter.sh/do-not-disrupt";
+
+/// The annotation Multus sets on pods with the IPs of each of their networks.
+const NETWORK_STATUS: &str = "k8s.v1.cni.cncf.io/network-status";
+
+/// Removing the operator's scheduling gate from pods once their Eip has an
+/// address, so they aren't scheduled before the address exists.
+#[derive(Clone, Debug)]
+pub(crate) struct SchedulingGate {
+    /// Labels added to the pod's node selector as the gate is removed, to
+    /// constrain which nodes it is placed on.
+    pub(crate) node_selector: HashMap<String, String>,
+}
+
+/// The metadata set on pods alongside the external-dns target annotation.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PodMetadata {
+    /// Whether pods holding an EIP are marked with Karpenter's do-not-disrupt
+    /// annotation.
+    pub(crate) karpenter_aware: bool,
+    /// Whether the Eip's DNS name is set as the external-dns hostname.
+    pub(crate) external_dns_hostname: bool,
+    /// The label the public IP is set in, if any.
+    pub(crate) public_ip_label: Option<String>,
+}
 
 pub(crate) struct Context {
-    ec2_client: aws_sdk_ec2::Client,
+    providers: Providers,
+    eip_index: EipIndex,
+    node_index: NodeIndex,
+    detach_grace: Duration,
+    namespaces: NamespaceFilter,
+    scheduling_gate: Option<SchedulingGate>,
+    metadata: PodMetadata,
+    autocreate: Autocreate,
+    foreign_associations: ForeignAssociationPolicy,
 }
 
 impl Context {
-    pub(crate) fn new(ec2_client: aws_sdk_ec2::Client) -> Self {
-        Self { ec2_client }
+    pub(crate) fn new(
+        providers: Providers,
+        eip_index: EipIndex,
+        node_index: NodeIndex,
+        detach_grace: Duration,
+        namespaces: NamespaceFilter,
+        scheduling_gate: Option<SchedulingGate>,
+        metadata: PodMetadata,
+    ) -> Self {
+        Self {
+            providers,
+            eip_index,
+            node_index,
+            detach_grace,
+            namespaces,
+            scheduling_gate,
+            metadata,
+            autocreate: Autocreate::default(),
+            foreign_associations: ForeignAssociationPolicy::default(),
+        }
+    }
+
+    /// Sets how Eips are created for pods with the autocreate label.
+    pub(crate) fn with_autocreate(mut self, autocreate: Autocreate) -> Self {
+        self.autocreate = autocreate;
+        self
+    }
+
+    /// Sets what is done with addresses associated with a network interface
+    /// their Eip doesn't recognize.
+    pub(crate) fn with_foreign_associations(mut self, policy: ForeignAssociationPolicy) -> Self {
+        self.foreign_associations = policy;
+        self
+    }
+
+    /// Associates the Eip's address with the pod's ENI and private IP, or for
+    /// pods on the host network, the node's, and returns the public IP of the
+    /// address. Eips naming a network attachment get the pod's IP on it, and
+    /// the ENI that IP belongs to.
+    async fn associate(
+        &self,
+        client: &Client,
+        node_api: &Api<Node>,
+        eip_api: &Api<Eip>,
+        pod: &Pod,
+        eip: &Eip,
+        allocation_id: &str,
+    ) -> Result<String, Error> {
+        let pod_name = pod.metadata.name.as_ref().ok_or(Error::MissingPodName)?;
+        let private_ip = private_ip(pod, eip)?;
+        let pod_ip = private_ip.as_str();
+        let node_name = pod.node_name().ok_or(Error::MissingNodeName)?;
+
+        let instance_id = self.node_index.instance_id(node_api, node_name).await?;
+        let namespace = eip.namespace().unwrap_or_default();
+        let provider = self.providers.for_eip(eip)?;
+
+        // Pods on the host network have no branch ENI of their own, so any
+        // pod-eni annotation is ignored, and the node's primary network
+        // interface is found by its primary private IP. The branch ENI is
+        // only on the pod's default network, too.
+        let eni_id = match get_eni_id_from_annotation(pod)
+            .filter(|_| !pod.host_network() && eip.network().is_none())
+        {
+            Some(eni_id) => eni_id,
+            None => provider.network_interface(&instance_id, pod_ip).await?,
+        };
+
+        let address = provider.describe(&namespace, allocation_id).await?;
+        if address.eni.as_deref() != Some(eni_id.as_str())
+            || address.private_ip_address.as_deref() != Some(pod_ip)
+        {
+            self.foreign_associations
+                .check(client, eip, &address, &eni_id)
+                .await?;
+            provider
+                .associate(&namespace, allocation_id, &eni_id, pod_ip)
+                .await?;
+        }
+        crate::eip::set_status_attached(eip_api, eip, pod_name, &eni_id, pod_ip).await?;
+        Ok(address.public_ip)
+    }
+
+    /// Attaches the Eip's address to the pod, recording any error in the
+    /// Eip's status, and returns the public IP of the address. Paused Eips
+    /// are left alone, so have none.
+    async fn attach(
+        &self,
+        client: &Client,
+        node_api: &Api<Node>,
+        eip_api: &Api<Eip>,
+        pod: &Pod,
+        eip: &Eip,
+    ) -> Result<Option<String>, Error> {
+        if paused(eip) {
+            event!(Level::INFO, "Not associating the EIP of a paused Eip.");
+            return Ok(None);
+        }
+        let name = pod.metadata.name.as_ref().ok_or(Error::MissingPodName)?;
+        let eip_name = eip.name().ok_or(Error::MissingEipName)?;
+        let pod_uid = pod.metadata.uid.as_ref().ok_or(Error::MissingPodUid)?;
+        if !eip.matches_pod_uid(pod_uid) {
+            return Err(Error::PodUidMismatch {
+                eip: eip_name.to_owned(),
+                pod: name.to_owned(),
+                pod_uid: pod_uid.to_owned(),
+            });
+        }
+        let allocation_id = eip.allocation_id().ok_or(Error::MissingAllocationId)?;
+        match self
+            .associate(client, node_api, eip_api, pod, eip, allocation_id)
+            .await
+        {
+            Ok(public_ip) => Ok(Some(public_ip)),
+            Err(err) => {
+                if err.is_allocation_not_found() {
+                    crate::eip::set_status_allocation_lost(eip_api, eip).await?;
+                }
+                crate::eip::set_status_error(eip_api, eip_name, &err).await?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Disassociates any address tagged for the Eip, for Eips whose status
+    /// never recorded an allocation, so the pod can still be deleted.
+    async fn disassociate_tagged(&self, eip: &Eip) -> Result<(), Error> {
+        let uid = eip.metadata.uid.as_ref().ok_or(Error::MissingEipUid)?;
+        let namespace = eip.namespace().unwrap_or_default();
+        let provider = self.providers.for_eip(eip)?;
+        let addresses = provider
+            .describe_by_tag(&namespace, crate::aws::EIP_UID_TAG, uid)
+            .await?;
+        for address in addresses {
+            if address.association_id.is_some() {
+                provider
+                    .disassociate(&namespace, &address.allocation_id)
+                    .await?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -24,7 +217,7 @@ impl eip_operator_shared::controller::Context for Context {
     type Resource = Pod;
     type Error = Error;
 
-    const FINALIZER_NAME: &'static str = "eip.materialize.cloud/disassociate";
+    const FINALIZER_NAME: &'static str = "disassociate";
 
     #[instrument(skip(self, client, api, pod), err)]
     async fn apply(
@@ -36,56 +229,184 @@ impl eip_operator_shared::controller::Context for Context {
         let name = pod.metadata.name.as_ref().ok_or(Error::MissingPodName)?;
         event!(Level::INFO, name = %name, "Applying pod.");
 
+        if !self.namespaces.allows(&pod.namespace().unwrap()) {
+            event!(
+                Level::INFO,
+                "Ignoring pod in a namespace which isn't managed."
+            );
+            return Ok(());
+        }
+
         let eip_api = Api::<Eip>::namespaced(client.clone(), &pod.namespace().unwrap());
         let node_api = Api::<Node>::all(client.clone());
 
         if should_autocreate_eip(pod) {
             event!(Level::INFO, should_autocreate_eip = true);
-            crate::eip::create_for_pod(&eip_api, name).await?;
+            crate::eip::create_for_pod(&eip_api, &self.autocreate.eip_for(pod)?).await?;
         }
 
-        let pod_ip = pod.ip().ok_or(Error::MissingPodIp)?;
-        let node_name = pod.node_name().ok_or(Error::MissingNodeName)?;
+        if let Some(gate) = &self.scheduling_gate {
+            if is_scheduling_gated(pod) {
+                let eips = self
+                    .eip_index
+                    .find_all_for_pod(&pod.namespace().unwrap(), name)?;
+                let public_ips: Option<Vec<&str>> =
+                    eips.iter().map(|eip| eip.public_ip_address()).collect();
+                match public_ips.as_deref() {
+                    Some([public_ip, ..]) => {
+                        event!(Level::INFO, "Removing the pod's scheduling gate.");
+                        remove_scheduling_gate(&api, name, public_ip, &gate.node_selector).await?;
+                    }
+                    _ => event!(Level::DEBUG, "Pod's Eips have no addresses yet."),
+                }
+                return Ok(());
+            }
+        }
 
-        let node = node_api.get(node_name).await?;
+        if !ready_for_association(pod) {
+            // Pods routinely lack these while their containers are created,
+            // so requeue quietly instead of reporting an error.
+            event!(Level::DEBUG, "Pod has no IP or node yet.");
+            return Ok(());
+        }
 
-        let provider_id = node.provider_id().ok_or(Error::MissingProviderId)?;
-        let instance_id = provider_id
-            .rsplit_once('/')
-            .ok_or(Error::MalformedProviderId)?
-            .1;
+        let eips = self
+            .eip_index
+            .find_all_for_pod(&pod.namespace().unwrap(), name)?;
+        if eips.is_empty() {
+            // Once its Eip is gone, the pod's address may be given to
+            // something else, so the pod shouldn't claim it any more.
+            if has_public_ip_metadata(pod, self.metadata.public_ip_label.as_deref()) {
+                event!(Level::INFO, "Removing the pod's public IP.");
+                remove_public_ip_metadata(&api, pod, self.metadata.public_ip_label.as_deref())
+                    .await?;
+            }
+            return Err(Error::NoEipResourceWithThatPodName(name.to_owned()));
+        }
+        // Each Eip is attached in turn, so that one failing doesn't hold up
+        // the others, and the first error is returned once all are done.
+        let mut results = vec![];
+        for eip in &eips {
+            results.push(
+                self.attach(&client, &node_api, &eip_api, pod, eip)
+                    .await
+                    .map(|public_ip| public_ip.map(|public_ip| (eip, public_ip))),
+            );
+        }
+        // The pod's public IP is that of its primary Eip, the first, so its
+        // metadata is only updated once that Eip is attached.
+        if let Some(Ok(Some((primary, _)))) = results.first() {
+            let public_ips: Vec<String> = results
+                .iter()
+                .filter_map(|result| result.as_ref().ok().and_then(Option::as_ref))
+                .map(|(_, public_ip)| public_ip.clone())
+                .collect();
+            let allocation_id = primary.allocation_id().ok_or(Error::MissingAllocationId)?;
+            let already_annotated = pod
+                .annotations()
+                .get(crate::EIP_ALLOCATION_ID_ANNOTATION.as_str())
+                .map(String::as_str)
+                == Some(allocation_id);
+            let hostname = primary
+                .spec
+                .dns_name
+                .as_deref()
+                .filter(|_| self.metadata.external_dns_hostname);
+            add_dns_target_annotation(
+                &api,
+                pod,
+                &public_ips,
+                allocation_id,
+                self.metadata.karpenter_aware,
+                hostname,
+                self.metadata.public_ip_label.as_deref(),
+            )
+            .await?;
+            if !already_annotated {
+                observe_attach_latency(pod);
+            }
+        }
+        match results.into_iter().find_map(Result::err) {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
 
-        let eni_id = match get_eni_id_from_annotation(pod) {
-            Some(eni_id) => eni_id,
-            None => {
-                let instance_description =
-                    crate::aws::describe_instance(&self.ec2_client, instance_id).await?;
+    fn fingerprint(&self, pod: &Self::Resource) -> Option<u64> {
+        // Include the Eips' attachments, so that a pod whose Eip was found
+        // to have drifted is reconciled again.
+        let eips = self
+            .eip_index
+            .find_all_for_pod(&pod.namespace().unwrap_or_default(), &pod.name_any())
+            .unwrap_or_default();
+        let eips: Vec<_> = eips
+            .iter()
+            .map(|eip| {
+                (
+                    eip.attachment(),
+                    &eip.spec.dns_name,
+                    eip.network(),
+                    paused(&**eip),
+                )
+            })
+            .collect();
+        Some(fingerprint_of(&(
+            &pod.metadata.labels,
+            &pod.metadata.annotations,
+            &pod.metadata.finalizers,
+            &pod.metadata.deletion_timestamp,
+            pod.ip(),
+            pod.node_name(),
+            eips,
+        )))
+    }
 
-                crate::aws::get_eni_from_private_ip(&instance_description, pod_ip)
-                    .ok_or(Error::NoInterfaceWithThatIp)?
-            }
-        };
+    fn on_success(&self, pod: &Self::Resource, intervals: &RequeueIntervals) -> Action {
+        if ready_for_association(pod) {
+            Action::requeue(intervals.success_for(pod))
+        } else {
+            Action::requeue(NOT_READY_REQUEUE)
+        }
+    }
 
-        let all_eips = eip_api.list(&ListParams::default()).await?.items;
-        let eip = all_eips
-            .into_iter()
-            .find(|eip| eip.matches_pod(name))
-            .ok_or_else(|| Error::NoEipResourceWithThatPodName(name.to_owned()))?;
-        let eip_name = eip.name().ok_or(Error::MissingEipName)?;
-        let allocation_id = eip.allocation_id().ok_or(Error::MissingAllocationId)?;
-        let eip_description = crate::aws::describe_address(&self.ec2_client, allocation_id)
-            .await?
-            .addresses
-            .ok_or(Error::MissingAddresses)?
-            .swap_remove(0);
-        let public_ip = eip_description.public_ip.ok_or(Error::MissingPublicIp)?;
-        if eip_description.network_interface_id != Some(eni_id.to_owned())
-            || eip_description.private_ip_address != Some(pod_ip.to_owned())
-        {
-            crate::aws::associate_eip(&self.ec2_client, allocation_id, &eni_id, pod_ip).await?;
+    fn is_dry_run(&self, err: &Self::Error) -> bool {
+        err.is_dry_run()
+    }
+
+    #[instrument(skip(self, _client, pod), err)]
+    async fn observe(&self, _client: Client, pod: &Self::Resource) -> Result<(), Self::Error> {
+        let name = pod.metadata.name.as_ref().ok_or(Error::MissingPodName)?;
+        let namespace = pod.namespace().unwrap();
+        if pod.ip().is_none() || !self.namespaces.allows(&namespace) {
+            return Ok(());
+        }
+        let eips = self.eip_index.find_all_for_pod(&namespace, name)?;
+        if eips.is_empty() {
+            event!(Level::INFO, "No Eip exists for the pod.");
+        }
+        for eip in eips {
+            let pod_ip = private_ip(pod, &eip)?;
+            let allocation_id = match eip.allocation_id() {
+                Some(allocation_id) => allocation_id,
+                None => {
+                    event!(Level::INFO, eip = %eip.name_any(), "The pod's Eip has no EIP allocated.");
+                    continue;
+                }
+            };
+            let address = self
+                .providers
+                .for_eip(&eip)?
+                .describe(&namespace, allocation_id)
+                .await?;
+            if address.private_ip_address.as_deref() != Some(pod_ip.as_str()) {
+                event!(
+                    Level::WARN,
+                    %allocation_id,
+                    private_ip_address = ?address.private_ip_address,
+                    "The pod's EIP is not associated with it."
+                );
+            }
         }
-        crate::eip::set_status_attached(&eip_api, eip_name, &eni_id, pod_ip).await?;
-        add_dns_target_annotation(&api, name, &public_ip, allocation_id).await?;
         Ok(())
     }
 
@@ -101,41 +422,59 @@ impl eip_operator_shared::controller::Context for Context {
 
         let eip_api = Api::<Eip>::namespaced(client.clone(), &pod.namespace().unwrap());
 
-        let all_eips = eip_api.list(&ListParams::default()).await?.items;
-        let eip = all_eips.into_iter().find(|eip| eip.matches_pod(name));
-        if let Some(eip) = eip {
-            let allocation_id = eip.allocation_id().ok_or(Error::MissingAllocationId)?;
-            let addresses = crate::aws::describe_address(&self.ec2_client, allocation_id)
-                .await?
-                .addresses
-                .ok_or(Error::MissingAddresses)?;
-            for address in addresses {
-                if let Some(association_id) = address.association_id {
-                    crate::aws::disassociate_eip(&self.ec2_client, &association_id).await?;
+        let eips = self
+            .eip_index
+            .find_all_for_pod(&pod.namespace().unwrap(), name)?;
+        let pod_uid = pod.metadata.uid.as_deref().unwrap_or_default();
+        let mut reused = false;
+        for eip in eips {
+            if !eip.matches_pod_uid(pod_uid) {
+                // A later pod reusing the name has the Eip, and its address.
+                reused = true;
+                event!(
+                    Level::INFO,
+                    eip = %eip.name_any(),
+                    "Not detaching the EIP of an Eip which selects another pod."
+                );
+                continue;
+            }
+            if !self.detach_grace.is_zero() && !should_autocreate_eip(pod) && eip.attached() {
+                // The Eip outlives the pod, so a replacement with the same
+                // name, as from a StatefulSet, may reuse the association. The
+                // Eip reconciler disassociates it if none shows up in time.
+                let deadline = Utc::now()
+                    + chrono::Duration::from_std(self.detach_grace)
+                        .unwrap_or_else(|_| chrono::Duration::zero());
+                crate::eip::set_status_detach_deferred(&eip_api, &eip, deadline).await?;
+            } else {
+                match eip.allocation_id() {
+                    Some(allocation_id) => {
+                        self.providers
+                            .for_eip(&eip)?
+                            .disassociate(&pod.namespace().unwrap(), allocation_id)
+                            .await?;
+                    }
+                    None => self.disassociate_tagged(&eip).await?,
                 }
+                crate::eip::set_status_detached(&eip_api, &eip).await?;
             }
-            crate::eip::set_status_detached(
-                &eip_api,
-                eip.metadata.name.as_ref().ok_or(Error::MissingEipName)?,
-            )
-            .await?;
-        };
-        if should_autocreate_eip(pod) {
+        }
+        if should_autocreate_eip(pod) && !reused {
             event!(Level::INFO, should_autocreate_eip = true);
-            crate::eip::delete(&eip_api, name).await?;
+            crate::eip::delete(&eip_api, &self.autocreate.name_for(pod)?).await?;
         }
         Ok(())
     }
 }
 
 /// Checks if the autocreate label is set to true on a pod.
-fn should_autocreate_eip(pod: &Pod) -> bool {
+pub(crate) fn should_autocreate_eip(pod: &Pod) -> bool {
     pod.metadata
         .labels
         .as_ref()
         .and_then(|label| {
             label
-                .get(crate::AUTOCREATE_EIP_LABEL)
+                .get(crate::AUTOCREATE_EIP_LABEL.as_str())
                 .map(|s| (*s).as_ref())
         })
         .unwrap_or("false")
@@ -143,15 +482,125 @@ fn should_autocreate_eip(pod: &Pod) -> bool {
         == "true"
 }
 
+/// Checks whether the pod has been scheduled and given an IP, and if it uses
+/// per-pod security groups, the pod-eni annotation describing its branch ENI.
+fn ready_for_association(pod: &Pod) -> bool {
+    let expects_branch_eni = !pod.host_network()
+        && pod.spec.as_ref().map_or(false, |spec| {
+            spec.containers.iter().any(|container| {
+                container.resources.as_ref().map_or(false, |resources| {
+                    [&resources.limits, &resources.requests]
+                        .iter()
+                        .any(|quantities| {
+                            quantities
+                                .as_ref()
+                                .map_or(false, |quantities| quantities.contains_key(POD_ENI))
+                        })
+                })
+            })
+        });
+    pod.ip().is_some()
+        && pod.node_name().is_some()
+        && (!expects_branch_eni || pod.annotations().contains_key(POD_ENI))
+}
+
+/// Checks whether the pod is held back from scheduling by a scheduling gate,
+/// which may not be the operator's.
+fn is_scheduling_gated(pod: &Pod) -> bool {
+    pod.node_name().is_none()
+        && pod
+            .status
+            .as_ref()
+            .and_then(|status| status.conditions.as_ref())
+            .map_or(false, |conditions| {
+                conditions.iter().any(|condition| {
+                    condition.type_ == "PodScheduled"
+                        && condition.reason.as_deref() == Some("SchedulingGated")
+                })
+            })
+}
+
+/// Removes the operator's scheduling gate from the pod, adding the labels to
+/// its node selector and annotating it with its public IP. Other gates are
+/// left alone, and removing a gate the pod doesn't have does nothing.
+#[instrument(skip(api), err)]
+async fn remove_scheduling_gate(
+    api: &Api<Pod>,
+    name: &str,
+    public_ip: &str,
+    node_selector: &HashMap<String, String>,
+) -> Result<Pod, kube::Error> {
+    // The typed PodSpec of our Kubernetes version has no scheduling gates,
+    // so the gate is removed with a strategic merge patch's delete directive.
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                crate::PUBLIC_IP_ANNOTATION.as_str(): public_ip
+            }
+        },
+        "spec": {
+            "nodeSelector": node_selector,
+            "schedulingGates": [
+                { "name": crate::SCHEDULING_GATE.as_str(), "$patch": "delete" }
+            ]
+        }
+    });
+    api.patch(
+        name,
+        &eip_operator_shared::patch_params(),
+        &Patch::Strategic(&patch),
+    )
+    .await
+}
+
+/// The private IP of the pod the Eip's address is associated with: its IP on
+/// the Eip's network attachment, if it names one, or else the pod's own.
+fn private_ip(pod: &Pod, eip: &Eip) -> Result<String, Error> {
+    let network = match eip.network() {
+        Some(network) => network,
+        None => return Ok(pod.ip().ok_or(Error::MissingPodIp)?.to_owned()),
+    };
+    network_ip(pod, network).ok_or_else(|| Error::MissingNetworkAttachmentIp {
+        pod: pod.name_any(),
+        network: network.to_owned(),
+    })
+}
+
+/// Parses the pod's Multus network-status annotation, and returns its first
+/// IP on the network attachment, named with or without its namespace.
+fn network_ip(pod: &Pod, network: &str) -> Option<String> {
+    let annotation = pod.annotations().get(NETWORK_STATUS)?;
+
+    /// An entry of the annotation Multus sets on pods, describing each of
+    /// their networks.
+    /// example: [{
+    ///     "name":"default/macvlan-conf",
+    ///     "interface":"net1",
+    ///     "ips":["10.1.160.12"],
+    ///     "mac":"0e:cb:3c:0d:97:3b"
+    /// }]
+    #[derive(Debug, Deserialize)]
+    struct NetworkStatus {
+        name: String,
+        #[serde(default)]
+        ips: Vec<String>,
+    }
+    let statuses: Vec<NetworkStatus> = serde_json::from_str(annotation).ok()?;
+
+    let namespaced = format!("{}/{}", pod.namespace().unwrap_or_default(), network);
+    statuses
+        .into_iter()
+        .find(|status| status.name == network || status.name == namespaced)?
+        .ips
+        .into_iter()
+        .next()
+}
+
 /// Parse the vpc.amazonaws.com/pod-eni annotation if it exists, and return the ENI ID.
 #[instrument(skip(pod))]
 fn get_eni_id_from_annotation(pod: &Pod) -> Option<String> {
     event!(Level::INFO, "Getting ENI ID from annotation.");
-    let annotation = pod
-        .metadata
-        .annotations
-        .as_ref()?
-        .get("vpc.amazonaws.com/pod-eni")?;
+    let annotation = pod.metadata.annotations.as_ref()?.get(POD_ENI)?;
     event!(Level::INFO, annotation = %annotation);
 
     /// An annotation attached to a pod by EKS describing the branch network
@@ -173,25 +622,382 @@ fn get_eni_id_from_annotation(pod: &Pod) -> Option<String> {
     Some(eni_descriptions.first()?.eni_id.to_owned())
 }
 
-/// Applies annotation to pod specifying the target IP for external-dns.
+/// Records the time from pod creation until its EIP was first attached.
+fn observe_attach_latency(pod: &Pod) {
+    if let Some(created) = &pod.metadata.creation_timestamp {
+        if let Ok(latency) = (Utc::now() - created.0).to_std() {
+            crate::metrics::POD_ATTACH_LATENCY.observe(latency.as_secs_f64());
+        }
+    }
+}
+
+/// Checks whether any managed pod still carries the external-dns target
+/// annotation for the IP, so DNS may still point at it.
 #[instrument(skip(api), err)]
+pub(crate) async fn dns_target_in_use(api: &Api<Pod>, ip: &str) -> Result<bool, kube::Error> {
+    let pods = api
+        .list(&ListParams::default().labels(&MANAGE_EIP_LABEL))
+        .await?;
+    Ok(pods
+        .iter()
+        .any(|pod| dns_targets(pod).any(|target| target == ip)))
+}
+
+/// The targets in the pod's external-dns target annotation, which may list
+/// several, separated by commas.
+fn dns_targets(pod: &Pod) -> impl Iterator<Item = &str> {
+    pod.annotations()
+        .get(crate::external_dns_target_annotation())
+        .into_iter()
+        .flat_map(|targets| targets.split(','))
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+}
+
+/// The public IPs the operator gave the pod before, in its public IP
+/// annotations.
+fn previous_public_ips(pod: &Pod) -> Vec<&str> {
+    [
+        crate::PUBLIC_IP_ANNOTATION.as_str(),
+        crate::PUBLIC_IPS_ANNOTATION.as_str(),
+    ]
+    .iter()
+    .filter_map(|key| pod.annotations().get(*key))
+    .flat_map(|ips| ips.split(','))
+    .map(str::trim)
+    .filter(|ip| !ip.is_empty())
+    .collect()
+}
+
+/// The public IPs of each of the pod's Eips, followed by any other targets it
+/// was given, such as by another operator, but not the public IPs it held
+/// before.
+fn merged_dns_targets(pod: &Pod, public_ips: &[String]) -> String {
+    let previous_ips = previous_public_ips(pod);
+    let others = dns_targets(pod).filter(|target| !previous_ips.contains(target));
+    let mut targets = vec![];
+    for target in public_ips.iter().map(String::as_str).chain(others) {
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    targets.join(",")
+}
+
+/// Annotates the pod with its public IP, the first of `public_ips`, and all
+/// of them, which are also added to its targets for external-dns. If `do_not_disrupt` is set, the pod is also annotated so
+/// that Karpenter doesn't voluntarily disrupt it, or its node, and if there
+/// is a `hostname`, it is set for external-dns. If there is a
+/// `public_ip_label`, the pod is also labeled with its public IP. As the
+/// patch is applied server-side, these are removed again if they aren't set.
+#[instrument(skip(api, pod), err)]
 async fn add_dns_target_annotation(
     api: &Api<Pod>,
-    name: &str,
-    eip_address: &str,
+    pod: &Pod,
+    public_ips: &[String],
     allocation_id: &str,
+    do_not_disrupt: bool,
+    hostname: Option<&str>,
+    public_ip_label: Option<&str>,
 ) -> Result<Pod, kube::Error> {
+    let eip_address = public_ips.first().map_or("", String::as_str);
+    let mut annotations = serde_json::json!({
+        crate::EIP_ALLOCATION_ID_ANNOTATION.as_str(): allocation_id,
+        crate::PUBLIC_IP_ANNOTATION.as_str(): eip_address,
+        crate::PUBLIC_IPS_ANNOTATION.as_str(): public_ips.join(","),
+        crate::external_dns_target_annotation(): merged_dns_targets(pod, public_ips)
+    });
+    if do_not_disrupt {
+        annotations[KARPENTER_DO_NOT_DISRUPT_ANNOTATION] = "true".into();
+    }
+    if let Some(hostname) = hostname {
+        annotations[crate::EXTERNAL_DNS_HOSTNAME_ANNOTATION] = hostname.into();
+    }
+    let labels = match public_ip_label {
+        Some(key) => serde_json::json!({ key: label_value(eip_address) }),
+        None => serde_json::json!({}),
+    };
     let patch = serde_json::json!({
         "apiVersion": "v1",
         "kind": "Pod",
         "metadata": {
-            "annotations": {
-                crate::EIP_ALLOCATION_ID_ANNOTATION: allocation_id,
-                crate::EXTERNAL_DNS_TARGET_ANNOTATION: eip_address
-            }
+            "annotations": annotations,
+            "labels": labels
         }
     });
     let patch = Patch::Apply(&patch);
-    let params = PatchParams::apply(crate::FIELD_MANAGER);
-    api.patch(name, &params, &patch).await
+    // The targets include any set by others, so the operator can take over
+    // the annotation without losing them.
+    let params = PatchParams::apply(eip_operator_shared::metadata_domain()).force();
+    api.patch(&pod.name_any(), &params, &patch).await
+}
+
+/// The IP as a label value, which can't contain the colons of IPv6 addresses.
+fn label_value(ip: &str) -> String {
+    ip.replace(':', "-")
+}
+
+/// Checks whether the pod carries its public IP, in the annotation or label.
+fn has_public_ip_metadata(pod: &Pod, public_ip_label: Option<&str>) -> bool {
+    pod.annotations()
+        .contains_key(crate::PUBLIC_IP_ANNOTATION.as_str())
+        || pod
+            .annotations()
+            .contains_key(crate::PUBLIC_IPS_ANNOTATION.as_str())
+        || public_ip_label.map_or(false, |key| pod.labels().contains_key(key))
+}
+
+/// Removes the pod's public IP annotations, and label if there is one, and
+/// its public IPs from its external-dns targets.
+#[instrument(skip(api, pod), err)]
+async fn remove_public_ip_metadata(
+    api: &Api<Pod>,
+    pod: &Pod,
+    public_ip_label: Option<&str>,
+) -> Result<Pod, kube::Error> {
+    let labels = match public_ip_label {
+        Some(key) => serde_json::json!({ key: null }),
+        None => serde_json::json!({}),
+    };
+    let public_ips = previous_public_ips(pod);
+    let targets: Vec<_> = dns_targets(pod)
+        .filter(|target| !public_ips.contains(target))
+        .collect();
+    let targets = match targets.is_empty() {
+        true => serde_json::Value::Null,
+        false => targets.join(",").into(),
+    };
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                crate::PUBLIC_IP_ANNOTATION.as_str(): null,
+                crate::PUBLIC_IPS_ANNOTATION.as_str(): null,
+                crate::external_dns_target_annotation(): targets
+            },
+            "labels": labels
+        }
+    });
+    api.patch(
+        &pod.name_any(),
+        &eip_operator_shared::patch_params(),
+        &Patch::Merge(&patch),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+    use std::sync::{Arc, Mutex};
+
+    use eip_operator_shared::controller::Context as _;
+    use hyper::{Body, Request, Response};
+    use kube_runtime::watcher;
+
+    use super::*;
+    use crate::eip::v2::{EipSelector, EipSpec};
+    use crate::eip::EipStatus;
+    use crate::mock::{self, MockProvider};
+
+    const STATUS_PATH: &str = "/apis/materialize.cloud/v2/namespaces/default/eips/my-pod/status";
+    const POD_UID: &str = "11111111-1111-1111-1111-111111111111";
+
+    fn pod(labels: &[&str], annotations: &[(&str, &str)]) -> Pod {
+        let mut pod = Pod::default();
+        pod.metadata.name = Some("my-pod".to_owned());
+        pod.metadata.namespace = Some("default".to_owned());
+        pod.metadata.uid = Some(POD_UID.to_owned());
+        pod.metadata.labels = Some(
+            labels
+                .iter()
+                .map(|label| (label.to_string(), "true".to_owned()))
+                .collect(),
+        );
+        pod.metadata.annotations = Some(
+            annotations
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        );
+        pod
+    }
+
+    /// The pod's Eip, with its address associated with the pod.
+    fn attached_eip(pod_uid: Option<&str>) -> Eip {
+        let mut eip = Eip::new(
+            "my-pod",
+            EipSpec {
+                selector: EipSelector::Pod {
+                    pod_name: "my-pod".to_owned(),
+                    pod_uid: pod_uid.map(str::to_owned),
+                    network: None,
+                },
+                provider: None,
+                dns_name: None,
+                transfer_to: None,
+                tags: None,
+            },
+        );
+        eip.metadata.namespace = Some("default".to_owned());
+        eip.status = Some(EipStatus {
+            allocation_id: Some("eipalloc-1".to_owned()),
+            public_ip_address: Some(mock::address("eipalloc-1", &[]).public_ip),
+            eni: Some("eni-1".to_owned()),
+            private_ip_address: Some("10.0.0.1".to_owned()),
+            ..Default::default()
+        });
+        eip
+    }
+
+    fn context(provider: &Arc<MockProvider>, eip: Eip, detach_grace: Duration) -> Context {
+        let eip_index = EipIndex::default();
+        eip_index.apply(watcher::Event::Restarted(vec![eip]));
+        Context::new(
+            Providers::new(provider.clone()),
+            eip_index,
+            NodeIndex::new(false),
+            detach_grace,
+            NamespaceFilter::new(None, HashSet::new()),
+            None,
+            PodMetadata::default(),
+        )
+    }
+
+    /// Cleans up after the pod against a Kubernetes API which echoes the
+    /// Eip back, returning the result and the requests made of the API.
+    async fn cleanup(context: &Context, eip: &Eip, pod: &Pod) -> (Result<(), Error>, Vec<String>) {
+        let echoed = serde_json::to_value(eip).unwrap();
+        let (client, requests) = mock::client(move |_| echoed.clone());
+        let api = Api::namespaced(client.clone(), "default");
+        let result = context.cleanup(client, api, pod).await;
+        let requests = requests.lock().unwrap().clone();
+        (result, requests)
+    }
+
+    fn provider() -> Arc<MockProvider> {
+        MockProvider::new([mock::associated(
+            mock::address("eipalloc-1", &[]),
+            "eni-1",
+            "10.0.0.1",
+        )])
+    }
+
+    #[tokio::test]
+    async fn cleanup_defers_detaching_within_the_detach_grace() {
+        let provider = provider();
+        let eip = attached_eip(None);
+        let context = context(&provider, eip.clone(), Duration::from_secs(60));
+        let (result, requests) = cleanup(&context, &eip, &pod(&[], &[])).await;
+        result.unwrap();
+        assert!(provider.calls().is_empty());
+        assert_eq!(requests, [format!("PATCH {}", STATUS_PATH)]);
+    }
+
+    #[tokio::test]
+    async fn cleanup_detaches_autocreated_eips_straight_away() {
+        let provider = provider();
+        let eip = attached_eip(None);
+        let context = context(&provider, eip.clone(), Duration::from_secs(60));
+        let pod = pod(&[crate::AUTOCREATE_EIP_LABEL.as_str()], &[]);
+        let (result, requests) = cleanup(&context, &eip, &pod).await;
+        result.unwrap();
+        assert_eq!(provider.calls(), ["disassociate eipalloc-1"]);
+        assert_eq!(
+            requests,
+            [
+                format!("PATCH {}", STATUS_PATH),
+                format!("DELETE {}", STATUS_PATH.trim_end_matches("/status")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cleanup_detaches_without_a_detach_grace() {
+        let provider = provider();
+        let eip = attached_eip(None);
+        let context = context(&provider, eip.clone(), Duration::ZERO);
+        let (result, requests) = cleanup(&context, &eip, &pod(&[], &[])).await;
+        result.unwrap();
+        assert_eq!(provider.calls(), ["disassociate eipalloc-1"]);
+        assert_eq!(requests, [format!("PATCH {}", STATUS_PATH)]);
+    }
+
+    #[tokio::test]
+    async fn cleanup_leaves_the_eip_of_a_pod_reusing_the_name() {
+        let provider = provider();
+        let eip = attached_eip(Some("22222222-2222-2222-2222-222222222222"));
+        let context = context(&provider, eip.clone(), Duration::ZERO);
+        let pod = pod(&[crate::AUTOCREATE_EIP_LABEL.as_str()], &[]);
+        let (result, requests) = cleanup(&context, &eip, &pod).await;
+        result.unwrap();
+        assert!(provider.calls().is_empty());
+        assert!(requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn removing_the_scheduling_gate_deletes_only_the_operators() {
+        let (service, mut handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let patches = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&patches);
+        let echoed = serde_json::to_vec(&pod(&[], &[])).unwrap();
+        tokio::spawn(async move {
+            while let Some((request, send)) = handle.next_request().await {
+                let content_type = request.headers()[hyper::header::CONTENT_TYPE].clone();
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                let patch: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                recorded.lock().unwrap().push((content_type, patch));
+                send.send_response(Response::new(Body::from(echoed.clone())));
+            }
+        });
+        let api = Api::<Pod>::namespaced(Client::new(service, "default"), "default");
+        let node_selector = HashMap::from([("eip".to_owned(), "true".to_owned())]);
+        remove_scheduling_gate(&api, "my-pod", "203.0.113.1", &node_selector)
+            .await
+            .unwrap();
+        let patches = patches.lock().unwrap();
+        let (content_type, patch) = &patches[0];
+        assert_eq!(content_type, "application/strategic-merge-patch+json");
+        assert_eq!(
+            *patch,
+            serde_json::json!({
+                "metadata": {
+                    "annotations": {"eip.materialize.cloud/public_ip": "203.0.113.1"}
+                },
+                "spec": {
+                    "nodeSelector": {"eip": "true"},
+                    "schedulingGates": [
+                        {"name": "eip.materialize.cloud/allocated", "$patch": "delete"}
+                    ]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn network_ips_are_found_by_the_attachment_name() {
+        let network_status = r#"[
+            {"name": "aws-cni", "interface": "eth0", "ips": ["10.0.0.1"]},
+            {"name": "default/macvlan-conf", "interface": "net1", "ips": ["10.1.160.12", "10.1.160.13"]},
+            {"name": "default/no-ips", "interface": "net2"}
+        ]"#;
+        let pod = pod(&[], &[(NETWORK_STATUS, network_status)]);
+        assert_eq!(
+            network_ip(&pod, "macvlan-conf").as_deref(),
+            Some("10.1.160.12")
+        );
+        assert_eq!(
+            network_ip(&pod, "default/macvlan-conf").as_deref(),
+            Some("10.1.160.12")
+        );
+        assert_eq!(network_ip(&pod, "aws-cni").as_deref(), Some("10.0.0.1"));
+        assert_eq!(network_ip(&pod, "no-ips"), None);
+        assert_eq!(network_ip(&pod, "other-conf"), None);
+    }
+
+    #[test]
+    fn network_ips_are_not_found_without_a_valid_annotation() {
+        assert_eq!(network_ip(&pod(&[], &[]), "macvlan-conf"), None);
+        let pod = pod(&[], &[(NETWORK_STATUS, "not json")]);
+        assert_eq!(network_ip(&pod, "macvlan-conf"), None);
+    }
 }
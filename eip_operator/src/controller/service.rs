@@ -0,0 +1,198 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+use k8s_openapi::api::core::v1::Service;
+use kube::api::{Api, Patch};
+use kube::{Client, ResourceExt};
+use kube_runtime::reflector::ObjectRef;
+use tracing::{event, instrument, Level};
+
+use eip_operator_shared::controller::fingerprint_of;
+use eip_operator_shared::Error;
+
+use crate::controller::NamespaceFilter;
+use crate::eip::v2::Eip;
+use crate::eip_index::EipIndex;
+
+/// The services naming each Eip, keyed by the namespace and name of the Eip,
+/// so that a change to an Eip reconciles the services reporting its address.
+#[derive(Clone, Default)]
+pub(crate) struct EipServices(Arc<RwLock<HashMap<(String, String), BTreeSet<String>>>>);
+
+impl EipServices {
+    /// Records which Eip, if any, the service names.
+    fn set(&self, namespace: &str, service: &str, eip_name: Option<&str>) {
+        let mut services = self.0.write().unwrap();
+        services.retain(|(eip_namespace, _), names| {
+            if eip_namespace == namespace {
+                names.remove(service);
+            }
+            !names.is_empty()
+        });
+        if let Some(eip_name) = eip_name {
+            services
+                .entry((namespace.to_owned(), eip_name.to_owned()))
+                .or_default()
+                .insert(service.to_owned());
+        }
+    }
+
+    /// The services naming the Eip.
+    pub(crate) fn for_eip(&self, eip: &Eip) -> Vec<ObjectRef<Service>> {
+        let namespace = eip.namespace().unwrap_or_default();
+        self.0
+            .read()
+            .unwrap()
+            .get(&(namespace.clone(), eip.name_any()))
+            .into_iter()
+            .flatten()
+            .map(|name| ObjectRef::new(name).within(&namespace))
+            .collect()
+    }
+}
+
+pub(crate) struct Context {
+    eip_index: EipIndex,
+    eip_services: EipServices,
+    namespaces: NamespaceFilter,
+}
+
+impl Context {
+    pub(crate) fn new(
+        eip_index: EipIndex,
+        eip_services: EipServices,
+        namespaces: NamespaceFilter,
+    ) -> Self {
+        Self {
+            eip_index,
+            eip_services,
+            namespaces,
+        }
+    }
+
+    /// The public IP of the Eip the service names, if it is attached.
+    fn public_ip(&self, service: &Service) -> Result<Option<String>, Error> {
+        let eip_name = match eip_name(service) {
+            Some(eip_name) => eip_name,
+            None => return Ok(None),
+        };
+        let eip = self
+            .eip_index
+            .find_by_name(&service.namespace().unwrap(), eip_name)?
+            .ok_or_else(|| Error::NoEipResourceWithThatName(eip_name.to_owned()))?;
+        Ok(eip
+            .public_ip_address()
+            .filter(|_| eip.attached())
+            .map(str::to_owned))
+    }
+}
+
+#[async_trait::async_trait]
+impl eip_operator_shared::controller::Context for Context {
+    type Resource = Service;
+    type Error = Error;
+
+    const FINALIZER_NAME: &'static str = "load_balancer_status";
+
+    #[instrument(skip(self, _client, api, service), err)]
+    async fn apply(
+        &self,
+        _client: Client,
+        api: Api<Self::Resource>,
+        service: &Self::Resource,
+    ) -> Result<(), Self::Error> {
+        let name = service.name_any();
+        event!(Level::INFO, name = %name, "Applying service.");
+
+        let namespace = service.namespace().unwrap();
+        if !self.namespaces.allows(&namespace) {
+            event!(
+                Level::INFO,
+                "Ignoring service in a namespace which isn't managed."
+            );
+            return Ok(());
+        }
+        self.eip_services.set(&namespace, &name, eip_name(service));
+
+        // Until the Eip is attached, report no address, so that nothing
+        // routes to an address which doesn't reach the service.
+        let public_ip = self.public_ip(service)?;
+        if ingress_ips(service) != Vec::from_iter(public_ip.as_deref()) {
+            event!(Level::INFO, public_ip = ?public_ip, "Updating load balancer status.");
+            set_load_balancer_ip(&api, &name, public_ip.as_deref()).await?;
+        }
+        Ok(())
+    }
+
+    fn fingerprint(&self, service: &Self::Resource) -> Option<u64> {
+        let public_ip = self.public_ip(service).ok().flatten();
+        Some(fingerprint_of(&(
+            &service.metadata.annotations,
+            &service.metadata.finalizers,
+            &service.metadata.deletion_timestamp,
+            ingress_ips(service),
+            public_ip,
+        )))
+    }
+
+    #[instrument(skip(self, _client, _api, service), err)]
+    async fn cleanup(
+        &self,
+        _client: Client,
+        _api: Api<Self::Resource>,
+        service: &Self::Resource,
+    ) -> Result<(), Self::Error> {
+        let name = service.name_any();
+        event!(Level::INFO, name = %name, "Cleaning up service.");
+        self.eip_services
+            .set(&service.namespace().unwrap_or_default(), &name, None);
+        Ok(())
+    }
+}
+
+/// The name of the Eip whose address the service reports.
+fn eip_name(service: &Service) -> Option<&str> {
+    service
+        .annotations()
+        .get(crate::SERVICE_EIP_ANNOTATION.as_str())
+        .map(String::as_str)
+}
+
+/// The IPs in the service's load balancer status.
+fn ingress_ips(service: &Service) -> Vec<&str> {
+    service
+        .status
+        .as_ref()
+        .and_then(|status| status.load_balancer.as_ref())
+        .and_then(|load_balancer| load_balancer.ingress.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|ingress| ingress.ip.as_deref())
+        .collect()
+}
+
+/// Sets the service's load balancer ingress to the IP, or clears it.
+#[instrument(skip(api), err)]
+async fn set_load_balancer_ip(
+    api: &Api<Service>,
+    name: &str,
+    ip: Option<&str>,
+) -> Result<Service, kube::Error> {
+    let ingress: Vec<_> = ip
+        .into_iter()
+        .map(|ip| serde_json::json!({ "ip": ip }))
+        .collect();
+    let patch = serde_json::json!({
+        "status": {
+            "loadBalancer": {
+                "ingress": ingress
+            }
+        }
+    });
+    api.patch_status(
+        name,
+        &eip_operator_shared::patch_params(),
+        &Patch::Merge(&patch),
+    )
+    .await
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+
+use tracing::{event, instrument, Level};
+
+use eip_operator::aws::NAMESPACE_TAG;
+use eip_operator::metrics::{ESTIMATED_HOURLY_COST, ESTIMATED_MONTHLY_COST};
+use eip_operator::provider::{Address, Providers};
+use eip_operator_shared::Error;
+
+/// The hours in an average month, as cloud pricing calculators count them.
+const HOURS_PER_MONTH: f64 = 730.0;
+
+/// The hourly prices of an address, which clouds charge differently for
+/// while it is attached and while it is idle.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct EipPrices {
+    pub(crate) attached: f64,
+    pub(crate) idle: f64,
+}
+
+/// Sets the estimated cost gauges from the addresses tagged for the
+/// clusters, replacing those of namespaces which no longer have any.
+#[instrument(skip(providers), err)]
+pub(crate) async fn report_costs(
+    providers: &Providers,
+    cluster_names: &[String],
+    prices: EipPrices,
+) -> Result<(), Error> {
+    let mut addresses = vec![];
+    for cluster_name in cluster_names {
+        for (_, provider) in providers.all() {
+            addresses.extend(
+                provider
+                    .describe_cluster_addresses(cluster_name, None)
+                    .await?,
+            );
+        }
+    }
+    let costs = hourly_costs(&addresses, prices);
+    ESTIMATED_HOURLY_COST.reset();
+    ESTIMATED_MONTHLY_COST.reset();
+    for ((namespace, state), hourly) in &costs {
+        ESTIMATED_HOURLY_COST
+            .with_label_values(&[namespace, state])
+            .set(*hourly);
+        ESTIMATED_MONTHLY_COST
+            .with_label_values(&[namespace, state])
+            .set(hourly * HOURS_PER_MONTH);
+    }
+    event!(
+        Level::DEBUG,
+        addresses = addresses.len(),
+        "Reported estimated EIP costs."
+    );
+    Ok(())
+}
+
+/// The hourly cost of the addresses, by the namespace they are tagged with
+/// and whether they are `attached` or `idle`.
+fn hourly_costs(addresses: &[Address], prices: EipPrices) -> BTreeMap<(String, &'static str), f64> {
+    let mut costs = BTreeMap::new();
+    for address in addresses {
+        let namespace = address.tag(NAMESPACE_TAG).unwrap_or_default().to_owned();
+        let (state, price) = match address.association_id {
+            Some(_) => ("attached", prices.attached),
+            None => ("idle", prices.idle),
+        };
+        *costs.entry((namespace, state)).or_insert(0.0) += price;
+    }
+    costs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn costs_are_summed_by_namespace_and_state() {
+        let prices = EipPrices {
+            attached: 0.005,
+            idle: 0.01,
+        };
+        let addresses = [
+            mock::associated(
+                mock::address("eipalloc-1", &[(NAMESPACE_TAG, "a")]),
+                "eni-1",
+                "10.0.0.1",
+            ),
+            mock::associated(
+                mock::address("eipalloc-2", &[(NAMESPACE_TAG, "a")]),
+                "eni-2",
+                "10.0.0.2",
+            ),
+            mock::address("eipalloc-3", &[(NAMESPACE_TAG, "a")]),
+            mock::address("eipalloc-4", &[(NAMESPACE_TAG, "b")]),
+        ];
+        let costs = hourly_costs(&addresses, prices);
+        assert_eq!(
+            costs.into_iter().collect::<Vec<_>>(),
+            [
+                (("a".to_owned(), "attached"), 0.01),
+                (("a".to_owned(), "idle"), 0.01),
+                (("b".to_owned(), "idle"), 0.01),
+            ]
+        );
+    }
+}
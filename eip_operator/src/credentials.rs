@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use aws_types::credentials::{future, CredentialsError, ProvideCredentials};
+use aws_types::Credentials;
+use tokio::sync::Mutex;
+
+const ACCESS_KEY_ID_FILE: &str = "access_key_id";
+const SECRET_ACCESS_KEY_FILE: &str = "secret_access_key";
+const SESSION_TOKEN_FILE: &str = "session_token";
+
+/// The symlink through which the kubelet swaps in all of a mounted Secret's
+/// files at once, pointing at the directory holding their current contents.
+const DATA_LINK: &str = "..data";
+
+/// Provides static AWS credentials from files in a directory, such as a
+/// mounted Secret. The credentials are read again whenever the files change,
+/// so rotated credentials are picked up without restarting the operator.
+#[derive(Debug)]
+pub struct FileCredentialsProvider {
+    dir: PathBuf,
+    cached: Mutex<Option<(Snapshot, Credentials)>>,
+}
+
+/// Identifies the contents of the files: the target of the `..data` symlink
+/// of a mounted Secret, or, outside of one, the files' modification times.
+#[derive(Debug, PartialEq, Eq)]
+enum Snapshot {
+    Data(PathBuf),
+    Modified(Vec<Option<SystemTime>>),
+}
+
+impl FileCredentialsProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The current snapshot of the files, and the directory to read them
+    /// from. The directory of a mounted Secret's snapshot is read directly,
+    /// so that all the files come from the same one even if it is swapped
+    /// while they are read.
+    async fn snapshot(&self) -> (Snapshot, PathBuf) {
+        if let Ok(target) = tokio::fs::read_link(self.dir.join(DATA_LINK)).await {
+            let dir = self.dir.join(&target);
+            return (Snapshot::Data(target), dir);
+        }
+        let mut modified = vec![];
+        for name in [
+            ACCESS_KEY_ID_FILE,
+            SECRET_ACCESS_KEY_FILE,
+            SESSION_TOKEN_FILE,
+        ] {
+            let metadata = tokio::fs::metadata(self.dir.join(name)).await;
+            modified.push(metadata.and_then(|metadata| metadata.modified()).ok());
+        }
+        (Snapshot::Modified(modified), self.dir.clone())
+    }
+
+    async fn load(&self) -> Result<Credentials, CredentialsError> {
+        let (snapshot, dir) = self.snapshot().await;
+        let mut cached = self.cached.lock().await;
+        match &*cached {
+            Some((cached_snapshot, credentials)) if *cached_snapshot == snapshot => {
+                Ok(credentials.clone())
+            }
+            _ => {
+                let credentials = read_credentials(&dir).await?;
+                *cached = Some((snapshot, credentials.clone()));
+                Ok(credentials)
+            }
+        }
+    }
+}
+
+async fn read(dir: &Path, name: &str) -> std::io::Result<String> {
+    tokio::fs::read_to_string(dir.join(name))
+        .await
+        .map(|value| value.trim().to_owned())
+}
+
+async fn read_credentials(dir: &Path) -> Result<Credentials, CredentialsError> {
+    let access_key_id = read(dir, ACCESS_KEY_ID_FILE)
+        .await
+        .map_err(CredentialsError::provider_error)?;
+    let secret_access_key = read(dir, SECRET_ACCESS_KEY_FILE)
+        .await
+        .map_err(CredentialsError::provider_error)?;
+    let session_token = read(dir, SESSION_TOKEN_FILE).await.ok();
+    Ok(Credentials::new(
+        access_key_id,
+        secret_access_key,
+        session_token,
+        None,
+        "FileCredentialsProvider",
+    ))
+}
+
+impl ProvideCredentials for FileCredentialsProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.load())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    /// Writes a snapshot of a mounted Secret's files, and points the
+    /// `..data` symlink at it, as the kubelet does.
+    fn mount(dir: &Path, snapshot: &str, access_key_id: &str) {
+        let snapshot_dir = dir.join(snapshot);
+        std::fs::create_dir_all(&snapshot_dir).unwrap();
+        std::fs::write(snapshot_dir.join(ACCESS_KEY_ID_FILE), access_key_id).unwrap();
+        std::fs::write(snapshot_dir.join(SECRET_ACCESS_KEY_FILE), "secret").unwrap();
+        let _ = std::fs::remove_file(dir.join(DATA_LINK));
+        symlink(snapshot, dir.join(DATA_LINK)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotated_credentials_are_picked_up() {
+        let dir =
+            std::env::temp_dir().join(format!("eip-operator-credentials-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        mount(&dir, "..2024_01_01", "AKIAOLD");
+        for name in [ACCESS_KEY_ID_FILE, SECRET_ACCESS_KEY_FILE] {
+            symlink(Path::new(DATA_LINK).join(name), dir.join(name)).unwrap();
+        }
+        let provider = FileCredentialsProvider::new(&dir);
+        let credentials = provider.provide_credentials().await.unwrap();
+        assert_eq!(credentials.access_key_id(), "AKIAOLD");
+        assert_eq!(credentials.session_token(), None);
+
+        // The same snapshot isn't read again.
+        std::fs::write(
+            dir.join("..2024_01_01").join(ACCESS_KEY_ID_FILE),
+            "AKIAEDITED",
+        )
+        .unwrap();
+        let credentials = provider.provide_credentials().await.unwrap();
+        assert_eq!(credentials.access_key_id(), "AKIAOLD");
+
+        mount(&dir, "..2024_01_02", "AKIANEW");
+        let credentials = provider.provide_credentials().await.unwrap();
+        assert_eq!(credentials.access_key_id(), "AKIANEW");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,8 @@
 use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
-use kube::api::{Api, DeleteParams, ListParams, Patch, PatchParams, PostParams};
+use k8s_openapi::chrono::{DateTime, SecondsFormat, Utc};
+use kube::api::{Api, DeleteParams, Patch, PatchParams};
 use kube::core::crd::merge_crds;
-use kube::{Client, CustomResourceExt};
+use kube::{Client, CustomResourceExt, ResourceExt};
 use kube_runtime::wait::{await_condition, conditions};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -45,24 +46,25 @@ pub mod v1 {
 
     #[derive(Clone, Serialize, Deserialize, Debug)]
     #[serde(rename_all = "camelCase")]
-    pub(crate) struct LaxEipSpec {
-        pub(crate) pod_name: Option<String>,
+    pub struct LaxEipSpec {
+        pub pod_name: Option<String>,
     }
 
-    pub(crate) type LaxEip = kube::api::Object<LaxEipSpec, kube::api::NotUsed>;
+    pub type LaxEip = kube::api::Object<LaxEipSpec, kube::api::NotUsed>;
 
     impl Eip {
-        pub(crate) fn lax_api(k8s_client: Client, namespace: Option<&str>) -> Api<LaxEip> {
-            Api::<LaxEip>::namespaced_with(
-                k8s_client,
-                namespace.unwrap_or("default"),
-                &kube::api::ApiResource::erase::<Self>(&()),
-            )
+        pub fn lax_api(k8s_client: Client, namespace: Option<&str>) -> Api<LaxEip> {
+            let resource = kube::api::ApiResource::erase::<Self>(&());
+            match namespace {
+                Some(namespace) => Api::<LaxEip>::namespaced_with(k8s_client, namespace, &resource),
+                None => Api::<LaxEip>::all_with(k8s_client, &resource),
+            }
         }
     }
 }
 
 pub mod v2 {
+    use k8s_openapi::chrono::{DateTime, Utc};
     use kube::api::Api;
     use kube::{Client, CustomResource, Resource};
     use schemars::JsonSchema;
@@ -71,21 +73,46 @@ pub mod v2 {
 
     use eip_operator_shared::Error;
 
-    use super::EipStatus;
+    use super::{EipHistoryEntry, EipStatus};
 
     #[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize, JsonSchema)]
     #[serde(rename_all = "camelCase")]
     pub enum EipSelector {
         #[serde(rename_all = "camelCase")]
-        Pod { pod_name: String },
+        Pod {
+            /// The pod's name, which defaults to the Eip's.
+            #[serde(default)]
+            pod_name: String,
+            /// The pod's UID, if only that instance of the pod may have the
+            /// address, and not a later pod reusing its name.
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pod_uid: Option<String>,
+            /// The Multus network attachment of the pod, as `name` or
+            /// `namespace/name`, whose IP the address is associated with,
+            /// rather than that of the pod's default network.
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            network: Option<String>,
+        },
         #[serde(rename_all = "camelCase")]
         Node { selector: BTreeMap<String, String> },
     }
 
+    /// The selector of an Eip created without one, which the Eip controller
+    /// sets to the pod named after the Eip.
+    impl Default for EipSelector {
+        fn default() -> Self {
+            Self::Pod {
+                pod_name: String::new(),
+                pod_uid: None,
+                network: None,
+            }
+        }
+    }
+
     impl std::fmt::Display for EipSelector {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
             match self {
-                Self::Pod { pod_name } => {
+                Self::Pod { pod_name, .. } => {
                     write!(f, "Pod({})", pod_name)
                 }
                 Self::Node { selector } => {
@@ -105,6 +132,28 @@ pub mod v2 {
         }
     }
 
+    /// The cloud which allocates an Eip's address.
+    #[derive(
+        Eq, PartialEq, Hash, Clone, Copy, Debug, Default, Deserialize, Serialize, JsonSchema,
+    )]
+    #[serde(rename_all = "lowercase")]
+    pub enum Provider {
+        #[default]
+        Aws,
+        Gcp,
+        Azure,
+    }
+
+    impl std::fmt::Display for Provider {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+            match self {
+                Self::Aws => write!(f, "aws"),
+                Self::Gcp => write!(f, "gcp"),
+                Self::Azure => write!(f, "azure"),
+            }
+        }
+    }
+
     /// The spec for the Eip Kubernetes custom resource.
     /// An `Eip` type is generated by deriving `CustomResource`.
     #[derive(CustomResource, Clone, Debug, Deserialize, Serialize, JsonSchema)]
@@ -121,10 +170,42 @@ pub mod v2 {
         printcolumn = r#"{"name": "PublicIP", "type": "string", "description": "Public IP address of the EIP.", "jsonPath": ".status.publicIpAddress"}"#,
         printcolumn = r#"{"name": "Selector", "type": "string", "description": "Selector for the pod or node to associate the EIP with.", "jsonPath": ".spec.selector", "priority": 1}"#,
         printcolumn = r#"{"name": "ENI", "type": "string", "description": "ID of the Elastic Network Interface of the pod.", "jsonPath": ".status.eni", "priority": 1}"#,
-        printcolumn = r#"{"name": "PrivateIP", "type": "string", "description": "Private IP address of the pod.", "jsonPath": ".status.privateIpAddress", "priority": 1}"#
+        printcolumn = r#"{"name": "PrivateIP", "type": "string", "description": "Private IP address of the pod.", "jsonPath": ".status.privateIpAddress", "priority": 1}"#,
+        printcolumn = r#"{"name": "Provider", "type": "string", "description": "Cloud which allocates the EIP.", "jsonPath": ".spec.provider", "priority": 1}"#,
+        printcolumn = r#"{"name": "LastError", "type": "string", "description": "Reason for the last failed reconciliation.", "jsonPath": ".status.lastErrorReason", "priority": 1}"#,
+        printcolumn = r#"{"name": "Owner", "type": "string", "description": "Name of the controller of the pod the Eip was autocreated for.", "jsonPath": ".metadata.ownerReferences[0].name", "priority": 1}"#
     )]
     pub struct EipSpec {
+        /// The pod or nodes to associate the address with, the pod named after
+        /// the Eip if unset.
+        #[serde(default)]
         pub selector: EipSelector,
+        /// The cloud which allocates the address, `aws` if unset.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub provider: Option<Provider>,
+        /// The DNS name external-dns should point at a pod's address, if the
+        /// operator manages the hostname annotation.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub dns_name: Option<String>,
+        /// The name of the cluster to hand the address over to, where an Eip
+        /// of the same namespace and name can adopt it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub transfer_to: Option<String>,
+        /// Tags of the address, beyond the operator's own and the default
+        /// tags, which they take precedence over.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub tags: Option<BTreeMap<String, String>>,
+    }
+
+    /// Checks whether the labels have every key of the selector, with the same
+    /// value.
+    pub fn selector_matches(
+        selector: &BTreeMap<String, String>,
+        labels: &BTreeMap<String, String>,
+    ) -> bool {
+        selector
+            .iter()
+            .all(|(key, value)| labels.get(key) == Some(value))
     }
 
     impl Eip {
@@ -132,7 +213,7 @@ pub mod v2 {
             <Self as kube::Resource>::version(&()).into_owned()
         }
 
-        pub(crate) fn api(k8s_client: Client, namespace: Option<&str>) -> Api<Self> {
+        pub fn api(k8s_client: Client, namespace: Option<&str>) -> Api<Self> {
             Api::<Self>::namespaced(k8s_client, namespace.unwrap_or("default"))
         }
 
@@ -150,26 +231,45 @@ pub mod v2 {
             match self.spec.selector {
                 EipSelector::Pod {
                     pod_name: ref this_pod_name,
+                    ..
                 } => pod_name == this_pod_name,
                 _ => false,
             }
         }
 
+        /// Whether the Eip selects the instance of its pod with the UID:
+        /// any, unless its selector names one.
+        pub fn matches_pod_uid(&self, pod_uid: &str) -> bool {
+            match self.spec.selector {
+                EipSelector::Pod {
+                    pod_uid: Some(ref this_pod_uid),
+                    ..
+                } => pod_uid == this_pod_uid,
+                _ => true,
+            }
+        }
+
+        /// The Multus network attachment of the pod the Eip's address is
+        /// associated with, if not the pod's default network.
+        pub fn network(&self) -> Option<&str> {
+            match self.spec.selector {
+                EipSelector::Pod {
+                    network: Some(ref network),
+                    ..
+                } => Some(network),
+                _ => None,
+            }
+        }
+
+        /// Whether the Eip was created without a selector, or with an empty
+        /// pod name, which the Eip controller hasn't defaulted yet.
+        pub fn selector_unset(&self) -> bool {
+            matches!(&self.spec.selector, EipSelector::Pod { pod_name, .. } if pod_name.is_empty())
+        }
+
         pub fn matches_node(&self, node_labels: &BTreeMap<String, String>) -> bool {
             match self.spec.selector {
-                EipSelector::Node { ref selector } => {
-                    for (key, value) in selector {
-                        match node_labels.get(key) {
-                            Some(node_value) => {
-                                if value != node_value {
-                                    return false;
-                                }
-                            }
-                            None => return false,
-                        }
-                    }
-                    true
-                }
+                EipSelector::Node { ref selector } => selector_matches(selector, node_labels),
                 _ => false,
             }
         }
@@ -179,6 +279,92 @@ pub mod v2 {
                 .as_ref()
                 .and_then(|status| status.allocation_id.as_deref())
         }
+
+        pub fn public_ip_address(&self) -> Option<&str> {
+            self.status
+                .as_ref()
+                .and_then(|status| status.public_ip_address.as_deref())
+        }
+
+        /// When the address will be disassociated, if the detachment of its
+        /// deleted pod was deferred.
+        pub fn detach_deadline(&self) -> Option<DateTime<Utc>> {
+            self.status
+                .as_ref()
+                .and_then(|status| status.detach_deadline.as_deref())
+                .and_then(|deadline| DateTime::parse_from_rfc3339(deadline).ok())
+                .map(|deadline| deadline.with_timezone(&Utc))
+        }
+
+        /// When the Eip's address was left unattached, if it is allocated but
+        /// not attached: the time of the transition its history last recorded,
+        /// or its creation if it has no history.
+        pub fn idle_since(&self) -> Option<DateTime<Utc>> {
+            if self.allocation_id().is_none() || self.attached() {
+                return None;
+            }
+            self.history()
+                .iter()
+                .rev()
+                .find(|entry| entry.event != "Idle")
+                .and_then(|entry| DateTime::parse_from_rfc3339(&entry.time).ok())
+                .map(|time| time.with_timezone(&Utc))
+                .or_else(|| self.metadata.creation_timestamp.as_ref().map(|time| time.0))
+        }
+
+        /// Whether the Eip was already reported idle since its address was
+        /// last left unattached.
+        pub fn idle_reported(&self) -> bool {
+            self.idle_since().is_some()
+                && self
+                    .history()
+                    .last()
+                    .map_or(false, |entry| entry.event == "Idle")
+        }
+
+        /// Whether the Eip's address was released for being idle, and none has
+        /// been allocated since.
+        pub fn idle_released(&self) -> bool {
+            self.allocation_id().is_none()
+                && self
+                    .history()
+                    .last()
+                    .map_or(false, |entry| entry.event == "IdleReleased")
+        }
+
+        /// Whether the Eip's status or history records it being attached
+        /// through the network interface. Eips which record no attachment at
+        /// all, such as those which just adopted an address, recognize any.
+        pub fn recognizes_eni(&self, eni: &str) -> bool {
+            let mut known = self
+                .status
+                .iter()
+                .filter_map(|status| status.eni.as_deref())
+                .chain(
+                    self.history()
+                        .iter()
+                        .filter_map(|entry| entry.eni.as_deref()),
+                )
+                .peekable();
+            known.peek().is_none() || known.any(|known| known == eni)
+        }
+
+        fn history(&self) -> &[EipHistoryEntry] {
+            self.status
+                .as_ref()
+                .and_then(|status| status.history.as_deref())
+                .unwrap_or_default()
+        }
+
+        /// The allocation ID, ENI, and private IP recorded in the status.
+        pub fn attachment(&self) -> (Option<&str>, Option<&str>, Option<&str>) {
+            let status = self.status.as_ref();
+            (
+                self.allocation_id(),
+                status.and_then(|status| status.eni.as_deref()),
+                status.and_then(|status| status.private_ip_address.as_deref()),
+            )
+        }
     }
 
     impl TryFrom<&super::v1::LaxEip> for Eip {
@@ -192,10 +378,20 @@ pub mod v2 {
                     EipSpec {
                         selector: EipSelector::Pod {
                             pod_name: pod_name.to_string(),
+                            pod_uid: None,
+                            network: None,
                         },
+                        provider: None,
+                        dns_name: None,
+                        transfer_to: None,
+                        tags: None,
                     },
                 );
-                eip.meta_mut().resource_version = eip_v1.metadata.resource_version.clone();
+                let meta = eip.meta_mut();
+                meta.resource_version = eip_v1.metadata.resource_version.clone();
+                meta.labels = eip_v1.metadata.labels.clone();
+                meta.annotations = eip_v1.metadata.annotations.clone();
+                meta.finalizers = eip_v1.metadata.finalizers.clone();
                 Ok(eip)
             } else {
                 Err(None)
@@ -205,22 +401,117 @@ pub mod v2 {
 }
 
 /// The status fields for the Eip Kubernetes custom resource.
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EipStatus {
     pub allocation_id: Option<String>,
     pub public_ip_address: Option<String>,
     pub eni: Option<String>,
     pub private_ip_address: Option<String>,
+    pub last_error: Option<String>,
+    pub last_error_reason: Option<String>,
+    pub last_error_time: Option<String>,
+    pub history: Option<Vec<EipHistoryEntry>>,
+    /// When the address will be disassociated, if its pod was deleted and a
+    /// replacement may still reuse the association.
+    pub detach_deadline: Option<String>,
+}
+
+/// A significant transition in the lifecycle of an Eip, kept in its status.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EipHistoryEntry {
+    pub time: String,
+    pub event: String,
+    pub allocation_id: Option<String>,
+    pub target: Option<String>,
+    pub eni: Option<String>,
+    pub private_ip_address: Option<String>,
+}
+
+/// The maximum number of entries kept in the Eip status history.
+const HISTORY_LIMIT: usize = 16;
+
+/// Returns the Eip's history with the new entry appended, dropping the oldest
+/// entries beyond `HISTORY_LIMIT`.
+fn history_with(eip: &Eip, entry: EipHistoryEntry) -> Vec<EipHistoryEntry> {
+    let mut history = eip
+        .status
+        .as_ref()
+        .and_then(|status| status.history.clone())
+        .unwrap_or_default();
+    history.push(entry);
+    let excess = history.len().saturating_sub(HISTORY_LIMIT);
+    history.drain(..excess);
+    history
+}
+
+/// How many times a status patch is attempted before a conflict is returned.
+const STATUS_PATCH_ATTEMPTS: u32 = 4;
+
+/// Merge-patches the Eip status, appending the entry, if any, to its history.
+/// The history is replaced as a whole, so such a patch is guarded by the
+/// resourceVersion of the Eip it was built from. When the API server reports
+/// a conflict, which can happen when the Eip and pod or node controllers
+/// update the same Eip at once, the history is built again from the Eip as
+/// it now is, so that neither update is lost.
+async fn patch_status(
+    api: &Api<Eip>,
+    eip: &Eip,
+    mut patch: serde_json::Value,
+    entry: Option<EipHistoryEntry>,
+) -> Result<Eip, kube::Error> {
+    let name = eip.name_any();
+    let entry = match entry {
+        Some(entry) => entry,
+        None => return merge_status(api, &name, &patch).await,
+    };
+    let mut current = None;
+    let mut attempt = 1;
+    loop {
+        let eip = current.as_ref().unwrap_or(eip);
+        if let Some(resource_version) = eip.resource_version() {
+            patch["metadata"]["resourceVersion"] = resource_version.into();
+        }
+        patch["status"]["history"] = serde_json::to_value(history_with(eip, entry.clone()))?;
+        match merge_status(api, &name, &patch).await {
+            Err(kube::Error::Api(err)) if err.code == 409 && attempt < STATUS_PATCH_ATTEMPTS => {
+                event!(
+                    Level::DEBUG,
+                    attempt,
+                    "Conflict patching Eip status, retrying with its current history."
+                );
+                current = Some(api.get_status(&name).await?);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn merge_status(
+    api: &Api<Eip>,
+    name: &str,
+    patch: &serde_json::Value,
+) -> Result<Eip, kube::Error> {
+    #[cfg(feature = "fault-injection")]
+    crate::faults::status_patch_fault(name)?;
+    api.patch_status(
+        name,
+        &eip_operator_shared::patch_params(),
+        &Patch::Merge(patch),
+    )
+    .await
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
 }
 
 /// Registers the Eip custom resource with Kubernetes,
 /// the specification of which is automatically derived from the structs.
 #[instrument(skip(k8s_client), err, fields(crd_data))]
-pub async fn register_custom_resource(
-    k8s_client: Client,
-    namespace: Option<&str>,
-) -> Result<(), Error> {
+pub async fn register_custom_resource(k8s_client: Client) -> Result<(), Error> {
     // https://github.com/kube-rs/kube-rs/blob/master/examples/crd_derive_schema.rs#L224
     let crd_api = Api::<CustomResourceDefinition>::all(k8s_client.clone());
     let data = merge_crds(vec![v1::Eip::crd(), v2::Eip::crd()], "v2").unwrap();
@@ -230,70 +521,83 @@ pub async fn register_custom_resource(
     crd_api
         .patch(
             CRD_NAME,
-            &PatchParams::apply(crate::FIELD_MANAGER),
+            &PatchParams::apply(eip_operator_shared::metadata_domain()),
             &crd_patch,
         )
         .await?;
-    let establish = await_condition(crd_api.clone(), CRD_NAME, conditions::is_crd_established());
-    tokio::time::timeout(std::time::Duration::from_secs(10), establish).await??;
-
-    upgrade_old_resources(k8s_client, namespace).await?;
-
-    Ok(())
+    wait_until_established(crd_api).await
 }
 
-async fn upgrade_old_resources(k8s_client: Client, namespace: Option<&str>) -> Result<(), Error> {
-    let eip_v1_api = v1::Eip::lax_api(k8s_client.clone(), namespace);
-    for eip_v1 in eip_v1_api.list(&ListParams::default()).await? {
-        match v2::Eip::try_from(&eip_v1) {
-            Ok(eip) => {
-                event!(
-                    Level::INFO,
-                    eip_v1 = serde_json::to_string(&eip_v1)?,
-                    "updating existing eip to latest version"
-                );
-                let eip_api =
-                    v2::Eip::api(k8s_client.clone(), eip_v1.metadata.namespace.as_deref());
-                eip_api
-                    .replace(
-                        eip.metadata.name.as_ref().unwrap(),
-                        &PostParams::default(),
-                        &eip,
-                    )
-                    .await?;
-            }
-            Err(Some(e)) => {
-                return Err(e);
-            }
-            Err(None) => {
-                // not a v1 Eip
-            }
-        }
+/// Checks that the Eip custom resource was installed by something else, such
+/// as Helm, and is established.
+#[instrument(skip(k8s_client), err)]
+pub async fn verify_custom_resource(k8s_client: Client) -> Result<(), Error> {
+    let crd_api = Api::<CustomResourceDefinition>::all(k8s_client);
+    if crd_api.get_opt(CRD_NAME).await?.is_none() {
+        return Err(Error::CrdNotInstalled(CRD_NAME.to_owned()));
     }
+    wait_until_established(crd_api).await
+}
 
+async fn wait_until_established(crd_api: Api<CustomResourceDefinition>) -> Result<(), Error> {
+    let establish = await_condition(crd_api, CRD_NAME, conditions::is_crd_established());
+    tokio::time::timeout(std::time::Duration::from_secs(10), establish).await??;
     Ok(())
 }
 
-/// Creates a K8S Eip resource.
-#[instrument(skip(api), err)]
-pub(crate) async fn create_for_pod(api: &Api<Eip>, pod_name: &str) -> Result<Eip, kube::Error> {
-    //info!("Applying K8S Eip: {}", pod_name);
-    let patch = Eip::new(
-        pod_name,
+/// An Eip with the name, for the pod, or only the instance of it with the
+/// UID.
+pub fn for_pod(name: &str, pod_name: &str, pod_uid: Option<&str>) -> Eip {
+    Eip::new(
+        name,
         EipSpec {
             selector: EipSelector::Pod {
                 pod_name: pod_name.to_owned(),
+                pod_uid: pod_uid.map(str::to_owned),
+                network: None,
             },
+            provider: None,
+            dns_name: None,
+            transfer_to: None,
+            tags: None,
         },
-    );
-    let patch = Patch::Apply(&patch);
-    let params = PatchParams::apply(crate::FIELD_MANAGER);
-    api.patch(pod_name, &params, &patch).await
+    )
+}
+
+/// Creates a K8S Eip resource for a pod, or updates the one created before.
+#[instrument(skip(api, eip), err)]
+pub async fn create_for_pod(api: &Api<Eip>, eip: &Eip) -> Result<Eip, kube::Error> {
+    let patch = Patch::Apply(eip);
+    let params = PatchParams::apply(eip_operator_shared::metadata_domain());
+    api.patch(&eip.name_any(), &params, &patch).await
+}
+
+/// Sets the selector of an Eip created without one to the pod named after it.
+#[instrument(skip(api, eip), err)]
+pub async fn set_default_selector(api: &Api<Eip>, eip: &Eip) -> Result<Eip, kube::Error> {
+    event!(Level::INFO, "Defaulting the selector of the Eip.");
+    let patch = serde_json::json!({
+        "apiVersion": Eip::version(),
+        "kind": "Eip",
+        "spec": {
+            "selector": {
+                "pod": {
+                    "podName": eip.name_any(),
+                }
+            }
+        }
+    });
+    api.patch(
+        &eip.name_any(),
+        &eip_operator_shared::patch_params(),
+        &Patch::Merge(&patch),
+    )
+    .await
 }
 
 /// Deletes a K8S Eip resource, if it exists.
 #[instrument(skip(api), err)]
-pub(crate) async fn delete(api: &Api<Eip>, name: &str) -> Result<(), kube::Error> {
+pub async fn delete(api: &Api<Eip>, name: &str) -> Result<(), kube::Error> {
     //info!("Deleting K8S Eip: {}", name);
     match api.delete(name, &DeleteParams::default()).await {
         Ok(_) => Ok(()),
@@ -306,10 +610,10 @@ pub(crate) async fn delete(api: &Api<Eip>, name: &str) -> Result<(), kube::Error
 }
 
 /// Sets the allocationId and publicIpAddress fields in the Eip status.
-#[instrument(skip(api), err)]
-pub(crate) async fn set_status_created(
+#[instrument(skip(api, eip), err)]
+pub async fn set_status_created(
     api: &Api<v2::Eip>,
-    name: &str,
+    eip: &Eip,
     allocation_id: &str,
     public_ip_address: &str,
 ) -> Result<Eip, kube::Error> {
@@ -320,11 +624,20 @@ pub(crate) async fn set_status_created(
         "status": {
             "allocationId": allocation_id,
             "publicIpAddress": public_ip_address,
+            "lastError": None::<String>,
+            "lastErrorReason": None::<String>,
+            "lastErrorTime": None::<String>,
         }
     });
-    let patch = Patch::Merge(&patch);
-    let params = PatchParams::default();
-    let result = api.patch_status(name, &params, &patch).await;
+    let entry = (eip.allocation_id() != Some(allocation_id)).then(|| EipHistoryEntry {
+        time: now(),
+        event: "Allocated".to_owned(),
+        allocation_id: Some(allocation_id.to_owned()),
+        target: Some(eip.spec.selector.to_string()),
+        eni: None,
+        private_ip_address: None,
+    });
+    let result = patch_status(api, eip, patch, entry).await;
     if result.is_ok() {
         event!(Level::INFO, "Done updating status for created EIP.");
     }
@@ -332,10 +645,11 @@ pub(crate) async fn set_status_created(
 }
 
 /// Sets the eni and privateIpAddress fields in the Eip status.
-#[instrument(skip(api), err)]
-pub(crate) async fn set_status_attached(
+#[instrument(skip(api, eip), err)]
+pub async fn set_status_attached(
     api: &Api<Eip>,
-    name: &str,
+    eip: &Eip,
+    target: &str,
     eni: &str,
     private_ip_address: &str,
 ) -> Result<Eip, kube::Error> {
@@ -346,11 +660,25 @@ pub(crate) async fn set_status_attached(
         "status": {
             "eni": eni,
             "privateIpAddress": private_ip_address,
+            "detachDeadline": None::<String>,
+            "lastError": None::<String>,
+            "lastErrorReason": None::<String>,
+            "lastErrorTime": None::<String>,
         }
     });
-    let patch = Patch::Merge(&patch);
-    let params = PatchParams::default();
-    let result = api.patch_status(name, &params, &patch).await;
+    let status = eip.status.as_ref();
+    let changed = status.and_then(|status| status.eni.as_deref()) != Some(eni)
+        || status.and_then(|status| status.private_ip_address.as_deref())
+            != Some(private_ip_address);
+    let entry = changed.then(|| EipHistoryEntry {
+        time: now(),
+        event: "Attached".to_owned(),
+        allocation_id: eip.allocation_id().map(str::to_owned),
+        target: Some(target.to_owned()),
+        eni: Some(eni.to_owned()),
+        private_ip_address: Some(private_ip_address.to_owned()),
+    });
+    let result = patch_status(api, eip, patch, entry).await;
     if result.is_ok() {
         event!(Level::INFO, "Done updating status for attached EIP.");
     }
@@ -358,22 +686,361 @@ pub(crate) async fn set_status_attached(
 }
 
 /// Unsets the eni and privateIpAddress fields in the Eip status.
-#[instrument(skip(api), err)]
-pub(crate) async fn set_status_detached(api: &Api<Eip>, name: &str) -> Result<Eip, kube::Error> {
+#[instrument(skip(api, eip), err)]
+pub async fn set_status_detached(api: &Api<Eip>, eip: &Eip) -> Result<Eip, kube::Error> {
     event!(Level::INFO, "Updating status for detached EIP.");
+    let result = clear_attachment(api, eip, "Detached").await;
+    if result.is_ok() {
+        event!(Level::INFO, "Done updating status for detached EIP.");
+    }
+    result
+}
+
+/// Sets the detachDeadline field in the Eip status, leaving the address
+/// associated until then.
+#[instrument(skip(api, eip), err)]
+pub async fn set_status_detach_deferred(
+    api: &Api<Eip>,
+    eip: &Eip,
+    deadline: DateTime<Utc>,
+) -> Result<Eip, kube::Error> {
+    event!(Level::INFO, "Updating status for deferred EIP detachment.");
+    let status = eip.status.as_ref();
+    let entry = EipHistoryEntry {
+        time: now(),
+        event: "DetachDeferred".to_owned(),
+        allocation_id: eip.allocation_id().map(str::to_owned),
+        target: None,
+        eni: status.and_then(|status| status.eni.clone()),
+        private_ip_address: status.and_then(|status| status.private_ip_address.clone()),
+    };
+    let patch = serde_json::json!({
+        "apiVersion": Eip::version(),
+        "kind": "Eip",
+        "status": {
+            "detachDeadline": deadline.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    });
+    patch_status(api, eip, patch, Some(entry)).await
+}
+
+/// Unsets the eni and privateIpAddress fields in the Eip status, after the
+/// address was found to no longer be associated with them, so that the pod or
+/// node reconciler associates it again.
+#[instrument(skip(api, eip), err)]
+pub async fn set_status_drifted(api: &Api<Eip>, eip: &Eip) -> Result<Eip, kube::Error> {
+    event!(Level::INFO, "Updating status for drifted EIP.");
+    clear_attachment(api, eip, "DriftDetected").await
+}
+
+async fn clear_attachment(api: &Api<Eip>, eip: &Eip, event: &str) -> Result<Eip, kube::Error> {
     let patch = serde_json::json!({
         "apiVersion": Eip::version(),
         "kind": "Eip",
         "status": {
             "eni": None::<String>,
             "privateIpAddress": None::<String>,
+            "detachDeadline": None::<String>,
         }
     });
-    let patch = Patch::Merge(&patch);
-    let params = PatchParams::default();
-    let result = api.patch_status(name, &params, &patch).await;
-    if result.is_ok() {
-        event!(Level::INFO, "Done updating status for detached EIP.");
+    let status = eip.status.as_ref();
+    let entry = eip.attached().then(|| EipHistoryEntry {
+        time: now(),
+        event: event.to_owned(),
+        allocation_id: eip.allocation_id().map(str::to_owned),
+        target: None,
+        eni: status.and_then(|status| status.eni.clone()),
+        private_ip_address: status.and_then(|status| status.private_ip_address.clone()),
+    });
+    patch_status(api, eip, patch, entry).await
+}
+
+/// Unsets the allocation and attachment fields in the Eip status, after its
+/// address was found to no longer exist, so that a new one is allocated.
+#[instrument(skip(api, eip), err)]
+pub async fn set_status_allocation_lost(api: &Api<Eip>, eip: &Eip) -> Result<Eip, kube::Error> {
+    event!(Level::INFO, "Updating status for lost EIP allocation.");
+    clear_allocation(api, eip, "AllocationLost").await
+}
+
+/// Records in the Eip's history that its address was reported idle.
+#[instrument(skip(api, eip), err)]
+pub async fn set_status_idle(api: &Api<Eip>, eip: &Eip) -> Result<Eip, kube::Error> {
+    event!(Level::INFO, "Updating status for idle EIP.");
+    let entry = EipHistoryEntry {
+        time: now(),
+        event: "Idle".to_owned(),
+        allocation_id: eip.allocation_id().map(str::to_owned),
+        target: None,
+        eni: None,
+        private_ip_address: None,
+    };
+    let patch = serde_json::json!({
+        "apiVersion": Eip::version(),
+        "kind": "Eip",
+        "status": {}
+    });
+    patch_status(api, eip, patch, Some(entry)).await
+}
+
+/// Unsets the allocation fields in the Eip status, after its address was
+/// released for being idle, so that none is allocated until it has a target.
+#[instrument(skip(api, eip), err)]
+pub async fn set_status_idle_released(api: &Api<Eip>, eip: &Eip) -> Result<Eip, kube::Error> {
+    event!(Level::INFO, "Updating status for released idle EIP.");
+    clear_allocation(api, eip, "IdleReleased").await
+}
+
+async fn clear_allocation(api: &Api<Eip>, eip: &Eip, event: &str) -> Result<Eip, kube::Error> {
+    let entry = EipHistoryEntry {
+        time: now(),
+        event: event.to_owned(),
+        allocation_id: eip.allocation_id().map(str::to_owned),
+        target: None,
+        eni: None,
+        private_ip_address: None,
+    };
+    let patch = serde_json::json!({
+        "apiVersion": Eip::version(),
+        "kind": "Eip",
+        "status": {
+            "allocationId": None::<String>,
+            "publicIpAddress": None::<String>,
+            "eni": None::<String>,
+            "privateIpAddress": None::<String>,
+        }
+    });
+    patch_status(api, eip, patch, Some(entry)).await
+}
+
+/// Records a failed reconciliation in the lastError fields of the Eip status.
+#[instrument(skip(api, error), err)]
+pub async fn set_status_error(
+    api: &Api<Eip>,
+    name: &str,
+    error: &Error,
+) -> Result<Eip, kube::Error> {
+    event!(
+        Level::INFO,
+        "Updating status for failed EIP reconciliation."
+    );
+    let patch = serde_json::json!({
+        "apiVersion": Eip::version(),
+        "kind": "Eip",
+        "status": {
+            "lastError": error.to_string(),
+            "lastErrorReason": error.reason(),
+            "lastErrorTime": now(),
+        }
+    });
+    merge_status(api, name, &patch).await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::{Body, Method, Request, Response, StatusCode};
+    use kube::{Api, Client};
+    use proptest::prelude::*;
+
+    use super::v2::{selector_matches, Eip, EipSelector, EipSpec};
+    use super::{set_status_idle, EipHistoryEntry, EipStatus};
+
+    pub(crate) fn labels() -> impl Strategy<Value = BTreeMap<String, String>> {
+        prop::collection::btree_map("[a-z./]{1,4}", "[a-z0-9]{0,3}", 0..6)
+    }
+
+    pub(crate) fn selector() -> impl Strategy<Value = EipSelector> {
+        prop_oneof![
+            ("[a-z0-9-]{1,12}", prop::option::of("[a-f0-9]{8}")).prop_map(|(pod_name, pod_uid)| {
+                EipSelector::Pod {
+                    pod_name,
+                    pod_uid,
+                    network: None,
+                }
+            }),
+            labels().prop_map(|selector| EipSelector::Node { selector }),
+        ]
+    }
+
+    fn eip(selector: EipSelector) -> Eip {
+        Eip::new(
+            "my-eip",
+            EipSpec {
+                selector,
+                provider: None,
+                dns_name: None,
+                transfer_to: None,
+                tags: None,
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn selectors_match_labels_including_them(selector in labels(), others in labels()) {
+            let mut labels = others;
+            labels.extend(selector.clone());
+            prop_assert!(selector_matches(&selector, &labels));
+        }
+
+        #[test]
+        fn empty_selectors_match_any_labels(labels in labels()) {
+            prop_assert!(selector_matches(&BTreeMap::new(), &labels));
+        }
+
+        #[test]
+        fn selectors_do_not_match_labels_missing_a_key(
+            selector in labels(),
+            others in labels(),
+            index in any::<prop::sample::Index>(),
+        ) {
+            prop_assume!(!selector.is_empty());
+            let mut labels = others;
+            labels.extend(selector.clone());
+            let key = selector.keys().nth(index.index(selector.len())).unwrap();
+            labels.remove(key);
+            prop_assert!(!selector_matches(&selector, &labels));
+        }
+
+        #[test]
+        fn selectors_do_not_match_labels_with_another_value(
+            selector in labels(),
+            index in any::<prop::sample::Index>(),
+        ) {
+            prop_assume!(!selector.is_empty());
+            let mut labels = selector.clone();
+            let key = selector.keys().nth(index.index(selector.len())).unwrap();
+            labels.get_mut(key).unwrap().push('!');
+            prop_assert!(!selector_matches(&selector, &labels));
+        }
+
+        #[test]
+        fn eips_match_nodes_by_their_selector(selector in selector(), labels in labels()) {
+            let eip = eip(selector.clone());
+            let expected = match &selector {
+                EipSelector::Node { selector } => selector_matches(selector, &labels),
+                EipSelector::Pod { .. } => false,
+            };
+            prop_assert_eq!(eip.matches_node(&labels), expected);
+        }
+
+        #[test]
+        fn eips_match_only_the_pod_they_name(selector in selector(), pod_name in "[a-z0-9-]{1,12}") {
+            let eip = eip(selector.clone());
+            let expected = match &selector {
+                EipSelector::Pod { pod_name: name, .. } => *name == pod_name,
+                EipSelector::Node { .. } => false,
+            };
+            prop_assert_eq!(eip.matches_pod(&pod_name), expected);
+            if let EipSelector::Pod { pod_name, .. } = &selector {
+                prop_assert!(eip.matches_pod(pod_name));
+            }
+        }
+
+        #[test]
+        fn eips_match_any_pod_uid_unless_they_name_one(selector in selector(), pod_uid in "[a-f0-9]{8}") {
+            let eip = eip(selector.clone());
+            let expected = match &selector {
+                EipSelector::Pod { pod_uid: Some(uid), .. } => *uid == pod_uid,
+                _ => true,
+            };
+            prop_assert_eq!(eip.matches_pod_uid(&pod_uid), expected);
+        }
+    }
+
+    #[test]
+    fn eips_recognize_the_enis_they_recorded_or_any_before_attaching() {
+        let mut eip = eip(EipSelector::default());
+        assert!(eip.recognizes_eni("eni-1"));
+        eip.status = Some(EipStatus {
+            eni: Some("eni-2".to_owned()),
+            history: Some(vec![EipHistoryEntry {
+                time: "2024-01-01T00:00:00Z".to_owned(),
+                event: "Attached".to_owned(),
+                allocation_id: Some("eipalloc-1".to_owned()),
+                target: Some("my-pod".to_owned()),
+                eni: Some("eni-1".to_owned()),
+                private_ip_address: Some("10.0.0.1".to_owned()),
+            }]),
+            ..Default::default()
+        });
+        assert!(eip.recognizes_eni("eni-1"));
+        assert!(eip.recognizes_eni("eni-2"));
+        assert!(!eip.recognizes_eni("eni-3"));
+    }
+
+    fn history_events(patch: &serde_json::Value) -> Vec<&str> {
+        patch["status"]["history"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["event"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn status_patches_keep_the_history_of_a_conflicting_update() {
+        let mut stale = eip(EipSelector::default());
+        stale.metadata.namespace = Some("default".to_owned());
+        stale.metadata.resource_version = Some("1".to_owned());
+        let mut current = stale.clone();
+        current.metadata.resource_version = Some("2".to_owned());
+        current.status = Some(EipStatus {
+            history: Some(vec![EipHistoryEntry {
+                time: "2024-01-01T00:00:00Z".to_owned(),
+                event: "Attached".to_owned(),
+                allocation_id: None,
+                target: Some("my-pod".to_owned()),
+                eni: Some("eni-1".to_owned()),
+                private_ip_address: Some("10.0.0.1".to_owned()),
+            }]),
+            ..Default::default()
+        });
+        // Another controller updated the Eip since it was read, so the first
+        // patch conflicts.
+        let (service, mut handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let patches = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&patches);
+        tokio::spawn(async move {
+            while let Some((request, send)) = handle.next_request().await {
+                let (parts, body) = request.into_parts();
+                if parts.method == Method::GET {
+                    let body = serde_json::to_vec(&current).unwrap();
+                    send.send_response(Response::new(Body::from(body)));
+                    continue;
+                }
+                let body = hyper::body::to_bytes(body).await.unwrap();
+                let mut patches = recorded.lock().unwrap();
+                patches.push(serde_json::from_slice::<serde_json::Value>(&body).unwrap());
+                let response = if patches.len() == 1 {
+                    let status = serde_json::json!({
+                        "kind": "Status",
+                        "apiVersion": "v1",
+                        "status": "Failure",
+                        "message": "the object has been modified",
+                        "reason": "Conflict",
+                        "code": 409,
+                    });
+                    Response::builder()
+                        .status(StatusCode::CONFLICT)
+                        .body(Body::from(serde_json::to_vec(&status).unwrap()))
+                        .unwrap()
+                } else {
+                    Response::new(Body::from(serde_json::to_vec(&current).unwrap()))
+                };
+                send.send_response(response);
+            }
+        });
+        let api = Api::<Eip>::namespaced(Client::new(service, "default"), "default");
+        set_status_idle(&api, &stale).await.unwrap();
+        let patches = patches.lock().unwrap();
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0]["metadata"]["resourceVersion"], "1");
+        assert_eq!(history_events(&patches[0]), ["Idle"]);
+        assert_eq!(patches[1]["metadata"]["resourceVersion"], "2");
+        assert_eq!(history_events(&patches[1]), ["Attached", "Idle"]);
     }
-    result
 }
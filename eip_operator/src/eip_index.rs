@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+use futures::future;
+use futures::stream::StreamExt;
+use kube::api::{Api, ListParams};
+use kube::ResourceExt;
+use kube_runtime::watcher;
+use tracing::{event, Level};
+
+use eip_operator_shared::Error;
+
+use crate::eip::v2::{Eip, EipSelector};
+
+/// Identifies a namespaced object by its namespace and name.
+type Key = (String, String);
+
+/// An in-memory index of Eips, kept up to date by a watcher, so that the pod
+/// and node reconcilers can find their Eip without listing every Eip.
+#[derive(Clone, Default)]
+pub(crate) struct EipIndex(Arc<RwLock<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    /// Whether the initial list of Eips has been received.
+    ready: bool,
+    eips: HashMap<Key, Arc<Eip>>,
+    /// Eips with a pod selector, keyed by the namespace and name of the pod.
+    /// A pod may be selected by several, such as one for its default network
+    /// and one for a network attachment.
+    by_pod: HashMap<Key, BTreeSet<Key>>,
+}
+
+fn key(eip: &Eip) -> Key {
+    (eip.namespace().unwrap_or_default(), eip.name_any())
+}
+
+impl Inner {
+    fn insert(&mut self, eip: Eip) {
+        let key = key(&eip);
+        self.remove(&key);
+        if let EipSelector::Pod { pod_name, .. } = &eip.spec.selector {
+            self.by_pod
+                .entry((key.0.clone(), pod_name.clone()))
+                .or_default()
+                .insert(key.clone());
+        }
+        self.eips.insert(key, Arc::new(eip));
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(eip) = self.eips.remove(key) {
+            if let EipSelector::Pod { pod_name, .. } = &eip.spec.selector {
+                let pod_key = (key.0.clone(), pod_name.clone());
+                if let Some(keys) = self.by_pod.get_mut(&pod_key) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.by_pod.remove(&pod_key);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl EipIndex {
+    /// Watches Eips with the supplied api, keeping the index up to date.
+    pub(crate) async fn run(self, api: Api<Eip>, list_params: ListParams) {
+        watcher(api, list_params)
+            .for_each(|event| {
+                match event {
+                    Ok(event) => self.apply(event),
+                    Err(err) => event!(Level::ERROR, err = %err, "Eip index watch error."),
+                }
+                future::ready(())
+            })
+            .await
+    }
+
+    /// Updates the index with an event of the watch.
+    pub(crate) fn apply(&self, event: watcher::Event<Eip>) {
+        let mut inner = self.0.write().unwrap();
+        match event {
+            watcher::Event::Applied(eip) => inner.insert(eip),
+            watcher::Event::Deleted(eip) => inner.remove(&key(&eip)),
+            watcher::Event::Restarted(eips) => {
+                *inner = Inner {
+                    ready: true,
+                    ..Default::default()
+                };
+                for eip in eips {
+                    inner.insert(eip);
+                }
+            }
+        }
+    }
+
+    /// Finds the Eips whose pod selector matches the named pod, ordered by
+    /// name.
+    ///
+    /// Errors if the initial list of Eips has not been received yet, since we
+    /// can't yet tell whether there is a matching Eip or not.
+    pub(crate) fn find_all_for_pod(
+        &self,
+        namespace: &str,
+        pod_name: &str,
+    ) -> Result<Vec<Arc<Eip>>, Error> {
+        let inner = self.0.read().unwrap();
+        if !inner.ready {
+            return Err(Error::EipIndexNotReady);
+        }
+        Ok(inner
+            .by_pod
+            .get(&(namespace.to_owned(), pod_name.to_owned()))
+            .into_iter()
+            .flatten()
+            .filter_map(|key| inner.eips.get(key))
+            .filter(|eip| eip.matches_pod(pod_name))
+            .cloned()
+            .collect())
+    }
+
+    /// Finds the pod's primary Eip, the first by name of those whose pod
+    /// selector matches the named pod, whose address is the pod's public IP.
+    ///
+    /// Errors if the initial list of Eips has not been received yet.
+    pub(crate) fn find_for_pod(
+        &self,
+        namespace: &str,
+        pod_name: &str,
+    ) -> Result<Option<Arc<Eip>>, Error> {
+        Ok(self
+            .find_all_for_pod(namespace, pod_name)?
+            .into_iter()
+            .next())
+    }
+
+    /// Finds the Eip with the name in the namespace.
+    ///
+    /// Errors if the initial list of Eips has not been received yet.
+    pub(crate) fn find_by_name(
+        &self,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Option<Arc<Eip>>, Error> {
+        let inner = self.0.read().unwrap();
+        if !inner.ready {
+            return Err(Error::EipIndexNotReady);
+        }
+        Ok(inner
+            .eips
+            .get(&(namespace.to_owned(), name.to_owned()))
+            .cloned())
+    }
+
+    /// Finds the Eips in the namespace whose node selector matches the labels.
+    pub(crate) fn find_for_node(
+        &self,
+        namespace: &str,
+        node_labels: &BTreeMap<String, String>,
+    ) -> Result<Vec<Arc<Eip>>, Error> {
+        let inner = self.0.read().unwrap();
+        if !inner.ready {
+            return Err(Error::EipIndexNotReady);
+        }
+        Ok(inner
+            .eips
+            .iter()
+            .filter(|((eip_namespace, _), eip)| {
+                eip_namespace == namespace && eip.matches_node(node_labels)
+            })
+            .map(|(_, eip)| Arc::clone(eip))
+            .collect())
+    }
+
+    /// Finds the Eips with a node selector which either match the labels, or
+    /// are attached to one of the node's IPs, so may need to move when the
+    /// node's labels change. Returns nothing until the initial list is
+    /// received.
+    pub(crate) fn find_related_to_node(
+        &self,
+        node_labels: &BTreeMap<String, String>,
+        node_ips: &[&str],
+    ) -> Vec<Arc<Eip>> {
+        let inner = self.0.read().unwrap();
+        if !inner.ready {
+            return vec![];
+        }
+        inner
+            .eips
+            .values()
+            .filter(|eip| match eip.spec.selector {
+                EipSelector::Node { .. } => {
+                    eip.matches_node(node_labels)
+                        || eip
+                            .attachment()
+                            .2
+                            .map_or(false, |private_ip| node_ips.contains(&private_ip))
+                }
+                EipSelector::Pod { .. } => false,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the initial list of Eips has been received.
+    pub(crate) fn ready(&self) -> bool {
+        self.0.read().unwrap().ready
+    }
+
+    /// All Eips currently in the index.
+    pub(crate) fn all(&self) -> Vec<Arc<Eip>> {
+        self.0.read().unwrap().eips.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip::v2::EipSpec;
+
+    fn eip(name: &str, network: Option<&str>) -> Eip {
+        let mut eip = Eip::new(
+            name,
+            EipSpec {
+                selector: EipSelector::Pod {
+                    pod_name: "my-pod".to_owned(),
+                    pod_uid: None,
+                    network: network.map(str::to_owned),
+                },
+                provider: None,
+                dns_name: None,
+                transfer_to: None,
+                tags: None,
+            },
+        );
+        eip.metadata.namespace = Some("default".to_owned());
+        eip
+    }
+
+    #[test]
+    fn pods_may_be_selected_by_several_eips() {
+        let index = EipIndex::default();
+        index.apply(watcher::Event::Restarted(vec![
+            eip("my-pod-macvlan", Some("macvlan-conf")),
+            eip("my-pod", None),
+        ]));
+        let names = |index: &EipIndex| -> Vec<String> {
+            index
+                .find_all_for_pod("default", "my-pod")
+                .unwrap()
+                .iter()
+                .map(|eip| eip.name_any())
+                .collect()
+        };
+        assert_eq!(names(&index), ["my-pod", "my-pod-macvlan"]);
+        assert_eq!(
+            index
+                .find_for_pod("default", "my-pod")
+                .unwrap()
+                .map(|eip| eip.name_any()),
+            Some("my-pod".to_owned())
+        );
+
+        index.apply(watcher::Event::Deleted(eip("my-pod", None)));
+        assert_eq!(names(&index), ["my-pod-macvlan"]);
+    }
+}
@@ -0,0 +1,184 @@
+//! Failures injected into cloud calls and Eip status patches, for testing how
+//! the operator retries and backs off under chaos. Only built with the
+//! `fault-injection` feature.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use tracing::{event, Level};
+
+use eip_operator_shared::Error;
+
+use crate::provider::{Address, AllocationRequest, CloudProvider};
+
+/// How long an injected timeout hangs before failing.
+const INJECTED_TIMEOUT: Duration = Duration::from_secs(30);
+
+static FAULT_RATES: OnceCell<FaultRates> = OnceCell::new();
+
+/// The probability, from 0 to 1, of each kind of fault being injected into a
+/// call.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FaultRates {
+    /// Cloud calls failing as if throttled.
+    pub throttle: f64,
+    /// Cloud calls on an address failing as if it no longer existed.
+    pub not_found: f64,
+    /// Cloud calls hanging, then failing.
+    pub timeout: f64,
+    /// Eip status patches failing with a server error.
+    pub status_patch: f64,
+}
+
+/// Sets the rates faults are injected at. Only the first call has any effect.
+pub fn set_fault_rates(rates: FaultRates) {
+    if FAULT_RATES.set(rates).is_ok() {
+        event!(
+            Level::WARN,
+            ?rates,
+            "Injecting faults into cloud calls and status patches."
+        );
+    }
+}
+
+fn rates() -> FaultRates {
+    FAULT_RATES.get().copied().unwrap_or_default()
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+/// Fails the Eip status patch, at the configured rate.
+pub(crate) fn status_patch_fault(name: &str) -> Result<(), kube::Error> {
+    if !roll(rates().status_patch) {
+        return Ok(());
+    }
+    event!(Level::INFO, %name, "Injecting a fault into an Eip status patch.");
+    Err(kube::Error::Api(kube::error::ErrorResponse {
+        status: "Failure".to_owned(),
+        message: format!("Injected fault patching the status of Eip {}", name),
+        reason: "InternalError".to_owned(),
+        code: 500,
+    }))
+}
+
+/// Fails the cloud call, at the configured rates. Only calls on an existing
+/// address can fail with it not being found.
+async fn cloud_fault(operation: &str, on_address: bool) -> Result<(), Error> {
+    let rates = rates();
+    let reason = if roll(rates.throttle) {
+        "Throttled"
+    } else if on_address && roll(rates.not_found) {
+        "AllocationNotFound"
+    } else if roll(rates.timeout) {
+        tokio::time::sleep(INJECTED_TIMEOUT).await;
+        "Timeout"
+    } else {
+        return Ok(());
+    };
+    event!(Level::INFO, %operation, %reason, "Injecting a fault into a cloud call.");
+    Err(Error::InjectedFault {
+        operation: operation.to_owned(),
+        reason,
+    })
+}
+
+/// Injects faults into the calls of another provider.
+pub struct FaultyProvider {
+    inner: Arc<dyn CloudProvider>,
+}
+
+impl FaultyProvider {
+    pub fn new(inner: Arc<dyn CloudProvider>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait::async_trait]
+impl CloudProvider for FaultyProvider {
+    async fn allocate(&self, request: AllocationRequest<'_>) -> Result<Address, Error> {
+        cloud_fault("allocate", false).await?;
+        self.inner.allocate(request).await
+    }
+
+    async fn describe(&self, namespace: &str, allocation_id: &str) -> Result<Address, Error> {
+        cloud_fault("describe", true).await?;
+        self.inner.describe(namespace, allocation_id).await
+    }
+
+    async fn describe_by_tag(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<Address>, Error> {
+        cloud_fault("describe_by_tag", false).await?;
+        self.inner.describe_by_tag(namespace, key, value).await
+    }
+
+    async fn describe_cluster_addresses(
+        &self,
+        cluster_name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Vec<Address>, Error> {
+        cloud_fault("describe_cluster_addresses", false).await?;
+        self.inner
+            .describe_cluster_addresses(cluster_name, namespace)
+            .await
+    }
+
+    async fn network_interface(
+        &self,
+        instance_id: &str,
+        private_ip: &str,
+    ) -> Result<String, Error> {
+        cloud_fault("network_interface", false).await?;
+        self.inner.network_interface(instance_id, private_ip).await
+    }
+
+    async fn dedicated_network_interface(
+        &self,
+        instance_id: &str,
+        ip_count: usize,
+    ) -> Result<(String, Vec<String>), Error> {
+        cloud_fault("dedicated_network_interface", false).await?;
+        self.inner
+            .dedicated_network_interface(instance_id, ip_count)
+            .await
+    }
+
+    async fn associate(
+        &self,
+        namespace: &str,
+        allocation_id: &str,
+        eni: &str,
+        private_ip: &str,
+    ) -> Result<(), Error> {
+        cloud_fault("associate", true).await?;
+        self.inner
+            .associate(namespace, allocation_id, eni, private_ip)
+            .await
+    }
+
+    async fn disassociate(&self, namespace: &str, allocation_id: &str) -> Result<(), Error> {
+        cloud_fault("disassociate", true).await?;
+        self.inner.disassociate(namespace, allocation_id).await
+    }
+
+    async fn release(&self, namespace: &str, address: &Address) -> Result<(), Error> {
+        cloud_fault("release", true).await?;
+        self.inner.release(namespace, address).await
+    }
+
+    async fn converge_tags(
+        &self,
+        namespace: &str,
+        address: &Address,
+        tags: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        cloud_fault("converge_tags", true).await?;
+        self.inner.converge_tags(namespace, address, tags).await
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::client::HttpConnector;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request};
+use hyper_tls::HttpsConnector;
+use k8s_openapi::chrono::{DateTime, TimeZone, Utc};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, instrument};
+
+use eip_operator_shared::Error;
+
+use crate::aws::{
+    ALLOCATED_AT_TAG, CLUSTER_NAME_TAG, GENERATED_TAGS, NAMESPACE_TAG, NODE_SELECTOR_TAG,
+    POD_NAME_TAG, PROTECTED_TAG, TRANSFERRED_FROM_TAG,
+};
+use crate::provider::{dry_run, Address, AllocationRequest, CloudProvider};
+
+const COMPUTE_API: &str = "https://compute.googleapis.com/compute/v1";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Tokens are refreshed this long before they expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// The labels recording which network interface and private IP an address is
+/// assigned to, since GCE only records the instance using it.
+const NETWORK_INTERFACE_LABEL: &str = "eip_network_interface";
+const PRIVATE_IP_LABEL: &str = "eip_private_ip";
+
+const ACCESS_CONFIG_NAME: &str = "eip-operator";
+
+/// Reserves regional static external IPs with the Compute Engine API, and
+/// assigns them to the network interfaces of GCE instances.
+///
+/// GCE addresses are identified by their name, and carry labels rather than
+/// tags, so tag keys and values are reduced to the characters labels allow.
+/// An address can only be assigned to a network interface as a whole, so the
+/// address of a pod in an alias IP range is assigned to its node's interface.
+pub struct GcpProvider {
+    project: String,
+    region: String,
+    client: hyper::Client<HttpsConnector<HttpConnector>>,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl GcpProvider {
+    pub fn new(project: String, region: String) -> Self {
+        Self {
+            project,
+            region,
+            client: hyper::Client::builder().build(HttpsConnector::new()),
+            token: Mutex::new(None),
+        }
+    }
+
+    fn addresses_url(&self) -> String {
+        format!(
+            "{}/projects/{}/regions/{}/addresses",
+            COMPUTE_API, self.project, self.region
+        )
+    }
+
+    /// An access token of the instance's service account, from the metadata server.
+    async fn token(&self) -> Result<String, Error> {
+        if let Some((token, expires)) = &*self.token.lock().unwrap() {
+            if *expires > Instant::now() + TOKEN_EXPIRY_MARGIN {
+                return Ok(token.clone());
+            }
+        }
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+            expires_in: u64,
+        }
+        let request = Request::get(METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .body(Body::empty())?;
+        let response: TokenResponse = self.send("metadata.token", request).await?;
+        let expires = Instant::now() + Duration::from_secs(response.expires_in);
+        *self.token.lock().unwrap() = Some((response.access_token.clone(), expires));
+        Ok(response.access_token)
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        operation: &'static str,
+        request: Request<Body>,
+    ) -> Result<T, Error> {
+        let response = self.client.request(request).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            return Err(Error::GcpApi {
+                operation,
+                status: status.as_u16(),
+                message: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        operation: &'static str,
+        method: Method,
+        url: String,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, Error> {
+        let request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token().await?))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))?;
+        self.send(operation, request).await
+    }
+
+    /// Makes a call which starts an operation, then waits for it to finish.
+    /// In dry-run mode, fails without making the call.
+    async fn mutate(
+        &self,
+        operation: &'static str,
+        url: String,
+        method: Method,
+        body: Option<serde_json::Value>,
+    ) -> Result<(), Error> {
+        if dry_run() {
+            return Err(Error::GcpDryRun(operation));
+        }
+        let mut pending: Operation = self.call(operation, method, url, body).await?;
+        while pending.status != "DONE" {
+            pending = self
+                .call(
+                    operation,
+                    Method::POST,
+                    format!("{}/wait", pending.self_link),
+                    None,
+                )
+                .await?;
+        }
+        match pending.error {
+            Some(error) => Err(Error::GcpOperation {
+                operation,
+                message: error.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    async fn get_address(&self, name: &str) -> Result<GcpAddress, Error> {
+        let url = format!("{}/{}", self.addresses_url(), name);
+        match self.call("addresses.get", Method::GET, url, None).await {
+            Err(Error::GcpApi { status: 404, .. }) => {
+                Err(Error::GcpAddressNotFound(name.to_owned()))
+            }
+            result => result,
+        }
+    }
+
+    /// Lists the region's addresses with all of the labels.
+    async fn list_addresses(&self, labels: &[(&str, &str)]) -> Result<Vec<GcpAddress>, Error> {
+        let filter = labels
+            .iter()
+            .map(|(key, value)| format!("(labels.{} = \"{}\")", label_key(key), label_value(value)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut addresses = vec![];
+        let mut page_token = None;
+        loop {
+            let mut url = format!("{}?filter={}", self.addresses_url(), encode(&filter));
+            if let Some(page_token) = &page_token {
+                url.push_str(&format!("&pageToken={}", encode(page_token)));
+            }
+            let page: AddressList = self.call("addresses.list", Method::GET, url, None).await?;
+            addresses.extend(page.items);
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                return Ok(addresses);
+            }
+        }
+    }
+
+    async fn set_labels(
+        &self,
+        address: &GcpAddress,
+        labels: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        let url = format!("{}/{}/setLabels", self.addresses_url(), address.name);
+        let body = json!({
+            "labels": labels,
+            "labelFingerprint": address.label_fingerprint,
+        });
+        self.mutate("addresses.setLabels", url, Method::POST, Some(body))
+            .await
+    }
+
+    async fn get_instance(&self, instance_path: &str) -> Result<Instance, Error> {
+        let url = format!("{}/{}", COMPUTE_API, instance_path);
+        self.call("instances.get", Method::GET, url, None).await
+    }
+
+    /// Removes the access configs with the address's IP from the instances using it.
+    async fn unassign(&self, address: &GcpAddress) -> Result<(), Error> {
+        for user in &address.users {
+            let instance_path = resource_path(user);
+            let instance = match self.get_instance(instance_path).await {
+                Err(Error::GcpApi { status: 404, .. }) => continue,
+                result => result?,
+            };
+            for interface in &instance.network_interfaces {
+                for access_config in &interface.access_configs {
+                    if access_config.nat_ip.as_deref() == Some(address.address.as_str()) {
+                        self.delete_access_config(instance_path, interface, access_config)
+                            .await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_access_config(
+        &self,
+        instance_path: &str,
+        interface: &NetworkInterface,
+        access_config: &AccessConfig,
+    ) -> Result<(), Error> {
+        let url = format!(
+            "{}/{}/deleteAccessConfig?accessConfig={}&networkInterface={}",
+            COMPUTE_API,
+            instance_path,
+            encode(&access_config.name),
+            encode(&interface.name),
+        );
+        self.mutate("instances.deleteAccessConfig", url, Method::POST, None)
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl CloudProvider for GcpProvider {
+    #[instrument(skip(self, request), err)]
+    async fn allocate(&self, request: AllocationRequest<'_>) -> Result<Address, Error> {
+        let name = format!("eip-{}", request.eip_uid);
+        let labels: HashMap<String, String> = request
+            .tags()
+            .iter()
+            .map(|(key, value)| to_label(key, value))
+            .collect();
+        let body = json!({
+            "name": name,
+            "addressType": "EXTERNAL",
+            "labels": labels,
+        });
+        self.mutate(
+            "addresses.insert",
+            self.addresses_url(),
+            Method::POST,
+            Some(body),
+        )
+        .await?;
+        Ok(self.get_address(&name).await?.into())
+    }
+
+    async fn describe(&self, _namespace: &str, allocation_id: &str) -> Result<Address, Error> {
+        Ok(self.get_address(allocation_id).await?.into())
+    }
+
+    async fn describe_by_tag(
+        &self,
+        _namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<Address>, Error> {
+        let addresses = self.list_addresses(&[(key, value)]).await?;
+        Ok(addresses.into_iter().map(Address::from).collect())
+    }
+
+    async fn describe_cluster_addresses(
+        &self,
+        cluster_name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Vec<Address>, Error> {
+        let mut labels = vec![(CLUSTER_NAME_TAG, cluster_name)];
+        if let Some(namespace) = namespace {
+            labels.push((NAMESPACE_TAG, namespace));
+        }
+        let addresses = self.list_addresses(&labels).await?;
+        Ok(addresses.into_iter().map(Address::from).collect())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn network_interface(
+        &self,
+        instance_id: &str,
+        private_ip: &str,
+    ) -> Result<String, Error> {
+        let url = format!(
+            "{}/projects/{}/aggregated/instances?filter={}",
+            COMPUTE_API,
+            self.project,
+            encode(&format!("name = \"{}\"", instance_id)),
+        );
+        let list: AggregatedInstanceList = self
+            .call("instances.aggregatedList", Method::GET, url, None)
+            .await?;
+        let instance = list
+            .items
+            .into_values()
+            .flat_map(|scope| scope.instances)
+            .find(|instance| instance.name == instance_id)
+            .ok_or(Error::MissingInstances)?;
+        if instance.status == "STOPPING" || instance.status == "TERMINATED" {
+            return Err(Error::InstanceTerminated(instance_id.to_owned()));
+        }
+        let interface = instance
+            .network_interfaces
+            .iter()
+            .find(|interface| {
+                interface.network_ip == private_ip
+                    || interface
+                        .alias_ip_ranges
+                        .iter()
+                        .any(|range| cidr_contains(&range.ip_cidr_range, private_ip))
+            })
+            .ok_or(Error::NoInterfaceWithThatIp)?;
+        Ok(format!(
+            "{}/networkInterfaces/{}",
+            resource_path(&instance.self_link),
+            interface.name
+        ))
+    }
+
+    #[instrument(skip(self), err)]
+    async fn associate(
+        &self,
+        _namespace: &str,
+        allocation_id: &str,
+        eni: &str,
+        private_ip: &str,
+    ) -> Result<(), Error> {
+        let (instance_path, interface_name) = eni
+            .split_once("/networkInterfaces/")
+            .ok_or(Error::NoInterfaceWithThatIp)?;
+        let address = self.get_address(allocation_id).await?;
+        if address
+            .users
+            .iter()
+            .any(|user| resource_path(user) != instance_path)
+        {
+            self.unassign(&address).await?;
+        }
+        let instance = self.get_instance(instance_path).await?;
+        let interface = instance
+            .network_interfaces
+            .iter()
+            .find(|interface| interface.name == interface_name)
+            .ok_or(Error::NoInterfaceWithThatIp)?;
+        let assigned = interface
+            .access_configs
+            .iter()
+            .any(|access_config| access_config.nat_ip.as_deref() == Some(address.address.as_str()));
+        if !assigned {
+            // An interface has at most one external IP, so any ephemeral one is replaced.
+            for access_config in &interface.access_configs {
+                self.delete_access_config(instance_path, interface, access_config)
+                    .await?;
+            }
+            let url = format!(
+                "{}/{}/addAccessConfig?networkInterface={}",
+                COMPUTE_API,
+                instance_path,
+                encode(interface_name),
+            );
+            let body = json!({
+                "name": ACCESS_CONFIG_NAME,
+                "type": "ONE_TO_ONE_NAT",
+                "natIP": address.address,
+            });
+            self.mutate("instances.addAccessConfig", url, Method::POST, Some(body))
+                .await?;
+            info!(%allocation_id, %eni, "Assigned address to network interface.");
+        }
+        // Operations are waited for, so the new labels are all that remain to be made visible.
+        let address = self.get_address(allocation_id).await?;
+        let mut labels = address.labels.clone();
+        labels.insert(
+            NETWORK_INTERFACE_LABEL.to_owned(),
+            label_value(interface_name),
+        );
+        labels.insert(PRIVATE_IP_LABEL.to_owned(), label_value(private_ip));
+        if labels != address.labels {
+            self.set_labels(&address, labels).await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn disassociate(&self, _namespace: &str, allocation_id: &str) -> Result<(), Error> {
+        match self.get_address(allocation_id).await {
+            Ok(address) => self.unassign(&address).await,
+            Err(err) if err.is_allocation_not_found() => {
+                debug!(already_released = true);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    #[instrument(skip(self, address), fields(allocation_id = %address.allocation_id), err)]
+    async fn release(&self, namespace: &str, address: &Address) -> Result<(), Error> {
+        if address.association_id.is_some() {
+            self.disassociate(namespace, &address.allocation_id).await?;
+        }
+        let url = format!("{}/{}", self.addresses_url(), address.allocation_id);
+        self.mutate("addresses.delete", url, Method::DELETE, None)
+            .await
+    }
+
+    async fn converge_tags(
+        &self,
+        _namespace: &str,
+        address: &Address,
+        tags: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        let current = self.get_address(&address.allocation_id).await?;
+        let mut labels = current.labels.clone();
+        labels.extend(tags.iter().map(|(key, value)| to_label(key, value)));
+        if labels == current.labels {
+            return Ok(());
+        }
+        self.set_labels(&current, labels).await
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Operation {
+    self_link: String,
+    status: String,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcpAddress {
+    name: String,
+    address: String,
+    #[serde(default)]
+    users: Vec<String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    label_fingerprint: Option<String>,
+}
+
+impl From<GcpAddress> for Address {
+    fn from(address: GcpAddress) -> Self {
+        let instance_path = address.users.first().map(|user| resource_path(user));
+        let eni = instance_path.map(|instance_path| {
+            let interface = address
+                .labels
+                .get(NETWORK_INTERFACE_LABEL)
+                .map_or("nic0", String::as_str);
+            format!("{}/networkInterfaces/{}", instance_path, interface)
+        });
+        let private_ip_address = eni.as_ref().and_then(|_| {
+            address
+                .labels
+                .get(PRIVATE_IP_LABEL)
+                .map(|value| value.replace('-', "."))
+        });
+        let instance_id = instance_path
+            .and_then(|instance_path| instance_path.rsplit('/').next())
+            .map(str::to_owned);
+        Address {
+            allocation_id: address.name,
+            public_ip: address.address,
+            association_id: eni.clone(),
+            eni,
+            private_ip_address,
+            instance_id,
+            tags: from_labels(address.labels),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AddressList {
+    #[serde(default)]
+    items: Vec<GcpAddress>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Instance {
+    name: String,
+    self_link: String,
+    status: String,
+    #[serde(default)]
+    network_interfaces: Vec<NetworkInterface>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NetworkInterface {
+    name: String,
+    #[serde(rename = "networkIP")]
+    network_ip: String,
+    #[serde(default)]
+    alias_ip_ranges: Vec<AliasIpRange>,
+    #[serde(default)]
+    access_configs: Vec<AccessConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AliasIpRange {
+    ip_cidr_range: String,
+}
+
+#[derive(Deserialize)]
+struct AccessConfig {
+    name: String,
+    #[serde(rename = "natIP")]
+    nat_ip: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AggregatedInstanceList {
+    #[serde(default)]
+    items: HashMap<String, InstancesScopedList>,
+}
+
+#[derive(Deserialize)]
+struct InstancesScopedList {
+    #[serde(default)]
+    instances: Vec<Instance>,
+}
+
+/// The path of a resource, starting from `projects/`, out of its URL.
+fn resource_path(url: &str) -> &str {
+    url.find("projects/").map_or(url, |start| &url[start..])
+}
+
+/// Reduces a string to the lowercase letters, digits, underscores, and
+/// dashes which labels allow, truncated to the maximum label length.
+fn sanitize_label(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9' | '_' | '-') => c,
+            _ => '_',
+        })
+        .take(MAX_LABEL_LENGTH)
+        .collect()
+}
+
+/// The label key of a tag key, which drops any domain prefix.
+fn label_key(key: &str) -> String {
+    sanitize_label(key.rsplit('/').next().unwrap_or(key))
+}
+
+fn label_value(value: &str) -> String {
+    sanitize_label(value)
+}
+
+/// The label of a tag. The allocation time is recorded in seconds since the
+/// epoch, so it can be read back.
+fn to_label(key: &str, value: &str) -> (String, String) {
+    let value = match DateTime::parse_from_rfc3339(value) {
+        Ok(time) if key == ALLOCATED_AT_TAG => time.timestamp().to_string(),
+        _ => label_value(value),
+    };
+    (label_key(key), value)
+}
+
+/// The tags of an address, out of its labels, for the tags the operator reads back.
+fn from_labels(labels: HashMap<String, String>) -> HashMap<String, String> {
+    labels
+        .into_iter()
+        .map(|(key, value)| {
+            let tag_key = GENERATED_TAGS
+                .iter()
+                .chain(&[
+                    POD_NAME_TAG,
+                    NODE_SELECTOR_TAG,
+                    TRANSFERRED_FROM_TAG,
+                    PROTECTED_TAG,
+                ])
+                .find(|tag_key| label_key(tag_key) == key)
+                .map_or(key, |tag_key| (*tag_key).to_owned());
+            let value = match value.parse() {
+                Ok(seconds) if tag_key == ALLOCATED_AT_TAG => Utc
+                    .timestamp_opt(seconds, 0)
+                    .single()
+                    .map_or(value, |time| time.to_rfc3339()),
+                _ => value,
+            };
+            (tag_key, value)
+        })
+        .collect()
+}
+
+/// Checks whether the IPv4 CIDR range contains the IP.
+fn cidr_contains(cidr: &str, ip: &str) -> bool {
+    let (network, prefix) = cidr.split_once('/').unwrap_or((cidr, "32"));
+    match (
+        network.parse::<Ipv4Addr>(),
+        prefix.parse::<u32>(),
+        ip.parse::<Ipv4Addr>(),
+    ) {
+        (Ok(network), Ok(prefix), Ok(ip)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Percent-encodes a query parameter value.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
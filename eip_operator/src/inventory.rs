@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use kube::api::{Api, ListParams};
+use kube::{Client, ResourceExt};
+use serde::Serialize;
+
+use eip_operator::aws::{self, Ec2Clients};
+use eip_operator::eip::v2::{Eip, EipSelector, Provider};
+use eip_operator::provider::{Address, Providers};
+use eip_operator_shared::Error;
+
+use crate::cli::{Args, ExportFormat};
+
+/// The tags each address must have, and the values expected of them.
+fn expected_tags<'a>(cluster_name: &'a str, eip: &'a Eip) -> [(&'static str, Option<&'a str>); 4] {
+    [
+        (aws::CLUSTER_NAME_TAG, Some(cluster_name)),
+        (aws::NAMESPACE_TAG, eip.metadata.namespace.as_deref()),
+        (aws::EIP_NAME_TAG, eip.metadata.name.as_deref()),
+        (aws::EIP_UID_TAG, eip.metadata.uid.as_deref()),
+    ]
+}
+
+/// An Eip and the address allocated for it, as seen by both Kubernetes and
+/// the cloud provider, or an address tagged for the cluster without an Eip.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Entry {
+    pub(crate) namespace: Option<String>,
+    pub(crate) eip: Option<String>,
+    pub(crate) provider: String,
+    /// The pod, or node selector, the Eip is for.
+    pub(crate) target: Option<String>,
+    pub(crate) allocation_id: Option<String>,
+    pub(crate) public_ip: Option<String>,
+    pub(crate) eni: Option<String>,
+    pub(crate) private_ip: Option<String>,
+    pub(crate) instance_id: Option<String>,
+    pub(crate) tags: BTreeMap<String, String>,
+    /// Where the Kubernetes and cloud provider views disagree.
+    pub(crate) discrepancies: Vec<String>,
+}
+
+impl Entry {
+    fn new(eip: &Eip, addresses: &[&Address], cluster_name: &str) -> Self {
+        let address = match eip.allocation_id() {
+            Some(allocation_id) => addresses
+                .iter()
+                .find(|address| address.allocation_id == allocation_id),
+            None => addresses.first(),
+        };
+        let mut discrepancies = vec![];
+        if addresses.len() > 1 {
+            discrepancies.push(format!(
+                "{} addresses are tagged for the Eip.",
+                addresses.len()
+            ));
+        }
+        match (eip.allocation_id(), address) {
+            (None, None) => discrepancies.push("The Eip has no address.".to_owned()),
+            (None, Some(address)) => discrepancies.push(format!(
+                "The Eip's status doesn't record its address {}.",
+                address.allocation_id
+            )),
+            (Some(allocation_id), None) => discrepancies.push(format!(
+                "The address {} in the Eip's status doesn't exist, or isn't tagged for the Eip.",
+                allocation_id
+            )),
+            (Some(_), Some(address)) => {
+                discrepancies.extend(status_discrepancies(eip, address));
+                for (key, expected) in expected_tags(cluster_name, eip) {
+                    let actual = address.tag(key);
+                    if actual != expected {
+                        discrepancies.push(format!(
+                            "The address's {} tag is {:?}, not {:?}.",
+                            key, actual, expected
+                        ));
+                    }
+                }
+            }
+        }
+        Self {
+            namespace: eip.namespace(),
+            eip: Some(eip.name_any()),
+            provider: eip.spec.provider.unwrap_or_default().to_string(),
+            target: Some(match &eip.spec.selector {
+                EipSelector::Pod { pod_name, .. } => format!("pod/{}", pod_name),
+                selector => selector.to_string(),
+            }),
+            allocation_id: address
+                .map(|address| address.allocation_id.clone())
+                .or_else(|| eip.allocation_id().map(str::to_owned)),
+            public_ip: address
+                .map(|address| address.public_ip.clone())
+                .or_else(|| eip.public_ip_address().map(str::to_owned)),
+            eni: address.and_then(|address| address.eni.clone()),
+            private_ip: address.and_then(|address| address.private_ip_address.clone()),
+            instance_id: address.and_then(|address| address.instance_id.clone()),
+            tags: address.map_or_else(BTreeMap::new, |address| {
+                address.tags.clone().into_iter().collect()
+            }),
+            discrepancies,
+        }
+    }
+
+    /// An address tagged for the cluster, but not for any of its Eips.
+    fn orphan(provider: Provider, address: &Address) -> Self {
+        let transferred_from = address
+            .tag(aws::TRANSFERRED_FROM_TAG)
+            .filter(|cluster| !cluster.is_empty());
+        let discrepancy = match transferred_from {
+            Some(cluster) => format!(
+                "The address was transferred from cluster {}, but not adopted yet.",
+                cluster
+            ),
+            None => "No Eip exists for the address.".to_owned(),
+        };
+        Self {
+            namespace: address.tag(aws::NAMESPACE_TAG).map(str::to_owned),
+            eip: address.tag(aws::EIP_NAME_TAG).map(str::to_owned),
+            provider: provider.to_string(),
+            target: None,
+            allocation_id: Some(address.allocation_id.clone()),
+            public_ip: Some(address.public_ip.clone()),
+            eni: address.eni.clone(),
+            private_ip: address.private_ip_address.clone(),
+            instance_id: address.instance_id.clone(),
+            tags: address.tags.clone().into_iter().collect(),
+            discrepancies: vec![discrepancy],
+        }
+    }
+}
+
+/// Where the Eip's status disagrees with its address.
+fn status_discrepancies(eip: &Eip, address: &Address) -> Vec<String> {
+    let mut discrepancies = vec![];
+    let status = eip.status.as_ref();
+    if eip.public_ip_address() != Some(address.public_ip.as_str()) {
+        discrepancies.push(format!(
+            "The Eip's status records public IP {:?}, but the address has {}.",
+            eip.public_ip_address(),
+            address.public_ip
+        ));
+    }
+    let status_eni = status.and_then(|status| status.eni.as_deref());
+    let status_private_ip = status.and_then(|status| status.private_ip_address.as_deref());
+    match (status_private_ip, address.private_ip_address.as_deref()) {
+        (None, Some(private_ip)) => discrepancies.push(format!(
+            "The address is associated with private IP {}, which the Eip's status doesn't record.",
+            private_ip
+        )),
+        (Some(private_ip), None) => discrepancies.push(format!(
+            "The Eip's status records private IP {}, but the address isn't associated.",
+            private_ip
+        )),
+        (Some(expected), Some(actual)) if expected != actual => discrepancies.push(format!(
+            "The Eip's status records private IP {}, but the address is associated with {}.",
+            expected, actual
+        )),
+        _ => {}
+    }
+    if status_eni.is_some() && status_eni != address.eni.as_deref() {
+        discrepancies.push(format!(
+            "The Eip's status records ENI {:?}, but the address is associated with {:?}.",
+            status_eni, address.eni
+        ));
+    }
+    discrepancies
+}
+
+/// Lists the cluster's Eips with their addresses, followed by the addresses
+/// tagged for the cluster which no Eip has.
+pub(crate) async fn inventory(
+    client: Client,
+    providers: &Providers,
+    cluster_name: &str,
+    namespace: Option<&str>,
+) -> Result<Vec<Entry>, Error> {
+    let eip_api = match namespace {
+        Some(namespace) => Api::<Eip>::namespaced(client, namespace),
+        None => Api::<Eip>::all(client),
+    };
+    let mut eips = eip_api.list(&ListParams::default()).await?.items;
+    eips.sort_by_key(|eip| (eip.namespace(), eip.name_any()));
+
+    let mut addresses = vec![];
+    for (kind, provider) in providers.all() {
+        for address in provider
+            .describe_cluster_addresses(cluster_name, namespace)
+            .await?
+        {
+            addresses.push((kind, address));
+        }
+    }
+    let mut by_uid: HashMap<&str, Vec<&Address>> = HashMap::new();
+    for (_, address) in &addresses {
+        if let Some(uid) = address.tag(aws::EIP_UID_TAG) {
+            by_uid.entry(uid).or_default().push(address);
+        }
+    }
+
+    let mut entries = vec![];
+    for eip in &eips {
+        let mut tagged = by_uid
+            .remove(eip.metadata.uid.as_deref().unwrap_or_default())
+            .unwrap_or_default();
+        // The address in the status may have lost its tags.
+        if let Some(allocation_id) = eip.allocation_id() {
+            if !tagged
+                .iter()
+                .any(|address| address.allocation_id == allocation_id)
+            {
+                tagged.extend(
+                    addresses
+                        .iter()
+                        .map(|(_, address)| address)
+                        .find(|address| address.allocation_id == allocation_id),
+                );
+            }
+        }
+        entries.push(Entry::new(eip, &tagged, cluster_name));
+    }
+    let claimed: Vec<_> = entries
+        .iter()
+        .filter_map(|entry| entry.allocation_id.as_deref())
+        .collect();
+    let orphans: Vec<_> = addresses
+        .iter()
+        .filter(|(_, address)| !claimed.contains(&address.allocation_id.as_str()))
+        .map(|(kind, address)| Entry::orphan(*kind, address))
+        .collect();
+    entries.extend(orphans);
+    Ok(entries)
+}
+
+/// The providers configured by the operator's settings.
+async fn providers(args: &Args) -> Result<Providers, Error> {
+    let aws_config = crate::load_aws_config(args).await?;
+    let ec2_clients = Ec2Clients::new(
+        &aws_config,
+        &args.namespace_role_arns,
+        args.aws_use_fips_endpoint
+            .then(|| crate::fips_endpoint(&aws_config, "ec2"))
+            .transpose()?,
+    );
+    Ok(crate::providers(args, ec2_clients))
+}
+
+/// Prints the inventory of the cluster's Eips and addresses, for audits.
+pub(crate) async fn export(args: Args, format: ExportFormat) -> Result<(), Error> {
+    let client = Client::try_default().await?;
+    let providers = providers(&args).await?;
+    let entries = inventory(
+        client,
+        &providers,
+        &args.cluster_name,
+        args.namespace.as_deref(),
+    )
+    .await?;
+    match format {
+        ExportFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        ExportFormat::Csv => {
+            println!("namespace,eip,provider,target,allocation_id,public_ip,eni,private_ip,instance_id,tags,discrepancies");
+            for entry in &entries {
+                let fields = [
+                    entry.namespace.clone().unwrap_or_default(),
+                    entry.eip.clone().unwrap_or_default(),
+                    entry.provider.clone(),
+                    entry.target.clone().unwrap_or_default(),
+                    entry.allocation_id.clone().unwrap_or_default(),
+                    entry.public_ip.clone().unwrap_or_default(),
+                    entry.eni.clone().unwrap_or_default(),
+                    entry.private_ip.clone().unwrap_or_default(),
+                    entry.instance_id.clone().unwrap_or_default(),
+                    serde_json::to_string(&entry.tags)?,
+                    entry.discrepancies.join(" "),
+                ];
+                let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+                println!("{}", fields.join(","));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the discrepancies between the cluster's Eips and their addresses,
+/// without changing anything. Fails if there are any, unless an interval is
+/// given, in which case the check is repeated at that interval indefinitely.
+pub(crate) async fn verify(args: Args, interval: Option<Duration>) -> Result<(), Error> {
+    let client = Client::try_default().await?;
+    let providers = providers(&args).await?;
+    let interval = match interval {
+        Some(interval) => interval,
+        None => {
+            return match verify_once(client, &providers, &args).await? {
+                0 => Ok(()),
+                count => Err(Error::DiscrepanciesFound(count)),
+            }
+        }
+    };
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        ticks.tick().await;
+        if let Err(err) = verify_once(client.clone(), &providers, &args).await {
+            eprintln!("Failed to verify the Eips: {}", err);
+        }
+    }
+}
+
+/// Prints each discrepancy, and returns how many were found.
+async fn verify_once(client: Client, providers: &Providers, args: &Args) -> Result<usize, Error> {
+    let entries = inventory(
+        client,
+        providers,
+        &args.cluster_name,
+        args.namespace.as_deref(),
+    )
+    .await?;
+    let mut count = 0;
+    for entry in &entries {
+        for discrepancy in &entry.discrepancies {
+            println!(
+                "{}/{} {}: {}",
+                entry.namespace.as_deref().unwrap_or("-"),
+                entry.eip.as_deref().unwrap_or("-"),
+                entry.allocation_id.as_deref().unwrap_or("-"),
+                discrepancy
+            );
+            count += 1;
+        }
+    }
+    println!(
+        "Found {} discrepancies across {} Eips and addresses.",
+        count,
+        entries.len()
+    );
+    Ok(count)
+}
+
+/// Quotes the field if it contains a comma, quote, or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
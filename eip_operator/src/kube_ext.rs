@@ -2,10 +2,18 @@ use std::collections::BTreeMap;
 
 use k8s_openapi::api::core::v1::{Node, Pod};
 
+/// The taints Karpenter puts on nodes it is about to disrupt: `disrupted` in
+/// its v1 API, and `disruption` in v1beta1.
+const KARPENTER_DISRUPTION_TAINTS: [&str; 2] =
+    ["karpenter.sh/disrupted", "karpenter.sh/disruption"];
+
 pub(crate) trait NodeExt {
     fn ip(&self) -> Option<&str>;
     fn labels(&self) -> Option<&BTreeMap<String, String>>;
     fn provider_id(&self) -> Option<&str>;
+    fn disrupting(&self) -> bool;
+    fn dedicated_eni_ips(&self) -> Vec<&str>;
+    fn has_ip(&self, ip: &str) -> bool;
 }
 
 impl NodeExt for Node {
@@ -30,18 +38,53 @@ impl NodeExt for Node {
             .as_ref()
             .and_then(|spec| spec.provider_id.as_deref())
     }
+
+    /// Checks whether Karpenter has tainted the node to disrupt it, such as
+    /// to consolidate it away.
+    fn disrupting(&self) -> bool {
+        self.spec
+            .as_ref()
+            .and_then(|spec| spec.taints.as_ref())
+            .map_or(false, |taints| {
+                taints
+                    .iter()
+                    .any(|taint| KARPENTER_DISRUPTION_TAINTS.contains(&taint.key.as_str()))
+            })
+    }
+
+    /// The private IPs of the network interface the operator dedicated to
+    /// node EIPs on the node, if it has one.
+    fn dedicated_eni_ips(&self) -> Vec<&str> {
+        self.metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(crate::DEDICATED_ENI_IPS_ANNOTATION.as_str()))
+            .map_or(vec![], |ips| ips.split(',').collect())
+    }
+
+    /// Checks whether the IP is the node's internal IP, or one of its
+    /// dedicated network interface.
+    fn has_ip(&self, ip: &str) -> bool {
+        self.ip() == Some(ip) || self.dedicated_eni_ips().contains(&ip)
+    }
 }
 
 pub(crate) trait PodExt {
     fn ip(&self) -> Option<&str>;
     fn node_name(&self) -> Option<&str>;
+    fn host_network(&self) -> bool;
 }
 
 impl PodExt for Pod {
+    /// The private IP of the pod, which for pods on the host network is the
+    /// primary private IP of their node, on its primary network interface.
     fn ip(&self) -> Option<&str> {
-        self.status
-            .as_ref()
-            .and_then(|status| status.pod_ip.as_deref())
+        let status = self.status.as_ref()?;
+        if self.host_network() {
+            status.host_ip.as_deref()
+        } else {
+            status.pod_ip.as_deref()
+        }
     }
 
     fn node_name(&self) -> Option<&str> {
@@ -49,4 +92,13 @@ impl PodExt for Pod {
             .as_ref()
             .and_then(|spec| spec.node_name.as_deref())
     }
+
+    /// Whether the pod shares its node's network namespace, and so its
+    /// network interfaces and IPs.
+    fn host_network(&self) -> bool {
+        self.spec
+            .as_ref()
+            .and_then(|spec| spec.host_network)
+            .unwrap_or(false)
+    }
 }
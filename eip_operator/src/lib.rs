@@ -0,0 +1,20 @@
+//! The Eip custom resource and the cloud helpers of the eip-operator, for
+//! other controllers and CLIs which create or inspect Eips.
+//!
+//! - [`eip`] has the Eip types, including selector matching and status updates.
+//! - [`aws`] has the tags the operator puts on addresses, and its EC2 helpers.
+//! - [`provider`] has the [`provider::CloudProvider`] trait which [`aws`],
+//!   [`gcp`], and [`azure`] implement to manage addresses.
+
+pub use eip_operator_shared::Error;
+
+pub mod aws;
+pub mod azure;
+pub mod credentials;
+pub mod eip;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
+pub mod gcp;
+#[doc(hidden)]
+pub mod metrics;
+pub mod provider;
@@ -1,83 +1,750 @@
 use std::collections::{HashMap, HashSet};
-use std::time::Duration;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use aws_sdk_ec2::model::Filter;
+use aws_config::SdkConfig;
 use aws_sdk_ec2::Client as Ec2Client;
 use aws_sdk_servicequotas::model::ServiceQuota;
 use aws_sdk_servicequotas::Client as ServiceQuotaClient;
 use aws_smithy_http::endpoint::Endpoint as AWSEndpoint;
-use futures::future::join_all;
-use json_patch::{PatchOperation, RemoveOperation, TestOperation};
-use k8s_openapi::api::core::v1::Pod;
-use kube::api::{Api, ListParams, Patch, PatchParams};
-use kube::{Client, Resource, ResourceExt};
+use clap::{Parser, ValueEnum};
+use futures::future::{join_all, BoxFuture, Shared};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use futures::FutureExt;
+use k8s_openapi::api::core::v1::{Node, Pod};
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::api::{Api, ListParams};
+use kube::{Client, ResourceExt};
+use kube_runtime::reflector::ObjectRef;
+use once_cell::sync::{Lazy, OnceCell};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::task;
 use tracing::{debug, event, info, instrument, Level};
 
-use eip_operator_shared::controller::Controller;
-use eip_operator_shared::{run_with_tracing, Error, MANAGE_EIP_LABEL};
+use eip_operator_shared::controller::{Controller, RequeueIntervals};
+use eip_operator_shared::metrics::BuildInfo;
+#[cfg(feature = "metrics-server")]
+use eip_operator_shared::metrics::DebugEndpoint;
+use eip_operator_shared::proxy::ProxySettings;
+#[cfg(feature = "webhook")]
+use eip_operator_shared::webhook_tls::WebhookTls;
+use eip_operator_shared::{metadata_key, run_with_tracing, Error, MANAGE_EIP_LABEL};
 
-use eip::v2::Eip;
+use eip_operator::{aws, azure, credentials, eip, gcp, metrics, provider};
 
-mod aws;
+use autocreate::Autocreate;
+use aws::{AwsProvider, DefaultTags, Ec2Clients};
+use azure::AzureProvider;
+use cli::{Args, Command};
+use controller::eip::AddressPolicy;
+use controller::pod::{PodMetadata, SchedulingGate};
+use controller::service::EipServices;
+use controller::{ForeignAssociationPolicy, NamespaceFilter};
+use eip::v2::{Eip, EipSelector, Provider};
+use eip_index::EipIndex;
+use gcp::GcpProvider;
+use migrate::MigrationMode;
+use node_index::NodeIndex;
+use provider::{Address, Providers};
+use quota::{AllocationLimits, EipBudgets, EipQuota};
+use snapshot::SnapshotConfig;
+
+mod adopt;
+mod audit;
+mod autocreate;
+mod check;
+mod cilium;
+mod cli;
+mod clusters;
+mod config;
 mod controller;
-mod eip;
+#[cfg(feature = "metrics-server")]
+mod cost;
+mod eip_index;
+mod inventory;
 mod kube_ext;
+mod migrate;
+#[cfg(test)]
+mod mock;
+mod node_index;
+#[cfg(feature = "query-api")]
+mod query;
+mod quota;
+mod snapshot;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_sha: env!("GIT_SHA"),
+};
+const LONG_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_SHA"), ")");
 
-const LEGACY_MANAGE_EIP_LABEL: &str = "eip.aws.materialize.com/manage";
-const LEGACY_POD_FINALIZER_NAME: &str = "eip.aws.materialize.com/disassociate";
+static AUTOCREATE_EIP_LABEL: Lazy<String> = Lazy::new(|| metadata_key("autocreate_eip"));
+static EIP_ALLOCATION_ID_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("allocation_id"));
+static PUBLIC_IP_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("public_ip"));
+static PUBLIC_IPS_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("public_ips"));
+static EGRESS_GATEWAY_POLICY_ANNOTATION: Lazy<String> =
+    Lazy::new(|| metadata_key("egress_gateway_policy"));
+static SERVICE_EIP_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("eip_name"));
+static SCHEDULING_GATE: Lazy<String> = Lazy::new(|| metadata_key("allocated"));
+static ADOPT_ALLOCATION_ID_ANNOTATION: Lazy<String> =
+    Lazy::new(|| metadata_key("adopt_allocation_id"));
+static AUTOCREATE_NAME_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("autocreate_eip_name"));
+static EIP_TEMPLATE_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("eip_template"));
+static SNAPSHOT_TAGS_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("snapshot_tags"));
+static PROTECT_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("protect"));
+static DEDICATED_ENI_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("dedicated_eni"));
+static DEDICATED_ENI_IPS_ANNOTATION: Lazy<String> = Lazy::new(|| metadata_key("dedicated_eni_ips"));
+const DEFAULT_EXTERNAL_DNS_TARGET_ANNOTATION: &str = "external-dns.alpha.kubernetes.io/target";
+const EXTERNAL_DNS_HOSTNAME_ANNOTATION: &str = "external-dns.alpha.kubernetes.io/hostname";
 
-const FIELD_MANAGER: &str = "eip.materialize.cloud";
-const AUTOCREATE_EIP_LABEL: &str = "eip.materialize.cloud/autocreate_eip";
-const EIP_ALLOCATION_ID_ANNOTATION: &str = "eip.materialize.cloud/allocation_id";
-const EXTERNAL_DNS_TARGET_ANNOTATION: &str = "external-dns.alpha.kubernetes.io/target";
+/// The annotation external-dns reads a pod's targets from, set from
+/// `EXTERNAL_DNS_TARGET_ANNOTATION` at startup.
+static EXTERNAL_DNS_TARGET_ANNOTATION: OnceCell<String> = OnceCell::new();
+
+fn external_dns_target_annotation() -> &'static str {
+    EXTERNAL_DNS_TARGET_ANNOTATION
+        .get()
+        .map_or(DEFAULT_EXTERNAL_DNS_TARGET_ANNOTATION, String::as_str)
+}
 
 // See https://us-east-1.console.aws.amazon.com/servicequotas/home/services/ec2/quotas
 // and filter in the UI for EC2 quotas like this, or use the CLI:
 //   aws --profile=mz-cloud-staging-admin service-quotas list-service-quotas --service-code=ec2
-const EIP_QUOTA_CODE: &str = "L-0263D0A3";
+const DEFAULT_EIP_QUOTA_CODE: &str = "L-0263D0A3";
+
+// The number of orphaned EIPs cleaned up at the same time at startup
+const ORPHAN_CLEANUP_CONCURRENCY: usize = 16;
+
+// How long to wait for in-flight reconciliations when shutting down, which
+// should be less than the pod's termination grace period
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(25);
 
 // Watch our EIP quota status on a fixed interval
 const EIP_QUOTA_INTERVAL: tokio::time::Duration = Duration::from_secs(60);
 
+// Estimate the cost of managed addresses on a fixed interval
+#[cfg(feature = "metrics-server")]
+const COST_REPORT_INTERVAL: Duration = Duration::from_secs(300);
+
+// Quotas themselves rarely change, so only fetch them on this much longer interval
+const EIP_QUOTA_CACHE_TTL: Duration = Duration::from_secs(3600);
+
 fn main() -> Result<(), Error> {
     set_abort_on_panic();
+    if let Some(path) = config::path_from_args() {
+        config::load_into_env(path.as_ref())?;
+    }
+    let mut args = Args::parse();
+    if let Some(Command::Version) = args.command {
+        println!("eip-operator {}", LONG_VERSION);
+        return Ok(());
+    }
+    eip_operator_shared::set_metadata_domain(args.metadata_domain.clone());
+    EXTERNAL_DNS_TARGET_ANNOTATION
+        .set(args.external_dns_target_annotation.clone())
+        .expect("The external-dns target annotation is only set once.");
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()?;
-    runtime.block_on(run_with_tracing("eip_operator", run))?;
-    Ok(())
+    match args.command.take() {
+        Some(Command::Check) => runtime.block_on(check::run(args)),
+        Some(Command::Export { format }) => runtime.block_on(inventory::export(args, format)),
+        Some(Command::Verify { interval_secs }) => runtime.block_on(inventory::verify(
+            args,
+            interval_secs.map(Duration::from_secs),
+        )),
+        Some(Command::Adopt {
+            allocation_id,
+            eip_name,
+            pod,
+            node_selector,
+        }) => {
+            let selector = match pod {
+                Some(pod_name) => EipSelector::Pod {
+                    pod_name,
+                    pod_uid: None,
+                    network: None,
+                },
+                None => EipSelector::Node {
+                    selector: node_selector.unwrap_or_default().into_iter().collect(),
+                },
+            };
+            runtime.block_on(adopt::run(args, allocation_id, eip_name, selector))
+        }
+        _ => runtime.block_on(run_with_tracing("eip_operator", move || run(args))),
+    }
 }
 
-async fn run() -> Result<(), Error> {
+async fn run(args: Args) -> Result<(), Error> {
+    info!(
+        version = BUILD_INFO.version,
+        git_sha = BUILD_INFO.git_sha,
+        "Starting eip-operator."
+    );
+    crate::metrics::BUILD_INFO
+        .with_label_values(&[BUILD_INFO.version, BUILD_INFO.git_sha])
+        .set(1);
+    if let Some(config) = &args.config {
+        info!(config = %config.display(), "Loaded settings from config file.");
+    }
+
     debug!("Getting k8s_client...");
     let k8s_client = Client::try_default().await?;
 
+    if args.dry_run {
+        info!("Running in dry-run mode, so cloud changes are only checked, not made.");
+    }
+    provider::set_dry_run(args.dry_run);
+    #[cfg(not(feature = "fault-injection"))]
+    if args.injects_faults() {
+        event!(
+            Level::WARN,
+            "FAULT_*_RATE is set, but this build has no fault injection."
+        );
+    }
+    #[cfg(feature = "fault-injection")]
+    if args.injects_faults() {
+        eip_operator::faults::set_fault_rates(eip_operator::faults::FaultRates {
+            throttle: args.fault_throttle_rate,
+            not_found: args.fault_not_found_rate,
+            timeout: args.fault_timeout_rate,
+            status_patch: args.fault_status_patch_rate,
+        });
+    }
+
     debug!("Getting ec2_client...");
+    let aws_config = load_aws_config(&args).await?;
+    let use_fips = args.aws_use_fips_endpoint;
+    let ec2_clients = Ec2Clients::new(
+        &aws_config,
+        &args.namespace_role_arns,
+        use_fips
+            .then(|| fips_endpoint(&aws_config, "ec2"))
+            .transpose()?,
+    );
+    let ec2_client = ec2_clients.default_client().clone();
+    let providers = providers(&args, ec2_clients);
+
+    debug!("Getting quota_client...");
+    let quota_client = quota_client(&aws_config, use_fips)?;
+    let eip_quota = EipQuota::new(
+        args.eip_quota_reserved,
+        args.namespace_role_arns.keys().cloned().collect(),
+    );
+    let eip_budgets = EipBudgets::new(
+        args.default_namespace_eip_budget,
+        args.namespace_eip_budgets.clone(),
+    );
+
+    let mut default_tags = DefaultTags::new(
+        args.default_tags.clone(),
+        args.namespace_default_tags.clone(),
+    )?;
+    default_tags.extend(aws::audit_tags(aws::caller_identity(&aws_config).await));
+
+    let observe_only = args.observe_only;
+    if observe_only {
+        info!("Running in observe-only mode, so nothing is changed in Kubernetes or AWS.");
+    }
+    let settings = ClusterSettings {
+        providers,
+        allocation_limits: AllocationLimits {
+            quota: eip_quota.clone(),
+            budgets: eip_budgets.clone(),
+        },
+        namespace: args.namespace.clone(),
+        default_tags,
+        namespace_filter: NamespaceFilter::new(
+            args.namespace_allowlist(),
+            args.namespace_denylist(),
+        ),
+        eip_list_params: cli::list_params_with(
+            ListParams::default(),
+            args.eip_label_selector.as_deref(),
+            args.eip_field_selector.as_deref(),
+        ),
+        pod_list_params: cli::list_params_with(
+            ListParams::default().labels(&MANAGE_EIP_LABEL),
+            args.pod_label_selector.as_deref(),
+            args.pod_field_selector.as_deref(),
+        ),
+        node_list_params: cli::list_params_with(
+            ListParams::default().labels(&MANAGE_EIP_LABEL),
+            args.node_label_selector.as_deref(),
+            args.node_field_selector.as_deref(),
+        ),
+        reconcile_concurrency: args.reconcile_concurrency.map(|limit| limit as usize),
+        reconcile_debounce: Duration::from_millis(args.reconcile_debounce_ms),
+        requeue_intervals: args.requeue_intervals(),
+        pod_detach_grace: Duration::from_secs(args.pod_detach_grace_secs),
+        scheduling_gate: args.pod_scheduling_gate.then(|| SchedulingGate {
+            node_selector: args.pod_scheduling_gate_node_selector.clone(),
+        }),
+        service_load_balancer_status: args.service_load_balancer_status,
+        karpenter_aware: args.karpenter_aware,
+        node_scale_down_protection: args.node_scale_down_protection,
+        external_dns_hostname: args.external_dns_hostname,
+        pod_public_ip_label: args.pod_public_ip_label.clone(),
+        resolve_duplicate_eips: args.resolve_duplicate_eips,
+        foreign_association_policy: args.foreign_association_policy,
+        autocreate: Autocreate {
+            propagated_labels: args.autocreate_propagated_labels.clone(),
+            name_template: args.autocreate_name_template.clone(),
+            templates: args.autocreate_templates.clone(),
+        },
+        idle_eip_warning: Duration::from_secs(args.idle_eip_warning_secs),
+        idle_eip_release: args.idle_eip_release_secs.map(Duration::from_secs),
+        allocation_audit_interval: (args.allocation_audit_interval_secs > 0 && !observe_only)
+            .then(|| Duration::from_secs(args.allocation_audit_interval_secs)),
+        snapshot: args
+            .snapshot_s3_bucket
+            .clone()
+            .filter(|_| !observe_only)
+            .map(|bucket| SnapshotConfig {
+                s3_client: aws_sdk_s3::Client::new(&aws_config),
+                bucket,
+                prefix: args.snapshot_s3_prefix.clone(),
+                interval: Duration::from_secs(args.snapshot_interval_secs),
+            }),
+        orphan_cleanup_config: OrphanCleanupConfig {
+            mode: match args.orphan_cleanup_mode {
+                OrphanCleanupMode::Delete if args.dry_run || observe_only => {
+                    OrphanCleanupMode::DryRun
+                }
+                mode => mode,
+            },
+            dns_safety_check: args.dns_safety_check,
+            adopt_existing: args.adopt_existing,
+            min_age: Duration::from_secs(args.orphan_cleanup_min_age_secs),
+        },
+        migration_mode: match args.migration_mode {
+            MigrationMode::Migrate if observe_only => MigrationMode::DryRun,
+            mode => mode,
+        },
+        observe_only,
+        manage_crds: args.manage_crds,
+    };
+
+    let quota_event_client = k8s_client.clone();
+    #[cfg(feature = "webhook")]
+    let webhook_tls = match (
+        args.webhook_port,
+        args.webhook_tls_cert_file.clone(),
+        args.webhook_tls_key_file.clone(),
+        args.webhook_service.clone(),
+    ) {
+        (None, ..) => None,
+        (Some(_), Some(cert_file), Some(key_file), _) => Some(WebhookTls::Files {
+            cert_file,
+            key_file,
+        }),
+        (Some(_), _, _, Some((service_namespace, service_name))) => Some(WebhookTls::SelfSigned {
+            client: k8s_client.clone(),
+            service_namespace,
+            service_name,
+            configuration_name: args.webhook_configuration_name.clone(),
+        }),
+        (Some(_), ..) => return Err(Error::MissingWebhookCertificate),
+    };
+    let mut clusters = vec![(args.cluster_name.clone(), k8s_client)];
+    if let Some(dir) = &args.remote_kubeconfig_dir {
+        debug!("Getting remote cluster clients...");
+        clusters.extend(clusters::remote_clients(dir, &args.cluster_name).await?);
+    }
+
+    #[cfg(feature = "metrics-server")]
+    let cluster_names: Vec<_> = clusters.iter().map(|(name, _)| name.clone()).collect();
+
+    let shutdown = shutdown_signal().boxed().shared();
+    let readiness = Readiness::new(clusters.len());
+    let mut tasks = vec![];
+    let mut controllers = vec![];
+    let mut debug_states = vec![];
+    let mut local_eip_index = None;
+    #[cfg(feature = "query-api")]
+    let mut cluster_indexes = vec![];
+    for (cluster_name, k8s_client) in clusters {
+        info!(cluster_name = %cluster_name, "Starting tasks");
+        let cluster = run_cluster(
+            k8s_client,
+            cluster_name.clone(),
+            &settings,
+            shutdown.clone(),
+            readiness.clone(),
+            &mut tasks,
+        )
+        .await?;
+        controllers.extend(cluster.controllers);
+        #[cfg(feature = "query-api")]
+        cluster_indexes.push(query::ClusterIndexes {
+            cluster_name: cluster_name.clone(),
+            eip_index: cluster.eip_index.clone(),
+            node_index: cluster.node_index,
+        });
+        debug_states.push((cluster_name, cluster.debug_state));
+        local_eip_index.get_or_insert(cluster.eip_index);
+    }
+
+    #[cfg(not(feature = "webhook"))]
+    if args.webhook_port.is_some() {
+        event!(
+            Level::WARN,
+            "WEBHOOK_PORT is set, but this build has no webhook server."
+        );
+    }
+    #[cfg(feature = "webhook")]
+    if let (Some(port), Some(tls), Some(eip_index)) =
+        (args.webhook_port, webhook_tls, local_eip_index)
+    {
+        let config = webhook::WebhookConfig {
+            addr: ([0, 0, 0, 0], port).into(),
+            tls,
+            env_name: args.webhook_public_ip_env.clone(),
+            budgets: args
+                .webhook_enforce_eip_budgets
+                .then(|| eip_budgets.clone()),
+            namespaces: settings.namespace_filter.clone(),
+        };
+        tasks.push(task::spawn(async move {
+            if let Err(err) = webhook::serve(config, eip_index).await {
+                event!(Level::ERROR, err = %err, "Webhook server error");
+            }
+        }));
+    }
+
+    #[cfg(not(feature = "query-api"))]
+    if args.query_api_port.is_some() {
+        event!(
+            Level::WARN,
+            "QUERY_API_PORT is set, but this build has no query API."
+        );
+    }
+    #[cfg(feature = "query-api")]
+    if let (Some(port), Some(token)) = (args.query_api_port, args.query_api_token.clone()) {
+        tasks.push(task::spawn(async move {
+            let addr = ([0, 0, 0, 0], port).into();
+            if let Err(err) = query::serve(addr, token, cluster_indexes).await {
+                event!(Level::ERROR, err = %err, "Query API server error");
+            }
+        }));
+    }
+
+    #[cfg(not(feature = "metrics-server"))]
+    if args.metrics_port.is_some() {
+        event!(
+            Level::WARN,
+            "METRICS_PORT is set, but this build has no metrics server."
+        );
+    }
+    #[cfg(feature = "metrics-server")]
+    if let Some(metrics_port) = args.metrics_port {
+        let debug = args.debug_token.clone().map(|token| {
+            let debug_states = Arc::new(debug_states);
+            DebugEndpoint::new(token, move || {
+                // The local cluster's state stays at the top level.
+                let (_, local_state) = &debug_states[0];
+                let mut state = local_state();
+                if debug_states.len() > 1 {
+                    state["remote_clusters"] = debug_states[1..]
+                        .iter()
+                        .map(|(cluster_name, debug_state)| (cluster_name.clone(), debug_state()))
+                        .collect::<serde_json::Map<_, _>>()
+                        .into();
+                }
+                async move { Ok::<_, Error>(state) }.boxed()
+            })
+        });
+        let ready = Arc::clone(&readiness.ready);
+        tasks.push(task::spawn(async move {
+            let addr = ([0, 0, 0, 0], metrics_port).into();
+            if let Err(err) =
+                eip_operator_shared::metrics::serve(addr, ready, BUILD_INFO, debug).await
+            {
+                event!(Level::ERROR, err = %err, "Metrics server error");
+            }
+        }));
+        let providers = settings.providers.clone();
+        let prices = cost::EipPrices {
+            attached: args.eip_hourly_price_attached,
+            idle: args.eip_hourly_price_idle,
+        };
+        tasks.push(task::spawn(async move {
+            let mut interval = tokio::time::interval(COST_REPORT_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                if let Err(err) = cost::report_costs(&providers, &cluster_names, prices).await {
+                    event!(Level::ERROR, err = %err, "Cost reporting error");
+                }
+            }
+        }));
+    }
+    tasks.push({
+        let ec2_client = ec2_client.clone();
+        let eip_quota_code = args.eip_quota_code.clone();
+        let extra_quota_codes = args.extra_quota_codes.clone();
+        let headroom_warning = args.eip_quota_headroom_warning;
+        task::spawn(async move {
+            let mut interval = tokio::time::interval(EIP_QUOTA_INTERVAL);
+            // It's better to miss the occasional measurement than to hammer the endpoint
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            let mut cached_quotas = HashMap::new();
+            let mut headroom_low = false;
+
+            loop {
+                interval.tick().await;
+                // Note: the Err that might occur here will be handled by tracing
+                // instrumentation, rather than directly here.
+                if let Err(err) = report_eip_quota_status(
+                    &ec2_client,
+                    &quota_client,
+                    &eip_quota_code,
+                    &extra_quota_codes,
+                    &mut cached_quotas,
+                    &eip_quota,
+                )
+                .await
+                {
+                    event!(Level::ERROR, err = %err, "Quota reporting error");
+                }
+                if let Some(headroom) = eip_quota.headroom() {
+                    metrics::EIP_QUOTA_HEADROOM.set(headroom as i64);
+                    // Only warn as the headroom drops below the threshold.
+                    let was_low = std::mem::replace(&mut headroom_low, headroom < headroom_warning);
+                    if headroom_low && !was_low {
+                        quota::publish_low_headroom(
+                            quota_event_client.clone(),
+                            headroom,
+                            headroom_warning,
+                        )
+                        .await;
+                    }
+                }
+            }
+        })
+    });
+    let controllers = join_all(controllers);
+
+    // The other tasks run until the runtime shuts down after we return.
+    shutdown.await;
+    info!("Shutting down, waiting for in-flight reconciliations");
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, controllers)
+        .await
+        .is_err()
+    {
+        event!(
+            Level::WARN,
+            "Timed out waiting for in-flight reconciliations."
+        );
+    }
+
+    debug!("exiting");
+    Ok(())
+}
+
+/// Loads the AWS config from the environment, with the endpoint, proxy, and
+/// credentials settings applied.
+async fn load_aws_config(args: &Args) -> Result<SdkConfig, Error> {
     let mut config_loader = aws_config::from_env();
-    if let Ok(endpoint) = std::env::var("AWS_ENDPOINT_URL") {
+    if let Some(endpoint) = &args.aws_endpoint_url {
         config_loader = config_loader.endpoint_resolver(AWSEndpoint::immutable(
             endpoint.parse().expect("{endpoint} not valid URI"),
         ))
     }
-    let aws_config = config_loader.load().await;
-    let ec2_client = Ec2Client::new(&aws_config);
+    if let Some(proxy) = ProxySettings::from_env()? {
+        info!(proxy = %proxy.uri(), "Connecting to AWS through a proxy.");
+        config_loader = config_loader.http_connector(aws::proxied_connector(&proxy));
+    }
+    if let Some(dir) = &args.aws_credentials_dir {
+        config_loader =
+            config_loader.credentials_provider(credentials::FileCredentialsProvider::new(dir));
+    }
+    Ok(config_loader.load().await)
+}
 
-    debug!("Getting quota_client...");
-    let quota_client = ServiceQuotaClient::new(&aws_config);
+/// The FIPS endpoint of the AWS service in the configured region.
+fn fips_endpoint(aws_config: &SdkConfig, service: &str) -> Result<AWSEndpoint, Error> {
+    let region = aws_config
+        .region()
+        .ok_or_else(|| Error::FipsEndpointUnavailable("no AWS region is configured".to_owned()))?;
+    aws::fips_endpoint(service, region.as_ref()).map_err(|region| {
+        Error::FipsEndpointUnavailable(format!("region {} does not offer FIPS endpoints", region))
+    })
+}
 
-    debug!("Getting namespace from env...");
-    let namespace = std::env::var("NAMESPACE").ok();
+/// The providers of Eips' addresses: AWS, and GCP and Azure if they are
+/// configured.
+fn providers(args: &Args, ec2_clients: Ec2Clients) -> Providers {
+    let mut providers = Providers::new(Arc::new(AwsProvider::new(ec2_clients)));
+    if let (Some(project), Some(region)) = (&args.gcp_project, &args.gcp_region) {
+        info!(%project, %region, "Reserving the addresses of gcp Eips with GCP.");
+        providers.insert(
+            Provider::Gcp,
+            Arc::new(GcpProvider::new(project.clone(), region.clone())),
+        );
+    }
+    if let (Some(subscription_id), Some(resource_group), Some(location)) = (
+        &args.azure_subscription_id,
+        &args.azure_resource_group,
+        &args.azure_location,
+    ) {
+        info!(
+            %subscription_id,
+            %resource_group,
+            %location,
+            "Creating the public IPs of azure Eips with Azure."
+        );
+        providers.insert(
+            Provider::Azure,
+            Arc::new(AzureProvider::new(
+                subscription_id.clone(),
+                resource_group.clone(),
+                args.azure_node_resource_group
+                    .clone()
+                    .unwrap_or_else(|| resource_group.clone()),
+                location.clone(),
+                args.azure_client_id.clone(),
+            )),
+        );
+    }
+    #[cfg(feature = "fault-injection")]
+    if args.injects_faults() {
+        providers = providers
+            .wrap(|provider| Arc::new(eip_operator::faults::FaultyProvider::new(provider)));
+    }
+    providers
+}
 
-    debug!("Getting cluster name from env...");
-    let cluster_name =
-        std::env::var("CLUSTER_NAME").expect("Environment variable CLUSTER_NAME is required.");
+fn quota_client(aws_config: &SdkConfig, use_fips: bool) -> Result<ServiceQuotaClient, Error> {
+    let mut quota_config = aws_sdk_servicequotas::config::Builder::from(aws_config);
+    if use_fips {
+        quota_config = quota_config.endpoint_resolver(fips_endpoint(aws_config, "servicequotas")?);
+    }
+    Ok(ServiceQuotaClient::from_conf(quota_config.build()))
+}
 
-    debug!("Getting default tags from env...");
-    let default_tags: HashMap<String, String> =
-        serde_json::from_str(&std::env::var("DEFAULT_TAGS").unwrap_or_else(|_| "{}".to_owned()))?;
+/// The settings shared by every cluster the operator manages.
+#[derive(Clone)]
+struct ClusterSettings {
+    providers: Providers,
+    allocation_limits: AllocationLimits,
+    namespace: Option<String>,
+    default_tags: DefaultTags,
+    namespace_filter: NamespaceFilter,
+    eip_list_params: ListParams,
+    pod_list_params: ListParams,
+    node_list_params: ListParams,
+    reconcile_concurrency: Option<usize>,
+    reconcile_debounce: Duration,
+    requeue_intervals: RequeueIntervals,
+    pod_detach_grace: Duration,
+    scheduling_gate: Option<SchedulingGate>,
+    service_load_balancer_status: bool,
+    karpenter_aware: bool,
+    node_scale_down_protection: bool,
+    external_dns_hostname: bool,
+    pod_public_ip_label: Option<String>,
+    resolve_duplicate_eips: bool,
+    foreign_association_policy: ForeignAssociationPolicy,
+    autocreate: Autocreate,
+    idle_eip_warning: Duration,
+    idle_eip_release: Option<Duration>,
+    allocation_audit_interval: Option<Duration>,
+    snapshot: Option<SnapshotConfig>,
+    orphan_cleanup_config: OrphanCleanupConfig,
+    migration_mode: MigrationMode,
+    observe_only: bool,
+    manage_crds: bool,
+}
 
-    eip::register_custom_resource(k8s_client.clone(), namespace.as_deref()).await?;
+/// Whether the startup sweeps of orphaned EIPs of every cluster have
+/// finished, which the operator waits for before it is ready.
+#[derive(Clone)]
+struct Readiness {
+    ready: Arc<AtomicBool>,
+    pending_sweeps: Arc<AtomicUsize>,
+}
+
+impl Readiness {
+    fn new(sweeps: usize) -> Self {
+        Self {
+            ready: Arc::new(AtomicBool::new(false)),
+            pending_sweeps: Arc::new(AtomicUsize::new(sweeps)),
+        }
+    }
+
+    fn sweep_finished(&self) {
+        if self.pending_sweeps.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.ready.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// The controllers started for a cluster, and a snapshot of their state for
+/// the debug endpoint.
+struct Cluster {
+    controllers: Vec<task::JoinHandle<()>>,
+    debug_state: Box<dyn Fn() -> serde_json::Value + Send + Sync>,
+    /// The index of the cluster's Eips, for the webhook and query API.
+    eip_index: EipIndex,
+    /// The index of the cluster's nodes, for the query API.
+    #[cfg(feature = "query-api")]
+    node_index: NodeIndex,
+}
+
+/// Registers the Eip CRD in the cluster and migrates its old objects, then
+/// starts its controllers, indexes, and sweep of orphaned EIPs. Tasks which
+/// run until the process exits are added to `tasks`.
+async fn run_cluster(
+    k8s_client: Client,
+    cluster_name: String,
+    settings: &ClusterSettings,
+    shutdown: Shared<BoxFuture<'static, ()>>,
+    readiness: Readiness,
+    tasks: &mut Vec<task::JoinHandle<()>>,
+) -> Result<Cluster, Error> {
+    let ClusterSettings {
+        providers,
+        allocation_limits,
+        namespace,
+        default_tags,
+        namespace_filter,
+        eip_list_params,
+        pod_list_params,
+        node_list_params,
+        reconcile_concurrency,
+        reconcile_debounce,
+        requeue_intervals,
+        pod_detach_grace,
+        scheduling_gate,
+        service_load_balancer_status,
+        karpenter_aware,
+        node_scale_down_protection,
+        external_dns_hostname,
+        pod_public_ip_label,
+        resolve_duplicate_eips,
+        foreign_association_policy,
+        autocreate,
+        idle_eip_warning,
+        idle_eip_release,
+        allocation_audit_interval,
+        snapshot,
+        orphan_cleanup_config,
+        migration_mode,
+        observe_only,
+        manage_crds,
+    } = settings.clone();
+
+    if observe_only || !manage_crds {
+        eip::verify_custom_resource(k8s_client.clone()).await?;
+    } else {
+        eip::register_custom_resource(k8s_client.clone()).await?;
+    }
+    migrate::migrate_eips(k8s_client.clone(), namespace.as_deref(), migration_mode).await?;
 
     debug!("Getting pod api");
     let pod_api = match namespace {
@@ -91,189 +758,804 @@ async fn run() -> Result<(), Error> {
         None => Api::<Eip>::all(k8s_client.clone()),
     };
 
-    debug!("Cleaning up any orphaned EIPs");
-    cleanup_orphan_eips(
-        &ec2_client,
-        &eip_api,
-        &pod_api,
-        &cluster_name,
-        namespace.as_deref(),
-    )
-    .await?;
-
-    info!("Starting tasks");
-    let mut tasks = vec![];
-    tasks.push({
-        let ec2_client = ec2_client.clone();
-        task::spawn(async move {
-            let mut interval = tokio::time::interval(EIP_QUOTA_INTERVAL);
-            // It's better to miss the occasional measurement than to hammer the endpoint
-            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let eip_index = EipIndex::default();
+    let node_index = NodeIndex::new(karpenter_aware);
 
-            loop {
-                interval.tick().await;
-                // Note: the Err that might occur here will be handled by tracing
-                // instrumentation, rather than directly here.
-                if let Err(err) = report_eip_quota_status(&ec2_client, &quota_client).await {
-                    event!(Level::ERROR, err = %err, "Quota reporting error");
-                }
+    let pod_controller = {
+        let context = controller::pod::Context::new(
+            providers.clone(),
+            eip_index.clone(),
+            node_index.clone(),
+            pod_detach_grace,
+            namespace_filter.clone(),
+            scheduling_gate,
+            PodMetadata {
+                karpenter_aware,
+                external_dns_hostname,
+                public_ip_label: pod_public_ip_label,
+            },
+        )
+        .with_autocreate(autocreate)
+        .with_foreign_associations(foreign_association_policy);
+        let list_params = pod_list_params.clone();
+        match &namespace {
+            Some(namespace) => {
+                Controller::namespaced(namespace, k8s_client.clone(), list_params, context)
             }
+            None => Controller::namespaced_all(k8s_client.clone(), list_params, context),
+        }
+        .with_concurrency(reconcile_concurrency)
+        .with_debounce(reconcile_debounce)
+        .with_requeue_intervals(requeue_intervals)
+        .with_observe_only(observe_only)
+        .with_graceful_shutdown_on(shutdown.clone())
+        .watches(
+            eip_api.clone(),
+            eip_list_params.clone(),
+            |eip: Eip| match &eip.spec.selector {
+                EipSelector::Pod { pod_name, .. } => Some(
+                    ObjectRef::<Pod>::new(pod_name).within(&eip.namespace().unwrap_or_default()),
+                ),
+                EipSelector::Node { .. } => None,
+            },
+        )
+    };
+
+    let node_controller = {
+        let context = controller::node::Context::new(
+            providers.clone(),
+            namespace.clone(),
+            eip_index.clone(),
+            namespace_filter.clone(),
+            karpenter_aware,
+            node_scale_down_protection,
+        )
+        .with_node_index(node_index.clone())
+        .with_foreign_associations(foreign_association_policy);
+        let node_index = node_index.clone();
+        Controller::cluster(k8s_client.clone(), node_list_params, context)
+            .with_concurrency(reconcile_concurrency)
+            .with_debounce(reconcile_debounce)
+            .with_requeue_intervals(requeue_intervals)
+            .with_observe_only(observe_only)
+            .with_graceful_shutdown_on(shutdown.clone())
+            .watches(
+                eip_api.clone(),
+                eip_list_params.clone(),
+                move |eip: Eip| match &eip.spec.selector {
+                    EipSelector::Node { selector } => node_index
+                        .matching(selector)
+                        .iter()
+                        .map(|name| ObjectRef::<Node>::new(name))
+                        .collect(),
+                    EipSelector::Pod { .. } => vec![],
+                },
+            )
+    };
+
+    let service_controller = service_load_balancer_status.then(|| {
+        let eip_services = EipServices::default();
+        let context = controller::service::Context::new(
+            eip_index.clone(),
+            eip_services.clone(),
+            namespace_filter.clone(),
+        );
+        let list_params = ListParams::default().labels(&MANAGE_EIP_LABEL);
+        match &namespace {
+            Some(namespace) => {
+                Controller::namespaced(namespace, k8s_client.clone(), list_params, context)
+            }
+            None => Controller::namespaced_all(k8s_client.clone(), list_params, context),
+        }
+        .with_concurrency(reconcile_concurrency)
+        .with_debounce(reconcile_debounce)
+        .with_requeue_intervals(requeue_intervals)
+        .with_observe_only(observe_only)
+        .with_graceful_shutdown_on(shutdown.clone())
+        .watches(eip_api.clone(), eip_list_params.clone(), move |eip: Eip| {
+            eip_services.for_eip(&eip)
         })
     });
 
-    tasks.push({
-        let context = controller::pod::Context::new(ec2_client.clone());
-        let list_params = ListParams::default().labels(MANAGE_EIP_LABEL);
-        let pod_controller = match &namespace {
+    let eip_controller = {
+        let context = controller::eip::Context::new(
+            providers.clone(),
+            cluster_name.clone(),
+            default_tags,
+            AddressPolicy {
+                dns_safety_check: orphan_cleanup_config.dns_safety_check,
+                resolve_duplicates: resolve_duplicate_eips,
+                adopt_existing: orphan_cleanup_config.adopt_existing,
+                idle_warning_after: Some(idle_eip_warning),
+                idle_release_after: idle_eip_release,
+            },
+            node_index.clone(),
+            namespace_filter,
+            allocation_limits,
+        );
+        let list_params = eip_list_params.clone();
+        let eip_index = eip_index.clone();
+        let node_eip_index = eip_index.clone();
+        match &namespace {
             Some(namespace) => {
                 Controller::namespaced(namespace, k8s_client.clone(), list_params, context)
             }
             None => Controller::namespaced_all(k8s_client.clone(), list_params, context),
-        };
-        task::spawn(pod_controller.run())
-    });
+        }
+        .with_concurrency(reconcile_concurrency)
+        .with_debounce(reconcile_debounce)
+        .with_requeue_intervals(requeue_intervals)
+        .with_observe_only(observe_only)
+        .with_graceful_shutdown_on(shutdown.clone())
+        .watches(pod_api.clone(), pod_list_params, move |pod: Pod| {
+            eip_index
+                .find_all_for_pod(&pod.namespace().unwrap_or_default(), &pod.name_any())
+                .unwrap_or_default()
+                .iter()
+                .map(|eip| ObjectRef::from_obj(&**eip))
+                .collect::<Vec<_>>()
+        })
+        .watches(
+            Api::<Node>::all(k8s_client.clone()),
+            ListParams::default(),
+            move |node: Node| {
+                let node_ips: Vec<&str> = kube_ext::NodeExt::ip(&node)
+                    .into_iter()
+                    .chain(kube_ext::NodeExt::dedicated_eni_ips(&node))
+                    .collect();
+                node_eip_index
+                    .find_related_to_node(node.labels(), &node_ips)
+                    .iter()
+                    .map(|eip| ObjectRef::from_obj(&**eip))
+                    .collect::<Vec<_>>()
+            },
+        )
+    };
 
-    tasks.push({
-        let context = controller::node::Context::new(ec2_client.clone(), namespace.clone());
-        let list_params = ListParams::default().labels(MANAGE_EIP_LABEL);
-        let node_controller = Controller::cluster(k8s_client.clone(), list_params, context);
-        task::spawn(node_controller.run())
-    });
+    if let Some(interval) = allocation_audit_interval {
+        tasks.push(task::spawn(audit::run(
+            k8s_client.clone(),
+            interval,
+            cluster_name.clone(),
+            namespace.clone(),
+            eip_index.clone(),
+            providers.clone(),
+        )));
+    }
+
+    if let Some(snapshot) = snapshot {
+        tasks.push(task::spawn(snapshot::run(
+            snapshot,
+            cluster_name.clone(),
+            namespace.clone(),
+            eip_index.clone(),
+            providers.clone(),
+        )));
+    }
 
     tasks.push({
-        let context = controller::eip::Context::new(ec2_client, cluster_name, default_tags);
-        let list_params = ListParams::default();
-        let eip_controller = match &namespace {
-            Some(namespace) => Controller::namespaced(namespace, k8s_client, list_params, context),
-            None => Controller::namespaced_all(k8s_client, list_params, context),
+        let eip_api = eip_api.clone();
+        let node_api = Api::<Node>::all(k8s_client.clone());
+        let k8s_client = k8s_client.clone();
+        let sweep = move || {
+            let (providers, eip_api, pod_api, node_api) = (
+                providers.clone(),
+                eip_api.clone(),
+                pod_api.clone(),
+                node_api.clone(),
+            );
+            let (k8s_client, cluster_name, namespace) =
+                (k8s_client.clone(), cluster_name.clone(), namespace.clone());
+            async move {
+                debug!("Cleaning up any orphaned EIPs");
+                match cleanup_orphan_eips(
+                    &providers,
+                    &eip_api,
+                    &pod_api,
+                    &node_api,
+                    &cluster_name,
+                    namespace.as_deref(),
+                    orphan_cleanup_config,
+                )
+                .await
+                {
+                    // Legacy pods' finalizers may only be removed once their
+                    // EIPs have actually been released.
+                    Ok(()) if orphan_cleanup_config.mode == OrphanCleanupMode::Delete => {
+                        migrate::migrate_pods(k8s_client, migration_mode).await
+                    }
+                    result => result,
+                }
+            }
         };
-        task::spawn(eip_controller.run())
+        task::spawn(retry_sweep(sweep, requeue_intervals, readiness))
     });
 
-    join_all(tasks).await;
+    let debug_state = {
+        let eip_index = eip_index.clone();
+        let pod_states = pod_controller.reconcile_states();
+        let node_states = node_controller.reconcile_states();
+        let eip_states = eip_controller.reconcile_states();
+        Box::new(move || {
+            let eips: Vec<Eip> = eip_index.all().iter().map(|eip| (**eip).clone()).collect();
+            serde_json::json!({
+                "eips": eips,
+                "reconciliations": {
+                    "pod": pod_states.snapshot(),
+                    "node": node_states.snapshot(),
+                    "eip": eip_states.snapshot(),
+                },
+            })
+        })
+    };
 
-    debug!("exiting");
-    Ok(())
+    tasks.push(task::spawn(eip_index.clone().run(eip_api, eip_list_params)));
+    tasks.push(task::spawn(
+        node_index.clone().run(Api::<Node>::all(k8s_client.clone())),
+    ));
+    let mut controllers = vec![
+        task::spawn(pod_controller.run()),
+        task::spawn(node_controller.run()),
+        task::spawn(eip_controller.run()),
+    ];
+    if let Some(service_controller) = service_controller {
+        controllers.push(task::spawn(service_controller.run()));
+    }
+    Ok(Cluster {
+        controllers,
+        debug_state,
+        eip_index,
+        #[cfg(feature = "query-api")]
+        node_index,
+    })
+}
+
+/// Resolves once the process is asked to terminate, with SIGTERM or SIGINT.
+async fn shutdown_signal() {
+    let mut terminate =
+        signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler.");
+    tokio::select! {
+        _ = terminate.recv() => {},
+        _ = tokio::signal::ctrl_c() => {},
+    }
+    info!("Received shutdown signal");
+}
+
+/// What the startup sweep does with orphaned EIPs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OrphanCleanupMode {
+    /// Release them.
+    Delete,
+    /// Only log which EIPs would be released.
+    DryRun,
+    /// Skip the sweep entirely.
+    Disabled,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct OrphanCleanupConfig {
+    mode: OrphanCleanupMode,
+    dns_safety_check: bool,
+    /// Addresses tagged with the namespace and name of an existing Eip are
+    /// kept for it to adopt.
+    adopt_existing: bool,
+    /// Addresses allocated more recently than this are never released.
+    min_age: Duration,
+}
+
+/// Checks whether a string looks like a Kubernetes object UID.
+fn is_uid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Checks whether an address is tagged with the namespace and name of an Eip
+/// which may adopt it.
+fn adoptable_by_name(address: &Address, eip_names: &HashSet<(String, String)>) -> bool {
+    match (
+        address.tag(aws::NAMESPACE_TAG),
+        address.tag(aws::EIP_NAME_TAG),
+    ) {
+        (Some(namespace), Some(name)) => {
+            eip_names.contains(&(namespace.to_owned(), name.to_owned()))
+        }
+        _ => false,
+    }
 }
 
-/// Finds all EIPs tagged for this cluster, then compares them to the pod UIDs. If the EIP is not
-/// tagged with a pod UID, or the UID does not exist in this cluster, it deletes the EIP.
-#[instrument(skip(ec2_client, eip_api, pod_api), err)]
+/// Checks whether an address is an orphan which is safe to release: it must be tagged with
+/// exactly this cluster's name, must not be associated with an instance outside this cluster,
+/// must not have been allocated less than `min_age` ago, and, unless it predates Eips, must be
+/// tagged with the well-formed UID of an Eip which no longer exists.
+fn is_orphan(
+    address: &Address,
+    cluster_name: &str,
+    eip_uids: &HashSet<String>,
+    node_instance_ids: &HashSet<String>,
+    min_age: Duration,
+) -> bool {
+    let allocation_id = &address.allocation_id;
+    // Addresses allocated before the allocation time was tagged are old enough.
+    let allocated_at = address
+        .tag(aws::ALLOCATED_AT_TAG)
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+    if let Some(allocated_at) = allocated_at {
+        let age = (Utc::now() - allocated_at.with_timezone(&Utc))
+            .to_std()
+            .unwrap_or_default();
+        if age < min_age {
+            event!(Level::INFO,
+                %allocation_id,
+                %allocated_at,
+                "Not releasing recently allocated EIP",
+            );
+            return false;
+        }
+    }
+    let legacy = address.tag(aws::CLUSTER_NAME_TAG).is_none();
+    let cluster_tag = if legacy {
+        aws::LEGACY_CLUSTER_NAME_TAG
+    } else {
+        aws::CLUSTER_NAME_TAG
+    };
+    if address.tag(cluster_tag) != Some(cluster_name) {
+        event!(Level::WARN,
+            %allocation_id,
+            "Not releasing EIP without an exact cluster name tag match",
+        );
+        return false;
+    }
+    if let Some(instance_id) = &address.instance_id {
+        if !node_instance_ids.contains(instance_id) {
+            event!(Level::WARN,
+                %allocation_id,
+                %instance_id,
+                "Not releasing EIP associated with an instance outside this cluster",
+            );
+            return false;
+        }
+    }
+    if address.tag(aws::PROTECTED_TAG) == Some("true") {
+        event!(Level::INFO,
+            %allocation_id,
+            "Not releasing EIP of a protected Eip",
+        );
+        return false;
+    }
+    let eip_uid = address.tag(aws::EIP_UID_TAG);
+    let transferred = address
+        .tag(aws::TRANSFERRED_FROM_TAG)
+        .map_or(false, |cluster| !cluster.is_empty());
+    if transferred {
+        event!(Level::INFO,
+            %allocation_id,
+            "Not releasing EIP transferred from another cluster, which is yet to be adopted",
+        );
+        return false;
+    }
+    if legacy {
+        return eip_uid.map_or(true, |uid| !eip_uids.contains(uid));
+    }
+    match eip_uid {
+        Some(uid) if is_uid(uid) => !eip_uids.contains(uid),
+        uid => {
+            event!(Level::WARN,
+                %allocation_id,
+                eip_uid = %uid.unwrap_or("None"),
+                "Not releasing EIP without a well-formed Eip UID tag",
+            );
+            false
+        }
+    }
+}
+
+/// Runs the startup sweep until it succeeds, backing off between failed
+/// attempts like a failing reconciliation, then marks it finished so the
+/// operator can become ready.
+async fn retry_sweep<F, Fut>(mut sweep: F, intervals: RequeueIntervals, readiness: Readiness)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let mut failures = 0;
+    loop {
+        match sweep().await {
+            Ok(()) => {
+                readiness.sweep_finished();
+                return;
+            }
+            Err(err) => {
+                failures += 1;
+                let delay = intervals.error(failures);
+                event!(
+                    Level::ERROR,
+                    err = %err,
+                    attempt = failures,
+                    retry_in = ?delay,
+                    "Orphaned EIP cleanup error"
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Finds all EIPs tagged for this cluster with each provider, then compares them to the Eip
+/// UIDs. If the UID does not exist in this cluster, it deletes the EIP, subject to the guards in
+/// `is_orphan`.
+#[instrument(skip(providers, eip_api, pod_api, node_api), err)]
 async fn cleanup_orphan_eips(
-    ec2_client: &Ec2Client,
+    providers: &Providers,
     eip_api: &Api<Eip>,
     pod_api: &Api<Pod>,
+    node_api: &Api<Node>,
     cluster_name: &str,
     namespace: Option<&str>,
+    config: OrphanCleanupConfig,
 ) -> Result<(), Error> {
-    let mut describe_addresses = ec2_client.describe_addresses().filters(
-        Filter::builder()
-            .name(format!("tag:{}", aws::CLUSTER_NAME_TAG))
-            .values(cluster_name.to_owned())
-            .build(),
-    );
-    if let Some(namespace) = namespace {
-        describe_addresses = describe_addresses.filters(
-            Filter::builder()
-                .name(format!("tag:{}", aws::NAMESPACE_TAG))
-                .values(namespace.to_owned())
-                .build(),
-        )
+    if config.mode == OrphanCleanupMode::Disabled {
+        info!("Orphaned EIP cleanup is disabled");
+        return Ok(());
     }
-    let mut addresses = describe_addresses
-        .send()
-        .await?
-        .addresses
-        .ok_or(Error::MissingAddresses)?;
+    let dry_run = config.mode == OrphanCleanupMode::DryRun;
 
-    let mut legacy_addresses = aws::describe_addresses_with_tag_value(
-        ec2_client,
-        aws::LEGACY_CLUSTER_NAME_TAG,
-        cluster_name,
-    )
-    .await?
-    .addresses
-    .ok_or(Error::MissingAddresses)?;
+    let mut addresses = vec![];
+    for (kind, provider) in providers.all() {
+        for address in provider
+            .describe_cluster_addresses(cluster_name, namespace)
+            .await?
+        {
+            addresses.push((kind, provider, address));
+        }
+    }
 
-    addresses.append(&mut legacy_addresses);
+    let eips = eip_api.list(&ListParams::default()).await?;
+    let eip_uids: HashSet<String> = eips
+        .iter()
+        .filter_map(|eip| eip.metadata.uid.clone())
+        .collect();
+    // Addresses which restored Eips are yet to adopt are still tagged with
+    // the UIDs of the Eips they were snapshotted from.
+    let adopted: HashSet<&str> = eips
+        .iter()
+        .filter_map(controller::eip::adopted_allocation_id)
+        .collect();
+    let eip_names: HashSet<(String, String)> = eips
+        .iter()
+        .filter(|_| config.adopt_existing)
+        .map(|eip| (eip.namespace().unwrap_or_default(), eip.name_any()))
+        .collect();
 
-    let eip_uids: HashSet<String> = eip_api
+    let node_instance_ids: HashSet<String> = node_api
         .list(&ListParams::default())
         .await?
-        .into_iter()
-        .filter_map(|eip| eip.metadata.uid)
+        .iter()
+        .filter_map(|node| node_index::instance_id(node).ok())
+        .map(str::to_owned)
         .collect();
 
-    for address in addresses {
-        let eip_uid = aws::get_tag_from_address(&address, aws::EIP_UID_TAG);
-        if eip_uid.is_none() || !eip_uids.contains(eip_uid.unwrap()) {
+    stream::iter(addresses)
+        .filter(|(_, _, address)| {
+            futures::future::ready(
+                !adopted.contains(address.allocation_id.as_str())
+                    && !adoptable_by_name(address, &eip_names)
+                    && is_orphan(
+                        address,
+                        cluster_name,
+                        &eip_uids,
+                        &node_instance_ids,
+                        config.min_age,
+                    ),
+            )
+        })
+        .map(Ok)
+        .try_for_each_concurrent(ORPHAN_CLEANUP_CONCURRENCY, |(kind, provider, address)| async move {
+            if config.dns_safety_check
+                && controller::pod::dns_target_in_use(pod_api, &address.public_ip).await?
+            {
+                event!(Level::WARN,
+                    allocation_id = %address.allocation_id,
+                    public_ip = %address.public_ip,
+                    "Not releasing orphaned EIP, since a pod's DNS target annotation still points at it",
+                );
+                return Ok(());
+            }
             event!(Level::WARN,
-                allocation_id = %address.allocation_id.as_deref().unwrap_or("None"),
-                eip_uid = %eip_uid.unwrap_or("None"),
+                provider = %kind,
+                allocation_id = %address.allocation_id,
+                eip_uid = %address.tag(aws::EIP_UID_TAG).unwrap_or("None"),
+                dry_run,
                 "Cleaning up orphaned EIP",
             );
-            aws::disassociate_and_release_address(ec2_client, &address).await?;
-        }
-    }
-
-    // Manually remove the old finalizer, since we just removed the EIPs.
-    // https://docs.rs/kube-runtime/0.65.0/src/kube_runtime/finalizer.rs.html#133
-    let legacy_pods = pod_api
-        .list(&ListParams::default().labels(LEGACY_MANAGE_EIP_LABEL))
+            if dry_run {
+                return Ok(());
+            }
+            // Addresses are released with the credentials of the namespace
+            // they were allocated for.
+            let namespace = address.tag(aws::NAMESPACE_TAG).unwrap_or_default();
+            provider.release(namespace, &address).await
+        })
         .await?;
-    for pod in legacy_pods {
-        if let Some(position) = pod
-            .finalizers()
-            .iter()
-            .position(|s| s == LEGACY_POD_FINALIZER_NAME)
-        {
-            let pod_name = pod.meta().name.as_ref().ok_or(Error::MissingPodName)?;
-            let finalizer_path = format!("/metadata/finalizers/{}", position);
-            pod_api
-                .patch::<Pod>(
-                    pod_name,
-                    &PatchParams::default(),
-                    &Patch::Json(json_patch::Patch(vec![
-                        PatchOperation::Test(TestOperation {
-                            path: finalizer_path.clone(),
-                            value: LEGACY_POD_FINALIZER_NAME.into(),
-                        }),
-                        PatchOperation::Remove(RemoveOperation {
-                            path: finalizer_path,
-                        }),
-                    ])),
-                )
-                .await?;
-        }
-    }
+
     Ok(())
 }
 
-#[instrument(skip(ec2_client, quota_client), err)]
+/// Reports the number of allocated EIPs against the EIP quota, recording both
+/// for the Eip controller to check, then the values of any extra EC2 quotas.
+#[instrument(skip(ec2_client, quota_client, cached_quotas, eip_quota), err)]
 async fn report_eip_quota_status(
     ec2_client: &Ec2Client,
     quota_client: &ServiceQuotaClient,
+    eip_quota_code: &str,
+    extra_quota_codes: &[String],
+    cached_quotas: &mut HashMap<String, (Instant, f64)>,
+    eip_quota: &EipQuota,
 ) -> Result<(), Error> {
-    let addresses_result = ec2_client.describe_addresses().send().await?;
+    let addresses_result =
+        aws::observe_call("DescribeAddresses", ec2_client.describe_addresses().send()).await?;
     let allocated = addresses_result.addresses().unwrap_or_default().len();
-    let quota_result = quota_client
+    let quota = get_ec2_quota(quota_client, eip_quota_code, cached_quotas).await?;
+    eip_quota.update(allocated, quota);
+    event!(Level::INFO, eips_allocated = %allocated, eip_quota = %quota, "eip_quota_checked");
+    for quota_code in extra_quota_codes {
+        let quota = get_ec2_quota(quota_client, quota_code, cached_quotas).await?;
+        event!(Level::INFO, %quota_code, %quota, "quota_checked");
+    }
+    Ok(())
+}
+
+/// Gets the value of an EC2 quota. Quotas are cached in `cached_quotas`,
+/// along with when they were fetched, and only fetched again once they are
+/// older than `EIP_QUOTA_CACHE_TTL`.
+async fn get_ec2_quota(
+    quota_client: &ServiceQuotaClient,
+    quota_code: &str,
+    cached_quotas: &mut HashMap<String, (Instant, f64)>,
+) -> Result<f64, Error> {
+    if let Some((fetched, quota)) = cached_quotas.get(quota_code) {
+        if fetched.elapsed() < EIP_QUOTA_CACHE_TTL {
+            return Ok(*quota);
+        }
+    }
+    let quota_request = quota_client
         .get_service_quota()
         .service_code("ec2")
-        .quota_code(EIP_QUOTA_CODE)
-        .send()
-        .await?;
+        .quota_code(quota_code);
+    let quota_result = aws::observe_call("GetServiceQuota", quota_request.send()).await?;
     let quota = quota_result
         .quota()
         .and_then(|q: &ServiceQuota| q.value)
         .unwrap_or(0f64);
-    event!(Level::INFO, eips_allocated = %allocated, eip_quota = %quota, "eip_quota_checked");
-    Ok(())
+    cached_quotas.insert(quota_code.to_owned(), (Instant::now(), quota));
+    Ok(quota)
 }
 
 fn set_abort_on_panic() {
     let old_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info| {
         old_hook(panic_info);
+        // Panics in reconciliations are caught and retried like any other
+        // reconciliation error.
+        if eip_operator_shared::controller::in_reconcile() {
+            return;
+        }
+        eip_operator_shared::flush_error_reports();
         std::process::abort();
     }));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockProvider};
+
+    const UID: &str = "01234567-89ab-cdef-0123-456789abcdef";
+    const GONE_UID: &str = "fedcba98-7654-3210-fedc-ba9876543210";
+
+    fn tags(uid: &str) -> Vec<(&str, &str)> {
+        vec![
+            (aws::CLUSTER_NAME_TAG, "my-cluster"),
+            (aws::NAMESPACE_TAG, "default"),
+            (aws::EIP_NAME_TAG, "my-eip"),
+            (aws::EIP_UID_TAG, uid),
+        ]
+    }
+
+    fn is_orphan(address: &Address) -> bool {
+        super::is_orphan(
+            address,
+            "my-cluster",
+            &HashSet::from([UID.to_owned()]),
+            &HashSet::from(["i-cluster".to_owned()]),
+            Duration::from_secs(600),
+        )
+    }
+
+    #[test]
+    fn addresses_of_deleted_eips_are_orphans() {
+        assert!(is_orphan(&mock::address("eipalloc-1", &tags(GONE_UID))));
+        assert!(!is_orphan(&mock::address("eipalloc-1", &tags(UID))));
+    }
+
+    #[test]
+    fn addresses_of_other_clusters_are_not_orphans() {
+        let mut tags = tags(GONE_UID);
+        tags[0] = (aws::CLUSTER_NAME_TAG, "other-cluster");
+        assert!(!is_orphan(&mock::address("eipalloc-1", &tags)));
+    }
+
+    #[test]
+    fn legacy_addresses_without_an_eip_are_orphans() {
+        let address = mock::address(
+            "eipalloc-1",
+            &[(aws::LEGACY_CLUSTER_NAME_TAG, "my-cluster")],
+        );
+        assert!(is_orphan(&address));
+    }
+
+    #[test]
+    fn addresses_without_a_well_formed_uid_are_not_orphans() {
+        assert!(!is_orphan(&mock::address("eipalloc-1", &tags("not-a-uid"))));
+        assert!(!is_orphan(&mock::address(
+            "eipalloc-1",
+            &tags(GONE_UID)[..3]
+        )));
+    }
+
+    #[test]
+    fn recently_allocated_addresses_are_not_orphans() {
+        let now = Utc::now().to_rfc3339();
+        let mut tags = tags(GONE_UID);
+        tags.push((aws::ALLOCATED_AT_TAG, &now));
+        assert!(!is_orphan(&mock::address("eipalloc-1", &tags)));
+    }
+
+    #[test]
+    fn addresses_associated_outside_the_cluster_are_not_orphans() {
+        let mut address = mock::address("eipalloc-1", &tags(GONE_UID));
+        address.instance_id = Some("i-elsewhere".to_owned());
+        assert!(!is_orphan(&address));
+        address.instance_id = Some("i-cluster".to_owned());
+        assert!(is_orphan(&address));
+    }
+
+    #[test]
+    fn protected_and_transferred_addresses_are_not_orphans() {
+        let mut protected = tags(GONE_UID);
+        protected.push((aws::PROTECTED_TAG, "true"));
+        assert!(!is_orphan(&mock::address("eipalloc-1", &protected)));
+        let mut transferred = tags(GONE_UID);
+        transferred.push((aws::TRANSFERRED_FROM_TAG, "other-cluster"));
+        assert!(!is_orphan(&mock::address("eipalloc-1", &transferred)));
+    }
+
+    /// A cluster with an Eip with `UID`, annotated to adopt
+    /// `eipalloc-adopted`, and one node.
+    fn cluster() -> Client {
+        let (client, _) = mock::client(|request| match request.uri().path() {
+            "/apis/materialize.cloud/v2/eips" => serde_json::json!({
+                "apiVersion": "materialize.cloud/v2",
+                "kind": "EipList",
+                "metadata": {},
+                "items": [{
+                    "apiVersion": "materialize.cloud/v2",
+                    "kind": "Eip",
+                    "metadata": {
+                        "name": "my-eip",
+                        "namespace": "default",
+                        "uid": UID,
+                        "annotations": {
+                            ADOPT_ALLOCATION_ID_ANNOTATION.as_str(): "eipalloc-adopted",
+                        },
+                    },
+                    "spec": {"selector": {"pod": {"podName": "my-pod"}}},
+                }],
+            }),
+            "/api/v1/nodes" => serde_json::json!({
+                "apiVersion": "v1",
+                "kind": "NodeList",
+                "metadata": {},
+                "items": [{
+                    "apiVersion": "v1",
+                    "kind": "Node",
+                    "metadata": {"name": "my-node"},
+                    "spec": {"providerID": "aws:///us-east-1a/i-cluster"},
+                }],
+            }),
+            path => panic!("Unexpected request for {}", path),
+        });
+        client
+    }
+
+    /// Sweeps the mock provider's addresses in the `cluster()`.
+    async fn sweep(provider: &Arc<MockProvider>, config: OrphanCleanupConfig) {
+        let client = cluster();
+        cleanup_orphan_eips(
+            &Providers::new(provider.clone()),
+            &Api::all(client.clone()),
+            &Api::all(client.clone()),
+            &Api::all(client),
+            "my-cluster",
+            None,
+            config,
+        )
+        .await
+        .unwrap();
+    }
+
+    fn config(mode: OrphanCleanupMode) -> OrphanCleanupConfig {
+        OrphanCleanupConfig {
+            mode,
+            dns_safety_check: false,
+            adopt_existing: false,
+            min_age: Duration::from_secs(600),
+        }
+    }
+
+    #[tokio::test]
+    async fn sweep_releases_only_orphans() {
+        let provider = MockProvider::new([
+            mock::address("eipalloc-orphan", &tags(GONE_UID)),
+            mock::address("eipalloc-adopted", &tags(GONE_UID)),
+            mock::address("eipalloc-kept", &tags(UID)),
+        ]);
+        sweep(&provider, config(OrphanCleanupMode::Delete)).await;
+        assert_eq!(provider.calls(), ["release eipalloc-orphan"]);
+    }
+
+    #[tokio::test]
+    async fn sweep_keeps_addresses_adoptable_by_name() {
+        let provider = MockProvider::new([mock::address("eipalloc-orphan", &tags(GONE_UID))]);
+        let config = OrphanCleanupConfig {
+            adopt_existing: true,
+            ..config(OrphanCleanupMode::Delete)
+        };
+        sweep(&provider, config).await;
+        assert!(provider.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sweep_releases_nothing_in_dry_run_mode() {
+        let provider = MockProvider::new([mock::address("eipalloc-orphan", &tags(GONE_UID))]);
+        sweep(&provider, config(OrphanCleanupMode::DryRun)).await;
+        assert!(provider.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_sweeps_are_retried_before_the_operator_is_ready() {
+        let provider = MockProvider::new([mock::address("eipalloc-orphan", &tags(GONE_UID))]);
+        provider.fail_describes(2);
+        let client = cluster();
+        let providers = Providers::new(provider.clone());
+        let readiness = Readiness::new(1);
+        let intervals = RequeueIntervals {
+            error_min: Duration::ZERO,
+            error_max: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut attempts = 0;
+        let sweep = || {
+            attempts += 1;
+            let (providers, client) = (providers.clone(), client.clone());
+            async move {
+                cleanup_orphan_eips(
+                    &providers,
+                    &Api::all(client.clone()),
+                    &Api::all(client.clone()),
+                    &Api::all(client),
+                    "my-cluster",
+                    None,
+                    config(OrphanCleanupMode::Delete),
+                )
+                .await
+            }
+        };
+        retry_sweep(sweep, intervals, readiness.clone()).await;
+        assert_eq!(attempts, 3);
+        assert!(readiness.ready.load(Ordering::SeqCst));
+        assert_eq!(provider.calls(), ["release eipalloc-orphan"]);
+    }
+}
@@ -0,0 +1,117 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, register_gauge_vec, register_histogram, register_histogram_vec,
+    register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    GaugeVec, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+
+/// Always 1, labelled with the version and git commit the operator was built from.
+pub static BUILD_INFO: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "eip_operator_build_info",
+        "The version and git commit the operator was built from.",
+        &["version", "git_sha"]
+    )
+    .unwrap()
+});
+
+/// Time from pod creation until its EIP is associated and the DNS target
+/// annotation has been applied.
+pub static POD_ATTACH_LATENCY: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "eip_operator_pod_attach_latency_seconds",
+        "Seconds from pod creation until its EIP is attached and annotated.",
+        exponential_buckets(1.0, 2.0, 12).unwrap()
+    )
+    .unwrap()
+});
+
+/// AWS API calls, by operation and outcome (`success`, `throttled`, `dry_run` or `error`).
+pub static AWS_API_CALLS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "eip_operator_aws_api_calls_total",
+        "AWS API calls made, by operation and outcome.",
+        &["operation", "outcome"]
+    )
+    .unwrap()
+});
+
+/// AWS API call latency, by operation, including any retries made by the SDK.
+pub static AWS_API_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "eip_operator_aws_api_latency_seconds",
+        "Latency of AWS API calls, by operation.",
+        &["operation"]
+    )
+    .unwrap()
+});
+
+/// Eips whose actual association no longer matched their status.
+pub static ASSOCIATION_DRIFT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "eip_operator_association_drift_total",
+        "Eips found associated with a different ENI or private IP than their status recorded."
+    )
+    .unwrap()
+});
+
+/// Eips whose address was found to have been released outside of the
+/// operator.
+pub static ALLOCATIONS_LOST: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "eip_operator_allocations_lost_total",
+        "Eips whose address was found to have been released outside of the operator."
+    )
+    .unwrap()
+});
+
+/// Eips whose address was found associated with a network interface they
+/// didn't recognize.
+pub static FOREIGN_ASSOCIATIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "eip_operator_foreign_associations_total",
+        "Eips whose address was found associated with a network interface they didn't recognize."
+    )
+    .unwrap()
+});
+
+/// How many EIPs of the EIP quota are unallocated, as last measured.
+pub static EIP_QUOTA_HEADROOM: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "eip_operator_eip_quota_headroom",
+        "EIPs of the EIP quota which are unallocated, as last measured."
+    )
+    .unwrap()
+});
+
+/// When each idle Eip's address was left unattached, as a Unix timestamp.
+pub static EIP_IDLE_SINCE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "eip_operator_eip_idle_since_seconds",
+        "When the address of each Eip which is allocated but unattached was left unattached, in seconds since the epoch.",
+        &["namespace", "eip"]
+    )
+    .unwrap()
+});
+
+/// The estimated hourly cost of the managed addresses, by namespace and
+/// whether they are attached or idle.
+pub static ESTIMATED_HOURLY_COST: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "eip_operator_estimated_hourly_cost",
+        "Estimated hourly cost of the managed addresses, by namespace and state.",
+        &["namespace", "state"]
+    )
+    .unwrap()
+});
+
+/// The estimated monthly cost of the managed addresses, by namespace and
+/// whether they are attached or idle.
+pub static ESTIMATED_MONTHLY_COST: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "eip_operator_estimated_monthly_cost",
+        "Estimated monthly cost of the managed addresses, by namespace and state.",
+        &["namespace", "state"]
+    )
+    .unwrap()
+});
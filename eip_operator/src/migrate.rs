@@ -0,0 +1,173 @@
+use std::fmt::Debug;
+
+use clap::ValueEnum;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use json_patch::{PatchOperation, RemoveOperation, TestOperation};
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{Api, ListParams, Patch, PostParams};
+use kube::{Client, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use tracing::{event, info, instrument, Level};
+
+use eip_operator_shared::{Error, MANAGE_EIP_LABEL};
+
+use crate::eip::{v1, v2};
+
+const LEGACY_MANAGE_EIP_LABEL: &str = "eip.aws.materialize.com/manage";
+const LEGACY_POD_FINALIZER_NAME: &str = "eip.aws.materialize.com/disassociate";
+const LEGACY_EIP_FINALIZER_NAME: &str = "eip.aws.materialize.com/destroy";
+
+// The number of legacy objects migrated at the same time at startup
+const MIGRATION_CONCURRENCY: usize = 16;
+
+/// What the startup migration does with objects left behind by earlier
+/// versions of the operator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum MigrationMode {
+    /// Migrate them.
+    Migrate,
+    /// Only log what would be migrated.
+    DryRun,
+    /// Skip the migration entirely.
+    Disabled,
+}
+
+/// Upgrades v1 Eips to v2, and removes legacy finalizers from Eips. Every
+/// step only acts on objects still carrying a legacy marker, so this is safe
+/// to run on every startup.
+#[instrument(skip(k8s_client), err)]
+pub(crate) async fn migrate_eips(
+    k8s_client: Client,
+    namespace: Option<&str>,
+    mode: MigrationMode,
+) -> Result<(), Error> {
+    if mode == MigrationMode::Disabled {
+        info!("Legacy migration is disabled");
+        return Ok(());
+    }
+    let dry_run = mode == MigrationMode::DryRun;
+
+    let eip_v1_api = v1::Eip::lax_api(k8s_client.clone(), namespace);
+    for eip_v1 in eip_v1_api.list(&ListParams::default()).await? {
+        match v2::Eip::try_from(&eip_v1) {
+            Ok(eip) => {
+                event!(
+                    Level::INFO,
+                    eip_v1 = serde_json::to_string(&eip_v1)?,
+                    dry_run,
+                    "updating existing eip to latest version"
+                );
+                if dry_run {
+                    continue;
+                }
+                let eip_api =
+                    v2::Eip::api(k8s_client.clone(), eip_v1.metadata.namespace.as_deref());
+                eip_api
+                    .replace(
+                        eip.metadata.name.as_ref().unwrap(),
+                        &PostParams {
+                            field_manager: Some(eip_operator_shared::metadata_domain().to_owned()),
+                            ..Default::default()
+                        },
+                        &eip,
+                    )
+                    .await?;
+            }
+            Err(Some(e)) => {
+                return Err(e);
+            }
+            Err(None) => {
+                // not a v1 Eip
+            }
+        }
+    }
+
+    let eip_api = match namespace {
+        Some(namespace) => Api::<v2::Eip>::namespaced(k8s_client.clone(), namespace),
+        None => Api::<v2::Eip>::all(k8s_client.clone()),
+    };
+    let eips = eip_api.list(&ListParams::default()).await?;
+    remove_finalizers(&k8s_client, eips, LEGACY_EIP_FINALIZER_NAME, dry_run).await
+}
+
+/// Removes the legacy finalizer from pods in every namespace, which is only
+/// safe once the legacy EIPs have been released by the orphaned EIP cleanup.
+/// Pods which carry only the legacy label are no longer managed, so are
+/// logged for their owners to relabel.
+#[instrument(skip(k8s_client), err)]
+pub(crate) async fn migrate_pods(k8s_client: Client, mode: MigrationMode) -> Result<(), Error> {
+    if mode == MigrationMode::Disabled {
+        return Ok(());
+    }
+    let dry_run = mode == MigrationMode::DryRun;
+
+    let legacy_pods = Api::<Pod>::all(k8s_client.clone())
+        .list(&ListParams::default().labels(LEGACY_MANAGE_EIP_LABEL))
+        .await?;
+    for pod in legacy_pods.iter() {
+        if !pod.labels().contains_key(MANAGE_EIP_LABEL.as_str()) {
+            event!(
+                Level::WARN,
+                namespace = %pod.namespace().unwrap_or_default(),
+                name = %pod.name_any(),
+                "Pod only has the legacy manage label, so its EIP is not managed"
+            );
+        }
+    }
+    remove_finalizers(&k8s_client, legacy_pods, LEGACY_POD_FINALIZER_NAME, dry_run).await
+}
+
+/// Manually removes the finalizer from each object which has it, since its
+/// controller no longer exists to do so.
+/// https://docs.rs/kube-runtime/0.65.0/src/kube_runtime/finalizer.rs.html#133
+async fn remove_finalizers<K>(
+    k8s_client: &Client,
+    objects: impl IntoIterator<Item = K>,
+    finalizer_name: &str,
+    dry_run: bool,
+) -> Result<(), Error>
+where
+    K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+    K::DynamicType: Default,
+{
+    stream::iter(objects)
+        .map(Ok)
+        .try_for_each_concurrent(MIGRATION_CONCURRENCY, |object| async move {
+            if let Some(position) = object.finalizers().iter().position(|s| s == finalizer_name) {
+                let name = object.name_any();
+                event!(
+                    Level::INFO,
+                    namespace = %object.namespace().unwrap_or_default(),
+                    %name,
+                    finalizer_name,
+                    dry_run,
+                    "Removing legacy finalizer"
+                );
+                if dry_run {
+                    return Ok(());
+                }
+                let finalizer_path = format!("/metadata/finalizers/{}", position);
+                let api = Api::<K>::namespaced(
+                    k8s_client.clone(),
+                    &object.namespace().unwrap_or_default(),
+                );
+                api.patch::<K>(
+                    &name,
+                    &eip_operator_shared::patch_params(),
+                    &Patch::Json(json_patch::Patch(vec![
+                        PatchOperation::Test(TestOperation {
+                            path: finalizer_path.clone(),
+                            value: finalizer_name.into(),
+                        }),
+                        PatchOperation::Remove(RemoveOperation {
+                            path: finalizer_path,
+                        }),
+                    ])),
+                )
+                .await?;
+            }
+            Ok::<_, Error>(())
+        })
+        .await
+}
@@ -0,0 +1,231 @@
+use std::sync::{Arc, Mutex};
+
+use hyper::{Body, Request, Response};
+use kube::Client;
+
+use eip_operator::aws::{CLUSTER_NAME_TAG, LEGACY_CLUSTER_NAME_TAG, NAMESPACE_TAG};
+use eip_operator::provider::{Address, AllocationRequest, CloudProvider};
+use eip_operator_shared::Error;
+
+/// A cloud provider keeping its addresses in memory, so that reconcilers can
+/// be tested without a cloud account. Each call changing an address is
+/// recorded, in order.
+#[derive(Default)]
+pub(crate) struct MockProvider {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    addresses: Vec<Address>,
+    calls: Vec<String>,
+    allocations: usize,
+    failing_describes: usize,
+}
+
+impl MockProvider {
+    pub(crate) fn new(addresses: impl IntoIterator<Item = Address>) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(State {
+                addresses: addresses.into_iter().collect(),
+                ..Default::default()
+            }),
+        })
+    }
+
+    /// The addresses currently allocated.
+    pub(crate) fn addresses(&self) -> Vec<Address> {
+        self.state.lock().unwrap().addresses.clone()
+    }
+
+    /// The address with the allocation ID, if it is still allocated.
+    pub(crate) fn address(&self, allocation_id: &str) -> Option<Address> {
+        self.addresses()
+            .into_iter()
+            .find(|address| address.allocation_id == allocation_id)
+    }
+
+    /// The calls which changed an address, such as `release eipalloc-1`.
+    pub(crate) fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Makes the next `count` descriptions of the cluster's addresses fail.
+    pub(crate) fn fail_describes(&self, count: usize) {
+        self.state.lock().unwrap().failing_describes = count;
+    }
+
+    fn update(&self, allocation_id: &str, call: String, update: impl FnOnce(&mut Address)) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(address) = state
+            .addresses
+            .iter_mut()
+            .find(|address| address.allocation_id == allocation_id)
+        {
+            update(address);
+        }
+        state.calls.push(call);
+    }
+}
+
+/// An unassociated address with the tags.
+pub(crate) fn address(allocation_id: &str, tags: &[(&str, &str)]) -> Address {
+    Address {
+        allocation_id: allocation_id.to_owned(),
+        public_ip: format!("198.51.100.{}", allocation_id.len()),
+        association_id: None,
+        eni: None,
+        private_ip_address: None,
+        instance_id: None,
+        tags: tags
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+/// The address, associated with the network interface and private IP.
+pub(crate) fn associated(mut address: Address, eni: &str, private_ip: &str) -> Address {
+    address.association_id = Some(format!("eipassoc-{}", address.allocation_id));
+    address.eni = Some(eni.to_owned());
+    address.private_ip_address = Some(private_ip.to_owned());
+    address
+}
+
+#[async_trait::async_trait]
+impl CloudProvider for MockProvider {
+    async fn allocate(&self, request: AllocationRequest<'_>) -> Result<Address, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.allocations += 1;
+        let allocation_id = format!("eipalloc-new{}", state.allocations);
+        let mut allocated = address(&allocation_id, &[]);
+        allocated.public_ip = format!("203.0.113.{}", state.allocations);
+        allocated.tags = request.tags().into_iter().collect();
+        state.addresses.push(allocated.clone());
+        state.calls.push(format!("allocate {}", allocation_id));
+        Ok(allocated)
+    }
+
+    async fn describe(&self, _namespace: &str, allocation_id: &str) -> Result<Address, Error> {
+        self.address(allocation_id).ok_or(Error::MissingAddresses)
+    }
+
+    async fn describe_by_tag(
+        &self,
+        _namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<Address>, Error> {
+        Ok(self
+            .addresses()
+            .into_iter()
+            .filter(|address| address.tag(key) == Some(value))
+            .collect())
+    }
+
+    async fn describe_cluster_addresses(
+        &self,
+        cluster_name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Vec<Address>, Error> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.failing_describes > 0 {
+                state.failing_describes -= 1;
+                return Err(Error::MissingAddresses);
+            }
+        }
+        Ok(self
+            .addresses()
+            .into_iter()
+            .filter(|address| {
+                address.tag(CLUSTER_NAME_TAG) == Some(cluster_name)
+                    || address.tag(LEGACY_CLUSTER_NAME_TAG) == Some(cluster_name)
+            })
+            .filter(|address| namespace.map_or(true, |ns| address.tag(NAMESPACE_TAG) == Some(ns)))
+            .collect())
+    }
+
+    async fn network_interface(
+        &self,
+        instance_id: &str,
+        _private_ip: &str,
+    ) -> Result<String, Error> {
+        Ok(format!("eni-{}", instance_id))
+    }
+
+    async fn associate(
+        &self,
+        _namespace: &str,
+        allocation_id: &str,
+        eni: &str,
+        private_ip: &str,
+    ) -> Result<(), Error> {
+        let call = format!("associate {} {} {}", allocation_id, eni, private_ip);
+        self.update(allocation_id, call, |address| {
+            *address = associated(address.clone(), eni, private_ip);
+        });
+        Ok(())
+    }
+
+    async fn disassociate(&self, _namespace: &str, allocation_id: &str) -> Result<(), Error> {
+        let call = format!("disassociate {}", allocation_id);
+        self.update(allocation_id, call, |address| {
+            address.association_id = None;
+            address.eni = None;
+            address.private_ip_address = None;
+            address.instance_id = None;
+        });
+        Ok(())
+    }
+
+    async fn release(&self, _namespace: &str, address: &Address) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .addresses
+            .retain(|allocated| allocated.allocation_id != address.allocation_id);
+        state
+            .calls
+            .push(format!("release {}", address.allocation_id));
+        Ok(())
+    }
+
+    async fn converge_tags(
+        &self,
+        _namespace: &str,
+        address: &Address,
+        tags: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        for (key, value) in tags {
+            if address.tag(key) != Some(*value) {
+                let call = format!("tag {} {}={}", address.allocation_id, key, value);
+                self.update(&address.allocation_id, call, |address| {
+                    address.tags.insert(key.to_string(), value.to_string());
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A Kubernetes client whose requests are answered with the JSON `respond`
+/// returns for them, so that reconcilers can be tested without a cluster.
+/// The method and path of each request are recorded, in order.
+pub(crate) fn client(
+    respond: impl Fn(&Request<Body>) -> serde_json::Value + Send + 'static,
+) -> (Client, Arc<Mutex<Vec<String>>>) {
+    let (service, mut handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+    let requests = Arc::new(Mutex::new(vec![]));
+    let recorded = Arc::clone(&requests);
+    tokio::spawn(async move {
+        while let Some((request, send)) = handle.next_request().await {
+            recorded
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", request.method(), request.uri().path()));
+            let body = serde_json::to_vec(&respond(&request)).unwrap();
+            send.send_response(Response::new(Body::from(body)));
+        }
+    });
+    (Client::new(service, "default"), requests)
+}
@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use futures::future;
+use futures::stream::StreamExt;
+use k8s_openapi::api::core::v1::Node;
+use kube::api::{Api, ListParams};
+use kube::ResourceExt;
+use kube_runtime::watcher;
+use tracing::{event, Level};
+
+use eip_operator_shared::Error;
+
+use crate::kube_ext::NodeExt;
+
+/// An in-memory map of node names to their EC2 instance ids and labels, kept
+/// up to date by a watcher, so that the pod reconciler doesn't need to fetch
+/// the node on every reconciliation, Eip changes can be mapped to the nodes
+/// they select, and the Eip reconciler can tell which node holds an address.
+#[derive(Clone, Default)]
+pub(crate) struct NodeIndex {
+    nodes: Arc<RwLock<HashMap<String, Entry>>>,
+    /// Whether nodes which Karpenter is disrupting are reported as such.
+    karpenter_aware: bool,
+}
+
+struct Entry {
+    instance_id: Option<String>,
+    /// The node's internal IP, and those of its dedicated network interface.
+    ips: Vec<String>,
+    labels: BTreeMap<String, String>,
+    disrupting: bool,
+}
+
+impl Entry {
+    fn has_ip(&self, ip: &str) -> bool {
+        self.ips.iter().any(|entry_ip| entry_ip == ip)
+    }
+
+    fn new(node: &Node) -> Self {
+        Self {
+            instance_id: instance_id(node).ok().map(str::to_owned),
+            ips: node
+                .ip()
+                .into_iter()
+                .chain(node.dedicated_eni_ips())
+                .map(str::to_owned)
+                .collect(),
+            labels: node.metadata.labels.clone().unwrap_or_default(),
+            disrupting: node.disrupting(),
+        }
+    }
+}
+
+/// Parses the EC2 instance id or GCE instance name out of the node's provider id.
+pub(crate) fn instance_id(node: &Node) -> Result<&str, Error> {
+    let provider_id = node.provider_id().ok_or(Error::MissingProviderId)?;
+    Ok(provider_id
+        .rsplit_once('/')
+        .ok_or(Error::MalformedProviderId)?
+        .1)
+}
+
+impl NodeIndex {
+    pub(crate) fn new(karpenter_aware: bool) -> Self {
+        Self {
+            nodes: Default::default(),
+            karpenter_aware,
+        }
+    }
+
+    /// Watches nodes with the supplied api, keeping the index up to date.
+    pub(crate) async fn run(self, api: Api<Node>) {
+        watcher(api, ListParams::default())
+            .for_each(|event| {
+                match event {
+                    Ok(event) => self.apply(event),
+                    Err(err) => event!(Level::ERROR, err = %err, "Node index watch error."),
+                }
+                future::ready(())
+            })
+            .await
+    }
+
+    /// Updates the index with an event of the watch.
+    pub(crate) fn apply(&self, event: watcher::Event<Node>) {
+        let mut nodes = self.nodes.write().unwrap();
+        match event {
+            watcher::Event::Applied(node) => {
+                nodes.insert(node.name_any(), Entry::new(&node));
+            }
+            watcher::Event::Deleted(node) => {
+                nodes.remove(&node.name_any());
+            }
+            watcher::Event::Restarted(new_nodes) => {
+                *nodes = new_nodes
+                    .iter()
+                    .map(|node| (node.name_any(), Entry::new(node)))
+                    .collect();
+            }
+        }
+    }
+
+    /// Resolves the instance id of the named node, falling back to fetching
+    /// the node if it isn't in the index (yet).
+    pub(crate) async fn instance_id(
+        &self,
+        node_api: &Api<Node>,
+        node_name: &str,
+    ) -> Result<String, Error> {
+        let cached = self
+            .nodes
+            .read()
+            .unwrap()
+            .get(node_name)
+            .and_then(|entry| entry.instance_id.clone());
+        match cached {
+            Some(id) => Ok(id),
+            None => {
+                let node = node_api.get(node_name).await?;
+                Ok(instance_id(&node)?.to_owned())
+            }
+        }
+    }
+
+    /// The names of the nodes whose labels include all of the selector's.
+    pub(crate) fn matching(&self, selector: &BTreeMap<String, String>) -> Vec<String> {
+        self.nodes
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| {
+                selector
+                    .iter()
+                    .all(|(key, value)| entry.labels.get(key) == Some(value))
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The labels of the node with the given internal or dedicated network
+    /// interface IP, if it is known.
+    pub(crate) fn labels_for_ip(&self, ip: &str) -> Option<BTreeMap<String, String>> {
+        self.nodes
+            .read()
+            .unwrap()
+            .values()
+            .find(|entry| entry.has_ip(ip))
+            .map(|entry| entry.labels.clone())
+    }
+
+    /// The name of the node with the given internal or dedicated network
+    /// interface IP, if it is known.
+    pub(crate) fn name_for_ip(&self, ip: &str) -> Option<String> {
+        self.nodes
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(_, entry)| entry.has_ip(ip))
+            .map(|(name, _)| name.clone())
+    }
+
+    /// Checks whether the node with the given internal or dedicated network
+    /// interface IP is being disrupted by Karpenter, so shouldn't hold an EIP.
+    /// Always false unless the index is Karpenter aware.
+    pub(crate) fn disrupting_ip(&self, ip: &str) -> bool {
+        self.karpenter_aware
+            && self
+                .nodes
+                .read()
+                .unwrap()
+                .values()
+                .any(|entry| entry.has_ip(ip) && entry.disrupting)
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use eip_operator_shared::Error;
+use k8s_openapi::chrono::{DateTime, Utc};
+
+use crate::aws::{
+    ALLOCATED_AT_TAG, CLUSTER_NAME_TAG, EIP_NAME_TAG, EIP_UID_TAG, NAMESPACE_TAG, NAME_TAG,
+    NODE_SELECTOR_TAG, POD_NAME_TAG,
+};
+use crate::eip::v2::{Eip, EipSelector, Provider};
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether mutating cloud calls are only checked, rather than executed.
+/// EC2 still checks the permissions and parameters of each call, while other
+/// clouds skip them.
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// An address allocated by a cloud provider, and its current association, if any.
+#[derive(Clone, Debug)]
+pub struct Address {
+    pub allocation_id: String,
+    pub public_ip: String,
+    pub association_id: Option<String>,
+    /// The network interface the address is associated with.
+    pub eni: Option<String>,
+    pub private_ip_address: Option<String>,
+    pub instance_id: Option<String>,
+    pub tags: HashMap<String, String>,
+}
+
+impl Address {
+    /// The value of the address's tag with the key, if it has one.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+}
+
+/// What a new address is allocated for, to tag it with.
+#[derive(Debug)]
+pub struct AllocationRequest<'a> {
+    pub eip_uid: &'a str,
+    pub eip_name: &'a str,
+    pub selector: &'a EipSelector,
+    pub cluster_name: &'a str,
+    pub namespace: &'a str,
+    pub default_tags: &'a HashMap<String, String>,
+}
+
+impl AllocationRequest<'_> {
+    /// The tags of the new address, identifying the Eip it is allocated for.
+    pub fn tags(&self) -> Vec<(String, String)> {
+        self.tags_at(Utc::now())
+    }
+
+    /// The tags of an address allocated at the time.
+    pub fn tags_at(&self, allocated_at: DateTime<Utc>) -> Vec<(String, String)> {
+        let mut tags: Vec<(String, String)> = self
+            .default_tags
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        tags.push((EIP_UID_TAG.to_owned(), self.eip_uid.to_owned()));
+        tags.push((EIP_NAME_TAG.to_owned(), self.eip_name.to_owned()));
+        tags.push((NAMESPACE_TAG.to_owned(), self.namespace.to_owned()));
+        tags.push((ALLOCATED_AT_TAG.to_owned(), allocated_at.to_rfc3339()));
+        tags.push((CLUSTER_NAME_TAG.to_owned(), self.cluster_name.to_owned()));
+        match self.selector {
+            EipSelector::Pod { pod_name, .. } => {
+                tags.push((POD_NAME_TAG.to_owned(), pod_name.clone()));
+            }
+            EipSelector::Node { selector } => {
+                tags.push((
+                    NODE_SELECTOR_TAG.to_owned(),
+                    serde_json::to_string(selector).unwrap(),
+                ));
+            }
+        }
+        tags.push((
+            NAME_TAG.to_owned(),
+            format!(
+                "eip-operator:{}:{}:{}",
+                self.cluster_name, self.namespace, self.eip_name
+            ),
+        ));
+        tags
+    }
+}
+
+/// Allocates, associates, and releases addresses with a particular cloud.
+///
+/// Addresses of Eips in a namespace are managed with the credentials for
+/// that namespace, so each method acting on an address takes its namespace.
+#[async_trait::async_trait]
+pub trait CloudProvider: Send + Sync {
+    /// Allocates an address, tagged for the Eip it is requested for.
+    async fn allocate(&self, request: AllocationRequest<'_>) -> Result<Address, Error>;
+
+    /// Describes the address with the allocation ID.
+    async fn describe(&self, namespace: &str, allocation_id: &str) -> Result<Address, Error>;
+
+    /// Describes every address with the tag.
+    async fn describe_by_tag(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<Address>, Error>;
+
+    /// Describes every address tagged for the cluster, including those of
+    /// namespaces with their own credentials, optionally only those tagged
+    /// for one namespace.
+    async fn describe_cluster_addresses(
+        &self,
+        cluster_name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Vec<Address>, Error>;
+
+    /// Finds the network interface of the instance with the private IP.
+    async fn network_interface(&self, instance_id: &str, private_ip: &str)
+        -> Result<String, Error>;
+
+    /// Finds the network interface the operator dedicated to the addresses
+    /// of node Eips on the instance, creating and attaching one if there is
+    /// none yet, and assigning it secondary private IPs until it has at least
+    /// as many as asked for. Returns its ID and private IPs, primary first.
+    async fn dedicated_network_interface(
+        &self,
+        _instance_id: &str,
+        _ip_count: usize,
+    ) -> Result<(String, Vec<String>), Error> {
+        Err(Error::DedicatedEniUnsupported)
+    }
+
+    /// Associates the address with the network interface and private IP,
+    /// waiting until the association is visible.
+    async fn associate(
+        &self,
+        namespace: &str,
+        allocation_id: &str,
+        eni: &str,
+        private_ip: &str,
+    ) -> Result<(), Error>;
+
+    /// Disassociates the address with the allocation ID from anything it is
+    /// associated with. An address which no longer exists has nothing to
+    /// disassociate.
+    async fn disassociate(&self, namespace: &str, allocation_id: &str) -> Result<(), Error>;
+
+    /// Disassociates the address if it is associated, then releases it.
+    async fn release(&self, namespace: &str, address: &Address) -> Result<(), Error>;
+
+    /// Sets any of the tags which the address lacks or has a different value for.
+    async fn converge_tags(
+        &self,
+        namespace: &str,
+        address: &Address,
+        tags: &[(&str, &str)],
+    ) -> Result<(), Error>;
+}
+
+/// The configured cloud providers. AWS is always configured, as it is the
+/// provider of Eips which don't name one.
+#[derive(Clone)]
+pub struct Providers {
+    providers: HashMap<Provider, Arc<dyn CloudProvider>>,
+}
+
+impl Providers {
+    pub fn new(aws: Arc<dyn CloudProvider>) -> Self {
+        Self {
+            providers: HashMap::from([(Provider::Aws, aws)]),
+        }
+    }
+
+    /// Adds the provider of the kind.
+    pub fn insert(&mut self, kind: Provider, provider: Arc<dyn CloudProvider>) {
+        self.providers.insert(kind, provider);
+    }
+
+    /// The provider of the Eip's address.
+    pub fn for_eip(&self, eip: &Eip) -> Result<&dyn CloudProvider, Error> {
+        let kind = eip.spec.provider.unwrap_or_default();
+        self.providers
+            .get(&kind)
+            .map(|provider| &**provider)
+            .ok_or_else(|| Error::ProviderNotConfigured(kind.to_string()))
+    }
+
+    /// Replaces every provider with the result of wrapping it, such as to
+    /// inject faults into its calls.
+    pub fn wrap(self, wrap: impl Fn(Arc<dyn CloudProvider>) -> Arc<dyn CloudProvider>) -> Self {
+        Self {
+            providers: self
+                .providers
+                .into_iter()
+                .map(|(kind, provider)| (kind, wrap(provider)))
+                .collect(),
+        }
+    }
+
+    /// Every configured provider, with its kind.
+    pub fn all(&self) -> impl Iterator<Item = (Provider, &dyn CloudProvider)> {
+        self.providers
+            .iter()
+            .map(|(kind, provider)| (*kind, &**provider))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+
+    use k8s_openapi::chrono::TimeZone;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::eip::tests::selector;
+
+    proptest! {
+        #[test]
+        fn allocation_tags_identify_the_eip(
+            selector in selector(),
+            default_tags in prop::collection::hash_map("[a-z]{1,8}", "[a-z0-9]{0,8}", 0..5),
+            eip_name in "[a-z0-9-]{1,12}",
+            namespace in "[a-z0-9-]{1,12}",
+            seconds in 0i64..4_000_000_000,
+        ) {
+            let allocated_at = Utc.timestamp_opt(seconds, 0).unwrap();
+            let request = AllocationRequest {
+                eip_uid: "01234567-89ab-cdef-0123-456789abcdef",
+                eip_name: &eip_name,
+                selector: &selector,
+                cluster_name: "my-cluster",
+                namespace: &namespace,
+                default_tags: &default_tags,
+            };
+            let tags = request.tags_at(allocated_at);
+            let keys: HashSet<_> = tags.iter().map(|(key, _)| key.as_str()).collect();
+            prop_assert_eq!(keys.len(), tags.len(), "duplicate tag keys");
+            let tags: HashMap<_, _> = tags.into_iter().collect();
+
+            prop_assert_eq!(&tags[EIP_UID_TAG], request.eip_uid);
+            prop_assert_eq!(&tags[EIP_NAME_TAG], &eip_name);
+            prop_assert_eq!(&tags[NAMESPACE_TAG], &namespace);
+            prop_assert_eq!(&tags[CLUSTER_NAME_TAG], "my-cluster");
+            prop_assert_eq!(
+                DateTime::parse_from_rfc3339(&tags[ALLOCATED_AT_TAG]).unwrap(),
+                allocated_at
+            );
+            prop_assert_eq!(
+                &tags[NAME_TAG],
+                &format!("eip-operator:my-cluster:{}:{}", namespace, eip_name)
+            );
+            for (key, value) in &default_tags {
+                prop_assert_eq!(&tags[key], value);
+            }
+            match &selector {
+                EipSelector::Pod { pod_name, .. } => {
+                    prop_assert_eq!(&tags[POD_NAME_TAG], pod_name);
+                    prop_assert!(!tags.contains_key(NODE_SELECTOR_TAG));
+                }
+                EipSelector::Node { selector } => {
+                    let tagged: BTreeMap<String, String> =
+                        serde_json::from_str(&tags[NODE_SELECTOR_TAG]).unwrap();
+                    prop_assert_eq!(&tagged, selector);
+                    prop_assert!(!tags.contains_key(POD_NAME_TAG));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use kube::ResourceExt;
+use serde::Serialize;
+use tracing::{event, Level};
+
+use eip_operator_shared::Error;
+
+use crate::eip::v2::{Eip, EipSelector};
+use crate::eip_index::EipIndex;
+use crate::node_index::NodeIndex;
+
+/// The path the address assignments are served at.
+const ASSIGNMENTS_PATH: &str = "/assignments";
+
+/// The indexes of a cluster, which its address assignments are read from.
+#[derive(Clone)]
+pub(crate) struct ClusterIndexes {
+    pub(crate) cluster_name: String,
+    pub(crate) eip_index: EipIndex,
+    pub(crate) node_index: NodeIndex,
+}
+
+/// An Eip, the pod or node it selects, and the address it was given.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Assignment {
+    cluster: String,
+    namespace: String,
+    eip: String,
+    provider: String,
+    allocation_id: Option<String>,
+    public_ip: Option<String>,
+    pod: Option<String>,
+    node: Option<String>,
+    eni: Option<String>,
+    private_ip: Option<String>,
+    attached: bool,
+}
+
+impl Assignment {
+    fn new(cluster: &ClusterIndexes, eip: &Eip) -> Self {
+        let (allocation_id, eni, private_ip) = eip.attachment();
+        let (pod, node) = match &eip.spec.selector {
+            EipSelector::Pod { pod_name, .. } => (Some(pod_name.clone()), None),
+            EipSelector::Node { .. } => (
+                None,
+                private_ip.and_then(|ip| cluster.node_index.name_for_ip(ip)),
+            ),
+        };
+        Self {
+            cluster: cluster.cluster_name.clone(),
+            namespace: eip.namespace().unwrap_or_default(),
+            eip: eip.name_any(),
+            provider: eip.spec.provider.unwrap_or_default().to_string(),
+            allocation_id: allocation_id.map(str::to_owned),
+            public_ip: eip.public_ip_address().map(str::to_owned),
+            pod,
+            node,
+            eni: eni.map(str::to_owned),
+            private_ip: private_ip.map(str::to_owned),
+            attached: eip.attached(),
+        }
+    }
+
+    /// Checks the assignment against the filters of the query string, each
+    /// of which must match exactly.
+    fn matches(&self, filters: &HashMap<String, String>) -> bool {
+        filters.iter().all(|(key, value)| {
+            let field = match key.as_str() {
+                "cluster" => Some(&self.cluster),
+                "namespace" => Some(&self.namespace),
+                "eip" => Some(&self.eip),
+                "provider" => Some(&self.provider),
+                "allocationId" => self.allocation_id.as_ref(),
+                "publicIp" => self.public_ip.as_ref(),
+                "pod" => self.pod.as_ref(),
+                "node" => self.node.as_ref(),
+                "privateIp" => self.private_ip.as_ref(),
+                // Unknown filters match nothing, rather than being ignored.
+                _ => None,
+            };
+            field == Some(value)
+        })
+    }
+}
+
+/// Serves the address assignments of every cluster's Eips as JSON, to
+/// requests bearing the token, so that network automation needs neither
+/// Kubernetes API access nor cloud credentials to look them up.
+pub(crate) async fn serve(
+    addr: SocketAddr,
+    token: String,
+    clusters: Vec<ClusterIndexes>,
+) -> Result<(), Error> {
+    let token = Arc::new(token);
+    let clusters = Arc::new(clusters);
+    let make_service = make_service_fn(move |_conn| {
+        let token = Arc::clone(&token);
+        let clusters = Arc::clone(&clusters);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle_request(&request, &token, &clusters);
+                async move { response }
+            }))
+        }
+    });
+    event!(Level::INFO, %addr, "Serving the query API.");
+    Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+fn handle_request(
+    request: &Request<Body>,
+    token: &str,
+    clusters: &[ClusterIndexes],
+) -> Result<Response<Body>, Error> {
+    if request.method() != Method::GET || request.uri().path() != ASSIGNMENTS_PATH {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())?);
+    }
+    if !authorized(request, token) {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(Body::empty())?);
+    }
+    let filters: HashMap<String, String> =
+        form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect();
+    let mut assignments = vec![];
+    for cluster in clusters {
+        for eip in cluster.eip_index.all() {
+            let assignment = Assignment::new(cluster, &eip);
+            if assignment.matches(&filters) {
+                assignments.push(assignment);
+            }
+        }
+    }
+    assignments.sort_by(|a, b| {
+        (&a.cluster, &a.namespace, &a.eip).cmp(&(&b.cluster, &b.namespace, &b.eip))
+    });
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&assignments)?))?)
+}
+
+fn authorized(request: &Request<Body>, token: &str) -> bool {
+    request
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map_or(false, |bearer| bearer == token)
+}
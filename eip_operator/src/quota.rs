@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{Client, CustomResourceExt, ResourceExt};
+use kube_runtime::events::{Event, EventType, Recorder};
+use tracing::{event, Level};
+
+use eip_operator_shared::Error;
+
+use crate::eip::v2::{Eip, Provider};
+
+/// The EIP quota of the operator's AWS account, and how many EIPs it had
+/// allocated, as last measured by the quota reporter. The Eip controller
+/// checks it before allocating, rather than making calls bound to fail.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EipQuota {
+    usage: Arc<Mutex<Option<Usage>>>,
+    /// How many EIPs of the quota are left for other uses.
+    reserved: usize,
+    /// Namespaces whose EIPs are allocated with their own role, possibly in
+    /// another account, so aren't counted against this quota.
+    own_roles: Arc<HashSet<String>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Usage {
+    allocated: usize,
+    quota: usize,
+}
+
+impl EipQuota {
+    pub(crate) fn new(reserved: usize, own_roles: HashSet<String>) -> Self {
+        Self {
+            usage: Arc::default(),
+            reserved,
+            own_roles: Arc::new(own_roles),
+        }
+    }
+
+    /// Records the quota and the number of allocated EIPs. A quota of zero
+    /// means it couldn't be found, so doesn't hold allocations back.
+    pub(crate) fn update(&self, allocated: usize, quota: f64) {
+        *self.usage.lock().unwrap() = (quota >= 1.0).then_some(Usage {
+            allocated,
+            quota: quota as usize,
+        });
+    }
+
+    /// How many EIPs of the quota are unallocated, once measured.
+    pub(crate) fn headroom(&self) -> Option<usize> {
+        self.usage
+            .lock()
+            .unwrap()
+            .map(|usage| usage.quota.saturating_sub(usage.allocated))
+    }
+
+    /// Counts an EIP allocated since the usage was last measured.
+    pub(crate) fn count_allocation(&self) {
+        if let Some(usage) = self.usage.lock().unwrap().as_mut() {
+            usage.allocated += 1;
+        }
+    }
+
+    /// Fails if allocating the Eip an EIP would leave fewer than the reserved
+    /// EIPs of the quota. Passes until the usage is first measured, and for
+    /// Eips allocated by other clouds or with other roles.
+    pub(crate) fn check(&self, eip: &Eip) -> Result<(), Error> {
+        if eip.spec.provider.unwrap_or_default() != Provider::Aws
+            || eip
+                .namespace()
+                .map_or(false, |namespace| self.own_roles.contains(&namespace))
+        {
+            return Ok(());
+        }
+        match *self.usage.lock().unwrap() {
+            Some(Usage { allocated, quota }) if allocated + self.reserved >= quota => {
+                Err(Error::QuotaHeadroomExhausted {
+                    allocated,
+                    quota,
+                    reserved: self.reserved,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The most addresses the Eips of each namespace may have, so that one
+/// tenant can't exhaust the quota for everyone.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EipBudgets {
+    /// The budget of namespaces without their own.
+    default: Option<usize>,
+    by_namespace: Arc<HashMap<String, usize>>,
+}
+
+impl EipBudgets {
+    pub(crate) fn new(default: Option<usize>, by_namespace: HashMap<String, usize>) -> Self {
+        Self {
+            default,
+            by_namespace: Arc::new(by_namespace),
+        }
+    }
+
+    /// The budget of the namespace, if it has one.
+    pub(crate) fn for_namespace(&self, namespace: &str) -> Option<usize> {
+        self.by_namespace.get(namespace).copied().or(self.default)
+    }
+
+    /// Fails if the namespace, which already has this many addresses, may not
+    /// have another.
+    pub(crate) fn check(&self, namespace: &str, allocated: usize) -> Result<(), Error> {
+        match self.for_namespace(namespace) {
+            Some(budget) if allocated >= budget => Err(Error::NamespaceBudgetExhausted {
+                namespace: namespace.to_owned(),
+                budget,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The limits checked before allocating an address.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AllocationLimits {
+    pub(crate) quota: EipQuota,
+    pub(crate) budgets: EipBudgets,
+}
+
+/// Publishes a Warning event that the quota headroom is low. Quotas are
+/// account-wide, so the event is on the cluster-scoped Eip
+/// CustomResourceDefinition rather than on any Eip.
+pub(crate) async fn publish_low_headroom(client: Client, headroom: usize, threshold: usize) {
+    event!(
+        Level::WARN,
+        headroom,
+        threshold,
+        "EIP quota headroom is low."
+    );
+    let reference = ObjectReference {
+        api_version: Some("apiextensions.k8s.io/v1".to_owned()),
+        kind: Some("CustomResourceDefinition".to_owned()),
+        name: Some(Eip::crd_name().to_owned()),
+        ..Default::default()
+    };
+    let recorder = Recorder::new(
+        client,
+        eip_operator_shared::metadata_domain().to_owned().into(),
+        reference,
+    );
+    if let Err(err) = recorder
+        .publish(Event {
+            type_: EventType::Warning,
+            reason: "EipQuotaHeadroomLow".to_owned(),
+            note: Some(format!(
+                "Only {} EIPs of the quota are unallocated, fewer than {}",
+                headroom, threshold
+            )),
+            action: "CheckQuota".to_owned(),
+            secondary: None,
+        })
+        .await
+    {
+        event!(Level::WARN, err = %err, "Failed to publish event.");
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use aws_sdk_s3::types::ByteStream;
+use kube::ResourceExt;
+use tracing::{event, instrument, Level};
+
+use eip_operator_shared::Error;
+
+use crate::eip::v2::Eip;
+use crate::eip_index::EipIndex;
+use crate::provider::{Address, Providers};
+
+/// Where and how often the Eips of each cluster are snapshotted.
+#[derive(Clone, Debug)]
+pub(crate) struct SnapshotConfig {
+    pub(crate) s3_client: aws_sdk_s3::Client,
+    pub(crate) bucket: String,
+    /// Prepended to the cluster name to form the key of its snapshot.
+    pub(crate) prefix: String,
+    pub(crate) interval: Duration,
+}
+
+/// Periodically writes a snapshot of the cluster's Eips to S3, so that they
+/// can be restored with the same addresses after the cluster is rebuilt.
+pub(crate) async fn run(
+    config: SnapshotConfig,
+    cluster_name: String,
+    namespace: Option<String>,
+    eip_index: EipIndex,
+    providers: Providers,
+) {
+    let mut interval = tokio::time::interval(config.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        if let Err(err) = write_snapshot(
+            &config,
+            &cluster_name,
+            namespace.as_deref(),
+            &eip_index,
+            &providers,
+        )
+        .await
+        {
+            event!(Level::ERROR, err = %err, "Failed to write the Eip snapshot.");
+        }
+    }
+}
+
+#[instrument(skip(config, eip_index, providers), err)]
+async fn write_snapshot(
+    config: &SnapshotConfig,
+    cluster_name: &str,
+    namespace: Option<&str>,
+    eip_index: &EipIndex,
+    providers: &Providers,
+) -> Result<(), Error> {
+    if !eip_index.ready() {
+        event!(
+            Level::DEBUG,
+            "Not snapshotting Eips before they are listed."
+        );
+        return Ok(());
+    }
+    let mut addresses = HashMap::new();
+    for (_, provider) in providers.all() {
+        for address in provider
+            .describe_cluster_addresses(cluster_name, namespace)
+            .await?
+        {
+            addresses.insert(address.allocation_id.clone(), address);
+        }
+    }
+    let mut eips = eip_index.all();
+    eips.sort_by_key(|eip| (eip.namespace(), eip.name_any()));
+    let items: Vec<_> = eips
+        .iter()
+        .map(|eip| restorable(eip, &addresses))
+        .collect::<Result<_, _>>()?;
+    let snapshot = serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "List",
+        "items": items,
+    });
+    let key = format!("{}{}.json", config.prefix, cluster_name);
+    config
+        .s3_client
+        .put_object()
+        .bucket(&config.bucket)
+        .key(&key)
+        .content_type("application/json")
+        .body(ByteStream::from(serde_json::to_vec_pretty(&snapshot)?))
+        .send()
+        .await?;
+    event!(Level::INFO, %key, eips = items.len(), "Wrote the Eip snapshot.");
+    Ok(())
+}
+
+/// The Eip as it should be restored: without its status or server-set
+/// metadata, and annotated with the allocation ID to adopt, along with the
+/// tags of the address for reference.
+fn restorable(eip: &Eip, addresses: &HashMap<String, Address>) -> Result<Eip, Error> {
+    let mut restored = Eip::new(&eip.name_any(), eip.spec.clone());
+    restored.metadata.namespace = eip.namespace();
+    restored.metadata.labels = eip.metadata.labels.clone();
+    let mut annotations = eip.annotations().clone();
+    annotations.remove("kubectl.kubernetes.io/last-applied-configuration");
+    if let Some(allocation_id) = eip.allocation_id() {
+        annotations.insert(
+            crate::ADOPT_ALLOCATION_ID_ANNOTATION.clone(),
+            allocation_id.to_owned(),
+        );
+        if let Some(address) = addresses.get(allocation_id) {
+            annotations.insert(
+                crate::SNAPSHOT_TAGS_ANNOTATION.clone(),
+                serde_json::to_string(&address.tags.iter().collect::<BTreeMap<_, _>>())?,
+            );
+        }
+    }
+    restored.metadata.annotations = Some(annotations);
+    Ok(restored)
+}
@@ -0,0 +1,487 @@
+use std::net::SocketAddr;
+
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use json_patch::{AddOperation, PatchOperation};
+use k8s_openapi::api::core::v1::{Container, Pod};
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview, Operation};
+use kube::ResourceExt;
+use tokio::net::TcpListener;
+use tracing::{event, Level};
+
+use eip_operator_shared::webhook_tls::WebhookTls;
+use eip_operator_shared::{Error, MANAGE_EIP_LABEL};
+
+use crate::controller::pod::should_autocreate_eip;
+use crate::controller::NamespaceFilter;
+use crate::eip_index::EipIndex;
+use crate::quota::EipBudgets;
+
+/// The path the mutating webhook for pods is served at.
+const MUTATE_POD_PATH: &str = "/mutate-pod";
+
+/// Settings of the mutating webhook which exposes a pod's public IP to its
+/// containers, and optionally enforces the namespaces' EIP budgets.
+#[derive(Clone)]
+pub(crate) struct WebhookConfig {
+    pub(crate) addr: SocketAddr,
+    pub(crate) tls: WebhookTls,
+    /// The environment variable the public IP is exposed to containers as.
+    pub(crate) env_name: String,
+    /// The budgets pods which would autocreate an Eip are denied beyond.
+    pub(crate) budgets: Option<EipBudgets>,
+    /// The namespaces whose pods are managed. Pods in other namespaces are
+    /// admitted unchanged.
+    pub(crate) namespaces: NamespaceFilter,
+}
+
+/// Serves the mutating webhook over TLS.
+pub(crate) async fn serve(config: WebhookConfig, eip_index: EipIndex) -> Result<(), Error> {
+    let certificate = config.tls.load().await?;
+    let listener = TcpListener::bind(config.addr).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let acceptor = match certificate.acceptor() {
+            Ok(acceptor) => tokio_native_tls::TlsAcceptor::from(acceptor),
+            Err(err) => {
+                event!(Level::ERROR, err = %err, "Failed to load the webhook certificate.");
+                continue;
+            }
+        };
+        let eip_index = eip_index.clone();
+        let env_name = config.env_name.clone();
+        let budgets = config.budgets.clone();
+        let namespaces = config.namespaces.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    event!(Level::WARN, err = %err, "Webhook TLS handshake failed.");
+                    return;
+                }
+            };
+            let service = service_fn(move |request| {
+                let eip_index = eip_index.clone();
+                let env_name = env_name.clone();
+                let budgets = budgets.clone();
+                let namespaces = namespaces.clone();
+                async move {
+                    handle_request(
+                        request,
+                        &eip_index,
+                        &env_name,
+                        budgets.as_ref(),
+                        &namespaces,
+                    )
+                    .await
+                }
+            });
+            if let Err(err) = Http::new().serve_connection(stream, service).await {
+                event!(Level::WARN, err = %err, "Webhook connection error.");
+            }
+        });
+    }
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    eip_index: &EipIndex,
+    env_name: &str,
+    budgets: Option<&EipBudgets>,
+    namespaces: &NamespaceFilter,
+) -> Result<Response<Body>, Error> {
+    if request.method() != Method::POST || request.uri().path() != MUTATE_POD_PATH {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())?);
+    }
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+    let review: AdmissionReview<Pod> = match serde_json::from_slice(&body) {
+        Ok(review) => review,
+        Err(err) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(err.to_string()))?);
+        }
+    };
+    let response = match review.try_into() {
+        Ok(request) => mutate_pod(&request, eip_index, env_name, budgets, namespaces),
+        Err(err) => AdmissionResponse::invalid(err.to_string()),
+    };
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&response.into_review())?))?)
+}
+
+/// Admits the pod, exposing its public IP to each of its containers through
+/// the public IP annotation, which the operator sets once the address is
+/// known. If the pod's Eip already has an address, the annotation is set
+/// straight away. Pods which would autocreate an Eip beyond their namespace's
+/// budget are denied, and pods in namespaces which aren't managed are left
+/// alone, as the operator won't give them an address.
+fn mutate_pod(
+    request: &AdmissionRequest<Pod>,
+    eip_index: &EipIndex,
+    env_name: &str,
+    budgets: Option<&EipBudgets>,
+    namespaces: &NamespaceFilter,
+) -> AdmissionResponse {
+    let response = AdmissionResponse::from(request);
+    let pod = match (&request.operation, &request.object) {
+        (Operation::Create, Some(pod)) if pod.labels().contains_key(MANAGE_EIP_LABEL.as_str()) => {
+            pod
+        }
+        _ => return response,
+    };
+    if let Some(namespace) = &request.namespace {
+        if !namespaces.allows(namespace) {
+            return response;
+        }
+    }
+    let eip = match (&request.namespace, &pod.metadata.name) {
+        (Some(namespace), Some(name)) => eip_index.find_for_pod(namespace, name).ok().flatten(),
+        _ => None,
+    };
+    if let (Some(budgets), Some(namespace), None) = (budgets, &request.namespace, &eip) {
+        if should_autocreate_eip(pod) {
+            if let Err(err) = check_budget(budgets, eip_index, namespace) {
+                return response.deny(err.to_string());
+            }
+        }
+    }
+    let public_ip = eip.and_then(|eip| eip.public_ip_address().map(str::to_owned));
+    let patch = json_patch::Patch(public_ip_patch(pod, public_ip.as_deref(), env_name));
+    match response.with_patch(patch) {
+        Ok(response) => response,
+        Err(err) => AdmissionResponse::invalid(err.to_string()),
+    }
+}
+
+/// Fails if the namespace already has as many Eips as its budget allows. Eips
+/// are counted rather than addresses, so that admission needn't wait on the
+/// cloud. Admits pods until the index is ready.
+fn check_budget(budgets: &EipBudgets, eip_index: &EipIndex, namespace: &str) -> Result<(), Error> {
+    if !eip_index.ready() {
+        return Ok(());
+    }
+    let eips = eip_index
+        .all()
+        .iter()
+        .filter(|eip| eip.namespace().as_deref() == Some(namespace))
+        .count();
+    budgets.check(namespace, eips)
+}
+
+/// The JSON patch adding the environment variable to each of the pod's
+/// containers which don't already set it, and the public IP annotation, if
+/// the public IP is known.
+fn public_ip_patch(pod: &Pod, public_ip: Option<&str>, env_name: &str) -> Vec<PatchOperation> {
+    let mut operations = vec![];
+    if let Some(public_ip) = public_ip {
+        let key = crate::PUBLIC_IP_ANNOTATION.as_str();
+        operations.push(match &pod.metadata.annotations {
+            Some(_) => add(
+                format!("/metadata/annotations/{}", escape(key)),
+                public_ip.into(),
+            ),
+            None => add(
+                "/metadata/annotations".to_owned(),
+                serde_json::json!({ key: public_ip }),
+            ),
+        });
+    }
+    let env_var = serde_json::json!({
+        "name": env_name,
+        "valueFrom": {
+            "fieldRef": {
+                "fieldPath": format!(
+                    "metadata.annotations['{}']",
+                    crate::PUBLIC_IP_ANNOTATION.as_str()
+                ),
+            },
+        },
+    });
+    let spec = pod.spec.as_ref();
+    let containers = [
+        ("containers", spec.map(|spec| spec.containers.as_slice())),
+        (
+            "initContainers",
+            spec.and_then(|spec| spec.init_containers.as_deref()),
+        ),
+    ];
+    for (field, containers) in containers {
+        for (i, container) in containers.unwrap_or_default().iter().enumerate() {
+            if sets_env(container, env_name) {
+                continue;
+            }
+            operations.push(match &container.env {
+                Some(_) => add(format!("/spec/{}/{}/env/-", field, i), env_var.clone()),
+                None => add(
+                    format!("/spec/{}/{}/env", field, i),
+                    serde_json::json!([env_var]),
+                ),
+            });
+        }
+    }
+    operations
+}
+
+fn sets_env(container: &Container, env_name: &str) -> bool {
+    container.env.as_ref().map_or(false, |env| {
+        env.iter().any(|env_var| env_var.name == env_name)
+    })
+}
+
+fn add(path: String, value: serde_json::Value) -> PatchOperation {
+    PatchOperation::Add(AddOperation { path, value })
+}
+
+/// Escapes a key for use in a JSON pointer.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use k8s_openapi::api::core::v1::{EnvVar, PodSpec};
+    use kube_runtime::watcher;
+
+    use super::*;
+    use crate::eip::v2::{Eip, EipSelector, EipSpec};
+    use crate::eip::EipStatus;
+
+    const ENV_NAME: &str = "PUBLIC_IP";
+
+    fn container(env: Option<&[&str]>) -> Container {
+        Container {
+            name: "main".to_owned(),
+            env: env.map(|names| {
+                names
+                    .iter()
+                    .map(|name| EnvVar {
+                        name: (*name).to_owned(),
+                        ..Default::default()
+                    })
+                    .collect()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn pod(labels: &[&str], containers: Vec<Container>) -> Pod {
+        let mut pod = Pod::default();
+        pod.metadata.name = Some("my-pod".to_owned());
+        pod.metadata.labels = Some(
+            labels
+                .iter()
+                .map(|label| (label.to_string(), "true".to_owned()))
+                .collect(),
+        );
+        pod.spec = Some(PodSpec {
+            containers,
+            ..Default::default()
+        });
+        pod
+    }
+
+    fn managed_pod() -> Pod {
+        pod(&[MANAGE_EIP_LABEL.as_str()], vec![container(None)])
+    }
+
+    fn eip(name: &str, public_ip: Option<&str>) -> Eip {
+        let mut eip = Eip::new(
+            name,
+            EipSpec {
+                selector: EipSelector::Pod {
+                    pod_name: name.to_owned(),
+                    pod_uid: None,
+                    network: None,
+                },
+                provider: None,
+                dns_name: None,
+                transfer_to: None,
+                tags: None,
+            },
+        );
+        eip.metadata.namespace = Some("default".to_owned());
+        eip.status = Some(EipStatus {
+            public_ip_address: public_ip.map(str::to_owned),
+            ..Default::default()
+        });
+        eip
+    }
+
+    fn index(eips: Vec<Eip>) -> EipIndex {
+        let index = EipIndex::default();
+        index.apply(watcher::Event::Restarted(eips));
+        index
+    }
+
+    /// An admission request for the operation on the pod, in the default
+    /// namespace.
+    fn request(operation: &str, pod: &Pod) -> AdmissionRequest<Pod> {
+        let review: AdmissionReview<Pod> = serde_json::from_value(serde_json::json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+                "kind": {"group": "", "version": "v1", "kind": "Pod"},
+                "resource": {"group": "", "version": "v1", "resource": "pods"},
+                "name": "my-pod",
+                "namespace": "default",
+                "operation": operation,
+                "userInfo": {},
+                "object": pod,
+            },
+        }))
+        .unwrap();
+        review.try_into().unwrap()
+    }
+
+    fn mutate(
+        request: &AdmissionRequest<Pod>,
+        eip_index: &EipIndex,
+        budgets: Option<&EipBudgets>,
+        namespaces: &NamespaceFilter,
+    ) -> (bool, Option<serde_json::Value>) {
+        let response = mutate_pod(request, eip_index, ENV_NAME, budgets, namespaces);
+        let patch = response
+            .patch
+            .map(|patch| serde_json::from_slice(&patch).unwrap());
+        (response.allowed, patch)
+    }
+
+    fn all_namespaces() -> NamespaceFilter {
+        NamespaceFilter::new(None, HashSet::new())
+    }
+
+    fn env_var() -> serde_json::Value {
+        serde_json::json!({
+            "name": ENV_NAME,
+            "valueFrom": {
+                "fieldRef": {
+                    "fieldPath": "metadata.annotations['eip.materialize.cloud/public_ip']",
+                },
+            },
+        })
+    }
+
+    fn patch(pod: &Pod, public_ip: Option<&str>) -> serde_json::Value {
+        serde_json::to_value(json_patch::Patch(public_ip_patch(pod, public_ip, ENV_NAME))).unwrap()
+    }
+
+    #[test]
+    fn the_annotation_key_is_escaped_in_the_patch_path() {
+        let mut pod = managed_pod();
+        pod.metadata.annotations = Some(BTreeMap::new());
+        assert_eq!(
+            patch(&pod, Some("203.0.113.1"))[0],
+            serde_json::json!({
+                "op": "add",
+                "path": "/metadata/annotations/eip.materialize.cloud~1public_ip",
+                "value": "203.0.113.1",
+            })
+        );
+    }
+
+    #[test]
+    fn the_annotations_are_created_if_the_pod_has_none() {
+        assert_eq!(
+            patch(&managed_pod(), Some("203.0.113.1"))[0],
+            serde_json::json!({
+                "op": "add",
+                "path": "/metadata/annotations",
+                "value": {"eip.materialize.cloud/public_ip": "203.0.113.1"},
+            })
+        );
+    }
+
+    #[test]
+    fn the_env_var_is_added_to_each_container_which_does_not_set_it() {
+        let mut pod = pod(
+            &[MANAGE_EIP_LABEL.as_str()],
+            vec![
+                container(None),
+                container(Some(&["OTHER"])),
+                container(Some(&[ENV_NAME])),
+            ],
+        );
+        pod.spec.as_mut().unwrap().init_containers = Some(vec![container(None)]);
+        assert_eq!(
+            patch(&pod, None),
+            serde_json::json!([
+                {"op": "add", "path": "/spec/containers/0/env", "value": [env_var()]},
+                {"op": "add", "path": "/spec/containers/1/env/-", "value": env_var()},
+                {"op": "add", "path": "/spec/initContainers/0/env", "value": [env_var()]},
+            ])
+        );
+    }
+
+    #[test]
+    fn created_pods_get_the_public_ip_of_their_eip() {
+        let index = index(vec![eip("my-pod", Some("203.0.113.1"))]);
+        let request = request("CREATE", &managed_pod());
+        let (allowed, applied) = mutate(&request, &index, None, &all_namespaces());
+        assert!(allowed);
+        assert_eq!(applied, Some(patch(&managed_pod(), Some("203.0.113.1"))));
+    }
+
+    #[test]
+    fn unmanaged_pods_and_other_operations_are_admitted_unchanged() {
+        let index = index(vec![]);
+        let unmanaged = pod(&[], vec![container(None)]);
+        for request in [
+            request("CREATE", &unmanaged),
+            request("UPDATE", &managed_pod()),
+        ] {
+            assert_eq!(
+                mutate(&request, &index, None, &all_namespaces()),
+                (true, None)
+            );
+        }
+    }
+
+    #[test]
+    fn pods_in_unmanaged_namespaces_are_admitted_unchanged() {
+        let namespaces = NamespaceFilter::new(None, HashSet::from(["default".to_owned()]));
+        let request = request("CREATE", &managed_pod());
+        assert_eq!(
+            mutate(&request, &index(vec![]), None, &namespaces),
+            (true, None)
+        );
+    }
+
+    #[test]
+    fn autocreating_pods_beyond_the_budget_are_denied() {
+        let budgets = EipBudgets::new(None, HashMap::from([("default".to_owned(), 1)]));
+        let labels = [
+            MANAGE_EIP_LABEL.as_str(),
+            crate::AUTOCREATE_EIP_LABEL.as_str(),
+        ];
+        let request = request("CREATE", &pod(&labels, vec![container(None)]));
+        let (allowed, _) = mutate(
+            &request,
+            &index(vec![eip("other-pod", None)]),
+            Some(&budgets),
+            &all_namespaces(),
+        );
+        assert!(!allowed);
+        let (allowed, _) = mutate(&request, &index(vec![]), Some(&budgets), &all_namespaces());
+        assert!(allowed);
+    }
+
+    #[test]
+    fn budgets_are_checked_once_the_index_is_ready() {
+        let budgets = EipBudgets::new(Some(0), HashMap::new());
+        assert!(check_budget(&budgets, &EipIndex::default(), "default").is_ok());
+        let err = check_budget(&budgets, &index(vec![]), "default").unwrap_err();
+        assert_eq!(err.reason(), "NamespaceBudgetExceeded");
+        let budgets = EipBudgets::new(Some(2), HashMap::new());
+        let index = index(vec![eip("my-pod", None)]);
+        assert!(check_budget(&budgets, &index, "default").is_ok());
+        assert!(check_budget(&budgets, &index, "other").is_ok());
+    }
+}
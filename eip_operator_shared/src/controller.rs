@@ -1,20 +1,185 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use futures::stream::StreamExt;
+use futures::{future, Future};
 use kube::api::{Api, ListParams};
 use kube::core::{ClusterResourceScope, NamespaceResourceScope};
 use kube::{Client, Resource, ResourceExt};
 use kube_runtime::controller::Action;
+use kube_runtime::events::{Event as KubeEvent, EventType, Recorder};
 use kube_runtime::finalizer::{finalizer, Event};
+use kube_runtime::reflector::ObjectRef;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
 use rand::{thread_rng, Rng};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::Semaphore;
 use tracing::{event, Level};
 
+/// Annotation overriding the interval, in seconds, at which an individual
+/// resource is requeued after a successful reconciliation.
+pub static REQUEUE_INTERVAL_ANNOTATION: Lazy<String> =
+    Lazy::new(|| crate::metadata_key("requeue_interval_secs"));
+
+/// Annotation suspending the reconciliation of an individual resource, while
+/// leaving its finalizers in place.
+pub static PAUSED_ANNOTATION: Lazy<String> = Lazy::new(|| crate::metadata_key("paused"));
+
+/// Annotation forcing an immediate reconciliation of an individual resource
+/// whenever its value, such as a timestamp, changes.
+pub static RESYNC_ANNOTATION: Lazy<String> = Lazy::new(|| crate::metadata_key("resync"));
+
+/// The value of the resource's resync annotation, if any.
+fn resync_requested(resource: &impl Resource) -> Option<String> {
+    resource
+        .meta()
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(RESYNC_ANNOTATION.as_str()))
+        .cloned()
+}
+
+/// Whether the resource's reconciliation is suspended by its paused annotation.
+pub fn paused(resource: &impl Resource) -> bool {
+    resource
+        .meta()
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(PAUSED_ANNOTATION.as_str()))
+        .map_or(false, |paused| paused == "true")
+}
+
+/// The windows from which requeue delays are randomly chosen, after successful
+/// and failed reconciliations. Retries of repeatedly failing resources back off
+/// exponentially from the error window, up to `error_backoff_max`.
+#[derive(Clone, Copy, Debug)]
+pub struct RequeueIntervals {
+    pub success_min: Duration,
+    pub success_max: Duration,
+    pub error_min: Duration,
+    pub error_max: Duration,
+    pub error_backoff_max: Duration,
+}
+
+impl Default for RequeueIntervals {
+    fn default() -> Self {
+        Self {
+            success_min: Duration::from_secs(2400),
+            success_max: Duration::from_secs(3600),
+            error_min: Duration::from_millis(4000),
+            error_max: Duration::from_millis(8000),
+            error_backoff_max: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RequeueIntervals {
+    /// A random delay from the success window.
+    pub fn success(&self) -> Duration {
+        random_between(self.success_min, self.success_max)
+    }
+
+    /// A random delay from the error window, doubled for each consecutive
+    /// failure after the first, and capped at `error_backoff_max`.
+    pub fn error(&self, consecutive_failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(consecutive_failures.saturating_sub(1));
+        random_between(self.error_min, self.error_max)
+            .saturating_mul(factor)
+            .min(self.error_backoff_max)
+    }
+
+    /// The success requeue delay for a resource, honoring its
+    /// `REQUEUE_INTERVAL_ANNOTATION`, if any.
+    pub fn success_for(&self, resource: &impl Resource) -> Duration {
+        resource
+            .meta()
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(REQUEUE_INTERVAL_ANNOTATION.as_str()))
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| self.success())
+    }
+}
+
+fn random_between(min: Duration, max: Duration) -> Duration {
+    if min >= max {
+        return min;
+    }
+    thread_rng().gen_range(min..max)
+}
+
+/// Reconciliations which panicked, by resource kind.
+static RECONCILE_PANICS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "eip_operator_reconcile_panics_total",
+        "Reconciliations which panicked, by resource kind.",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+thread_local! {
+    static IN_RECONCILE: Cell<bool> = Cell::new(false);
+}
+
+/// Whether the current thread is running a reconciliation, whose panics are
+/// caught and turned into reconciliation errors. Panic hooks which abort the
+/// process should not do so in that case.
+pub fn in_reconcile() -> bool {
+    IN_RECONCILE.with(Cell::get)
+}
+
+/// Runs the future, catching any panic while polling it.
+async fn catch_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    let mut future = Box::pin(future);
+    future::poll_fn(move |cx| {
+        IN_RECONCILE.with(|flag| flag.set(true));
+        let result = catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx)));
+        IN_RECONCILE.with(|flag| flag.set(false));
+        match result {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(panic) => Poll::Ready(Err(panic_message(&*panic))),
+        }
+    })
+    .await
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+/// Hashes a serializable value, for use in `Context::fingerprint`.
+pub fn fingerprint_of(value: &impl Serialize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(value)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
 #[async_trait::async_trait]
 pub trait Context {
     type Resource: Resource;
     type Error: std::error::Error;
 
+    /// The name of the finalizer, within the metadata domain.
     const FINALIZER_NAME: &'static str;
 
     async fn apply(
@@ -35,6 +200,8 @@ pub trait Context {
         client: Client,
         api: Api<Self::Resource>,
         resource: Arc<Self::Resource>,
+        intervals: RequeueIntervals,
+        observe_only: bool,
     ) -> Result<Action, kube_runtime::finalizer::Error<Self::Error>>
     where
         Self: Send + Sync + 'static,
@@ -49,32 +216,216 @@ pub trait Context {
             + std::fmt::Debug
             + std::marker::Unpin,
     {
+        if paused(&*resource) {
+            // Neither apply nor clean up, so a paused resource which is
+            // deleted keeps its finalizer until it is unpaused.
+            event!(
+                Level::INFO,
+                "Skipping the reconciliation of a paused resource."
+            );
+            return Ok(self.on_success(&resource, &intervals));
+        }
+        if observe_only {
+            self.observe(client, &resource)
+                .await
+                .map_err(kube_runtime::finalizer::Error::ApplyFailed)?;
+            return Ok(self.on_success(&resource, &intervals));
+        }
         finalizer(
             &api,
-            Self::FINALIZER_NAME,
+            &crate::metadata_key(Self::FINALIZER_NAME),
             Arc::clone(&resource),
             |event| async {
                 match event {
-                    Event::Apply(resource) => self.apply(client, api.clone(), &resource).await?,
+                    Event::Apply(resource) => {
+                        let result = self.apply(client.clone(), api.clone(), &resource).await;
+                        if let Err(err) = &result {
+                            if self.is_dry_run(err) {
+                                publish_dry_run_event(client, &*resource, err).await;
+                            }
+                        }
+                        result?
+                    }
                     Event::Cleanup(resource) => {
-                        self.cleanup(client, api.clone(), &resource).await?
+                        match self.cleanup(client.clone(), api.clone(), &resource).await {
+                            // Don't hold up deletions while in dry-run mode.
+                            Err(err) if self.is_dry_run(&err) => {
+                                publish_dry_run_event(client, &*resource, &err).await;
+                            }
+                            result => result?,
+                        }
                     }
                 }
-                Ok(self.on_success(&resource))
+                Ok(self.on_success(&resource, &intervals))
             },
         )
         .await
     }
 
-    fn on_success(&self, _resource: &Self::Resource) -> Action {
-        Action::requeue(Duration::from_secs(thread_rng().gen_range(2400..3600)))
+    /// Reports on the resource without changing anything, not even its
+    /// finalizers, for controllers which only observe.
+    async fn observe(
+        &self,
+        _client: Client,
+        _resource: &Self::Resource,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Whether the error only reports a change which was skipped because the
+    /// operator is in dry-run mode. Such errors are published as Events on
+    /// the resource, and cleanups which fail with them are treated as done,
+    /// so that finalizers are still removed.
+    fn is_dry_run(&self, _err: &Self::Error) -> bool {
+        false
+    }
+
+    /// A fingerprint of the parts of the resource which can affect its
+    /// reconciliation. Events which don't change the fingerprint of a resource
+    /// which was reconciled successfully are skipped, until its next periodic
+    /// requeue. `None` means every event is reconciled.
+    fn fingerprint(&self, _resource: &Self::Resource) -> Option<u64> {
+        None
+    }
+
+    fn on_success(&self, resource: &Self::Resource, intervals: &RequeueIntervals) -> Action {
+        Action::requeue(intervals.success_for(resource))
     }
     fn on_error(
         self: Arc<Self>,
         _resource: Arc<Self::Resource>,
         _err: &kube_runtime::finalizer::Error<Self::Error>,
+        intervals: &RequeueIntervals,
+        consecutive_failures: u32,
     ) -> Action {
-        Action::requeue(Duration::from_millis(thread_rng().gen_range(4000..8000)))
+        Action::requeue(intervals.error(consecutive_failures))
+    }
+}
+
+/// Publishes an Event on the resource about a change skipped in dry-run mode.
+async fn publish_dry_run_event<K>(client: Client, resource: &K, err: &impl std::fmt::Display)
+where
+    K: Resource,
+    K::DynamicType: Default,
+{
+    let recorder = Recorder::new(
+        client,
+        crate::metadata_domain().to_owned().into(),
+        resource.object_ref(&Default::default()),
+    );
+    let event = KubeEvent {
+        type_: EventType::Normal,
+        reason: "DryRun".to_owned(),
+        note: Some(err.to_string()),
+        action: "DryRun".to_owned(),
+        secondary: None,
+    };
+    if let Err(err) = recorder.publish(event).await {
+        event!(Level::WARN, err = %err, "Failed to publish event.");
+    }
+}
+
+/// The outcome of the most recent reconciliation of a resource.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReconcileRecord {
+    pub last_reconcile_unix_secs: u64,
+    pub error: Option<String>,
+    pub consecutive_failures: u32,
+    #[serde(skip)]
+    last_reconcile: Instant,
+    #[serde(skip)]
+    fingerprint: Option<u64>,
+    /// The value of the resync annotation when the resource was reconciled.
+    #[serde(skip)]
+    resync: Option<String>,
+}
+
+/// The most recent reconciliation outcomes of a controller, keyed by
+/// `namespace/name` (or just `name` for cluster scoped resources).
+#[derive(Clone, Debug, Default)]
+pub struct ReconcileStates(Arc<Mutex<BTreeMap<String, ReconcileRecord>>>);
+
+impl ReconcileStates {
+    fn record(
+        &self,
+        key: String,
+        error: Option<String>,
+        fingerprint: Option<u64>,
+        resync: Option<String>,
+    ) {
+        let last_reconcile_unix_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let mut records = self.0.lock().unwrap();
+        let consecutive_failures = match &error {
+            Some(_) => records
+                .get(&key)
+                .map_or(0, |record| record.consecutive_failures)
+                .saturating_add(1),
+            None => 0,
+        };
+        records.insert(
+            key,
+            ReconcileRecord {
+                last_reconcile_unix_secs,
+                error,
+                consecutive_failures,
+                last_reconcile: Instant::now(),
+                fingerprint,
+                resync,
+            },
+        );
+    }
+
+    /// The number of reconciliations of the resource which have failed since
+    /// it was last reconciled successfully.
+    fn consecutive_failures(&self, key: &str) -> u32 {
+        self.0
+            .lock()
+            .unwrap()
+            .get(key)
+            .map_or(0, |record| record.consecutive_failures)
+    }
+
+    /// Time elapsed since the resource was last reconciled successfully, if
+    /// its most recent reconciliation succeeded.
+    fn since_last_success(&self, key: &str) -> Option<Duration> {
+        self.last_success(key).map(|(elapsed, _)| elapsed)
+    }
+
+    /// Time elapsed since the resource was last reconciled successfully, and
+    /// its fingerprint at the time, if its most recent reconciliation succeeded.
+    fn last_success(&self, key: &str) -> Option<(Duration, Option<u64>)> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|record| record.error.is_none())
+            .map(|record| (record.last_reconcile.elapsed(), record.fingerprint))
+    }
+
+    /// Whether the resource's resync annotation has changed since it was last
+    /// reconciled.
+    fn resync_pending(&self, key: &str, resync: &Option<String>) -> bool {
+        resync.is_some()
+            && self
+                .0
+                .lock()
+                .unwrap()
+                .get(key)
+                .map_or(true, |record| record.resync != *resync)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ReconcileRecord> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// The key of a resource in `ReconcileStates`.
+fn state_key(resource: &impl Resource) -> String {
+    match resource.namespace() {
+        Some(namespace) => format!("{}/{}", namespace, resource.name_any()),
+        None => resource.name_any(),
     }
 }
 
@@ -95,6 +446,11 @@ where
     make_api: MakeApi<Ctx>,
     controller: kube_runtime::controller::Controller<Ctx::Resource>,
     context: Ctx,
+    states: ReconcileStates,
+    concurrency: Option<Arc<Semaphore>>,
+    debounce: Duration,
+    requeue: RequeueIntervals,
+    observe_only: bool,
 }
 
 impl<Ctx: Context> Controller<Ctx>
@@ -126,6 +482,11 @@ where
             make_api,
             controller,
             context,
+            states: ReconcileStates::default(),
+            concurrency: None,
+            debounce: Duration::ZERO,
+            requeue: RequeueIntervals::default(),
+            observe_only: false,
         }
     }
 
@@ -148,6 +509,11 @@ where
             make_api,
             controller,
             context,
+            states: ReconcileStates::default(),
+            concurrency: None,
+            debounce: Duration::ZERO,
+            requeue: RequeueIntervals::default(),
+            observe_only: false,
         }
     }
 
@@ -168,22 +534,170 @@ where
             make_api,
             controller,
             context,
+            states: ReconcileStates::default(),
+            concurrency: None,
+            debounce: Duration::ZERO,
+            requeue: RequeueIntervals::default(),
+            observe_only: false,
         }
     }
 
-    pub async fn run(self) {
+    /// Limits the number of reconciliations which may run at the same time.
+    /// `None` leaves it unlimited.
+    pub fn with_concurrency(mut self, limit: Option<usize>) -> Self {
+        self.concurrency = limit.map(|limit| Arc::new(Semaphore::new(limit)));
+        self
+    }
+
+    /// Defers reconciliations of a resource which was successfully reconciled
+    /// less than `window` ago until the window has passed, so that bursts of
+    /// events collapse into a single reconciliation. Deletions are never deferred.
+    pub fn with_debounce(mut self, window: Duration) -> Self {
+        self.debounce = window;
+        self
+    }
+
+    /// Sets the windows from which requeue delays are chosen.
+    pub fn with_requeue_intervals(mut self, intervals: RequeueIntervals) -> Self {
+        self.requeue = intervals;
+        self
+    }
+
+    /// Only observes resources, with `Context::observe`, rather than
+    /// reconciling them.
+    pub fn with_observe_only(mut self, observe_only: bool) -> Self {
+        self.observe_only = observe_only;
+        self
+    }
+
+    /// Stops starting new reconciliations once `trigger` resolves, and makes
+    /// `run` return once the in-flight reconciliations have finished.
+    pub fn with_graceful_shutdown_on(
+        mut self,
+        trigger: impl Future<Output = ()> + Send + Sync + 'static,
+    ) -> Self {
+        self.controller = self.controller.graceful_shutdown_on(trigger);
+        self
+    }
+
+    /// Also reconciles the resources returned by `mapper` whenever a watched
+    /// resource of another kind changes.
+    pub fn watches<Other, I>(
+        mut self,
+        api: Api<Other>,
+        lp: ListParams,
+        mapper: impl Fn(Other) -> I + Sync + Send + 'static,
+    ) -> Self
+    where
+        Other: Resource + Clone + DeserializeOwned + std::fmt::Debug + Send + 'static,
+        Other::DynamicType: Clone,
+        I: IntoIterator<Item = ObjectRef<Ctx::Resource>> + 'static,
+        I::IntoIter: Send,
+    {
+        self.controller = self.controller.watches(api, lp, mapper);
+        self
+    }
+
+    /// A handle to the most recent reconciliation outcomes of this controller.
+    pub fn reconcile_states(&self) -> ReconcileStates {
+        self.states.clone()
+    }
+
+    pub async fn run(self)
+    where
+        Ctx::Error: From<crate::Error>,
+    {
         let Self {
             client,
             make_api,
             controller,
             context,
+            states,
+            concurrency,
+            debounce,
+            requeue,
+            observe_only,
         } = self;
+        let error_states = states.clone();
+        let kind = Ctx::Resource::kind(&Default::default()).into_owned();
         controller
             .run(
                 |resource, context| {
-                    context.reconcile(client.clone(), make_api(&resource), resource)
+                    let key = state_key(&*resource);
+                    let states = states.clone();
+                    let concurrency = concurrency.clone();
+                    let kind = kind.clone();
+                    // A changed resync annotation skips both the debounce and
+                    // the fingerprint check.
+                    let resync = resync_requested(&*resource);
+                    let resync_pending = states.resync_pending(&key, &resync);
+                    let deferral = states
+                        .since_last_success(&key)
+                        .filter(|_| resource.meta().deletion_timestamp.is_none())
+                        .filter(|_| !resync_pending)
+                        .and_then(|elapsed| debounce.checked_sub(elapsed))
+                        .filter(|deferral| !deferral.is_zero());
+                    // Paused resources are recorded without a fingerprint, so
+                    // that they are reconciled as soon as they are unpaused.
+                    let fingerprint = context
+                        .fingerprint(&resource)
+                        .filter(|_| !paused(&*resource));
+                    // If nothing relevant changed since the last successful
+                    // reconciliation, just restore its periodic requeue.
+                    let unchanged = states
+                        .last_success(&key)
+                        .filter(|(_, last_fingerprint)| {
+                            fingerprint.is_some() && *last_fingerprint == fingerprint
+                        })
+                        .and_then(|(elapsed, _)| requeue.success_min.checked_sub(elapsed))
+                        .filter(|_| resource.meta().deletion_timestamp.is_none())
+                        .filter(|_| !resync_pending);
+                    let reconciliation = context.reconcile(
+                        client.clone(),
+                        make_api(&resource),
+                        resource,
+                        requeue,
+                        observe_only,
+                    );
+                    async move {
+                        if let Some(deferral) = deferral {
+                            return Ok(Action::requeue(deferral));
+                        }
+                        if let Some(remaining) = unchanged {
+                            return Ok(Action::requeue(remaining));
+                        }
+                        let _permit = match &concurrency {
+                            Some(semaphore) => Some(semaphore.acquire().await.unwrap()),
+                            None => None,
+                        };
+                        let result = match catch_panic(reconciliation).await {
+                            Ok(result) => result,
+                            Err(message) => {
+                                event!(
+                                    Level::ERROR,
+                                    panic = %message,
+                                    resource = %key,
+                                    "{} reconciliation panicked.",
+                                    kind
+                                );
+                                RECONCILE_PANICS.with_label_values(&[&kind]).inc();
+                                Err(kube_runtime::finalizer::Error::ApplyFailed(
+                                    crate::Error::ReconcilePanicked(message).into(),
+                                ))
+                            }
+                        };
+                        let error = result.as_ref().err().map(|err| err.to_string());
+                        if resync_pending {
+                            event!(Level::INFO, resource = %key, "Resynced {}.", kind);
+                        }
+                        states.record(key, error, fingerprint, resync);
+                        result
+                    }
+                },
+                |resource, err, context| {
+                    let failures = error_states.consecutive_failures(&state_key(&*resource));
+                    context.on_error(resource, err, &requeue, failures)
                 },
-                |resource, err, context| context.on_error(resource, err),
                 Arc::new(context),
             )
             .for_each(|reconciliation_result| async move {
@@ -198,6 +712,16 @@ where
                             kind
                         );
                     }
+                    Err(kube_runtime::controller::Error::ReconcilerFailed(err, resource)) => {
+                        event!(
+                            Level::ERROR,
+                            err = %err,
+                            resource_name = %resource.name,
+                            resource_namespace = resource.namespace.as_deref().unwrap_or(""),
+                            "{} reconciliation error.",
+                            kind
+                        )
+                    }
                     Err(err) => event!(
                         Level::ERROR,
                         err = %err,
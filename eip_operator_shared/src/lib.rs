@@ -1,34 +1,101 @@
+#[cfg(feature = "otel")]
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::net::AddrParseError;
+#[cfg(feature = "otel")]
 use std::str::FromStr;
 use std::time::Duration;
 
 use aws_sdk_ec2::error::{
-    AllocateAddressError, AssociateAddressError, DescribeAddressesError, DescribeInstancesError,
-    DisassociateAddressError, ReleaseAddressError,
+    AllocateAddressError, AssignPrivateIpAddressesError, AssociateAddressError,
+    AttachNetworkInterfaceError, CreateNetworkInterfaceError, CreateTagsError,
+    DescribeAddressesError, DescribeInstancesError, DescribeNetworkInterfacesError,
+    DisassociateAddressError, ModifyNetworkInterfaceAttributeError, ReleaseAddressError,
 };
 use aws_sdk_ec2::types::SdkError;
+use aws_sdk_s3::error::PutObjectError;
 use aws_sdk_servicequotas::error::GetServiceQuotaError;
 use aws_sdk_servicequotas::types::SdkError as ServiceQuotaSdkError;
+use aws_smithy_types::retry::ProvideErrorKind;
 use futures::Future;
+#[cfg(feature = "otel")]
 use hyper::client::HttpConnector;
+#[cfg(feature = "otel")]
 use hyper_tls::HttpsConnector;
-use opentelemetry::sdk::trace::{Config, Sampler};
+use once_cell::sync::{Lazy, OnceCell};
+#[cfg(feature = "otel")]
+use opentelemetry::sdk::trace::{BatchSpanProcessor, Config, Sampler, TracerProvider};
+#[cfg(feature = "otel")]
 use opentelemetry::sdk::Resource as OtelResource;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::TracerProvider as _;
+#[cfg(feature = "otel")]
 use opentelemetry::KeyValue;
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::{SpanExporter, SpanExporterBuilder, WithExportConfig};
+#[cfg(feature = "otel")]
+use proxy::ProxySettings;
 use tokio::time::error::Elapsed;
+#[cfg(feature = "otel")]
 use tonic::metadata::{MetadataKey, MetadataMap};
+#[cfg(feature = "otel")]
 use tonic::transport::Endpoint;
+#[cfg(feature = "otel")]
 use tracing::{Metadata, Subscriber};
-use tracing_subscriber::filter::{EnvFilter, Targets};
+use tracing_subscriber::filter::EnvFilter;
+#[cfg(feature = "otel")]
+use tracing_subscriber::filter::Targets;
+#[cfg(feature = "otel")]
 use tracing_subscriber::fmt;
-use tracing_subscriber::layer::{Context as LayerContext, Filter as LayerFilter, SubscriberExt};
+use tracing_subscriber::layer::SubscriberExt;
+#[cfg(feature = "otel")]
+use tracing_subscriber::layer::{Context as LayerContext, Filter as LayerFilter};
 use tracing_subscriber::prelude::*;
 
-pub const MANAGE_EIP_LABEL: &str = "eip.materialize.cloud/manage";
+/// The default domain of the operators' labels, annotations, and finalizers.
+pub const DEFAULT_METADATA_DOMAIN: &str = "eip.materialize.cloud";
+
+static METADATA_DOMAIN: OnceCell<String> = OnceCell::new();
+
+pub static MANAGE_EIP_LABEL: Lazy<String> = Lazy::new(|| metadata_key("manage"));
+
+/// Sets the domain of the operators' labels, annotations, and finalizers, so
+/// that they need not carry Materialize's. This must be called before any of
+/// them are used.
+pub fn set_metadata_domain(domain: String) {
+    METADATA_DOMAIN
+        .set(domain)
+        .expect("The metadata domain must be set before it is used.");
+}
+
+/// The domain of the operators' labels, annotations, and finalizers, which is
+/// also used as the field manager of their patches.
+pub fn metadata_domain() -> &'static str {
+    METADATA_DOMAIN.get_or_init(|| DEFAULT_METADATA_DOMAIN.to_owned())
+}
+
+/// The key of the label, annotation, or finalizer with the name, in the
+/// metadata domain.
+pub fn metadata_key(name: &str) -> String {
+    format!("{}/{}", metadata_domain(), name)
+}
+
+/// The params of the operators' patches which aren't applied server-side,
+/// such as status updates, so that the fields they set are owned by the
+/// metadata domain's field manager, just like those which are applied.
+pub fn patch_params() -> kube::api::PatchParams {
+    kube::api::PatchParams {
+        field_manager: Some(metadata_domain().to_owned()),
+        ..Default::default()
+    }
+}
 
 pub mod controller;
+pub mod metrics;
+pub mod proxy;
+#[cfg(feature = "otel")]
+mod sampling;
+pub mod webhook_tls;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -37,11 +104,25 @@ pub enum Error {
         #[from]
         source: std::io::Error,
     },
+    #[error("Invalid config file {0}: {1}")]
+    InvalidConfig(String, String),
+    #[error("AWS_USE_FIPS_ENDPOINT is set, but {0}.")]
+    FipsEndpointUnavailable(String),
+    #[error(
+        "WEBHOOK_PORT is set, but neither WEBHOOK_TLS_CERT_FILE and WEBHOOK_TLS_KEY_FILE \
+         nor WEBHOOK_SERVICE is."
+    )]
+    MissingWebhookCertificate,
     #[error("Kubernetes error: {source}")]
     Kube {
         #[from]
         source: kube::Error,
     },
+    #[error("Kubeconfig error: {source}")]
+    Kubeconfig {
+        #[from]
+        source: kube::config::KubeconfigError,
+    },
     #[error("Kubernetes error: {source}")]
     KubeRuntimeWaitError {
         #[from]
@@ -49,8 +130,22 @@ pub enum Error {
     },
     #[error("No EIP found with that podName.")]
     NoEipResourceWithThatPodName(String),
+    #[error("Eip {eip} selects another pod named {pod}, with UID {pod_uid}.")]
+    PodUidMismatch {
+        eip: String,
+        pod: String,
+        pod_uid: String,
+    },
     #[error("No EIP found with that node selector.")]
     NoEipResourceWithThatNodeSelector,
+    #[error("No Eip named {0} found.")]
+    NoEipResourceWithThatName(String),
+    #[error("Namespace {0} is not managed by this operator.")]
+    NamespaceNotManaged(String),
+    #[error("The {0} CustomResourceDefinition is not installed.")]
+    CrdNotInstalled(String),
+    #[error("The Eip index has not finished its initial list yet.")]
+    EipIndexNotReady,
     #[error("EIP does not have a status.")]
     MissingEipStatus,
     #[error("EIP does not have a UID in its metadata.")]
@@ -65,6 +160,12 @@ pub enum Error {
     MissingPodIp,
     #[error("Node does not have an IP address.")]
     MissingNodeIp,
+    #[error("The address {0} was released outside of the operator.")]
+    AllocationLost(String),
+    #[error("Address {allocation_id} is associated with network interface {eni}, which the Eip doesn't recognize.")]
+    ForeignAssociation { allocation_id: String, eni: String },
+    #[error("Pod {pod} has no IP on network attachment {network}.")]
+    MissingNetworkAttachmentIp { pod: String, network: String },
     #[error("Pod does not have a node name in its spec.")]
     MissingNodeName,
     #[error("Node does not have labels.")]
@@ -83,12 +184,53 @@ pub enum Error {
     MissingReservations,
     #[error("DescribeInstancesResult.reservations[0].instances was None.")]
     MissingInstances,
-    #[error("DescribeInstancesResult.reservations[0].instances[0].network_interfaces was None.")]
+    #[error("No instance {0} found in DescribeInstancesResult.reservations.")]
+    InstanceNotFound(String),
+    #[error("Instance {0} is shutting down or terminated.")]
+    InstanceTerminated(String),
+    #[error("Instance network_interfaces was None.")]
     MissingNetworkInterfaces,
-    #[error("No interface found with IP matching pod.")]
-    MissingAddresses,
     #[error("DescribeAddressesResult.addresses was None.")]
+    MissingAddresses,
+    #[error("Webhook certificate error: {0}")]
+    WebhookCertificate(String),
+    #[error("No interface found with IP matching pod.")]
     NoInterfaceWithThatIp,
+    #[error("Invalid tag {key:?}: {reason}")]
+    InvalidTag { key: String, reason: String },
+    #[error("Address {0} is not tagged for this Eip, so can't be adopted.")]
+    AddressNotAdoptable(String),
+    #[error("Several addresses are tagged with Eip name {0}, so none is adopted.")]
+    MultipleAddressesAdoptable(String),
+    #[error("Eip {0} is protected, so its EIP is neither disassociated nor released.")]
+    ReleaseProtected(String),
+    #[error("{allocated} of the EIP quota of {quota} are allocated, and {reserved} reserved, so no more are.")]
+    QuotaHeadroomExhausted {
+        allocated: usize,
+        quota: usize,
+        reserved: usize,
+    },
+    #[error("Namespace {namespace} already has its budget of {budget} EIPs.")]
+    NamespaceBudgetExhausted { namespace: String, budget: usize },
+    #[error("Invalid Eip name template {template:?}: {reason}")]
+    InvalidEipNameTemplate { template: String, reason: String },
+    #[error("No Eip template is named {0}.")]
+    UnknownEipTemplate(String),
+    #[error("A pod's external-dns target annotation still points at {0}.")]
+    AddressStillInDns(String),
+    #[error("{0} checks failed.")]
+    ChecksFailed(usize),
+    #[error("{0} discrepancies were found between Eips and their addresses.")]
+    DiscrepanciesFound(usize),
+    #[error("Injected a {reason} fault into {operation}.")]
+    InjectedFault {
+        operation: String,
+        reason: &'static str,
+    },
+    #[error("Reconciliation panicked: {0}")]
+    ReconcilePanicked(String),
+    #[error("EIP association was not visible after associating it.")]
+    AssociationNotVisible,
     #[error("AWS allocate_address reported error: {source}")]
     AllocateAddress {
         #[from]
@@ -119,12 +261,87 @@ pub enum Error {
         #[from]
         source: SdkError<ReleaseAddressError>,
     },
+    #[error("AWS create_tags reported error: {source}")]
+    AwsCreateTags {
+        #[from]
+        source: SdkError<CreateTagsError>,
+    },
+    #[error("AWS describe_network_interfaces reported error: {source}")]
+    AwsDescribeNetworkInterfaces {
+        #[from]
+        source: SdkError<DescribeNetworkInterfacesError>,
+    },
+    #[error("AWS create_network_interface reported error: {source}")]
+    AwsCreateNetworkInterface {
+        #[from]
+        source: SdkError<CreateNetworkInterfaceError>,
+    },
+    #[error("AWS attach_network_interface reported error: {source}")]
+    AwsAttachNetworkInterface {
+        #[from]
+        source: SdkError<AttachNetworkInterfaceError>,
+    },
+    #[error("AWS assign_private_ip_addresses reported error: {source}")]
+    AwsAssignPrivateIpAddresses {
+        #[from]
+        source: SdkError<AssignPrivateIpAddressesError>,
+    },
+    #[error("AWS modify_network_interface_attribute reported error: {source}")]
+    AwsModifyNetworkInterfaceAttribute {
+        #[from]
+        source: SdkError<ModifyNetworkInterfaceAttributeError>,
+    },
+    #[error("The node's dedicated network interface has no private IP left for Eip {0}.")]
+    NoFreePrivateIp(String),
+    #[error("The cloud provider can't attach dedicated network interfaces to nodes.")]
+    DedicatedEniUnsupported,
+    #[error("Node {node}'s own private IP is taken by Eip {eip}; annotate Eips sharing a node with dedicated_eni.")]
+    NodeIpTaken { node: String, eip: String },
+    #[error("Instance {0} has no primary network interface to create a dedicated one alongside.")]
+    MissingPrimaryNetworkInterface(String),
     #[error("AWS get service quota reported error: {source}")]
     AwsGetServiceQuota {
         #[from]
         source: ServiceQuotaSdkError<GetServiceQuotaError>,
     },
+    #[error("AWS put_object reported error: {source}")]
+    AwsPutObject {
+        #[from]
+        source: SdkError<PutObjectError>,
+    },
 
+    #[error("GCP {operation} reported error {status}: {message}")]
+    GcpApi {
+        operation: &'static str,
+        status: u16,
+        message: String,
+    },
+    #[error("GCP {operation} failed: {message}")]
+    GcpOperation {
+        operation: &'static str,
+        message: String,
+    },
+    #[error("GCP address {0} was not found.")]
+    GcpAddressNotFound(String),
+    #[error("GCP {0} was skipped in dry-run mode.")]
+    GcpDryRun(&'static str),
+    #[error("Azure {operation} reported error {status}: {message}")]
+    AzureApi {
+        operation: &'static str,
+        status: u16,
+        message: String,
+    },
+    #[error("Azure {operation} failed: {message}")]
+    AzureOperation {
+        operation: &'static str,
+        message: String,
+    },
+    #[error("Azure public IP {0} was not found.")]
+    AzurePublicIpNotFound(String),
+    #[error("Azure {0} was skipped in dry-run mode.")]
+    AzureDryRun(&'static str),
+    #[error("The {0} cloud provider is not configured.")]
+    ProviderNotConfigured(String),
     #[error("serde_json error: {source}")]
     SerdeJson {
         #[from]
@@ -141,26 +358,63 @@ pub enum Error {
         #[from]
         source: Elapsed,
     },
+    #[error("Hyper error: {source}")]
+    Hyper {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("HTTP error: {source}")]
+    Http {
+        #[from]
+        source: hyper::http::Error,
+    },
     #[error("Hyper url error: {source}")]
     HyperUrl {
         #[from]
         source: hyper::http::uri::InvalidUri,
     },
+    #[cfg(feature = "otel")]
+    #[error("TLS error: {source}")]
+    NativeTls {
+        #[from]
+        source: native_tls::Error,
+    },
+    #[cfg(feature = "otel")]
+    #[error("Reqwest error: {source}")]
+    Reqwest {
+        #[from]
+        source: reqwest::Error,
+    },
+    #[cfg(feature = "otel")]
     #[error("Tonic transport error: {source}")]
     TonicTransport {
         #[from]
         source: tonic::transport::Error,
     },
+    #[cfg(feature = "otel")]
     #[error("Tonic metadata key error: {source}")]
     TonicInvalidMetadataKey {
         #[from]
         source: tonic::metadata::errors::InvalidMetadataKey,
     },
+    #[cfg(feature = "otel")]
     #[error("Tonic metadata value error: {source}")]
     TonicInvalidMetadataValue {
         #[from]
         source: tonic::metadata::errors::InvalidMetadataValue,
     },
+    #[cfg(feature = "otel")]
+    #[error("OpenTelemetry trace error: {source}")]
+    OtelTrace {
+        #[from]
+        source: opentelemetry::trace::TraceError,
+    },
+    #[cfg(feature = "otel")]
+    #[error("Unsupported OPENTELEMETRY_PROTOCOL {0}; use grpc or http.")]
+    UnsupportedOtelProtocol(String),
+    #[cfg(feature = "otel")]
+    #[error("Invalid OPENTELEMETRY_SAMPLE_RATE {0}; use a number from 0 to 1.")]
+    InvalidOtelSampleRate(String),
     #[error("AddrParse error: {source}")]
     AddrParse {
         #[from]
@@ -175,8 +429,118 @@ pub enum Error {
     CiliumRuleNotFound,
 }
 
+impl Error {
+    /// A short, machine-readable reason for the error, suitable for
+    /// surfacing in resource statuses.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::AllocateAddress { source } => aws_reason(source),
+            Self::AwsDescribeInstances { source } => aws_reason(source),
+            Self::AwsDescribeAddresses { source } => aws_reason(source),
+            Self::AwsAssociateAddress { source } => aws_reason(source),
+            Self::AwsDisassociateAddress { source } => aws_reason(source),
+            Self::AwsReleaseAddress { source } => aws_reason(source),
+            Self::AwsCreateTags { source } => aws_reason(source),
+            Self::AwsDescribeNetworkInterfaces { source } => aws_reason(source),
+            Self::AwsCreateNetworkInterface { source } => aws_reason(source),
+            Self::AwsAttachNetworkInterface { source } => aws_reason(source),
+            Self::AwsAssignPrivateIpAddresses { source } => aws_reason(source),
+            Self::AwsModifyNetworkInterfaceAttribute { source } => aws_reason(source),
+            Self::AwsGetServiceQuota { source } => aws_reason(source),
+            Self::AwsPutObject { source } => aws_reason(source),
+            Self::GcpApi { status: 429, .. } => "Throttled",
+            Self::GcpApi { message, .. } | Self::GcpOperation { message, .. } => {
+                if message.contains("QUOTA_EXCEEDED") || message.contains("quotaExceeded") {
+                    "QuotaExceeded"
+                } else {
+                    "GcpError"
+                }
+            }
+            Self::GcpAddressNotFound(_) | Self::AllocationLost(_) => "AllocationNotFound",
+            Self::GcpDryRun(_) => "DryRun",
+            Self::AzureApi { status: 429, .. } => "Throttled",
+            Self::AzureApi { message, .. } | Self::AzureOperation { message, .. } => {
+                if message.contains("QuotaExceeded")
+                    || message.contains("PublicIPCountLimitReached")
+                {
+                    "QuotaExceeded"
+                } else {
+                    "AzureError"
+                }
+            }
+            Self::AzurePublicIpNotFound(_) => "AllocationNotFound",
+            Self::AzureDryRun(_) => "DryRun",
+            Self::ProviderNotConfigured(_) => "ProviderNotConfigured",
+            Self::Kube { .. } | Self::KubeRuntimeWaitError { .. } => "KubernetesError",
+            Self::NoInterfaceWithThatIp | Self::MissingPrimaryNetworkInterface(_) => "MissingEni",
+            Self::DedicatedEniUnsupported => "DedicatedEniUnsupported",
+            Self::NodeIpTaken { .. } => "NodeIpTaken",
+            Self::InstanceNotFound(_) => "InstanceNotFound",
+            Self::InstanceTerminated(_) => "InstanceTerminated",
+            Self::AssociationNotVisible => "AssociationNotVisible",
+            Self::MissingPodIp | Self::MissingNodeIp | Self::NoFreePrivateIp(_) => {
+                "MissingPrivateIp"
+            }
+            Self::MultipleEipsTaggedForPod => "MultipleEipsTagged",
+            Self::InvalidTag { .. } => "InvalidTag",
+            Self::AddressNotAdoptable(_) => "AddressNotAdoptable",
+            Self::MultipleAddressesAdoptable(_) => "MultipleAddressesAdoptable",
+            Self::ReleaseProtected(_) => "ReleaseProtected",
+            Self::QuotaHeadroomExhausted { .. } => "QuotaExceeded",
+            Self::NamespaceBudgetExhausted { .. } => "NamespaceBudgetExceeded",
+            Self::InvalidEipNameTemplate { .. } => "InvalidEipNameTemplate",
+            Self::UnknownEipTemplate(_) => "UnknownEipTemplate",
+            Self::InjectedFault { reason, .. } => reason,
+            Self::NamespaceNotManaged(_) => "NamespaceNotManaged",
+            Self::NoEipResourceWithThatPodName(_)
+            | Self::NoEipResourceWithThatNodeSelector
+            | Self::NoEipResourceWithThatName(_) => "NoMatchingEip",
+            Self::PodUidMismatch { .. } => "PodUidMismatch",
+            Self::MissingNetworkAttachmentIp { .. } => "MissingNetworkAttachment",
+            Self::ForeignAssociation { .. } => "ForeignAssociation",
+            _ => "Error",
+        }
+    }
+
+    /// Whether the error only reports a cloud API call skipped by dry-run mode.
+    pub fn is_dry_run(&self) -> bool {
+        self.reason() == "DryRun"
+    }
+
+    /// Whether the error reports that an address no longer exists, for
+    /// example because it was released out-of-band.
+    pub fn is_allocation_not_found(&self) -> bool {
+        self.reason() == "AllocationNotFound"
+    }
+}
+
+/// The reason for an AWS SDK error, from the error code of the service's
+/// response. Errors which never reached the service have no code.
+fn aws_reason<E: ProvideErrorKind>(err: &SdkError<E>) -> &'static str {
+    let code = match err {
+        SdkError::ServiceError(context) => context.err().code(),
+        _ => None,
+    };
+    match code {
+        Some("DryRunOperation") => "DryRun",
+        Some("AddressLimitExceeded") => "QuotaExceeded",
+        Some("InvalidAllocationID.NotFound") => "AllocationNotFound",
+        Some("RequestLimitExceeded" | "Throttling" | "ThrottlingException") => "Throttled",
+        _ => "AwsError",
+    }
+}
+
+/// Flushes any pending error reports, for use before the process exits abnormally.
+pub fn flush_error_reports() {
+    if let Some(client) = sentry::Hub::current().client() {
+        client.flush(Some(Duration::from_secs(2)));
+    }
+}
+
+#[cfg(feature = "otel")]
 struct MyEnvFilter(EnvFilter);
 
+#[cfg(feature = "otel")]
 impl<S> LayerFilter<S> for MyEnvFilter
 where
     S: Subscriber,
@@ -186,12 +550,110 @@ where
     }
 }
 
+/// Builds an OTLP span exporter sending traces over gRPC, using tonic.
+#[cfg(feature = "otel")]
+fn grpc_span_exporter(
+    otel_endpoint: String,
+    otel_headers: HashMap<String, String>,
+    ca_bundle: Option<&str>,
+) -> Result<SpanExporter, Error> {
+    // Build endpoint with the correct timeout as exposed here:
+    // https://docs.rs/opentelemetry-otlp/latest/opentelemetry_otlp/struct.TonicExporterBuilder.html#method.with_channel
+    let endpoint = Endpoint::from_shared(otel_endpoint)?.timeout(Duration::from_secs(
+        opentelemetry_otlp::OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT,
+    ));
+    // TODO(guswynn): investigate if this should be non-lazy
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+
+    let mut tls = native_tls::TlsConnector::builder();
+    tls.request_alpns(&["h2"]);
+    for cert in ca_bundle.into_iter().flat_map(pem_certificates) {
+        tls.add_root_certificate(native_tls::Certificate::from_pem(cert.as_bytes())?);
+    }
+    let tls = tls.build()?;
+    let connector = HttpsConnector::from((http, tokio_native_tls::TlsConnector::from(tls.clone())));
+    // Plaintext gRPC is h2c, which a proxy can only tunnel, not forward.
+    let channel = match ProxySettings::from_env()? {
+        Some(proxy) => endpoint.connect_with_connector_lazy(proxy.connector(connector, tls, true)),
+        None => endpoint.connect_with_connector_lazy(connector),
+    };
+
+    let mut mmap = MetadataMap::new();
+    for (k, v) in otel_headers {
+        mmap.insert(MetadataKey::from_str(&k)?, v.parse()?);
+    }
+
+    Ok(SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_channel(channel)
+            .with_metadata(mmap),
+    )
+    .build_span_exporter()?)
+}
+
+/// Builds an OTLP span exporter sending protobuf-encoded traces over HTTP.
+#[cfg(feature = "otel")]
+fn http_span_exporter(
+    otel_endpoint: String,
+    otel_headers: HashMap<String, String>,
+    ca_bundle: Option<&str>,
+) -> Result<SpanExporter, Error> {
+    let mut exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(otel_endpoint)
+        .with_headers(otel_headers);
+    if let Some(ca_bundle) = ca_bundle {
+        let mut client = reqwest::Client::builder();
+        for cert in pem_certificates(ca_bundle) {
+            client = client.add_root_certificate(reqwest::Certificate::from_pem(cert.as_bytes())?);
+        }
+        exporter = exporter.with_http_client(client.build()?);
+    }
+    Ok(SpanExporterBuilder::from(exporter).build_span_exporter()?)
+}
+
+/// The PEM CA certificates to trust, in addition to the system's, when
+/// exporting traces: the contents of `OPENTELEMETRY_CA_CERT`, or of the file
+/// at `OPENTELEMETRY_CA_CERT_FILE`.
+#[cfg(feature = "otel")]
+fn otel_ca_bundle() -> Result<Option<String>, Error> {
+    if let Ok(bundle) = std::env::var("OPENTELEMETRY_CA_CERT") {
+        return Ok(Some(bundle));
+    }
+    match std::env::var("OPENTELEMETRY_CA_CERT_FILE") {
+        Ok(path) => Ok(Some(std::fs::read_to_string(path)?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Splits a PEM bundle into its certificates, as the TLS libraries only read
+/// the first certificate of a PEM file.
+#[cfg(feature = "otel")]
+fn pem_certificates(bundle: &str) -> impl Iterator<Item = &str> {
+    bundle
+        .split_inclusive("-----END CERTIFICATE-----")
+        .filter(|cert| cert.contains("-----BEGIN CERTIFICATE-----"))
+}
+
 pub async fn run_with_tracing<F, Fut>(service_name: &'static str, f: F) -> Result<(), Error>
 where
     F: FnOnce() -> Fut,
     Fut: Future<Output = Result<(), Error>>,
 {
+    // Sentry reads the rest of its configuration (SENTRY_ENVIRONMENT, etc.)
+    // from the environment itself.
+    let sentry_guard = std::env::var("SENTRY_DSN").ok().map(|_| {
+        sentry::init(sentry::ClientOptions {
+            release: Some(format!("{}@{}", service_name, env!("CARGO_PKG_VERSION")).into()),
+            ..Default::default()
+        })
+    });
+    let sentry_layer = sentry_guard.as_ref().map(|_| sentry_tracing::layer());
+
     match std::env::var("OPENTELEMETRY_ENDPOINT") {
+        #[cfg(feature = "otel")]
         Ok(otel_endpoint) => {
             let otel_headers: HashMap<String, String> = serde_json::from_str(
                 &std::env::var("OPENTELEMETRY_HEADERS").unwrap_or_else(|_| "{}".to_owned()),
@@ -201,36 +663,14 @@ where
                 &std::env::var("OPENTELEMETRY_TOPLEVEL_FIELDS").unwrap_or_else(|_| "{}".to_owned()),
             )?;
             let otel_sample_rate =
-                &std::env::var("OPENTELEMETRY_SAMPLE_RATE").unwrap_or_else(|_| "0.05".to_owned());
+                std::env::var("OPENTELEMETRY_SAMPLE_RATE").unwrap_or_else(|_| "0.05".to_owned());
+            let otel_sample_rate = otel_sample_rate
+                .parse()
+                .map_err(|_| Error::InvalidOtelSampleRate(otel_sample_rate.clone()))?;
             let otel_targets = std::env::var("OPENTELEMETRY_LEVEL_TARGETS")
                 .unwrap_or_else(|_| "DEBUG".to_owned())
                 .parse::<Targets>()?;
 
-            // Build endpoint with the correct timeout as exposed here:
-            // https://docs.rs/opentelemetry-otlp/latest/opentelemetry_otlp/struct.TonicExporterBuilder.html#method.with_channel
-            let endpoint = Endpoint::from_shared(otel_endpoint)?.timeout(Duration::from_secs(
-                opentelemetry_otlp::OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT,
-            ));
-            // TODO(guswynn): investigate if this should be non-lazy
-            let mut http = HttpConnector::new();
-            http.enforce_http(false);
-
-            let connector = HttpsConnector::from((
-                http,
-                tokio_native_tls::TlsConnector::from(
-                    native_tls::TlsConnector::builder()
-                        .request_alpns(&["h2"])
-                        .build()
-                        .unwrap(),
-                ),
-            ));
-            let channel = endpoint.connect_with_connector_lazy(connector);
-
-            let mut mmap = MetadataMap::new();
-            for (k, v) in otel_headers {
-                mmap.insert(MetadataKey::from_str(&k)?, v.parse()?);
-            }
-
             // Add the attributes that all spans should have applied
             let otr = OtelResource::new(
                 otel_toplevel_fields
@@ -239,23 +679,33 @@ where
                     .chain([KeyValue::new("service.name", service_name)]),
             );
 
-            let otlp_exporter = opentelemetry_otlp::new_exporter()
-                .tonic()
-                .with_channel(channel)
-                .with_metadata(mmap);
+            let ca_bundle = otel_ca_bundle()?;
+            let otlp_exporter = match std::env::var("OPENTELEMETRY_PROTOCOL").as_deref() {
+                Ok("http") => {
+                    http_span_exporter(otel_endpoint, otel_headers, ca_bundle.as_deref())?
+                }
+                Ok("grpc") | Err(_) => {
+                    grpc_span_exporter(otel_endpoint, otel_headers, ca_bundle.as_deref())?
+                }
+                Ok(protocol) => return Err(Error::UnsupportedOtelProtocol(protocol.to_owned())),
+            };
 
-            let tracer = opentelemetry_otlp::new_pipeline()
-                .tracing()
-                .with_exporter(otlp_exporter)
-                .with_trace_config(
+            // Sample everything up front, and decide whether to export once the
+            // span has ended, so that spans which failed are always exported.
+            let span_processor = sampling::ErrorAwareSpanProcessor::new(
+                BatchSpanProcessor::builder(otlp_exporter, opentelemetry::runtime::Tokio).build(),
+                otel_sample_rate,
+            );
+            let provider = TracerProvider::builder()
+                .with_span_processor(span_processor)
+                .with_config(
                     Config::default()
-                        .with_sampler(Sampler::TraceIdRatioBased(
-                            otel_sample_rate.parse().unwrap(),
-                        ))
+                        .with_sampler(Sampler::AlwaysOn)
                         .with_resource(otr),
                 )
-                .install_batch(opentelemetry::runtime::Tokio)
-                .unwrap();
+                .build();
+            let tracer = provider.tracer(service_name);
+            let _ = opentelemetry::global::set_tracer_provider(provider);
             let otel_layer = tracing_opentelemetry::layer()
                 .with_tracer(tracer)
                 .with_filter(otel_targets);
@@ -265,14 +715,29 @@ where
             tracing_subscriber::Registry::default()
                 .with(otel_layer)
                 .with(stdout_layer)
+                .with(sentry_layer)
                 .init();
         }
-        Err(_) => {
+        _otel_endpoint => {
             tracing_subscriber::fmt()
                 .with_env_filter(EnvFilter::from_default_env())
                 .json()
+                .finish()
+                .with(sentry_layer)
                 .init();
+            #[cfg(not(feature = "otel"))]
+            if _otel_endpoint.is_ok() {
+                tracing::warn!(
+                    "OPENTELEMETRY_ENDPOINT is set, but this build has no OpenTelemetry support."
+                );
+            }
         }
     };
-    f().await
+    let result = f().await;
+    // Export any spans still buffered by the batch span processor. Shutting
+    // down blocks until the export finishes, so keep it off the async workers.
+    #[cfg(feature = "otel")]
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+    flush_error_reports();
+    result
 }
@@ -0,0 +1,14 @@
+use serde::Serialize;
+
+#[cfg(feature = "metrics-server")]
+mod server;
+
+#[cfg(feature = "metrics-server")]
+pub use server::{serve, DebugEndpoint};
+
+/// The version and git commit a binary was built from.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+}
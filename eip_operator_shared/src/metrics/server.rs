@@ -0,0 +1,121 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, TextEncoder};
+use tracing::{event, Level};
+
+use super::BuildInfo;
+use crate::Error;
+
+type DebugStateFn =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<serde_json::Value, Error>> + Send + Sync>;
+
+/// Serves a JSON dump of the operator's internal state at `/debug/state`, to
+/// requests bearing the configured token.
+#[derive(Clone)]
+pub struct DebugEndpoint {
+    token: String,
+    state: DebugStateFn,
+}
+
+impl DebugEndpoint {
+    pub fn new<F>(token: String, state: F) -> Self
+    where
+        F: Fn() -> BoxFuture<'static, Result<serde_json::Value, Error>> + Send + Sync + 'static,
+    {
+        Self {
+            token,
+            state: Arc::new(state),
+        }
+    }
+
+    fn authorized(&self, request: &Request<Body>) -> bool {
+        request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map_or(false, |token| token == self.token)
+    }
+}
+
+/// Serves the metrics in the default `prometheus` registry at `/metrics`, a
+/// readiness check at `/readyz` which succeeds once `ready` is set, the build
+/// info at `/version`, and the debug endpoint, if any.
+pub async fn serve(
+    addr: SocketAddr,
+    ready: Arc<AtomicBool>,
+    build_info: BuildInfo,
+    debug: Option<DebugEndpoint>,
+) -> Result<(), Error> {
+    let make_service = make_service_fn(move |_conn| {
+        let ready = Arc::clone(&ready);
+        let debug = debug.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(request, Arc::clone(&ready), build_info, debug.clone())
+            }))
+        }
+    });
+    event!(Level::INFO, %addr, "Serving metrics.");
+    Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    ready: Arc<AtomicBool>,
+    build_info: BuildInfo,
+    debug: Option<DebugEndpoint>,
+) -> Result<Response<Body>, Infallible> {
+    let response = match (request.uri().path(), debug) {
+        ("/metrics", _) => {
+            let encoder = TextEncoder::new();
+            let mut buffer = vec![];
+            match encoder.encode(&prometheus::gather(), &mut buffer) {
+                Ok(()) => Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, encoder.format_type())
+                    .body(Body::from(buffer)),
+                Err(err) => {
+                    event!(Level::ERROR, err = %err, "Failed to encode metrics.");
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::empty())
+                }
+            }
+        }
+        ("/readyz", _) => Response::builder()
+            .status(if ready.load(Ordering::SeqCst) {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            })
+            .body(Body::empty()),
+        ("/version", _) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!(build_info).to_string())),
+        ("/debug/state", Some(debug)) if !debug.authorized(&request) => Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(Body::empty()),
+        ("/debug/state", Some(debug)) => match (debug.state)().await {
+            Ok(state) => Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(state.to_string())),
+            Err(err) => {
+                event!(Level::ERROR, err = %err, "Failed to collect debug state.");
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+            }
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.unwrap())
+}
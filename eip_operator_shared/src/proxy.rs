@@ -0,0 +1,127 @@
+//! Routing outbound connections through the proxy in `HTTPS_PROXY`, for
+//! clusters without direct internet egress.
+
+use headers::Authorization;
+use hyper::client::HttpConnector;
+use hyper::Uri;
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_tls::HttpsConnector;
+
+use crate::Error;
+
+/// The proxy configured by the `HTTPS_PROXY` and `NO_PROXY` environment
+/// variables, or their lowercase forms.
+#[derive(Clone, Debug)]
+pub struct ProxySettings {
+    uri: Uri,
+    no_proxy: Vec<String>,
+}
+
+impl ProxySettings {
+    /// The proxy configured in the environment, if any.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let proxy = match env_var("HTTPS_PROXY") {
+            Some(proxy) => proxy,
+            None => return Ok(None),
+        };
+        let no_proxy = env_var("NO_PROXY")
+            .map(|no_proxy| parse_no_proxy(&no_proxy))
+            .unwrap_or_default();
+        Ok(Some(Self {
+            uri: proxy.parse()?,
+            no_proxy,
+        }))
+    }
+
+    /// The proxy's URI, without any credentials.
+    pub fn uri(&self) -> String {
+        let (_, uri) = split_credentials(&self.uri);
+        uri.to_string()
+    }
+
+    /// Wraps the connector so that it connects through the proxy, except to
+    /// hosts in `NO_PROXY`. TLS to proxied hosts is negotiated with `tls`,
+    /// inside a `CONNECT` tunnel. Plain HTTP requests are forwarded by the
+    /// proxy, unless `tunnel_http` is set, which is needed for protocols
+    /// such as h2c which can't be forwarded.
+    pub fn connector(
+        &self,
+        connector: HttpsConnector<HttpConnector>,
+        tls: native_tls::TlsConnector,
+        tunnel_http: bool,
+    ) -> ProxyConnector<HttpsConnector<HttpConnector>> {
+        let no_proxy = self.no_proxy.clone();
+        let intercept = Intercept::Custom(
+            (move |_scheme: Option<&str>, host: Option<&str>, _port: Option<u16>| {
+                !host.map_or(false, |host| bypasses(&no_proxy, host))
+            })
+            .into(),
+        );
+        let (credentials, uri) = split_credentials(&self.uri);
+        let mut proxy = Proxy::new(intercept, uri);
+        if let Some((username, password)) = credentials {
+            proxy.set_authorization(Authorization::basic(&username, &password));
+        }
+        if tunnel_http {
+            proxy.force_connect();
+        }
+        let mut connector = ProxyConnector::from_proxy_unsecured(connector, proxy);
+        connector.set_tls(Some(tls));
+        connector
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name)
+        .or_else(|_| std::env::var(name.to_lowercase()))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+/// Splits the user and password out of the URI's authority, if it has them.
+fn split_credentials(uri: &Uri) -> (Option<(String, String)>, Uri) {
+    let authority = match uri.authority() {
+        Some(authority) => authority.as_str(),
+        None => return (None, uri.clone()),
+    };
+    let (userinfo, host) = match authority.rsplit_once('@') {
+        Some(split) => split,
+        None => return (None, uri.clone()),
+    };
+    let (username, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+    let mut parts = uri.clone().into_parts();
+    parts.authority = host.parse().ok();
+    let uri = Uri::from_parts(parts).unwrap_or_else(|_| uri.clone());
+    (Some((username.to_owned(), password.to_owned())), uri)
+}
+
+/// Parses a comma-separated `NO_PROXY` list into lowercase hosts and domain
+/// suffixes, without leading dots or wildcards.
+fn parse_no_proxy(no_proxy: &str) -> Vec<String> {
+    no_proxy
+        .split(',')
+        .map(|entry| {
+            entry
+                .trim()
+                .trim_start_matches("*.")
+                .trim_start_matches('.')
+                .to_lowercase()
+        })
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+/// Whether connections to the host are made directly, rather than through
+/// the proxy.
+fn bypasses(no_proxy: &[String], host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let host = host.to_lowercase();
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim_start_matches('[').trim_end_matches(']');
+        entry == "*"
+            || host == entry
+            || host
+                .strip_suffix(entry)
+                .map_or(false, |subdomain| subdomain.ends_with('.'))
+    })
+}
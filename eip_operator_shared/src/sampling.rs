@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::sdk::trace::{Span, SpanProcessor};
+use opentelemetry::trace::{Span as _, StatusCode, TraceId, TraceResult};
+use opentelemetry::Context;
+
+/// The most spans kept of a trace which wasn't sampled, so that a trace
+/// which never ends can't grow without bound. Failed spans are kept anyway.
+const MAX_PENDING_SPANS: usize = 1024;
+
+/// Wraps another span processor, only forwarding the spans of traces whose
+/// trace id falls within the configured sample ratio, or in which any span
+/// ended with an error status.
+///
+/// This must be used with `Sampler::AlwaysOn`, since the sampling decision is
+/// deferred until the trace ends and we know whether it failed. The spans of
+/// a trace outside the sample ratio are held until all of its spans have
+/// ended, so that a failed trace is exported whole, rather than just its
+/// failed spans.
+#[derive(Debug)]
+pub(crate) struct ErrorAwareSpanProcessor<P> {
+    inner: P,
+    ratio: f64,
+    pending: Mutex<HashMap<TraceId, PendingTrace>>,
+}
+
+/// The spans of a trace outside the sample ratio which have ended, while
+/// others are still open.
+#[derive(Debug, Default)]
+struct PendingTrace {
+    open: usize,
+    failed: bool,
+    spans: Vec<SpanData>,
+}
+
+impl<P> ErrorAwareSpanProcessor<P> {
+    pub(crate) fn new(inner: P, ratio: f64) -> Self {
+        Self {
+            inner,
+            ratio,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Same algorithm as `Sampler::TraceIdRatioBased`, so that all spans in a
+    /// trace get the same decision.
+    fn ratio_sampled(&self, trace_id: TraceId) -> bool {
+        if self.ratio >= 1.0 {
+            return true;
+        }
+        let bytes = trace_id.to_bytes();
+        let (_, low) = bytes.split_at(8);
+        let rnd = u64::from_be_bytes(low.try_into().unwrap()) >> 1;
+        let prob_upper_bound = (self.ratio.max(0.0) * (1u64 << 63) as f64) as u64;
+        rnd < prob_upper_bound
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for ErrorAwareSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        let trace_id = span.span_context().trace_id();
+        if !self.ratio_sampled(trace_id) {
+            let mut pending = self.pending.lock().unwrap();
+            pending.entry(trace_id).or_default().open += 1;
+        }
+        self.inner.on_start(span, cx)
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let trace_id = span.span_context.trace_id();
+        if self.ratio_sampled(trace_id) {
+            return self.inner.on_end(span);
+        }
+        let ended = {
+            let mut pending = self.pending.lock().unwrap();
+            let trace = pending.entry(trace_id).or_default();
+            let failed = span.status_code == StatusCode::Error;
+            trace.failed |= failed;
+            if failed || trace.spans.len() < MAX_PENDING_SPANS {
+                trace.spans.push(span);
+            }
+            trace.open = trace.open.saturating_sub(1);
+            match trace.open {
+                0 => pending.remove(&trace_id),
+                _ => None,
+            }
+        };
+        if let Some(trace) = ended.filter(|trace| trace.failed) {
+            for span in trace.spans {
+                self.inner.on_end(span);
+            }
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        // Export what ended of the failed traces still open, as they won't
+        // end now.
+        let pending = std::mem::take(self.pending.get_mut().unwrap());
+        for trace in pending.into_values().filter(|trace| trace.failed) {
+            for span in trace.spans {
+                self.inner.on_end(span);
+            }
+        }
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry::sdk::export::trace::SpanData;
+    use opentelemetry::sdk::trace::{Config, Sampler, Span, SpanProcessor, TracerProvider};
+    use opentelemetry::trace::{
+        Span as _, StatusCode, TraceContextExt, TraceResult, Tracer, TracerProvider as _,
+    };
+    use opentelemetry::Context;
+
+    use super::ErrorAwareSpanProcessor;
+
+    /// Records the names of the spans it's handed.
+    #[derive(Clone, Debug, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn names(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl SpanProcessor for Recorder {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.0.lock().unwrap().push(span.name.into_owned());
+        }
+
+        fn force_flush(&self) -> TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    fn provider(recorder: &Recorder, ratio: f64) -> TracerProvider {
+        TracerProvider::builder()
+            .with_span_processor(ErrorAwareSpanProcessor::new(recorder.clone(), ratio))
+            .with_config(Config::default().with_sampler(Sampler::AlwaysOn))
+            .build()
+    }
+
+    /// Ends a child of the root span, failed or not, and returns the root.
+    fn trace(provider: &TracerProvider, child_status: StatusCode) -> Context {
+        let tracer = provider.tracer("test");
+        let cx = Context::current_with_span(tracer.start("reconcile"));
+        let mut child = tracer.start_with_context("describe", &cx);
+        child.set_status(child_status, String::new());
+        child.end();
+        cx
+    }
+
+    #[test]
+    fn failed_traces_are_exported_whole_once_their_root_ends() {
+        let recorder = Recorder::default();
+        let provider = provider(&recorder, 0.0);
+        let cx = trace(&provider, StatusCode::Error);
+        assert!(recorder.names().is_empty());
+        cx.span().end();
+        assert_eq!(recorder.names(), ["describe", "reconcile"]);
+    }
+
+    #[test]
+    fn unsampled_traces_without_failures_are_dropped() {
+        let recorder = Recorder::default();
+        let provider = provider(&recorder, 0.0);
+        trace(&provider, StatusCode::Ok).span().end();
+        assert!(recorder.names().is_empty());
+    }
+
+    #[test]
+    fn sampled_traces_are_exported_as_their_spans_end() {
+        let recorder = Recorder::default();
+        let provider = provider(&recorder, 1.0);
+        let cx = trace(&provider, StatusCode::Ok);
+        assert_eq!(recorder.names(), ["describe"]);
+        cx.span().end();
+        assert_eq!(recorder.names(), ["describe", "reconcile"]);
+    }
+}
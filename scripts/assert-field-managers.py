@@ -0,0 +1,43 @@
+#!/usr/bin/python2.7
+# Python2! I know, but it's what's available in the AWS CLI container, OK?
+from __future__ import print_function, unicode_literals
+import sys
+import json
+
+from _common import fail_quietly_unless_explicit_success
+
+MANAGER = "eip.materialize.cloud"
+
+
+def owned_by_operator(obj):
+    return [
+        entry
+        for entry in obj["metadata"].get("managedFields", [])
+        if entry["manager"] == MANAGER
+    ]
+
+
+with fail_quietly_unless_explicit_success() as succeed:
+    items = json.load(sys.stdin)["items"]
+    eips = [e for e in items if e["kind"] == "Eip"]
+    pods = [p for p in items if p["kind"] == "Pod"]
+    assert eips and pods
+
+    for eip in eips:
+        entries = owned_by_operator(eip)
+        # The Eip status is only written through the status subresource.
+        assert any(e.get("subresource") == "status" for e in entries), eip
+        for entry in entries:
+            if entry.get("subresource") == "status":
+                assert set(entry["fieldsV1"]) == {"f:status"}, entry
+            elif "f:spec" in entry["fieldsV1"]:
+                # Only autocreated Eips have their spec set by the operator.
+                assert entry["operation"] == "Apply", entry
+
+    for pod in pods:
+        for entry in owned_by_operator(pod):
+            # Pod annotations are applied server-side, and the operator
+            # never writes the spec of a running pod.
+            assert "f:spec" not in entry["fieldsV1"], entry
+
+    succeed("Field ownership is split as documented")